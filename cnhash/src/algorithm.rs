@@ -0,0 +1,102 @@
+//! Selection of the variant to compute.
+use cryptonight_hash::{CryptoNight, PowAlgorithm, Variant};
+
+use crate::Result;
+
+pub const USAGE: &str = "    -a, --algorithm <NAME>
+                         Variant to compute, as printed by --list-algorithms.
+                         Defaults to cn/0.
+        --height <N>     Block height, which selects the program of cn/r and
+                         cn/wow. Defaults to 0.";
+
+/// The variant to compute and the block height to compute it for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Algorithm {
+    variant: Variant,
+    height: u64,
+}
+
+impl Algorithm {
+    /// Try to parse an algorithm option.
+    ///
+    /// Returns whether `flag` was recognized. The value of the option is
+    /// consumed from `args`.
+    pub fn parse_flag<I: Iterator<Item=String>>(&mut self, flag: &str, args: &mut I) -> Result<bool> {
+        match flag {
+            "-a" | "--algorithm" => self.variant = crate::parse_value(flag, args)?,
+            "--height" => self.height = crate::parse_value(flag, args)?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Create a hasher for the algorithm.
+    pub fn hasher(self) -> CryptoNight {
+        PowAlgorithm::Variant(self.variant).hasher(self.height)
+    }
+
+    /// Size of the scratchpads to allocate for the algorithm.
+    pub fn scratchpad_size(self) -> usize {
+        self.variant.scratchpad_size()
+    }
+
+    /// Check that the variant can hash `data`.
+    ///
+    /// Variant 1 and the variants based on it need at least 43 bytes.
+    pub fn check_input(self, data: &[u8]) -> Result<()> {
+        let min_input_len = self.variant.min_input_len();
+        if data.len() < min_input_len {
+            return Err(format!("{} requires at least {} bytes of input", self.variant.name(), min_input_len).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm { variant: Variant::V0, height: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Algorithm> {
+        let mut algorithm = Algorithm::default();
+        let mut args = args.iter().map(|arg| arg.to_string());
+
+        while let Some(flag) = args.next() {
+            assert!(algorithm.parse_flag(&flag, &mut args)?);
+        }
+
+        Ok(algorithm)
+    }
+
+    #[test]
+    fn test_parse_flag() {
+        assert_eq!(parse(&[]).unwrap(), Algorithm::default());
+        assert_eq!(parse(&["-a", "cn/wow", "--height", "12"]).unwrap(), Algorithm { variant: Variant::Wow, height: 12 });
+        assert!(parse(&["--algorithm", "cn/unknown"]).is_err());
+        assert!(!Algorithm::default().parse_flag("--format", &mut std::iter::empty()).unwrap());
+    }
+
+    #[test]
+    fn test_hasher() {
+        let hasher = parse(&["--algorithm", "cn/r", "--height", "1806260"]).unwrap().hasher();
+
+        assert_eq!(hasher.variant(), Variant::R);
+        assert_eq!(hasher.height(), 1_806_260);
+    }
+
+    #[test]
+    fn test_check_input() {
+        let algorithm = parse(&["-a", "cn/1"]).unwrap();
+
+        assert!(algorithm.check_input(&[0; 43]).is_ok());
+        assert!(algorithm.check_input(&[0; 42]).is_err());
+        assert!(Algorithm::default().check_input(&[]).is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+//! The `bench` subcommand: measure the hash rate of this machine.
+use std::time::{Duration, Instant};
+
+use cryptonight_hash::CryptoNightHasher;
+
+use crate::algorithm::{self, Algorithm};
+use crate::worker::{self, WorkerOptions};
+use crate::Result;
+
+pub fn usage() -> String {
+    format!("\
+Measure the hash rate of this machine.
+
+USAGE:
+    cnhash bench [OPTIONS]
+
+OPTIONS:
+        --seconds <N>    Duration of the benchmark. Defaults to 10.
+{}
+{}
+    -h, --help           Print this message and exit.", algorithm::USAGE, worker::USAGE)
+}
+
+pub fn run<I: Iterator<Item=String>>(mut args: I) -> Result<()> {
+    let mut algorithm = Algorithm::default();
+    let mut workers = WorkerOptions::default();
+    let mut seconds = 10;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", usage());
+                return Ok(());
+            }
+            "--seconds" => seconds = crate::parse_value(&arg, &mut args)?,
+            flag => if !algorithm.parse_flag(flag, &mut args)? && !workers.parse_flag(flag, &mut args)? {
+                return Err(format!("unknown argument '{}'\n\n{}", flag, usage()).into());
+            }
+        }
+    }
+
+    let duration = Duration::from_secs(seconds);
+    let start = Instant::now();
+
+    let counts = workers.run(algorithm.scratchpad_size(), move |index, scratchpad| {
+        let mut hasher = CryptoNightHasher::with_scratchpad(algorithm.hasher(), scratchpad);
+        // A Monero-style 76 byte hashing blob with a per-thread prefix.
+        let mut blob = [0u8; 76];
+        blob[0] = index as u8;
+        let mut hashes = 0u64;
+
+        while start.elapsed() < duration {
+            blob[39..43].copy_from_slice(&(hashes as u32).to_le_bytes());
+            hasher.hash(&blob[..]);
+            hashes += 1;
+        }
+
+        hashes
+    })?;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let total: u64 = counts.iter().sum();
+
+    for (index, count) in counts.iter().enumerate() {
+        println!("thread {}: {:.2} H/s", index, *count as f64 / elapsed);
+    }
+    println!("total: {} hashes in {:.2} s, {:.2} H/s", total, elapsed, total as f64 / elapsed);
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Output encodings for digests.
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+pub const USAGE: &str = "        --format <FORMAT>
+                         Encoding of the digests: hex (default), base64,
+                         raw or multihash. Raw digests are written as 32
+                         bytes without separators, all others one per line.";
+
+/// Multicodec code used for multihash output.
+///
+/// CryptoNight has no registered code, so this is taken from the range
+/// reserved for private use.
+const MULTIHASH_CODE: u64 = 0x30_0000;
+
+/// Encoding used for writing digests.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    #[default]
+    Hex,
+    Base64,
+    Raw,
+    /// Hex encoded multihash: varint code, varint length, digest.
+    Multihash,
+}
+
+impl Format {
+    /// Write a digest in this format, including its separator.
+    pub fn write<W: Write>(self, out: &mut W, digest: &[u8]) -> io::Result<()> {
+        match self {
+            Format::Hex => writeln!(out, "{}", hex::encode(digest)),
+            Format::Base64 => writeln!(out, "{}", base64::encode(digest)),
+            Format::Raw => out.write_all(digest),
+            Format::Multihash => {
+                let mut multihash = Vec::with_capacity(digest.len() + 8);
+                write_varint(&mut multihash, MULTIHASH_CODE);
+                write_varint(&mut multihash, digest.len() as u64);
+                multihash.extend_from_slice(digest);
+
+                writeln!(out, "{}", hex::encode(multihash))
+            }
+        }
+    }
+
+    /// Whether digests in this format are separated by newlines.
+    pub fn is_line_based(self) -> bool {
+        self != Format::Raw
+    }
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Format::Hex),
+            "base64" => Ok(Format::Base64),
+            "raw" => Ok(Format::Raw),
+            "multihash" => Ok(Format::Multihash),
+            _ => Err(UnknownFormat),
+        }
+    }
+}
+
+/// Error for unrecognized format names.
+#[derive(Debug)]
+pub struct UnknownFormat;
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("expected one of hex, base64, raw or multihash")
+    }
+}
+
+/// Append an unsigned LEB128 varint, as used by multiformats.
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_varint() {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 0x20);
+        write_varint(&mut buffer, 300);
+        write_varint(&mut buffer, MULTIHASH_CODE);
+
+        assert_eq!(buffer, [0x20, 0xac, 0x02, 0x80, 0x80, 0xc0, 0x01]);
+    }
+
+    #[test]
+    fn test_formats() {
+        let digest = [0xffu8; 4];
+        let encode = |format: Format| {
+            let mut out = Vec::new();
+            format.write(&mut out, &digest).unwrap();
+            out
+        };
+
+        assert_eq!(encode(Format::Hex), b"ffffffff\n");
+        assert_eq!(encode(Format::Base64), b"/////w==\n");
+        assert_eq!(encode(Format::Raw), digest);
+        assert_eq!(encode(Format::Multihash), b"8080c00104ffffffff\n");
+    }
+}
@@ -0,0 +1,194 @@
+//! Command line interface for the cryptonight-hash crate.
+//!
+//! Inputs are given as hex strings, either as arguments or, in streaming
+//! mode, as newline-delimited lines on standard input. Every input results
+//! in one hex encoded digest on its own line.
+//!
+//! The `bench` and `scan` subcommands run multi-threaded workloads and
+//! allow experimenting with the memory and affinity settings.
+use std::env;
+use std::error::Error;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::process;
+use std::str::FromStr;
+
+use cryptonight_hash::{CryptoNightHasher, Variant};
+
+use crate::algorithm::Algorithm;
+use crate::format::Format;
+
+mod algorithm;
+mod bench;
+mod format;
+mod scan;
+mod worker;
+
+fn usage() -> String {
+    format!("\
+Compute CryptoNight digests of hex encoded inputs.
+
+USAGE:
+    cnhash [OPTIONS] [HEX]...
+    cnhash bench [OPTIONS]
+    cnhash scan [OPTIONS] <BLOB>
+
+Run `cnhash <SUBCOMMAND> --help` for the options of the subcommands.
+
+OPTIONS:
+    -s, --stream         Read newline-delimited hex inputs from stdin and
+                         write one digest per line until stdin is closed.
+        --list-algorithms
+                         Print the supported algorithms, one per line, as
+                         tab-separated name, scratchpad size in bytes and
+                         number of main loop iterations.
+{}
+{}
+    -h, --help           Print this message and exit.", algorithm::USAGE, format::USAGE)
+}
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Parse the value of option `flag` from the next argument.
+fn parse_value<T, I>(flag: &str, args: &mut I) -> Result<T>
+    where T: FromStr, T::Err: std::fmt::Display, I: Iterator<Item=String> {
+    let value = args.next().ok_or_else(|| format!("option '{}' requires a value", flag))?;
+
+    value.parse().map_err(|e| format!("invalid value '{}' for '{}': {}", value, flag, e).into())
+}
+
+/// What the program was asked to do.
+enum Mode {
+    Help,
+    ListAlgorithms,
+    Arguments(Vec<String>, Algorithm, Format),
+    Stream(Algorithm, Format),
+}
+
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Mode> {
+    let mut stream = false;
+    let mut algorithm = Algorithm::default();
+    let mut format = Format::default();
+    let mut inputs = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Mode::Help),
+            "--list-algorithms" => return Ok(Mode::ListAlgorithms),
+            "-s" | "--stream" => stream = true,
+            "--format" => format = parse_value(&arg, &mut args)?,
+            flag if flag.starts_with('-') && flag.len() > 1 => if !algorithm.parse_flag(flag, &mut args)? {
+                return Err(format!("unknown option '{}'", flag).into());
+            }
+            _ => inputs.push(arg),
+        }
+    }
+
+    if stream {
+        if !inputs.is_empty() {
+            return Err("--stream does not take any inputs as arguments".into());
+        }
+
+        Ok(Mode::Stream(algorithm, format))
+    } else {
+        Ok(Mode::Arguments(inputs, algorithm, format))
+    }
+}
+
+/// Hash every argument and print the results in order.
+fn hash_arguments(inputs: &[String], algorithm: Algorithm, format: Format) -> Result<()> {
+    let mut hasher = CryptoNightHasher::new(algorithm.hasher());
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    for input in inputs {
+        let data = hex::decode(input.trim())
+            .map_err(|e| format!("invalid input '{}': {}", input, e))?;
+        algorithm.check_input(&data)
+            .map_err(|e| format!("invalid input '{}': {}", input, e))?;
+        let digest = hasher.hash(data);
+        format.write(&mut out, &digest)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Hash stdin line by line.
+///
+/// Output is flushed after every line so the program can be driven
+/// interactively through a pipe. Lines that are not valid hex, or too short
+/// for the algorithm, are reported on stderr and, for line based formats,
+/// answered with an empty line, so every input line always has exactly one
+/// matching output line.
+fn hash_stream(algorithm: Algorithm, format: Format) -> Result<()> {
+    let mut hasher = CryptoNightHasher::new(algorithm.hasher());
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    for (number, line) in stdin.lock().lines().enumerate() {
+        let line = line?;
+
+        let data = hex::decode(line.trim()).map_err(Into::into)
+            .and_then(|data| algorithm.check_input(&data).map(|()| data));
+
+        match data {
+            Ok(data) => {
+                let digest = hasher.hash(data);
+                format.write(&mut out, &digest)?;
+            }
+            Err(e) => {
+                eprintln!("cnhash: line {}: {}", number + 1, e);
+                if format.is_line_based() {
+                    writeln!(out)?;
+                }
+            }
+        }
+
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Print the supported algorithms in a tab-separated format.
+fn list_algorithms() -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    for variant in Variant::all() {
+        writeln!(out, "{}\t{}\t{}", variant.name(), variant.scratchpad_size(), variant.iterations())?;
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let mut args = env::args().skip(1).peekable();
+
+    match args.peek().map(String::as_str) {
+        Some("bench") => return bench::run(args.skip(1)),
+        Some("scan") => return scan::run(args.skip(1)),
+        _ => (),
+    }
+
+    match parse_args(args)? {
+        Mode::Help => {
+            println!("{}", usage());
+            Ok(())
+        }
+        Mode::ListAlgorithms => list_algorithms(),
+        Mode::Arguments(ref inputs, ..) if inputs.is_empty() => {
+            Err(format!("no inputs given\n\n{}", usage()).into())
+        }
+        Mode::Arguments(inputs, algorithm, format) => hash_arguments(&inputs, algorithm, format),
+        Mode::Stream(algorithm, format) => hash_stream(algorithm, format),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("cnhash: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,129 @@
+//! The `scan` subcommand: search a nonce range for hashes meeting a difficulty.
+use std::io::{self, Write};
+
+use cryptonight_hash::CryptoNightHasher;
+
+use crate::algorithm::{self, Algorithm};
+use crate::format::{self, Format};
+use crate::worker::{self, WorkerOptions};
+use crate::Result;
+
+pub fn usage() -> String {
+    format!("\
+Scan a range of nonces in a hashing blob for hashes meeting a difficulty.
+
+Every matching nonce is printed with its hash, tab-separated. The raw
+output format is not supported.
+
+USAGE:
+    cnhash scan [OPTIONS] <BLOB>
+
+OPTIONS:
+        --nonce-offset <N>
+                         Byte offset of the 32 bit nonce in the blob.
+                         Defaults to 39, as in Monero hashing blobs.
+        --start <N>      First nonce to try. Defaults to 0.
+        --count <N>      Number of nonces to try. Defaults to all remaining.
+        --difficulty <N> Required difficulty. Defaults to 1.
+{}
+{}
+{}
+    -h, --help           Print this message and exit.", algorithm::USAGE, format::USAGE, worker::USAGE)
+}
+
+pub fn run<I: Iterator<Item=String>>(mut args: I) -> Result<()> {
+    let mut algorithm = Algorithm::default();
+    let mut workers = WorkerOptions::default();
+    let mut nonce_offset = 39;
+    let mut start = 0u32;
+    let mut count = None;
+    let mut difficulty = 1u64;
+    let mut format = Format::default();
+    let mut blob = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", usage());
+                return Ok(());
+            }
+            "--nonce-offset" => nonce_offset = crate::parse_value(&arg, &mut args)?,
+            "--start" => start = crate::parse_value(&arg, &mut args)?,
+            "--count" => count = Some(crate::parse_value::<u64, _>(&arg, &mut args)?),
+            "--difficulty" => difficulty = crate::parse_value(&arg, &mut args)?,
+            "--format" => format = crate::parse_value(&arg, &mut args)?,
+            flag if flag.starts_with('-') => if !algorithm.parse_flag(flag, &mut args)? && !workers.parse_flag(flag, &mut args)? {
+                return Err(format!("unknown option '{}'\n\n{}", flag, usage()).into());
+            }
+            _ if blob.is_none() => blob = Some(hex::decode(arg.trim())?),
+            _ => return Err(format!("unexpected argument '{}'", arg).into()),
+        }
+    }
+
+    if !format.is_line_based() {
+        return Err("scan does not support the raw output format".into());
+    }
+
+    let blob = blob.ok_or_else(|| format!("no blob given\n\n{}", usage()))?;
+    if blob.len() < nonce_offset + 4 {
+        return Err("the nonce does not fit inside the blob".into());
+    }
+    algorithm.check_input(&blob)?;
+
+    let end = count.map_or(1 << 32, |count| (u64::from(start) + count).min(1 << 32));
+    let threads = workers.thread_count() as u64;
+
+    workers.run(algorithm.scratchpad_size(), move |index, scratchpad| {
+        let mut hasher = CryptoNightHasher::with_scratchpad(algorithm.hasher(), scratchpad);
+        let mut blob = blob.clone();
+        let mut nonce = u64::from(start) + index as u64;
+
+        while nonce < end {
+            blob[nonce_offset..nonce_offset + 4].copy_from_slice(&(nonce as u32).to_le_bytes());
+            let hash = hasher.hash(&blob);
+
+            if check_hash(&hash, difficulty) {
+                let mut line = format!("{}\t", nonce).into_bytes();
+                format.write(&mut line, &hash).expect("writing to a Vec cannot fail");
+                let _ = io::stdout().write_all(&line);
+            }
+
+            nonce += threads;
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Check whether a hash meets the given difficulty.
+///
+/// This is the CryptoNote rule: the hash, read as a 256 bit little endian
+/// integer, multiplied by the difficulty must not overflow 256 bits.
+fn check_hash(hash: &[u8], difficulty: u64) -> bool {
+    let mut carry = 0u128;
+
+    for word in hash.chunks_exact(8) {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(word);
+        let product = u128::from(u64::from_le_bytes(bytes)) * u128::from(difficulty) + carry;
+        carry = product >> 64;
+    }
+
+    carry == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_hash() {
+        let mut hash = [0xffu8; 32];
+        assert!(check_hash(&hash, 1));
+        assert!(!check_hash(&hash, 2));
+
+        hash[31] = 0x7f;
+        assert!(check_hash(&hash, 2));
+        assert!(!check_hash(&hash, 3));
+    }
+}
@@ -0,0 +1,124 @@
+//! Options shared by the subcommands that run hashing threads.
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+use cryptonight_hash::{MemoryOptions, Scratchpad};
+
+use crate::Result;
+
+pub const USAGE: &str = "    -t, --threads <N>    Number of worker threads. Defaults to the number of
+                         CPUs given to --affinity, or 1.
+        --huge-pages     Allocate scratchpads on huge pages.
+        --lock-memory    Lock scratchpads into RAM.
+        --affinity <CPUS>
+                         Comma separated list of CPUs to pin the worker
+                         threads to, e.g. 0,2,4. Threads are assigned to the
+                         CPUs round-robin.";
+
+/// Thread and memory configuration for hashing workers.
+#[derive(Default)]
+pub struct WorkerOptions {
+    threads: Option<usize>,
+    memory: MemoryOptions,
+    affinity: Vec<usize>,
+}
+
+impl WorkerOptions {
+    /// Try to parse a worker option.
+    ///
+    /// Returns whether `flag` was recognized. Options that take a value
+    /// consume it from `args`.
+    pub fn parse_flag<I: Iterator<Item=String>>(&mut self, flag: &str, args: &mut I) -> Result<bool> {
+        match flag {
+            "-t" | "--threads" => {
+                let threads: usize = crate::parse_value(flag, args)?;
+                if threads == 0 {
+                    return Err("--threads must be at least 1".into());
+                }
+                self.threads = Some(threads);
+            }
+            "--huge-pages" => self.memory.huge_pages = true,
+            "--lock-memory" => self.memory.lock = true,
+            "--affinity" => {
+                let cpus: String = crate::parse_value(flag, args)?;
+                self.affinity = cpus.split(',')
+                    .map(|cpu| cpu.trim().parse().map_err(|_| format!("invalid CPU '{}'", cpu)))
+                    .collect::<std::result::Result<_, _>>()?;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Number of worker threads to run.
+    pub fn thread_count(&self) -> usize {
+        self.threads.unwrap_or_else(|| self.affinity.len().max(1))
+    }
+
+    /// Run `work` on every worker thread and collect the results.
+    ///
+    /// Each worker is pinned to its CPU, if requested, before allocating its
+    /// own scratchpad of `scratchpad_size` bytes, so the memory is local to
+    /// the CPU that uses it.
+    pub fn run<T, F>(&self, scratchpad_size: usize, work: F) -> Result<Vec<T>>
+        where T: Send + 'static, F: Fn(usize, Scratchpad) -> T + Send + Sync + 'static {
+        let work = Arc::new(work);
+        let threads: Vec<_> = (0..self.thread_count())
+            .map(|index| {
+                let work = Arc::clone(&work);
+                let cpu = if self.affinity.is_empty() {
+                    None
+                } else {
+                    Some(self.affinity[index % self.affinity.len()])
+                };
+                let memory = self.memory;
+
+                thread::spawn(move || {
+                    if let Some(cpu) = cpu {
+                        if let Err(e) = pin_current_thread(cpu) {
+                            eprintln!("cnhash: thread {}: could not pin to CPU {}: {}", index, cpu, e);
+                        }
+                    }
+
+                    let scratchpad = Scratchpad::allocate_with(scratchpad_size, memory);
+                    let report = scratchpad.report();
+                    if report.is_degraded() {
+                        eprintln!("cnhash: thread {}: {}", index, report);
+                    }
+
+                    work(index, scratchpad)
+                })
+            })
+            .collect();
+
+        threads.into_iter()
+            .map(|handle| handle.join().map_err(|_| "worker thread panicked".into()))
+            .collect()
+    }
+}
+
+/// Pin the calling thread to the given CPU.
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CPU index out of range"));
+    }
+
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "thread affinity is not supported on this platform"))
+}
@@ -0,0 +1,361 @@
+//! Module implementing the main digest functions using the ARMv8 AES and NEON instructions.
+//!
+//! This is the AArch64 counterpart of the aesni module. The ARM AES
+//! instructions split a round differently: `AESE` adds the round key before
+//! the substitution, and `AESMC` mixes the columns separately. An x86 style
+//! round is therefore `AESE` with a zero key, `AESMC` and an XOR with the key.
+//!
+//! There is no equivalent of `AESKEYGENASSIST`, so the round keys are derived
+//! by the portable implementation. That only happens twice per hash.
+//!
+//! This module currently requires the following CPU extensions to work:
+//!
+//! * AES
+//! * NEON (part of the base AArch64 instruction set)
+use std::arch::aarch64::*;
+use std::mem::size_of;
+use std::slice;
+
+use slice_cast::cast_mut;
+
+use crate::aes::{derive_key as derive_soft_key, integer_math, random_math, tweak1_1};
+use crate::params::{Conceal, Fast, Lite, Pico, R, Rto, SoftShell, Upx2, V0, V1, V2, Wow, Xao, Zls, VariantParams};
+use crate::random_math::Program;
+use crate::{LoopState, Phase, Tweaks, U64p, Variant};
+
+/// Type for a set of explode/implode AES keys.
+type KeysType = [uint8x16_t; 10];
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+pub unsafe fn digest_main<F: FnMut(Phase)>(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, mut after_phase: F) {
+    let mut state = LoopState::new(keccac);
+
+    // Cast to NEON types
+    let scratchpad: &mut [uint8x16_t] = cast_mut(scratchpad);
+    let keccac: &mut [uint8x16_t] = cast_mut(&mut keccac[..192]);
+
+    init_scratchpad(keccac, scratchpad);
+    after_phase(Phase::Init);
+    main_loop_variant(&mut state, scratchpad, tweaks.iterations(), tweaks);
+    after_phase(Phase::MainLoop);
+    finalize_state(keccac, &scratchpad);
+    after_phase(Phase::Finalize);
+}
+
+/// Fill the scratchpad from the initial state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+pub unsafe fn init(keccac: &[u8], scratchpad: &mut [u8]) {
+    let scratchpad: &mut [uint8x16_t] = cast_mut(scratchpad);
+    let keccac = slice::from_raw_parts(keccac.as_ptr() as *const uint8x16_t, 12);
+
+    init_scratchpad(keccac, scratchpad);
+}
+
+/// Run `iterations` rounds of the main loop, continuing from `state`.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+pub unsafe fn main_loop_partial(state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+    main_loop_variant(state, cast_mut(scratchpad), iterations, tweaks);
+}
+
+/// Fold the scratchpad back into the state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+pub unsafe fn finalize(keccac: &mut [u8], scratchpad: &[u8]) {
+    let scratchpad = slice::from_raw_parts(scratchpad.as_ptr() as *const uint8x16_t, scratchpad.len() / size_of::<uint8x16_t>());
+    let keccac: &mut [uint8x16_t] = cast_mut(&mut keccac[..192]);
+
+    finalize_state(keccac, scratchpad);
+}
+
+/// Derive 10 round keys from the 32 bytes in two blocks.
+#[target_feature(enable = "neon")]
+unsafe fn derive_key(key0: uint8x16_t, key1: uint8x16_t) -> KeysType {
+    let mut main = [0u8; 32];
+    vst1q_u8(main.as_mut_ptr(), key0);
+    vst1q_u8(main[16..].as_mut_ptr(), key1);
+
+    let buffer = derive_soft_key(&main);
+    let mut keys = [vdupq_n_u8(0); 10];
+    for (key, bytes) in keys.iter_mut().zip(buffer.chunks_exact(16)) {
+        *key = vld1q_u8(bytes.as_ptr());
+    }
+
+    keys
+}
+
+/// One AES encryption round, with the semantics of x86 `AESENC`.
+#[inline]
+#[target_feature(enable = "aes")]
+unsafe fn aes_round(block: uint8x16_t, key: uint8x16_t) -> uint8x16_t {
+    veorq_u8(vaesmcq_u8(vaeseq_u8(block, vdupq_n_u8(0))), key)
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+unsafe fn init_scratchpad(keccac: &[uint8x16_t], scratchpad: &mut [uint8x16_t]) {
+    let keys = derive_key(keccac[0], keccac[1]);
+    let mut blocks: [uint8x16_t; 8] = *(keccac[4..].as_ptr() as *const [uint8x16_t; 8]);
+
+    for scratchpad_chunk in scratchpad.chunks_exact_mut(blocks.len()) {
+        for block in blocks.iter_mut() {
+            for key in keys.iter() {
+                *block = aes_round(*block, *key);
+            }
+        }
+
+        scratchpad_chunk.copy_from_slice(&blocks);
+    }
+}
+
+/// Run the main loop specialized for the variant.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+unsafe fn main_loop_variant(state: &mut LoopState, scratchpad: &mut [uint8x16_t], iterations: usize, tweaks: Tweaks) {
+    match tweaks.variant {
+        Variant::V0 => main_loop::<V0>(state, scratchpad, iterations, tweaks),
+        Variant::V1 => main_loop::<V1>(state, scratchpad, iterations, tweaks),
+        Variant::V2 => main_loop::<V2>(state, scratchpad, iterations, tweaks),
+        Variant::R => main_loop::<R>(state, scratchpad, iterations, tweaks),
+        Variant::Lite => main_loop::<Lite>(state, scratchpad, iterations, tweaks),
+        Variant::Pico => main_loop::<Pico>(state, scratchpad, iterations, tweaks),
+        Variant::Fast => main_loop::<Fast>(state, scratchpad, iterations, tweaks),
+        Variant::Zls => main_loop::<Zls>(state, scratchpad, iterations, tweaks),
+        Variant::Xao => main_loop::<Xao>(state, scratchpad, iterations, tweaks),
+        Variant::Rto => main_loop::<Rto>(state, scratchpad, iterations, tweaks),
+        Variant::Upx2 => main_loop::<Upx2>(state, scratchpad, iterations, tweaks),
+        Variant::Conceal => main_loop::<Conceal>(state, scratchpad, iterations, tweaks),
+        Variant::SoftShell => main_loop::<SoftShell>(state, scratchpad, iterations, tweaks),
+        Variant::Wow => main_loop::<Wow>(state, scratchpad, iterations, tweaks),
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+unsafe fn main_loop<V: VariantParams>(state: &mut LoopState, scratchpad: &mut [uint8x16_t], iterations: usize, tweaks: Tweaks) {
+    let base = V::VARIANT.base();
+    let shuffle = base == Variant::V2 || base == Variant::R;
+    let reverse = V::VARIANT == Variant::Upx2;
+    let conceal = V::VARIANT == Variant::Conceal;
+    // CryptoNight-WOW leaves `a` and `c` out of the random math and shuffle.
+    let wow = V::VARIANT == Variant::Wow;
+    // The mask of the soft shell and custom variants is only known at runtime.
+    let address_mask = if tweaks.is_dynamic() { tweaks.address_mask() } else { V::ADDRESS_MASK };
+    let tweak1_2 = from_u64s(0, tweaks.tweak1_2);
+    let program = match V::VARIANT {
+        Variant::R => Some(Program::generate(tweaks.height).compile()),
+        Variant::Wow => Some(Program::generate_wow(tweaks.height).compile()),
+        _ => None,
+    };
+    let mut registers = [0u32; 9];
+    registers[..4].copy_from_slice(&state.random_math);
+
+    let mut a = vld1q_u8(state.a.as_ptr());
+    let mut b = vld1q_u8(state.b.as_ptr());
+    let mut b1 = vld1q_u8(state.b1.as_ptr());
+    let mut accumulators = vld1q_f32(state.conceal.as_ptr() as *const f32);
+
+    for _ in 0..iterations {
+        // First transfer
+        let index = to_sp_index(a, address_mask);
+        let mut block = *scratchpad.get_unchecked(index);
+        if conceal {
+            block = conceal_tweak(block, &mut accumulators);
+        }
+        let mut c = aes_round(block, a);
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, index, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                c = veorq_u8(c, chunks);
+            }
+        }
+
+        let address = scratchpad.get_unchecked_mut(index);
+        *address = veorq_u8(c, b);
+        if base == Variant::V1 {
+            let bytes = &mut *(address as *mut uint8x16_t as *mut [u8; 16]);
+            bytes[11] = tweak1_1(bytes[11]);
+        }
+
+        // Second transfer
+        let index = to_sp_index(c, address_mask);
+        let mut value = *scratchpad.get_unchecked(index);
+        if base == Variant::V2 {
+            let c = to_u64p(c);
+            let mask = integer_math(c.low(), c.high(), &mut state.division_result, &mut state.sqrt_result);
+            value = veorq_u8(value, from_u64s(mask, 0));
+        }
+
+        // The random math modifies `a` for the sum, but not for the shuffle.
+        let mut addend = a;
+        if let Some(program) = program.as_ref() {
+            let (mask, a_mask) = random_math(program, &mut registers, to_u64p(a), to_u64p(b), to_u64p(b1));
+            value = veorq_u8(value, from_u64s(mask, 0));
+            if !wow {
+                addend = veorq_u8(addend, from_u64s(a_mask.low(), a_mask.high()));
+            }
+        }
+
+        let mut product = cn_8byte_mul(c, value);
+        if base == Variant::V2 || wow {
+            let neighbour = scratchpad.get_unchecked_mut(index ^ 1);
+            *neighbour = veorq_u8(*neighbour, product);
+            product = veorq_u8(product, *scratchpad.get_unchecked(index ^ 2));
+        }
+
+        let mut next_b = c;
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, index, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                next_b = veorq_u8(next_b, chunks);
+            }
+        }
+
+        let sum = cn_8byte_add(addend, product);
+        a = veorq_u8(value, sum);
+        let address = scratchpad.get_unchecked_mut(index);
+        if V::VARIANT == Variant::Rto {
+            // The low half of the sum, shifted into the high half.
+            let shifted = vextq_u8(vdupq_n_u8(0), sum, 8);
+            *address = veorq_u8(veorq_u8(sum, tweak1_2), shifted);
+        } else if base == Variant::V1 {
+            *address = veorq_u8(sum, tweak1_2);
+        } else {
+            *address = sum;
+        }
+
+        b1 = b;
+        b = next_b;
+    }
+
+    vst1q_u8(state.a.as_mut_ptr(), a);
+    vst1q_u8(state.b.as_mut_ptr(), b);
+    vst1q_u8(state.b1.as_mut_ptr(), b1);
+    vst1q_f32(state.conceal.as_mut_ptr() as *mut f32, accumulators);
+    state.random_math.copy_from_slice(&registers[..4]);
+}
+
+/// The variant 2 shuffle of the three blocks sharing a cache line with `index`.
+///
+/// The shuffle is reversed for CryptoNight-UPX2.
+///
+/// Returns the XOR of the three blocks before the shuffle.
+#[inline(always)]
+unsafe fn shuffle_add(scratchpad: &mut [uint8x16_t], index: usize, a: uint8x16_t, b: uint8x16_t, b1: uint8x16_t, reverse: bool) -> uint8x16_t {
+    let chunk1 = *scratchpad.get_unchecked(index ^ 1);
+    let chunk2 = *scratchpad.get_unchecked(index ^ 2);
+    let chunk3 = *scratchpad.get_unchecked(index ^ 3);
+
+    let (first, second) = if reverse { (chunk1, chunk3) } else { (chunk3, chunk1) };
+    *scratchpad.get_unchecked_mut(index ^ 1) = cn_8byte_add(first, b1);
+    *scratchpad.get_unchecked_mut(index ^ 2) = cn_8byte_add(second, b);
+    *scratchpad.get_unchecked_mut(index ^ 3) = cn_8byte_add(chunk2, a);
+
+    veorq_u8(veorq_u8(chunk1, chunk2), chunk3)
+}
+
+/// Keep the sign and mantissa, but force the value into [2, 4).
+#[inline(always)]
+unsafe fn conceal_normalize(x: float32x4_t) -> float32x4_t {
+    let bits = vandq_u32(vreinterpretq_u32_f32(x), vdupq_n_u32(0x807F_FFFF));
+    vreinterpretq_f32_u32(vorrq_u32(bits, vdupq_n_u32(0x4000_0000)))
+}
+
+/// The Conceal tweak to the block read by the first transfer.
+#[inline(always)]
+unsafe fn conceal_tweak(block: uint8x16_t, accumulators: &mut float32x4_t) -> uint8x16_t {
+    let mut r = vaddq_f32(vcvtq_f32_s32(vreinterpretq_s32_u8(block)), *accumulators);
+    r = conceal_normalize(vmulq_f32(r, vmulq_f32(r, r)));
+
+    let old = conceal_normalize(*accumulators);
+    *accumulators = vaddq_f32(*accumulators, r);
+
+    // The product stays within the range of an i32, so the conversion
+    // truncates like the SSE one.
+    let mask = vcvtq_s32_f32(vmulq_f32(old, vdupq_n_f32(536_870_880.0)));
+    veorq_u8(block, vreinterpretq_u8_s32(mask))
+}
+
+/// Build a vector from its low and high 64 bit halves.
+#[inline(always)]
+unsafe fn from_u64s(low: u64, high: u64) -> uint8x16_t {
+    vreinterpretq_u8_u64(vcombine_u64(vcreate_u64(low), vcreate_u64(high)))
+}
+
+#[inline(always)]
+unsafe fn to_u64p(x: uint8x16_t) -> U64p {
+    let x = vreinterpretq_u64_u8(x);
+    U64p::new(vgetq_lane_u64(x, 0), vgetq_lane_u64(x, 1))
+}
+
+#[inline(always)]
+unsafe fn to_sp_index(a: uint8x16_t, mask: usize) -> usize {
+    let a = vgetq_lane_u32(vreinterpretq_u32_u8(a), 0);
+
+    // Mask to the scratchpad size and divide by the length of a slice.
+    (a as usize & mask) / size_of::<uint8x16_t>()
+}
+
+#[inline(always)]
+unsafe fn cn_8byte_add(a: uint8x16_t, b: uint8x16_t) -> uint8x16_t {
+    vreinterpretq_u8_u64(vaddq_u64(vreinterpretq_u64_u8(a), vreinterpretq_u64_u8(b)))
+}
+
+#[inline(always)]
+unsafe fn cn_8byte_mul(a: uint8x16_t, b: uint8x16_t) -> uint8x16_t {
+    let a = vgetq_lane_u64(vreinterpretq_u64_u8(a), 0);
+    let b = vgetq_lane_u64(vreinterpretq_u64_u8(b), 0);
+    let c = u128::from(a) * u128::from(b);
+
+    from_u64s((c >> 64) as u64, c as u64)
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "neon")]
+unsafe fn finalize_state(keccac: &mut [uint8x16_t], scratchpad: &[uint8x16_t]) {
+    let keys = derive_key(keccac[2], keccac[3]);
+    let final_block: &mut [uint8x16_t] = &mut keccac[4..];
+
+    for scratchpad_chunk in scratchpad.chunks_exact(final_block.len()) {
+        for (block, sp_slice) in final_block.iter_mut().zip(scratchpad_chunk.iter()) {
+            *block = veorq_u8(*block, *sp_slice);
+            for key in keys.iter() {
+                *block = aes_round(*block, *key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{aes, arm_aes_available, CryptoNight, Scratchpad};
+
+    use super::*;
+
+    #[test]
+    fn test_main_loop_matches_portable() {
+        if !arm_aes_available() {
+            return;
+        }
+
+        let keccac: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut expected = Scratchpad::allocate(CryptoNight::SP_SIZE);
+        aes::init_scratchpad(&keccac, expected.as_mut());
+        let mut actual = Scratchpad::allocate(CryptoNight::SP_SIZE);
+        actual.as_mut().copy_from_slice(expected.as_ref());
+
+        for &variant in Variant::all() {
+            let tweaks = Tweaks { variant, tweak1_2: 0x0123_4567_89ab_cdef, height: 1_806_260, custom: None };
+            let mut expected_state = LoopState::new(&keccac);
+            let mut actual_state = expected_state;
+
+            aes::main_loop_partial(&mut expected_state, expected.as_mut(), 1000, tweaks);
+            unsafe { main_loop_partial(&mut actual_state, actual.as_mut(), 1000, tweaks) };
+
+            assert_eq!(actual_state, expected_state, "{}", variant);
+            assert!(actual.as_ref() == expected.as_ref(), "{}", variant);
+        }
+    }
+}
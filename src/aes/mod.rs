@@ -1,28 +1,91 @@
 //! Portable Rust AES and hashing implementation for CryptoNight.
+use std::convert::{TryFrom, TryInto};
 use std::ops::BitXor;
 
-use slice_cast::cast_mut;
-
 use constants::*;
 
-use crate::aes::u64p::U64p;
-use crate::ROUNDS;
+use crate::{LoopState, Phase, Tweaks, U64p, Variant};
+use crate::params::{Conceal, Fast, Lite, Pico, R, Rto, SoftShell, Upx2, V0, V1, V2, Wow, Xao, Zls, VariantParams};
+use crate::random_math::{Compiled, Program};
 
 mod constants;
-mod u64p;
-
-pub fn digest_main(keccac: &mut [u8], scratchpad: &mut [u8]) {
+#[cfg(all(target_arch = "powerpc64", target_os = "linux", feature = "aesni", not(feature = "portable")))]
+mod ppc64;
+#[cfg(all(target_arch = "riscv64", feature = "aesni", not(feature = "portable")))]
+mod riscv64;
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+mod ssse3;
+#[cfg(any(test, all(target_arch = "arm", feature = "aesni", not(feature = "portable"), not(feature = "constant-time"))))]
+mod table;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128", feature = "aesni", not(feature = "portable")))]
+mod wasm32;
+
+pub fn digest_main<F: FnMut(Phase)>(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, mut after_phase: F) {
     init_scratchpad(keccac, scratchpad);
+    after_phase(Phase::Init);
 
-    let a = U64p::from(&keccac[..16]) ^ U64p::from(&keccac[32..48]);
-    let b = U64p::from(&keccac[16..32]) ^ U64p::from(&keccac[48..64]);
-
-    main_loop(a, b, scratchpad);
+    main_loop_partial(&mut LoopState::new(keccac), scratchpad, tweaks.iterations(), tweaks);
+    after_phase(Phase::MainLoop);
 
     finalize_state(keccac, &scratchpad);
+    after_phase(Phase::Finalize);
+}
+
+/// Compute several hashes of the same variant and number of iterations at once.
+///
+/// The main loops of the hashes are interleaved, see `main_loop_interleaved`.
+pub fn digest_main_n<const N: usize>(keccacs: [&mut [u8]; N], mut scratchpads: [&mut [u8]; N], tweaks: [Tweaks; N]) {
+    let mut states = [LoopState::default(); N];
+    for ((state, keccac), scratchpad) in states.iter_mut().zip(keccacs.iter()).zip(scratchpads.iter_mut()) {
+        init_scratchpad(keccac, scratchpad);
+        *state = LoopState::new(keccac);
+    }
+
+    let (mut lane_states, mut lane_scratchpads) = (states.iter_mut(), scratchpads.iter_mut());
+    let hashes = tweaks.map(|tweaks| (lane_states.next().unwrap(), &mut **lane_scratchpads.next().unwrap(), tweaks));
+    match tweaks[0].variant {
+        Variant::V0 => main_loop_interleaved::<V0, N>(hashes),
+        Variant::V1 => main_loop_interleaved::<V1, N>(hashes),
+        Variant::V2 => main_loop_interleaved::<V2, N>(hashes),
+        Variant::R => main_loop_interleaved::<R, N>(hashes),
+        Variant::Lite => main_loop_interleaved::<Lite, N>(hashes),
+        Variant::Pico => main_loop_interleaved::<Pico, N>(hashes),
+        Variant::Fast => main_loop_interleaved::<Fast, N>(hashes),
+        Variant::Zls => main_loop_interleaved::<Zls, N>(hashes),
+        Variant::Xao => main_loop_interleaved::<Xao, N>(hashes),
+        Variant::Rto => main_loop_interleaved::<Rto, N>(hashes),
+        Variant::Upx2 => main_loop_interleaved::<Upx2, N>(hashes),
+        Variant::Conceal => main_loop_interleaved::<Conceal, N>(hashes),
+        Variant::SoftShell => main_loop_interleaved::<SoftShell, N>(hashes),
+        Variant::Wow => main_loop_interleaved::<Wow, N>(hashes),
+    }
+
+    for (keccac, scratchpad) in IntoIterator::into_iter(keccacs).zip(scratchpads) {
+        finalize_state(keccac, scratchpad);
+    }
 }
 
-fn init_scratchpad(keccac: &[u8], scratchpad: &mut [u8]) {
+/// Run `iterations` rounds of the main loop, continuing from `state`.
+pub fn main_loop_partial(state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+    match tweaks.variant {
+        Variant::V0 => main_loop::<V0>(state, scratchpad, iterations, tweaks),
+        Variant::V1 => main_loop::<V1>(state, scratchpad, iterations, tweaks),
+        Variant::V2 => main_loop::<V2>(state, scratchpad, iterations, tweaks),
+        Variant::R => main_loop::<R>(state, scratchpad, iterations, tweaks),
+        Variant::Lite => main_loop::<Lite>(state, scratchpad, iterations, tweaks),
+        Variant::Pico => main_loop::<Pico>(state, scratchpad, iterations, tweaks),
+        Variant::Fast => main_loop::<Fast>(state, scratchpad, iterations, tweaks),
+        Variant::Zls => main_loop::<Zls>(state, scratchpad, iterations, tweaks),
+        Variant::Xao => main_loop::<Xao>(state, scratchpad, iterations, tweaks),
+        Variant::Rto => main_loop::<Rto>(state, scratchpad, iterations, tweaks),
+        Variant::Upx2 => main_loop::<Upx2>(state, scratchpad, iterations, tweaks),
+        Variant::Conceal => main_loop::<Conceal>(state, scratchpad, iterations, tweaks),
+        Variant::SoftShell => main_loop::<SoftShell>(state, scratchpad, iterations, tweaks),
+        Variant::Wow => main_loop::<Wow>(state, scratchpad, iterations, tweaks),
+    }
+}
+
+pub fn init_scratchpad(keccac: &[u8], scratchpad: &mut [u8]) {
     let round_keys_buffer = derive_key(&keccac[..32]);
 
     let mut blocks = [0u8; 128];
@@ -39,27 +102,288 @@ fn init_scratchpad(keccac: &[u8], scratchpad: &mut [u8]) {
     }
 }
 
-fn main_loop(mut a: U64p, mut b: U64p, scratchpad: &mut [u8]) {
-    // Cast to u128 for easier handling. Scratch pad is only used in 16 byte blocks
-    let scratchpad: &mut [U64p] = unsafe { cast_mut(scratchpad) };
+fn main_loop<V: VariantParams>(state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+    let mut lane = Lane::new::<V>(state, scratchpad, tweaks);
+    for _ in 0..iterations {
+        lane.step::<V>();
+    }
+    lane.store();
+}
+
+/// Run the main loops of several hashes of the same variant side by side.
+///
+/// The iterations of the hashes are independent, so the CPU can work on
+/// one while the scratchpad accesses of the others are waiting for memory.
+fn main_loop_interleaved<V: VariantParams, const N: usize>(hashes: [LaneParts; N]) {
+    let iterations = hashes[0].2.iterations();
+    let mut lanes = hashes.map(|(state, scratchpad, tweaks)| Lane::new::<V>(state, scratchpad, tweaks));
+    for _ in 0..iterations {
+        for lane in lanes.iter_mut() {
+            lane.step::<V>();
+        }
+    }
+    for lane in lanes.iter_mut() {
+        lane.store();
+    }
+}
+
+/// The state, scratchpad and tweaks of one of the hashes of an interleaved main loop.
+type LaneParts<'a> = (&'a mut LoopState, &'a mut [u8], Tweaks);
+
+/// The main loop variables of a single hash.
+struct Lane<'a> {
+    state: &'a mut LoopState,
+    scratchpad: &'a mut [u8],
+    address_mask: usize,
+    tweak1_2: U64p,
+    program: Option<Compiled>,
+    registers: [u32; 9],
+    a: U64p,
+    b: U64p,
+    b1: U64p,
+}
+
+impl<'a> Lane<'a> {
+    /// Load the variables from `state`.
+    fn new<V: VariantParams>(state: &'a mut LoopState, scratchpad: &'a mut [u8], tweaks: Tweaks) -> Self {
+        // The mask of the soft shell and custom variants is only known at runtime.
+        let address_mask = if tweaks.is_dynamic() { tweaks.address_mask() } else { V::ADDRESS_MASK };
+        let program = match V::VARIANT {
+            Variant::R => Some(Program::generate(tweaks.height).compile()),
+            Variant::Wow => Some(Program::generate_wow(tweaks.height).compile()),
+            _ => None,
+        };
+        let mut registers = [0u32; 9];
+        registers[..4].copy_from_slice(&state.random_math);
+
+        Lane {
+            address_mask,
+            tweak1_2: U64p::new(0, tweaks.tweak1_2),
+            program,
+            registers,
+            a: U64p::from_le_bytes(state.a),
+            b: U64p::from_le_bytes(state.b),
+            b1: U64p::from_le_bytes(state.b1),
+            state,
+            scratchpad,
+        }
+    }
+
+    /// Run a single iteration of the main loop.
+    #[inline(always)]
+    fn step<V: VariantParams>(&mut self) {
+        let base = V::VARIANT.base();
+        let shuffle = base == Variant::V2 || base == Variant::R;
+        let reverse = V::VARIANT == Variant::Upx2;
+        let conceal = V::VARIANT == Variant::Conceal;
+        // CryptoNight-WOW leaves `a` and `c` out of the random math and shuffle.
+        let wow = V::VARIANT == Variant::Wow;
+        let (a, b, b1) = (self.a, self.b, self.b1);
+        let scratchpad = &mut *self.scratchpad;
 
-    for _ in 0..ROUNDS {
         // First transfer
-        let address: usize = a.into();
-        aes_round(&mut scratchpad[address].as_mut(), a.as_ref());
-        let tmp = b;
-        b = scratchpad[address];
-        scratchpad[address] = scratchpad[address] ^ tmp;
+        let offset = block_offset(a, self.address_mask);
+        let block = &mut scratchpad[offset..offset + 16];
+        if conceal {
+            conceal_tweak(block, &mut self.state.conceal);
+        }
+        aes_round(block, a.as_ref());
+        let mut c = U64p::try_from(&block[..]).unwrap();
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, offset, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                c = c ^ chunks;
+            }
+        }
+
+        let block = &mut scratchpad[offset..offset + 16];
+        block.copy_from_slice((c ^ b).as_ref());
+        if base == Variant::V1 {
+            block[11] = tweak1_1(block[11]);
+        }
 
         // Second transfer
-        let address: usize = b.into();
-        let tmp = a + b * scratchpad[address];
-        a = scratchpad[address] ^ tmp;
-        scratchpad[address] = tmp;
+        let offset = block_offset(c, self.address_mask);
+        let mut value = U64p::try_from(&scratchpad[offset..offset + 16]).unwrap();
+        if base == Variant::V2 {
+            let mask = integer_math(c.low(), c.high(), &mut self.state.division_result, &mut self.state.sqrt_result);
+            value = value ^ U64p::new(mask, 0);
+        }
+
+        // The random math modifies `a` for the sum, but not for the shuffle.
+        let mut addend = a;
+        if let Some(program) = self.program.as_ref() {
+            let (mask, a_mask) = random_math(program, &mut self.registers, a, b, b1);
+            value = value ^ U64p::new(mask, 0);
+            if !wow {
+                addend = addend ^ a_mask;
+            }
+        }
+
+        let mut product = c * value;
+        if base == Variant::V2 || wow {
+            let neighbour = U64p::try_from(&scratchpad[offset ^ 0x10..(offset ^ 0x10) + 16]).unwrap();
+            scratchpad[offset ^ 0x10..(offset ^ 0x10) + 16].copy_from_slice((neighbour ^ product).as_ref());
+            product = product ^ U64p::try_from(&scratchpad[offset ^ 0x20..(offset ^ 0x20) + 16]).unwrap();
+        }
+
+        let mut next_b = c;
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, offset, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                next_b = next_b ^ chunks;
+            }
+        }
+
+        let sum = addend + product;
+        self.a = value ^ sum;
+
+        let block = &mut scratchpad[offset..offset + 16];
+        if V::VARIANT == Variant::Rto {
+            block.copy_from_slice((sum ^ self.tweak1_2 ^ U64p::new(0, sum.low())).as_ref());
+        } else if base == Variant::V1 {
+            block.copy_from_slice((sum ^ self.tweak1_2).as_ref());
+        } else {
+            block.copy_from_slice(sum.as_ref());
+        }
+
+        self.b1 = b;
+        self.b = next_b;
+    }
+
+    /// Store the variables back into the state.
+    fn store(&mut self) {
+        self.state.a = self.a.to_le_bytes();
+        self.state.b = self.b.to_le_bytes();
+        self.state.b1 = self.b1.to_le_bytes();
+        self.state.random_math.copy_from_slice(&self.registers[..4]);
     }
 }
 
-fn finalize_state(keccac: &mut [u8], scratchpad: &[u8]) {
+/// Byte offset of the scratchpad block that `address` points to.
+#[inline]
+fn block_offset(address: U64p, mask: usize) -> usize {
+    address.low() as usize & mask
+}
+
+/// The variant 2 shuffle of the three blocks sharing a cache line with `offset`.
+///
+/// CryptoNight-UPX2 shuffles in `reverse`, swapping the blocks that the
+/// first two neighbours receive.
+///
+/// Returns the XOR of the three blocks before the shuffle, which
+/// CryptoNight-R mixes into `c`.
+#[inline]
+fn shuffle_add(scratchpad: &mut [u8], offset: usize, a: U64p, b: U64p, b1: U64p, reverse: bool) -> U64p {
+    let chunk = |scratchpad: &[u8], n: usize| U64p::try_from(&scratchpad[offset ^ n..(offset ^ n) + 16]).unwrap();
+    let chunk1 = chunk(scratchpad, 0x10);
+    let chunk2 = chunk(scratchpad, 0x20);
+    let chunk3 = chunk(scratchpad, 0x30);
+
+    let (first, second) = if reverse { (chunk1, chunk3) } else { (chunk3, chunk1) };
+    scratchpad[offset ^ 0x10..(offset ^ 0x10) + 16].copy_from_slice((first + b1).as_ref());
+    scratchpad[offset ^ 0x20..(offset ^ 0x20) + 16].copy_from_slice((second + b).as_ref());
+    scratchpad[offset ^ 0x30..(offset ^ 0x30) + 16].copy_from_slice((chunk2 + a).as_ref());
+
+    chunk1 ^ chunk2 ^ chunk3
+}
+
+/// The variant 2 division and square root steps.
+///
+/// Returns the mask for the low half of the value read by the second
+/// transfer, and updates the results from the halves of `c`.
+#[inline]
+pub fn integer_math(c_low: u64, c_high: u64, division_result: &mut u64, sqrt_result: &mut u64) -> u64 {
+    let mask = *division_result ^ (*sqrt_result << 32);
+
+    let divisor = u64::from(c_low.wrapping_add(*sqrt_result << 1) as u32 | 0x8000_0001);
+    *division_result = (c_high / divisor) as u32 as u64 | ((c_high % divisor) << 32);
+
+    let sqrt_input = c_low.wrapping_add(*division_result);
+    *sqrt_result = integer_sqrt(sqrt_input);
+
+    mask
+}
+
+/// Compute `sqrt(2^64 + n) * 2 - 2^33`, rounded down.
+///
+/// The floating point estimate may be off by one, which is fixed up with
+/// integer arithmetic.
+fn integer_sqrt(n: u64) -> u64 {
+    let mut r = ((n as f64 + 18_446_744_073_709_551_616.0).sqrt() * 2.0 - 8_589_934_592.0) as u64;
+
+    let s = r >> 1;
+    let b = r & 1;
+    let r2 = s.wrapping_mul(s + b).wrapping_add(r << 32);
+
+    if r2.wrapping_add(b) > n {
+        r = r.wrapping_sub(1);
+    }
+    if r2.wrapping_add(1 << 32) < n.wrapping_sub(s) {
+        r = r.wrapping_add(1);
+    }
+
+    r
+}
+
+/// The CryptoNight-R random math step.
+///
+/// Returns the mask for the low half of the value read by the second
+/// transfer, computed from the registers of the previous iteration, and
+/// the mask for `a`, computed by running the program on the loop state.
+#[inline]
+pub fn random_math(program: &Compiled, registers: &mut [u32; 9], a: U64p, b: U64p, b1: U64p) -> (u64, U64p) {
+    let sum = |x: u32, y: u32| u64::from(x.wrapping_add(y));
+    let mask = sum(registers[0], registers[1]) | (sum(registers[2], registers[3]) << 32);
+
+    registers[4] = a.low() as u32;
+    registers[5] = a.high() as u32;
+    registers[6] = b.low() as u32;
+    registers[7] = b1.low() as u32;
+    registers[8] = b1.high() as u32;
+    program.execute(registers);
+
+    let join = |low: u32, high: u32| u64::from(low) | (u64::from(high) << 32);
+    let a_mask = U64p::new(join(registers[2], registers[3]), join(registers[0], registers[1]));
+
+    (mask, a_mask)
+}
+
+/// The Conceal tweak to the block read by the first transfer.
+///
+/// Each 32 bit lane is mixed with its floating point accumulator, which is
+/// updated in turn. All operations are single precision with the default
+/// rounding, so the results match the SSE implementation bit for bit.
+#[inline]
+pub fn conceal_tweak(block: &mut [u8], accumulators: &mut [u32; 4]) {
+    // Keep the sign and mantissa, but force the value into [2, 4).
+    let normalize = |x: f32| f32::from_bits((x.to_bits() & 0x807F_FFFF) | 0x4000_0000);
+
+    for (lane, accumulator) in block.chunks_exact_mut(4).zip(accumulators.iter_mut()) {
+        let x = i32::from_le_bytes(lane.try_into().unwrap());
+        let old = f32::from_bits(*accumulator);
+
+        let r = x as f32 + old;
+        let r = normalize(r * (r * r));
+        *accumulator = (old + r).to_bits();
+
+        // The product stays within the range of an i32, so the conversion
+        // truncates like the SSE one.
+        let mask = (normalize(old) * 536_870_880.0) as i32;
+        lane.copy_from_slice(&(x ^ mask).to_le_bytes());
+    }
+}
+
+/// The variant 1 tweak to byte 11 of the block written by the first transfer.
+#[inline]
+pub fn tweak1_1(byte: u8) -> u8 {
+    const TABLE: u32 = 0x75310;
+    let index = (((byte >> 3) & 6) | (byte & 1)) << 1;
+
+    byte ^ ((TABLE >> index) & 0x30) as u8
+}
+
+pub fn finalize_state(keccac: &mut [u8], scratchpad: &[u8]) {
     let round_keys_buffer = derive_key(&keccac[32..64]);
     let final_block = &mut keccac[64..192];
     for scratchpad_chunk in scratchpad.chunks_exact(128) {
@@ -80,8 +404,33 @@ fn multiplicative_inverse(b: u8) -> u8 {
     }
 }
 
+/// Multiplicative inverse without lookup tables or branches.
+///
+/// Computes b^254, which is the inverse of b in GF(2^8), and 0 for 0.
+fn multiplicative_inverse_constant_time(b: u8) -> u8 {
+    let b2 = gmul(b, b);
+    let b3 = gmul(b2, b);
+    let b6 = gmul(b3, b3);
+    let b12 = gmul(b6, b6);
+    let b15 = gmul(b12, b3);
+    let b30 = gmul(b15, b15);
+    let b60 = gmul(b30, b30);
+    let b120 = gmul(b60, b60);
+    let b240 = gmul(b120, b120);
+    let b252 = gmul(b240, b12);
+
+    gmul(b252, b2)
+}
+
 fn s_box(c: u8) -> u8 {
-    let b = multiplicative_inverse(c);
+    // The lookup tables leak the input through the cache, so avoid them
+    // when constant time operation is requested.
+    let b = if cfg!(feature = "constant-time") {
+        multiplicative_inverse_constant_time(c)
+    } else {
+        multiplicative_inverse(c)
+    };
+
     b.bitxor(b.rotate_left(1))
         .bitxor(b.rotate_left(2))
         .bitxor(b.rotate_left(3))
@@ -89,6 +438,31 @@ fn s_box(c: u8) -> u8 {
         .bitxor(0x63)
 }
 
+/// Inverse of the S-box, for the decryption round.
+#[cfg(feature = "randomx")]
+fn inv_s_box(c: u8) -> u8 {
+    let b = c.rotate_left(1) ^ c.rotate_left(3) ^ c.rotate_left(6) ^ 0x05;
+
+    if cfg!(feature = "constant-time") {
+        multiplicative_inverse_constant_time(b)
+    } else {
+        multiplicative_inverse(b)
+    }
+}
+
+/// Multiplication in GF(2^8), without branching on the operands
+fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        // All ones if the lowest bit of b is set
+        product ^= a & (b & 1).wrapping_neg();
+        a = gmul2(a);
+        b >>= 1;
+    }
+
+    product
+}
+
 /// Optimized version of gmul for multiplying by two
 #[inline]
 fn gmul2(a: u8) -> u8 {
@@ -127,6 +501,35 @@ fn shift_rows(block: &mut [u8]) {
     block[3] = tmp;
 }
 
+/// Inverse of the SubBytes step
+#[cfg(feature = "randomx")]
+fn inv_sub_bytes(block: &mut [u8]) {
+    for c in block.iter_mut() {
+        *c = inv_s_box(*c)
+    }
+}
+
+/// Inverse of the ShiftRows step
+#[cfg(feature = "randomx")]
+fn inv_shift_rows(block: &mut [u8]) {
+    // Shifting right by one is the inverse of shifting left by one.
+    let tmp = block[13];
+    for col in (1..4).rev() {
+        let index = 1 + 4 * col;
+        block[index] = block[index - 4];
+    }
+    block[1] = tmp;
+
+    block.swap(2, 10);
+    block.swap(6, 14);
+
+    let tmp = block[3];
+    for col in 0..3 {
+        block[col * 4 + 3] = block[col * 4 + 7];
+    }
+    block[15] = tmp;
+}
+
 fn mix_column(slice: &mut [u8]) {
     let mut a = [0u8; 4];
     let mut b = [0u8; 4];
@@ -148,19 +551,81 @@ fn mix_columns(block: &mut [u8]) {
     }
 }
 
+#[cfg(feature = "randomx")]
+fn inv_mix_columns(block: &mut [u8]) {
+    for column in block.chunks_exact_mut(4) {
+        let mut a = [0u8; 4];
+        a.copy_from_slice(column);
+
+        for (i, dest) in column.iter_mut().enumerate() {
+            *dest = gmul(a[i], 14) ^ gmul(a[(i + 1) % 4], 11) ^ gmul(a[(i + 2) % 4], 13) ^ gmul(a[(i + 3) % 4], 9);
+        }
+    }
+}
+
 pub fn xor(block: &mut [u8], round_key: &[u8]) {
     for (c, k) in block.iter_mut().zip(round_key.iter()) {
         *c ^= *k;
     }
 }
 
+/// One AES encryption round, as computed by `AESENC`.
+///
+/// On 32-bit ARM this uses the table based round of the `table` module, and
+/// on WebAssembly with SIMD128 the vectorized round of the `wasm32` module.
+/// On POWER8 and later and RISC-V with Zkne, it uses the AES instructions if
+/// they are available, and on x86 without AES-NI the `ssse3` module.
 pub fn aes_round(block: &mut [u8], round_key: &[u8]) {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+        {
+            if crate::BuiltinBackend::selected() == crate::BuiltinBackend::Ssse3 {
+                return unsafe { ssse3::aes_round(block, round_key) };
+            }
+        }
+    #[cfg(all(target_arch = "powerpc64", target_os = "linux", feature = "aesni", not(feature = "portable")))]
+        {
+            if crate::BuiltinBackend::selected() == crate::BuiltinBackend::Ppc64 {
+                return unsafe { ppc64::aes_round(block, round_key) };
+            }
+        }
+    #[cfg(all(target_arch = "riscv64", feature = "aesni", not(feature = "portable")))]
+        {
+            if crate::BuiltinBackend::selected() == crate::BuiltinBackend::Riscv64 {
+                return unsafe { riscv64::aes_round(block, round_key) };
+            }
+        }
+    #[cfg(all(target_arch = "arm", feature = "aesni", not(feature = "portable"), not(feature = "constant-time")))]
+        {
+            table::aes_round(block, round_key)
+        }
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128", feature = "aesni", not(feature = "portable")))]
+        {
+            wasm32::aes_round(block, round_key)
+        }
+    #[cfg(not(any(all(target_arch = "arm", feature = "aesni", not(feature = "portable"), not(feature = "constant-time")), all(target_arch = "wasm32", target_feature = "simd128", feature = "aesni", not(feature = "portable")))))]
+        {
+            bytewise_aes_round(block, round_key)
+        }
+}
+
+/// The AES round one step at a time.
+#[cfg_attr(any(all(target_arch = "arm", feature = "aesni", not(feature = "portable"), not(feature = "constant-time")), all(target_arch = "wasm32", target_feature = "simd128", feature = "aesni", not(feature = "portable"))), allow(dead_code))]
+fn bytewise_aes_round(block: &mut [u8], round_key: &[u8]) {
     sub_bytes(block);
     shift_rows(block);
     mix_columns(block);
     xor(block, round_key);
 }
 
+/// One round of the equivalent inverse cipher, as computed by `AESDEC`.
+#[cfg(feature = "randomx")]
+pub fn aes_round_inverse(block: &mut [u8], round_key: &[u8]) {
+    inv_shift_rows(block);
+    inv_sub_bytes(block);
+    inv_mix_columns(block);
+    xor(block, round_key);
+}
+
 fn schedule_core(new_key: &mut [u8], rcon: u8) {
     new_key.rotate_left(1);
     sub_bytes(new_key);
@@ -207,6 +672,50 @@ mod tests {
         assert_eq!(0xCA, multiplicative_inverse(0x53));
     }
 
+    #[test]
+    fn test_multiplicative_inverse_constant_time() {
+        for b in 0..=255 {
+            assert_eq!(multiplicative_inverse(b), multiplicative_inverse_constant_time(b));
+        }
+    }
+
+    #[test]
+    fn test_integer_sqrt() {
+        let mut n = 0x0123_4567_89ab_cdefu64;
+        let samples = (0..10_000).map(|_| {
+            n = n.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            n
+        });
+
+        for n in samples.chain(vec![0, 1, u64::MAX, (1 << 32) - 1, 1 << 32]) {
+            // r = floor(sqrt(4 * (2^64 + n))) - 2^33, checked with exact arithmetic.
+            let x = u128::from(integer_sqrt(n)) + (1 << 33);
+            let target = (u128::from(n) + (1 << 64)) * 4;
+
+            assert!(x * x <= target && target < (x + 1) * (x + 1), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_interleaved_matches_single() {
+        for &variant in &[Variant::Upx2, Variant::Pico] {
+            let tweaks = Tweaks { variant, tweak1_2: 0x0123_4567_89ab_cdef, height: 0, custom: None };
+            let mut scratchpads = [(); 2].map(|_| crate::Scratchpad::allocate(tweaks.scratchpad_size()));
+            let mut keccacs = [[1u8; 200], [2u8; 200]];
+            let mut expected = keccacs;
+
+            for (keccac, scratchpad) in expected.iter_mut().zip(scratchpads.iter_mut()) {
+                digest_main(keccac, scratchpad.as_mut(), tweaks, |_| ());
+            }
+            let [first, second] = &mut scratchpads;
+            let [k1, k2] = &mut keccacs;
+            digest_main_n([k1, k2], [first.as_mut(), second.as_mut()], [tweaks; 2]);
+
+            assert_eq!(keccacs[0][..], expected[0][..], "{}", variant);
+            assert_eq!(keccacs[1][..], expected[1][..], "{}", variant);
+        }
+    }
+
     #[test]
     fn test_s_box() {
         // Sample values taken from https://en.wikipedia.org/wiki/Rijndael_S-box#Forward_S-box
@@ -242,6 +751,25 @@ mod tests {
         assert_eq!(input, hex!("8e 4d a1 bc"));
     }
 
+    #[test]
+    #[cfg(feature = "randomx")]
+    fn test_inverse_steps() {
+        let input: Vec<u8> = (0..16).map(|i| i * 17 + 3).collect();
+
+        let steps: [(fn(&mut [u8]), fn(&mut [u8])); 3] = [
+            (sub_bytes, inv_sub_bytes),
+            (shift_rows, inv_shift_rows),
+            (mix_columns, inv_mix_columns),
+        ];
+        for &(forward, inverse) in steps.iter() {
+            let mut block = input.clone();
+            forward(&mut block);
+            inverse(&mut block);
+
+            assert_eq!(block, input);
+        }
+    }
+
     #[test]
     fn test_derive_key() {
         let primary = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
@@ -0,0 +1,102 @@
+//! AES round using the vector crypto instructions of POWER8 and later.
+//!
+//! The whole round is a single `vcipher`, which has the same semantics as
+//! x86 `AESENC`. Only the round is accelerated: the rest of the main loop
+//! is the portable implementation, as the Altivec intrinsics are not
+//! available on stable Rust.
+//!
+//! `vcipher` numbers the bytes of a vector register from the most
+//! significant one, independent of the endianness of the platform. The
+//! halves of the block are therefore moved into the register as big endian
+//! integers, which puts the first byte of the block in the right place on
+//! both ppc64 and ppc64le.
+use std::arch::asm;
+use std::convert::TryInto;
+
+/// One AES encryption round, as computed by `AESENC`.
+///
+/// # Safety
+/// The CPU must support the POWER8 vector crypto instructions.
+#[inline]
+pub unsafe fn aes_round(block: &mut [u8], round_key: &[u8]) {
+    let (block_high, block_low) = to_halves(block);
+    let (key_high, key_low) = to_halves(round_key);
+    let (high, low): (u64, u64);
+
+    // VSX registers 32 to 35 are the vector registers v0 to v3.
+    asm!(
+        "mtvsrd 32, {block_high}",
+        "mtvsrd 33, {block_low}",
+        "xxpermdi 32, 32, 33, 0",
+        "mtvsrd 34, {key_high}",
+        "mtvsrd 35, {key_low}",
+        "xxpermdi 34, 34, 35, 0",
+        "vcipher 0, 0, 2",
+        "mfvsrd {high}, 32",
+        "xxswapd 33, 32",
+        "mfvsrd {low}, 33",
+        block_high = in(reg) block_high,
+        block_low = in(reg) block_low,
+        key_high = in(reg) key_high,
+        key_low = in(reg) key_low,
+        high = lateout(reg) high,
+        low = lateout(reg) low,
+        out("v0") _,
+        out("v1") _,
+        out("v2") _,
+        out("v3") _,
+        options(pure, nomem, nostack),
+    );
+
+    block[..8].copy_from_slice(&high.to_be_bytes());
+    block[8..16].copy_from_slice(&low.to_be_bytes());
+}
+
+/// Split a block into its first and second half, as big endian integers.
+#[inline(always)]
+fn to_halves(block: &[u8]) -> (u64, u64) {
+    let high = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let low = u64::from_be_bytes(block[8..16].try_into().unwrap());
+
+    (high, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::ppc_crypto_available;
+
+    use super::super::bytewise_aes_round;
+    use super::*;
+
+    #[test]
+    fn test_fips_197_round() {
+        if !ppc_crypto_available() {
+            return;
+        }
+
+        // The first round of the example in appendix B of FIPS 197.
+        let mut block = hex!("193de3bea0f4e22b9ac68d2ae9f84808");
+        unsafe { aes_round(&mut block, &hex!("a0fafe1788542cb123a339392a6c7605")) };
+
+        assert_eq!(block, hex!("a49c7ff2689f352b6b5bea43026a5049"));
+    }
+
+    #[test]
+    fn test_matches_bytewise() {
+        if !ppc_crypto_available() {
+            return;
+        }
+
+        let key = hex!("000102030405060708090a0b0c0d0e0f");
+        let mut expected = hex!("00112233445566778899aabbccddeeff");
+        let mut block = expected;
+
+        for _ in 0..1000 {
+            bytewise_aes_round(&mut expected, &key);
+            unsafe { aes_round(&mut block, &key) };
+            assert_eq!(block, expected);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! AES round using the RISC-V scalar cryptography extension Zkne.
+//!
+//! `aes64esm` computes half of the state after ShiftRows, SubBytes and
+//! MixColumns from both halves, so a round is two of them followed by
+//! XORing in the key. The state is held in two registers with the first
+//! byte of each half in the least significant byte.
+//!
+//! The instructions are emitted with inline assembly, as the intrinsics are
+//! not available on stable Rust.
+use std::arch::asm;
+use std::convert::TryInto;
+
+/// One AES encryption round, as computed by `AESENC`.
+///
+/// # Safety
+/// The CPU must support the Zkne extension.
+#[inline]
+pub unsafe fn aes_round(block: &mut [u8], round_key: &[u8]) {
+    let (block_low, block_high) = to_halves(block);
+    let (key_low, key_high) = to_halves(round_key);
+    let (low, high): (u64, u64);
+
+    asm!(
+        ".option push",
+        ".option arch, +zkne",
+        "aes64esm {low}, {block_low}, {block_high}",
+        "aes64esm {high}, {block_high}, {block_low}",
+        ".option pop",
+        block_low = in(reg) block_low,
+        block_high = in(reg) block_high,
+        // Written before `block_high` is read for the second half.
+        low = out(reg) low,
+        high = lateout(reg) high,
+        options(pure, nomem, nostack),
+    );
+
+    block[..8].copy_from_slice(&(low ^ key_low).to_le_bytes());
+    block[8..16].copy_from_slice(&(high ^ key_high).to_le_bytes());
+}
+
+/// Split a block into its first and second half, as little endian integers.
+#[inline(always)]
+fn to_halves(block: &[u8]) -> (u64, u64) {
+    let low = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let high = u64::from_le_bytes(block[8..16].try_into().unwrap());
+
+    (low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::riscv_aes_available;
+
+    use super::super::bytewise_aes_round;
+    use super::*;
+
+    #[test]
+    fn test_fips_197_round() {
+        if !riscv_aes_available() {
+            return;
+        }
+
+        // The first round of the example in appendix B of FIPS 197.
+        let mut block = hex!("193de3bea0f4e22b9ac68d2ae9f84808");
+        unsafe { aes_round(&mut block, &hex!("a0fafe1788542cb123a339392a6c7605")) };
+
+        assert_eq!(block, hex!("a49c7ff2689f352b6b5bea43026a5049"));
+    }
+
+    #[test]
+    fn test_matches_bytewise() {
+        if !riscv_aes_available() {
+            return;
+        }
+
+        let key = hex!("000102030405060708090a0b0c0d0e0f");
+        let mut expected = hex!("00112233445566778899aabbccddeeff");
+        let mut block = expected;
+
+        for _ in 0..1000 {
+            bytewise_aes_round(&mut expected, &key);
+            unsafe { aes_round(&mut block, &key) };
+            assert_eq!(block, expected);
+        }
+    }
+}
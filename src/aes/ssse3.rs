@@ -0,0 +1,188 @@
+//! AES round for x86 CPUs without AES-NI, using the SSSE3 `pshufb`.
+//!
+//! `pshufb` looks up sixteen bytes in a sixteen byte table at once. SubBytes
+//! is sixteen of those lookups, one for each row of the S-box. The indices
+//! are offset so that only the bytes whose high nibble selects the row stay
+//! in range, as `pshufb` returns zero for indices with the high bit set.
+//! ShiftRows and the rotations of MixColumns are shuffles as well.
+//!
+//! This sits between the byte-at-a-time software AES and the `aesni` module.
+//! The lookups do not depend on the state, so unlike the `table` module it
+//! is also used with the `constant-time` feature.
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::convert::TryInto;
+
+/// The AES S-box, one row per value of the high nibble.
+const S_BOX: [[u8; 16]; 16] = [
+    [
+        0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5,
+        0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    ],
+    [
+        0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0,
+        0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    ],
+    [
+        0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc,
+        0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    ],
+    [
+        0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a,
+        0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    ],
+    [
+        0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0,
+        0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    ],
+    [
+        0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b,
+        0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    ],
+    [
+        0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85,
+        0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    ],
+    [
+        0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5,
+        0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    ],
+    [
+        0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17,
+        0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    ],
+    [
+        0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88,
+        0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    ],
+    [
+        0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c,
+        0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    ],
+    [
+        0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9,
+        0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    ],
+    [
+        0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6,
+        0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    ],
+    [
+        0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e,
+        0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    ],
+    [
+        0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94,
+        0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    ],
+    [
+        0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68,
+        0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+    ],
+];
+
+/// One AES encryption round, as computed by `AESENC`.
+///
+/// # Safety
+/// The CPU must support SSSE3.
+#[inline]
+#[target_feature(enable = "ssse3")]
+pub unsafe fn aes_round(block: &mut [u8], round_key: &[u8]) {
+    let block: &mut [u8; 16] = (&mut block[..16]).try_into().unwrap();
+    let round_key: &[u8; 16] = round_key[..16].try_into().unwrap();
+
+    // SubBytes works on single bytes, so it may come after ShiftRows.
+    let state = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+    let state = mix_columns(sub_bytes(shift_rows(state)));
+    let key = _mm_loadu_si128(round_key.as_ptr() as *const __m128i);
+
+    _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, _mm_xor_si128(state, key));
+}
+
+#[inline(always)]
+unsafe fn sub_bytes(state: __m128i) -> __m128i {
+    let mut result = _mm_setzero_si128();
+    let mut offset = state;
+
+    for row in S_BOX.iter() {
+        // Bytes of this row become 0x70 to 0x7f, the others saturate past 0x80.
+        let index = _mm_adds_epu8(offset, _mm_set1_epi8(0x70));
+        let row = _mm_loadu_si128(row.as_ptr() as *const __m128i);
+        result = _mm_or_si128(result, _mm_shuffle_epi8(row, index));
+        offset = _mm_sub_epi8(offset, _mm_set1_epi8(16));
+    }
+
+    result
+}
+
+#[inline(always)]
+unsafe fn shift_rows(state: __m128i) -> __m128i {
+    _mm_shuffle_epi8(state, _mm_setr_epi8(0, 5, 10, 15, 4, 9, 14, 3, 8, 13, 2, 7, 12, 1, 6, 11))
+}
+
+/// Rotate each column up by one row.
+#[inline(always)]
+unsafe fn rotate_rows(state: __m128i) -> __m128i {
+    _mm_shuffle_epi8(state, _mm_setr_epi8(1, 2, 3, 0, 5, 6, 7, 4, 9, 10, 11, 8, 13, 14, 15, 12))
+}
+
+/// Multiply each byte by x in GF(2^8).
+#[inline(always)]
+unsafe fn xtime(state: __m128i) -> __m128i {
+    let overflow = _mm_and_si128(_mm_cmplt_epi8(state, _mm_setzero_si128()), _mm_set1_epi8(0x1b));
+
+    _mm_xor_si128(_mm_add_epi8(state, state), overflow)
+}
+
+/// Compute `2a0 + 3a1 + a2 + a3` for every row at once.
+#[inline(always)]
+unsafe fn mix_columns(state: __m128i) -> __m128i {
+    let rotated = rotate_rows(state);
+    let sum = _mm_xor_si128(state, rotated);
+    // Rotating `a0 + a1` by two rows gives `a2 + a3`.
+    let far = rotate_rows(rotate_rows(sum));
+
+    _mm_xor_si128(_mm_xor_si128(xtime(sum), rotated), far)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::ssse3_available;
+
+    use super::super::bytewise_aes_round;
+    use super::*;
+
+    #[test]
+    fn test_fips_197_round() {
+        if !ssse3_available() {
+            return;
+        }
+
+        // The first round of the example in appendix B of FIPS 197.
+        let mut block = hex!("193de3bea0f4e22b9ac68d2ae9f84808");
+        unsafe { aes_round(&mut block, &hex!("a0fafe1788542cb123a339392a6c7605")) };
+
+        assert_eq!(block, hex!("a49c7ff2689f352b6b5bea43026a5049"));
+    }
+
+    #[test]
+    fn test_matches_bytewise() {
+        if !ssse3_available() {
+            return;
+        }
+
+        let key = hex!("000102030405060708090a0b0c0d0e0f");
+        let mut expected = hex!("00112233445566778899aabbccddeeff");
+        let mut block = expected;
+
+        for _ in 0..1000 {
+            bytewise_aes_round(&mut expected, &key);
+            unsafe { aes_round(&mut block, &key) };
+            assert_eq!(block, expected);
+        }
+    }
+}
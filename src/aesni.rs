@@ -6,31 +6,93 @@
 //! This module currently requires the following CPU extensions to work:
 //!
 //! * AES
-//! * SSE2 (for most vector operations)
-//! * SSE4.1 (for extracting 64bit integers from vectors)
+//! * SSE2 (for the vector operations)
 #[cfg(target_arch = "x86")]
 use std::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 use std::mem::size_of;
+use std::slice;
 
 use slice_cast::cast_mut;
 
-use crate::ROUNDS;
+use crate::aes::{integer_math, random_math, tweak1_1};
+use crate::params::{Conceal, Fast, Lite, Pico, R, Rto, SoftShell, Upx2, V0, V1, V2, Wow, Xao, Zls, VariantParams};
+use crate::random_math::{Compiled, Program};
+use crate::{LoopState, Phase, Tweaks, U64p, Variant};
 
 /// Type for a set of explode/implode AES keys.
-type KeysType = [__m128i; 10];
+pub type KeysType = [__m128i; 10];
 
 #[target_feature(enable = "aes")]
 #[target_feature(enable = "sse2")]
-pub unsafe fn digest_main(keccac: &mut [u8], scratchpad: &mut [u8]) {
+pub unsafe fn digest_main<F: FnMut(Phase)>(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, mut after_phase: F) {
+    let mut state = LoopState::new(keccac);
+
     // Cast to SSE types
     let scratchpad: &mut [__m128i] = cast_mut(scratchpad);
     let keccac: &mut [__m128i] = cast_mut(&mut keccac[..192]);
 
     init_scratchpad(keccac, scratchpad);
-    main_loop(keccac, scratchpad);
+    after_phase(Phase::Init);
+    main_loop_variant(&mut state, scratchpad, tweaks.iterations(), tweaks);
+    after_phase(Phase::MainLoop);
     finalize_state(keccac, &scratchpad);
+    after_phase(Phase::Finalize);
+}
+
+/// Compute several hashes of the same variant and number of iterations at once.
+///
+/// The main loops of the hashes are interleaved, see `main_loop_interleaved`.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn digest_main_n<const N: usize>(keccacs: [&mut [u8]; N], scratchpads: [&mut [u8]; N], tweaks: [Tweaks; N]) {
+    debug_assert!(tweaks.iter().all(|t| t.variant == tweaks[0].variant && t.iterations() == tweaks[0].iterations()));
+    let mut states = [LoopState::default(); N];
+    for (state, keccac) in states.iter_mut().zip(keccacs.iter()) {
+        *state = LoopState::new(keccac);
+    }
+
+    let mut scratchpads = scratchpads.map(|scratchpad| -> &mut [__m128i] { cast_mut(scratchpad) });
+    let mut keccacs = keccacs.map(|keccac| -> &mut [__m128i] { cast_mut(&mut keccac[..192]) });
+
+    for (keccac, scratchpad) in keccacs.iter().zip(scratchpads.iter_mut()) {
+        init_scratchpad(keccac, scratchpad);
+    }
+    let (mut lane_states, mut lane_scratchpads) = (states.iter_mut(), scratchpads.iter_mut());
+    main_loop_variant_interleaved(tweaks.map(|tweaks| {
+        (lane_states.next().unwrap(), &mut **lane_scratchpads.next().unwrap(), tweaks)
+    }));
+    for (keccac, scratchpad) in keccacs.iter_mut().zip(scratchpads.iter()) {
+        finalize_state(keccac, scratchpad);
+    }
+}
+
+/// Fill the scratchpad from the initial state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn init(keccac: &[u8], scratchpad: &mut [u8]) {
+    let scratchpad: &mut [__m128i] = cast_mut(scratchpad);
+    let keccac = slice::from_raw_parts(keccac.as_ptr() as *const __m128i, 12);
+
+    init_scratchpad(keccac, scratchpad);
+}
+
+/// Run `iterations` rounds of the main loop, continuing from `state`.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn main_loop_partial(state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+    main_loop_variant(state, cast_mut(scratchpad), iterations, tweaks);
+}
+
+/// Fold the scratchpad back into the state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn finalize(keccac: &mut [u8], scratchpad: &[u8]) {
+    let scratchpad = slice::from_raw_parts(scratchpad.as_ptr() as *const __m128i, scratchpad.len() / size_of::<__m128i>());
+    let keccac: &mut [__m128i] = cast_mut(&mut keccac[..192]);
+
+    finalize_state(keccac, scratchpad);
 }
 
 /// Derive 10 round keys based on two initial keys.
@@ -42,7 +104,7 @@ pub unsafe fn digest_main(keccac: &mut [u8], scratchpad: &mut [u8]) {
 ///
 #[target_feature(enable = "aes")]
 #[target_feature(enable = "sse2")]
-unsafe fn derive_key(mut temp1: __m128i, mut temp3: __m128i) -> KeysType {
+pub unsafe fn derive_key(mut temp1: __m128i, mut temp3: __m128i) -> KeysType {
     let mut keys = [_mm_setzero_si128(); 10];
     keys[0] = temp1;
     keys[1] = temp3;
@@ -112,34 +174,284 @@ unsafe fn init_scratchpad(keccac: &[__m128i], scratchpad: &mut [__m128i]) {
     }
 }
 
+/// Run the main loop specialized for the variant.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+unsafe fn main_loop_variant(state: &mut LoopState, scratchpad: &mut [__m128i], iterations: usize, tweaks: Tweaks) {
+    match tweaks.variant {
+        Variant::V0 => main_loop::<V0>(state, scratchpad, iterations, tweaks),
+        Variant::V1 => main_loop::<V1>(state, scratchpad, iterations, tweaks),
+        Variant::V2 => main_loop::<V2>(state, scratchpad, iterations, tweaks),
+        Variant::R => main_loop::<R>(state, scratchpad, iterations, tweaks),
+        Variant::Lite => main_loop::<Lite>(state, scratchpad, iterations, tweaks),
+        Variant::Pico => main_loop::<Pico>(state, scratchpad, iterations, tweaks),
+        Variant::Fast => main_loop::<Fast>(state, scratchpad, iterations, tweaks),
+        Variant::Zls => main_loop::<Zls>(state, scratchpad, iterations, tweaks),
+        Variant::Xao => main_loop::<Xao>(state, scratchpad, iterations, tweaks),
+        Variant::Rto => main_loop::<Rto>(state, scratchpad, iterations, tweaks),
+        Variant::Upx2 => main_loop::<Upx2>(state, scratchpad, iterations, tweaks),
+        Variant::Conceal => main_loop::<Conceal>(state, scratchpad, iterations, tweaks),
+        Variant::SoftShell => main_loop::<SoftShell>(state, scratchpad, iterations, tweaks),
+        Variant::Wow => main_loop::<Wow>(state, scratchpad, iterations, tweaks),
+    }
+}
+
+/// Run the main loops of several hashes in lockstep, specialized for the variant.
+///
+/// All hashes must have the same variant and number of iterations.
 #[target_feature(enable = "aes")]
-#[target_feature(enable = "sse4.1")]
-unsafe fn main_loop(keccac: &[__m128i], scratchpad: &mut [__m128i]) {
-    let mut a = _mm_xor_si128(keccac[0], keccac[2]);
-    let mut b = _mm_xor_si128(keccac[1], keccac[3]);
+#[target_feature(enable = "sse2")]
+pub unsafe fn main_loop_variant_interleaved<const N: usize>(hashes: [LaneParts; N]) {
+    match hashes[0].2.variant {
+        Variant::V0 => main_loop_interleaved::<V0, N>(hashes),
+        Variant::V1 => main_loop_interleaved::<V1, N>(hashes),
+        Variant::V2 => main_loop_interleaved::<V2, N>(hashes),
+        Variant::R => main_loop_interleaved::<R, N>(hashes),
+        Variant::Lite => main_loop_interleaved::<Lite, N>(hashes),
+        Variant::Pico => main_loop_interleaved::<Pico, N>(hashes),
+        Variant::Fast => main_loop_interleaved::<Fast, N>(hashes),
+        Variant::Zls => main_loop_interleaved::<Zls, N>(hashes),
+        Variant::Xao => main_loop_interleaved::<Xao, N>(hashes),
+        Variant::Rto => main_loop_interleaved::<Rto, N>(hashes),
+        Variant::Upx2 => main_loop_interleaved::<Upx2, N>(hashes),
+        Variant::Conceal => main_loop_interleaved::<Conceal, N>(hashes),
+        Variant::SoftShell => main_loop_interleaved::<SoftShell, N>(hashes),
+        Variant::Wow => main_loop_interleaved::<Wow, N>(hashes),
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+unsafe fn main_loop<V: VariantParams>(state: &mut LoopState, scratchpad: &mut [__m128i], iterations: usize, tweaks: Tweaks) {
+    let mut lane = Lane::new::<V>(state, scratchpad, tweaks);
+    for _ in 0..iterations {
+        lane.step::<V>();
+    }
+    lane.store();
+}
+
+/// Run the main loops of several hashes of the same variant side by side.
+///
+/// The iterations of the hashes are independent, so the CPU can work on
+/// one while the scratchpad accesses of the others are waiting for memory.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+unsafe fn main_loop_interleaved<V: VariantParams, const N: usize>(hashes: [LaneParts; N]) {
+    let iterations = hashes[0].2.iterations();
+    let mut lanes = hashes.map(|(state, scratchpad, tweaks)| Lane::new::<V>(state, scratchpad, tweaks));
+    for _ in 0..iterations {
+        for lane in lanes.iter_mut() {
+            lane.step::<V>();
+        }
+    }
+    for lane in lanes.iter_mut() {
+        lane.store();
+    }
+}
+
+/// The state, scratchpad and tweaks of one of the hashes of an interleaved main loop.
+pub type LaneParts<'a> = (&'a mut LoopState, &'a mut [__m128i], Tweaks);
+
+/// The main loop variables of a single hash.
+struct Lane<'a> {
+    state: &'a mut LoopState,
+    scratchpad: &'a mut [__m128i],
+    address_mask: usize,
+    tweak1_2: __m128i,
+    program: Option<Compiled>,
+    registers: [u32; 9],
+    a: __m128i,
+    b: __m128i,
+    b1: __m128i,
+    accumulators: __m128,
+}
+
+impl<'a> Lane<'a> {
+    /// Load the variables from `state`.
+    #[target_feature(enable = "sse2")]
+    unsafe fn new<V: VariantParams>(state: &'a mut LoopState, scratchpad: &'a mut [__m128i], tweaks: Tweaks) -> Self {
+        // The mask of the soft shell and custom variants is only known at runtime.
+        let address_mask = if tweaks.is_dynamic() { tweaks.address_mask() } else { V::ADDRESS_MASK };
+        let program = match V::VARIANT {
+            Variant::R => Some(Program::generate(tweaks.height).compile()),
+            Variant::Wow => Some(Program::generate_wow(tweaks.height).compile()),
+            _ => None,
+        };
+        let mut registers = [0u32; 9];
+        registers[..4].copy_from_slice(&state.random_math);
+
+        Lane {
+            address_mask,
+            tweak1_2: _mm_set_epi64x(tweaks.tweak1_2 as i64, 0),
+            program,
+            registers,
+            a: _mm_loadu_si128(state.a.as_ptr() as *const __m128i),
+            b: _mm_loadu_si128(state.b.as_ptr() as *const __m128i),
+            b1: _mm_loadu_si128(state.b1.as_ptr() as *const __m128i),
+            accumulators: _mm_loadu_ps(state.conceal.as_ptr() as *const f32),
+            state,
+            scratchpad,
+        }
+    }
+
+    /// Run a single iteration of the main loop.
+    #[inline]
+    #[target_feature(enable = "aes")]
+    #[target_feature(enable = "sse2")]
+    unsafe fn step<V: VariantParams>(&mut self) {
+        let base = V::VARIANT.base();
+        let shuffle = base == Variant::V2 || base == Variant::R;
+        let reverse = V::VARIANT == Variant::Upx2;
+        let conceal = V::VARIANT == Variant::Conceal;
+        // CryptoNight-WOW leaves `a` and `c` out of the random math and shuffle.
+        let wow = V::VARIANT == Variant::Wow;
+        let (a, b, b1) = (self.a, self.b, self.b1);
+        let scratchpad = &mut *self.scratchpad;
 
-    for _ in 0..ROUNDS {
         // First transfer
-        let address = scratchpad.get_unchecked_mut(to_sp_index(a));
-        *address = _mm_aesenc_si128(*address, a);
-        let tmp = b;
-        b = *address;
-        *address = _mm_xor_si128(*address, tmp);
+        let index = to_sp_index(a, self.address_mask);
+        let mut block = *scratchpad.get_unchecked(index);
+        if conceal {
+            block = conceal_tweak(block, &mut self.accumulators);
+        }
+        let mut c = _mm_aesenc_si128(block, a);
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, index, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                c = _mm_xor_si128(c, chunks);
+            }
+        }
+
+        let address = scratchpad.get_unchecked_mut(index);
+        *address = _mm_xor_si128(c, b);
+        if base == Variant::V1 {
+            let bytes = &mut *(address as *mut __m128i as *mut [u8; 16]);
+            bytes[11] = tweak1_1(bytes[11]);
+        }
 
         // Second transfer
-        let address = scratchpad.get_unchecked_mut(to_sp_index(b));
-        let tmp = cn_8byte_add(a, cn_8byte_mul(b, *address));
-        a = _mm_xor_si128(*address, tmp);
-        *address = tmp;
+        let index = to_sp_index(c, self.address_mask);
+        let mut value = *scratchpad.get_unchecked(index);
+        if base == Variant::V2 {
+            let c = to_u64p(c);
+            let mask = integer_math(c.low(), c.high(), &mut self.state.division_result, &mut self.state.sqrt_result);
+            value = _mm_xor_si128(value, _mm_set_epi64x(0, mask as i64));
+        }
+
+        // The random math modifies `a` for the sum, but not for the shuffle.
+        let mut addend = a;
+        if let Some(program) = self.program.as_ref() {
+            let (mask, a_mask) = random_math(program, &mut self.registers, to_u64p(a), to_u64p(b), to_u64p(b1));
+            value = _mm_xor_si128(value, _mm_set_epi64x(0, mask as i64));
+            if !wow {
+                addend = _mm_xor_si128(addend, _mm_set_epi64x(a_mask.high() as i64, a_mask.low() as i64));
+            }
+        }
+
+        let mut product = cn_8byte_mul(c, value);
+        if base == Variant::V2 || wow {
+            let neighbour = scratchpad.get_unchecked_mut(index ^ 1);
+            *neighbour = _mm_xor_si128(*neighbour, product);
+            product = _mm_xor_si128(product, *scratchpad.get_unchecked(index ^ 2));
+        }
+
+        let mut next_b = c;
+        if shuffle {
+            let chunks = shuffle_add(scratchpad, index, a, b, b1, reverse);
+            if base == Variant::R && !wow {
+                next_b = _mm_xor_si128(next_b, chunks);
+            }
+        }
+
+        let sum = cn_8byte_add(addend, product);
+        self.a = _mm_xor_si128(value, sum);
+        let address = scratchpad.get_unchecked_mut(index);
+        if V::VARIANT == Variant::Rto {
+            *address = _mm_xor_si128(_mm_xor_si128(sum, self.tweak1_2), _mm_slli_si128(sum, 8));
+        } else if base == Variant::V1 {
+            *address = _mm_xor_si128(sum, self.tweak1_2);
+        } else {
+            *address = sum;
+        }
+
+        self.b1 = b;
+        self.b = next_b;
+    }
+
+    /// Store the variables back into the state.
+    #[target_feature(enable = "sse2")]
+    unsafe fn store(&mut self) {
+        _mm_storeu_si128(self.state.a.as_mut_ptr() as *mut __m128i, self.a);
+        _mm_storeu_si128(self.state.b.as_mut_ptr() as *mut __m128i, self.b);
+        _mm_storeu_si128(self.state.b1.as_mut_ptr() as *mut __m128i, self.b1);
+        _mm_storeu_ps(self.state.conceal.as_mut_ptr() as *mut f32, self.accumulators);
+        self.state.random_math.copy_from_slice(&self.registers[..4]);
     }
 }
 
+/// The variant 2 shuffle of the three blocks sharing a cache line with `index`.
+///
+/// The shuffle is reversed for CryptoNight-UPX2.
+///
+/// Returns the XOR of the three blocks before the shuffle.
 #[inline(always)]
-unsafe fn to_sp_index(a: __m128i) -> usize {
-    let a = _mm_extract_epi32(a, 0) as u32;
+unsafe fn shuffle_add(scratchpad: &mut [__m128i], index: usize, a: __m128i, b: __m128i, b1: __m128i, reverse: bool) -> __m128i {
+    let chunk1 = *scratchpad.get_unchecked(index ^ 1);
+    let chunk2 = *scratchpad.get_unchecked(index ^ 2);
+    let chunk3 = *scratchpad.get_unchecked(index ^ 3);
 
-    // Take the lowest 21 bits (2MB) and divide by the length of a slice.
-    (a & 0x1F_FFFF) as usize / size_of::<__m128i>()
+    let (first, second) = if reverse { (chunk1, chunk3) } else { (chunk3, chunk1) };
+    *scratchpad.get_unchecked_mut(index ^ 1) = _mm_add_epi64(first, b1);
+    *scratchpad.get_unchecked_mut(index ^ 2) = _mm_add_epi64(second, b);
+    *scratchpad.get_unchecked_mut(index ^ 3) = _mm_add_epi64(chunk2, a);
+
+    _mm_xor_si128(_mm_xor_si128(chunk1, chunk2), chunk3)
+}
+
+/// The Conceal tweak to the block read by the first transfer.
+#[inline(always)]
+unsafe fn conceal_tweak(block: __m128i, accumulators: &mut __m128) -> __m128i {
+    let mantissa = _mm_castsi128_ps(_mm_set1_epi32(0x807F_FFFFu32 as i32));
+    let exponent = _mm_castsi128_ps(_mm_set1_epi32(0x4000_0000));
+
+    let mut r = _mm_add_ps(_mm_cvtepi32_ps(block), *accumulators);
+    r = _mm_mul_ps(r, _mm_mul_ps(r, r));
+    r = _mm_or_ps(_mm_and_ps(r, mantissa), exponent);
+
+    let old = _mm_or_ps(_mm_and_ps(*accumulators, mantissa), exponent);
+    *accumulators = _mm_add_ps(*accumulators, r);
+
+    _mm_xor_si128(block, _mm_cvttps_epi32(_mm_mul_ps(old, _mm_set1_ps(536_870_880.0))))
+}
+
+#[inline(always)]
+unsafe fn to_u64p(x: __m128i) -> U64p {
+    U64p::new(low_u64(x), low_u64(_mm_unpackhi_epi64(x, x)))
+}
+
+/// The low 64 bits of a vector.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn low_u64(x: __m128i) -> u64 {
+    _mm_cvtsi128_si64(x) as u64
+}
+
+/// The low 64 bits of a vector, which 32-bit x86 can only move out in halves.
+#[cfg(target_arch = "x86")]
+#[inline(always)]
+unsafe fn low_u64(x: __m128i) -> u64 {
+    let low = _mm_cvtsi128_si32(x) as u32;
+    let high = _mm_cvtsi128_si32(_mm_srli_si128(x, 4)) as u32;
+
+    u64::from(high) << 32 | u64::from(low)
+}
+
+#[inline(always)]
+unsafe fn to_sp_index(a: __m128i, mask: usize) -> usize {
+    let a = _mm_cvtsi128_si32(a) as u32;
+
+    // Mask to the scratchpad size and divide by the length of a slice.
+    (a as usize & mask) / size_of::<__m128i>()
 }
 
 #[inline(always)]
@@ -149,11 +461,39 @@ unsafe fn cn_8byte_add(a: __m128i, b: __m128i) -> __m128i {
 
 #[inline(always)]
 unsafe fn cn_8byte_mul(a: __m128i, b: __m128i) -> __m128i {
-    let a = _mm_extract_epi64(a, 0) as u64;
-    let b = _mm_extract_epi64(b, 0) as u64;
+    let (high, low) = wide_mul(low_u64(a), low_u64(b));
+
+    _mm_set_epi64x(low as i64, high as i64)
+}
+
+/// The full 128 bit product of `a` and `b`, as the high and low halves.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn wide_mul(a: u64, b: u64) -> (u64, u64) {
     let c = u128::from(a) * u128::from(b);
 
-    _mm_set_epi64x(c as i64, (c >> 64) as i64)
+    ((c >> 64) as u64, c as u64)
+}
+
+/// The full 128 bit product of `a` and `b`, as the high and low halves.
+///
+/// A `u128` multiplication is a call into compiler-rt on 32-bit x86, so this
+/// builds it from the four 32 by 32 bit products instead.
+#[cfg(target_arch = "x86")]
+#[inline(always)]
+fn wide_mul(a: u64, b: u64) -> (u64, u64) {
+    let (a_low, a_high) = (a & 0xffff_ffff, a >> 32);
+    let (b_low, b_high) = (b & 0xffff_ffff, b >> 32);
+
+    let low = a_low * b_low;
+    let cross1 = a_low * b_high;
+    let cross2 = a_high * b_low;
+    let high = a_high * b_high;
+
+    // At most three 32 bit values, so this does not overflow.
+    let middle = (low >> 32) + (cross1 & 0xffff_ffff) + (cross2 & 0xffff_ffff);
+
+    (high + (cross1 >> 32) + (cross2 >> 32) + (middle >> 32), middle << 32 | low & 0xffff_ffff)
 }
 
 #[target_feature(enable = "aes")]
@@ -171,3 +511,47 @@ unsafe fn finalize_state(keccac: &mut [__m128i], scratchpad: &[__m128i]) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{aes, aesni_available, CryptoNight, Scratchpad};
+
+    use super::*;
+
+    #[test]
+    fn test_main_loop_matches_portable() {
+        if !aesni_available() {
+            return;
+        }
+
+        let keccac: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut expected = Scratchpad::allocate(CryptoNight::SP_SIZE);
+        aes::init_scratchpad(&keccac, expected.as_mut());
+        let mut actual = Scratchpad::allocate(CryptoNight::SP_SIZE);
+        actual.as_mut().copy_from_slice(expected.as_ref());
+
+        for &variant in Variant::all() {
+            let tweaks = Tweaks { variant, tweak1_2: 0x0123_4567_89ab_cdef, height: 1_806_260, custom: None };
+            let mut expected_state = LoopState::new(&keccac);
+            let mut actual_state = expected_state;
+
+            aes::main_loop_partial(&mut expected_state, expected.as_mut(), 1000, tweaks);
+            unsafe { main_loop_partial(&mut actual_state, actual.as_mut(), 1000, tweaks) };
+
+            assert_eq!(actual_state, expected_state, "{}", variant);
+            assert!(actual.as_ref() == expected.as_ref(), "{}", variant);
+        }
+    }
+
+    #[test]
+    fn test_wide_mul() {
+        let samples = [0, 1, 0xffff_ffff, 0x1_0000_0000, 0x0123_4567_89ab_cdef, u64::MAX - 1, u64::MAX];
+
+        for &a in samples.iter() {
+            for &b in samples.iter() {
+                let c = u128::from(a) * u128::from(b);
+                assert_eq!(wide_mul(a, b), ((c >> 64) as u64, c as u64), "{:x} * {:x}", a, b);
+            }
+        }
+    }
+}
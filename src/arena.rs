@@ -0,0 +1,182 @@
+//! A single allocation shared by several concurrent hashers.
+use std::slice::ChunksExactMut;
+
+use crate::{AllocationReport, CryptoNight, MemoryOptions, Scratchpad};
+
+/// One large allocation divided into equally sized scratchpads.
+///
+/// Worker pools can use an arena to set up the memory for all their
+/// hashers at once, rather than allocating a scratchpad per worker. When
+/// huge pages are requested, this means a single mapping has to be set up.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, ScratchpadArena};
+/// let mut arena = ScratchpadArena::new(2, CryptoNight::SP_SIZE);
+///
+/// for (i, scratchpad) in arena.iter_mut().enumerate() {
+///     // Each scratchpad could be handed to a different thread.
+///     let _digest = CryptoNight::digest_with_buffer([i as u8], scratchpad);
+/// }
+/// ```
+pub struct ScratchpadArena {
+    memory: Scratchpad,
+    slot_size: usize,
+}
+
+impl ScratchpadArena {
+    /// Allocate an arena holding `slots` scratchpads of `slot_size` bytes each.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no slots, or if `slot_size` is zero
+    /// or not a multiple of `CryptoNight::SP_ALIGNMENT`.
+    pub fn new(slots: usize, slot_size: usize) -> Self {
+        Self::with_options(slots, slot_size, MemoryOptions::default())
+    }
+
+    /// Allocate an arena using the given memory options.
+    ///
+    /// The options apply to the arena as a whole. See `Scratchpad::allocate_with`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics under the same conditions as `ScratchpadArena::new`.
+    pub fn with_options(slots: usize, slot_size: usize, options: MemoryOptions) -> Self {
+        assert!(slots > 0, "Arena must have at least one slot");
+        assert!(slot_size > 0 && slot_size & (CryptoNight::SP_ALIGNMENT - 1) == 0,
+                "Slot size must be a non-zero multiple of the scratchpad alignment");
+        let len = slots.checked_mul(slot_size).expect("Arena size overflows");
+
+        ScratchpadArena {
+            memory: Scratchpad::allocate_with(len, options),
+            slot_size,
+        }
+    }
+
+    /// Number of scratchpads in the arena.
+    pub fn len(&self) -> usize {
+        self.memory.len() / self.slot_size
+    }
+
+    /// Whether the arena has no scratchpads. This is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of each scratchpad in the arena.
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    /// Get the scratchpad in slot `index`, if it exists.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.iter_mut().nth(index)
+    }
+
+    /// Iterate over all scratchpads in the arena.
+    ///
+    /// The scratchpads do not overlap, so they can be handed to different
+    /// threads.
+    pub fn iter_mut(&mut self) -> ChunksExactMut<'_, u8> {
+        let slots = self.len();
+        split_slab(self.memory.as_mut(), slots)
+    }
+
+    /// Whether the arena is backed by huge pages.
+    pub fn huge_pages(&self) -> bool {
+        self.memory.huge_pages()
+    }
+
+    /// Whether the arena is locked into RAM.
+    pub fn locked(&self) -> bool {
+        self.memory.locked()
+    }
+
+    /// Whether the arena is shared with processes forked after its allocation.
+    pub fn shared(&self) -> bool {
+        self.memory.shared()
+    }
+
+    /// Report on the options requested and granted for the arena.
+    pub fn report(&self) -> AllocationReport {
+        self.memory.report()
+    }
+}
+
+/// Split a contiguous slab into `ways` adjacent scratchpads.
+///
+/// Interleaved hashing keeps several scratchpads busy at once. Taking them
+/// from a single slab keeps them close together, which is easier on the TLB
+/// than separate allocations, especially when the slab is on huge pages.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{split_slab, CryptoNight, Scratchpad};
+/// let mut slab = Scratchpad::allocate(2 * CryptoNight::SP_SIZE);
+/// let mut scratchpads = split_slab(slab.as_mut(), 2);
+///
+/// let first = scratchpads.next().unwrap();
+/// let second = scratchpads.next().unwrap();
+/// assert_eq!(first.len(), CryptoNight::SP_SIZE);
+/// assert_eq!(second.len(), CryptoNight::SP_SIZE);
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `ways` is zero, if the slab is not aligned to
+/// `CryptoNight::SP_ALIGNMENT`, or if it cannot be divided into equally
+/// sized, aligned scratchpads.
+pub fn split_slab(slab: &mut [u8], ways: usize) -> ChunksExactMut<'_, u8> {
+    assert!(ways > 0, "Slab must be split at least one way");
+    assert_eq!(slab.as_ptr() as usize & (CryptoNight::SP_ALIGNMENT - 1), 0,
+               "Slab is not properly aligned");
+    assert_eq!(slab.len() % ways, 0, "Slab cannot be split evenly");
+
+    let size = slab.len() / ways;
+    assert!(size > 0 && size & (CryptoNight::SP_ALIGNMENT - 1) == 0,
+            "Split scratchpads would not be properly aligned");
+
+    slab.chunks_exact_mut(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_disjoint() {
+        let mut arena = ScratchpadArena::new(3, 1024);
+        assert_eq!(arena.len(), 3);
+
+        let slots: Vec<_> = arena.iter_mut().map(|slot| (slot.as_ptr() as usize, slot.len())).collect();
+        assert_eq!(slots.len(), 3);
+
+        for window in slots.windows(2) {
+            assert_eq!(window[0].1, 1024);
+            assert_eq!(window[0].0 + window[0].1, window[1].0);
+        }
+
+        assert!(arena.get_mut(2).is_some());
+        assert!(arena.get_mut(3).is_none());
+    }
+
+    #[test]
+    fn test_split_slab() {
+        let mut slab = Scratchpad::allocate(3 * 1024);
+        let scratchpads: Vec<_> = split_slab(slab.as_mut(), 3).collect();
+
+        assert_eq!(scratchpads.len(), 3);
+        for scratchpad in scratchpads {
+            assert_eq!(scratchpad.len(), 1024);
+            assert_eq!(scratchpad.as_ptr() as usize & (CryptoNight::SP_ALIGNMENT - 1), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_slab_unaligned() {
+        let mut slab = Scratchpad::allocate(3 * 16);
+        let _ = split_slab(slab.as_mut(), 2);
+    }
+}
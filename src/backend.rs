@@ -0,0 +1,665 @@
+//! Selection of the implementation that computes the hashes.
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{A16, CryptoNight, LoopState, MemoryOptions, Scratchpad, Tweaks, Variant};
+
+/// An implementation of the three phases of the CryptoNight computation.
+///
+/// The crate hashes with the fastest `BuiltinBackend` by default. Other
+/// implementations, such as accelerators or instrumented wrappers around a
+/// built-in backend, can be made discoverable with `register_backend` and
+/// used for all hashes with `set_backend`.
+///
+/// The Keccak state passed to the phases is the 200 byte state after
+/// absorbing the input, and the scratchpad is exactly as large as the
+/// variant requires and aligned to `CryptoNight::SP_ALIGNMENT`. The built-in
+/// backends panic when called with a scratchpad that is not.
+pub trait Backend: Send + Sync {
+    /// Name of the backend, as reported in `HashEvent::backend`.
+    fn name(&self) -> &'static str;
+
+    /// Whether the backend can run on this machine.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Fill the scratchpad from the Keccak state.
+    fn init_scratchpad(&self, keccac: &[u8; 200], scratchpad: &mut [u8]);
+
+    /// Run `iterations` iterations of the main loop, continuing from `state`.
+    fn main_loop(&self, state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: &Tweaks);
+
+    /// Fold the scratchpad back into the Keccak state.
+    fn finalize(&self, keccac: &mut [u8; 200], scratchpad: &[u8]);
+}
+
+/// The implementations of the CryptoNight computation in this crate.
+///
+/// The fastest backend the CPU supports is detected on the first hash and
+/// used for the rest of the process. Backends that were not compiled in,
+/// due to the target or the crate features, are never selected.
+///
+/// When the target features enabled at compile time settle the choice, for
+/// example with `-C target-cpu=native` or `-C target-feature=+aes`, there is
+/// no detection at all. The backend is then called directly, and can be
+/// inlined.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum BuiltinBackend {
+    /// The software implementation, available everywhere.
+    Portable,
+    /// AES-NI and SSE2 on x86 and x86_64, with the `aesni` feature.
+    Aesni,
+    /// Software AES vectorized with SSSE3, on x86 and x86_64 CPUs without
+    /// AES-NI, with the `aesni` feature. Only the AES rounds use it.
+    Ssse3,
+    /// AES-NI with 256-bit VAES and AVX2 on x86_64, with the `vaes` feature.
+    Vaes,
+    /// AES-NI with 512-bit VAES and AVX-512 on x86_64, with the `vaes` feature.
+    VaesAvx512,
+    /// The ARMv8 cryptography extension on AArch64, with the `aesni` feature.
+    Aarch64,
+    /// The POWER8 vector crypto instructions on 64-bit PowerPC Linux, with
+    /// the `aesni` feature. Only the AES rounds use them.
+    Ppc64,
+    /// The Zkne scalar cryptography extension on 64-bit RISC-V, with the
+    /// `aesni` feature. Only the AES rounds use it.
+    Riscv64,
+    /// SIMD128 vectors on WebAssembly, with the `aesni` feature. Only the
+    /// AES rounds use them. Selected when building with the `simd128` target
+    /// feature, as WebAssembly has no runtime detection.
+    Wasm32,
+}
+
+static SELECTED: OnceLock<BuiltinBackend> = OnceLock::new();
+
+/// Time `BuiltinBackend::auto_tune` spends measuring, in total.
+const TUNING_TIME: Duration = Duration::from_millis(100);
+
+/// The outcome of `BuiltinBackend::auto_tune`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tuning {
+    /// The fastest backend for the variant, now used for all hashes.
+    pub backend: BuiltinBackend,
+    /// The number of hashes to compute at once in a thread: 1, or 2 or 4 to
+    /// use `CryptoNight::digest2_with_buffers` or
+    /// `CryptoNight::digest4_with_buffers`.
+    pub lanes: usize,
+    /// Whether scratchpads could be allocated on huge pages. If so, they are
+    /// worth requesting with `MemoryOptions::huge_pages`.
+    pub huge_pages: bool,
+}
+
+impl Tuning {
+    /// Hash with the backend of this tuning, as `BuiltinBackend::auto_tune`
+    /// does: unless it is the selected backend, it is installed with
+    /// `set_backend`. Otherwise, any installed backend is removed.
+    ///
+    /// # Panics
+    /// If the backend is not available on this machine.
+    pub fn apply(&self) {
+        if self.backend == BuiltinBackend::selected() {
+            set_backend(None);
+        } else {
+            set_backend(Some(Arc::new(self.backend)));
+        }
+    }
+}
+
+impl BuiltinBackend {
+    /// The backend used for hashing in this process.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::BuiltinBackend;
+    /// println!("Hashing with {}", BuiltinBackend::selected());
+    /// ```
+    #[inline]
+    pub fn selected() -> BuiltinBackend {
+        match BuiltinBackend::STATIC {
+            Some(backend) => backend,
+            None => *SELECTED.get_or_init(BuiltinBackend::detect),
+        }
+    }
+
+    /// Name of the backend, as reported in `HashEvent::backend`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltinBackend::Portable => "portable",
+            BuiltinBackend::Aesni => "aesni",
+            BuiltinBackend::Ssse3 => "ssse3",
+            BuiltinBackend::Vaes => "vaes",
+            BuiltinBackend::VaesAvx512 => "vaes-avx512",
+            BuiltinBackend::Aarch64 => "aarch64",
+            BuiltinBackend::Ppc64 => "ppc64",
+            BuiltinBackend::Riscv64 => "riscv64",
+            BuiltinBackend::Wasm32 => "wasm32",
+        }
+    }
+
+    /// All built-in backends, including those not compiled in, in order of
+    /// preference.
+    pub fn all() -> &'static [BuiltinBackend] {
+        &[
+            BuiltinBackend::VaesAvx512,
+            BuiltinBackend::Vaes,
+            BuiltinBackend::Aesni,
+            BuiltinBackend::Aarch64,
+            BuiltinBackend::Ppc64,
+            BuiltinBackend::Riscv64,
+            BuiltinBackend::Wasm32,
+            BuiltinBackend::Ssse3,
+            BuiltinBackend::Portable,
+        ]
+    }
+
+    /// Whether the backend is compiled in and supported by the CPU.
+    ///
+    /// The backends that only replace the AES rounds of the portable one are
+    /// only available when they are the selected backend.
+    pub fn is_available(self) -> bool {
+        match self {
+            BuiltinBackend::Portable => true,
+            #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            BuiltinBackend::VaesAvx512 => crate::avx512_available(),
+            #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            BuiltinBackend::Vaes => crate::vaes_available(),
+            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            BuiltinBackend::Aesni => crate::aesni_available(),
+            #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            BuiltinBackend::Aarch64 => crate::arm_aes_available(),
+            BuiltinBackend::Ssse3 | BuiltinBackend::Ppc64 | BuiltinBackend::Riscv64 | BuiltinBackend::Wasm32 => {
+                BuiltinBackend::selected() == self
+            }
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Measure the available backends and interleaving widths on this
+    /// machine for about 100 ms, and hash with the fastest backend. Also
+    /// check whether huge pages can be allocated.
+    ///
+    /// The fastest backend depends on the variant: on some low-power CPUs,
+    /// the portable backend beats AES-NI for the lite variants. Unless it is
+    /// the selected backend, it is installed with `set_backend`, which also
+    /// turns off interleaving. Otherwise, any installed backend is removed.
+    ///
+    /// Every measurement hashes at least once, so this takes longer for slow
+    /// variants and unoptimized builds.
+    ///
+    /// # Usage
+    /// ```no_run
+    /// # use cryptonight_hash::{BuiltinBackend, Variant};
+    /// let tuning = BuiltinBackend::auto_tune(Variant::Lite);
+    /// println!("Hashing {} at a time with {}", tuning.lanes, tuning.backend);
+    /// ```
+    pub fn auto_tune(variant: Variant) -> Tuning {
+        let tweaks = Tweaks::plain(variant);
+        let candidates: Vec<_> = BuiltinBackend::all().iter()
+            .copied()
+            .filter(|b| b.is_available())
+            .collect();
+        let budget = TUNING_TIME / (candidates.len() as u32 + 2);
+
+        let mut keccacs = [A16([0x5a; 200]), A16([0x5a; 200]), A16([0x5a; 200]), A16([0x5a; 200])];
+        let mut scratchpads = [(); 4].map(|_| Scratchpad::allocate(tweaks.scratchpad_size()));
+
+        let mut fastest = (0.0, BuiltinBackend::Portable);
+        for &backend in &candidates {
+            let rate = hash_rate(budget, || {
+                CryptoNight::digest_phases_with(backend, &mut keccacs[0].0, scratchpads[0].as_mut(), tweaks, |_| ());
+                1
+            });
+            if rate > fastest.0 {
+                fastest = (rate, backend);
+            }
+        }
+
+        let huge_pages = MemoryOptions { huge_pages: true, ..MemoryOptions::default() };
+        let huge_pages = Scratchpad::allocate_with(tweaks.scratchpad_size(), huge_pages).report().granted.huge_pages;
+
+        let (single, backend) = fastest;
+        if backend != BuiltinBackend::selected() {
+            set_backend(Some(Arc::new(backend)));
+            return Tuning { backend, lanes: 1, huge_pages };
+        }
+        set_backend(None);
+
+        let [k0, k1, k2, k3] = &mut keccacs;
+        let [s0, s1, s2, s3] = &mut scratchpads;
+        let mut fastest = (single, 1);
+        if backend.max_lanes() >= 2 {
+            let rate = hash_rate(budget, || {
+                CryptoNight::digest_main2(&mut k0.0, s0.as_mut(), tweaks, &mut k1.0, s1.as_mut(), tweaks);
+                2
+            });
+            if rate > fastest.0 {
+                fastest = (rate, 2);
+            }
+        }
+        if backend.max_lanes() >= 4 {
+            let rate = hash_rate(budget, || {
+                let keccacs = [&mut k0.0[..], &mut k1.0[..], &mut k2.0[..], &mut k3.0[..]];
+                CryptoNight::digest_main4(keccacs, [s0.as_mut(), s1.as_mut(), s2.as_mut(), s3.as_mut()], [tweaks; 4]);
+                4
+            });
+            if rate > fastest.0 {
+                fastest = (rate, 4);
+            }
+        }
+        let lanes = fastest.1;
+
+        Tuning { backend, lanes, huge_pages }
+    }
+
+    /// The most hashes the backend interleaves in one thread.
+    pub(crate) fn max_lanes(self) -> usize {
+        // The canary is checked for one hash at a time.
+        if cfg!(feature = "canary") {
+            return 1;
+        }
+
+        match self {
+            BuiltinBackend::VaesAvx512 if cfg!(all(target_arch = "x86_64", feature = "vaes")) => 4,
+            BuiltinBackend::Aarch64 => 1,
+            _ => 2,
+        }
+    }
+
+    /// Panic unless the backend can run on the scratchpad, before calling
+    /// into its unsafe code.
+    fn check(self, scratchpad: &[u8]) {
+        assert!(self.is_available(), "the {} backend is not available", self);
+        assert_eq!(scratchpad.as_ptr() as usize & (CryptoNight::SP_ALIGNMENT - 1), 0);
+        assert_eq!(scratchpad.len() % 128, 0);
+    }
+
+    /// Whether the backend runs the AES-NI main loop.
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+    pub(crate) fn has_aesni(self) -> bool {
+        matches!(self, BuiltinBackend::Aesni | BuiltinBackend::Vaes | BuiltinBackend::VaesAvx512)
+    }
+
+    /// The backend, if the target features enabled at compile time already
+    /// guarantee that the fastest one compiled in is supported.
+    const STATIC: Option<BuiltinBackend> = if cfg!(any(feature = "portable", not(feature = "aesni"))) {
+        Some(BuiltinBackend::Portable)
+    } else if cfg!(all(target_arch = "x86_64", feature = "vaes")) {
+        if cfg!(all(target_feature = "aes", target_feature = "vaes", target_feature = "avx2", target_feature = "avx512f")) {
+            Some(BuiltinBackend::VaesAvx512)
+        } else {
+            None
+        }
+    } else if cfg!(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "aes", target_feature = "sse2")) {
+        Some(BuiltinBackend::Aesni)
+    } else if cfg!(all(target_arch = "aarch64", target_feature = "aes", target_feature = "neon")) {
+        Some(BuiltinBackend::Aarch64)
+    } else if cfg!(all(target_arch = "riscv64", target_feature = "zkne")) {
+        Some(BuiltinBackend::Riscv64)
+    } else if cfg!(all(target_arch = "wasm32", target_feature = "simd128")) {
+        // There is no runtime detection on WebAssembly.
+        Some(BuiltinBackend::Wasm32)
+    } else {
+        None
+    };
+
+    /// Find the fastest backend the CPU supports.
+    fn detect() -> BuiltinBackend {
+        #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            {
+                if crate::avx512_available() {
+                    return BuiltinBackend::VaesAvx512;
+                }
+                if crate::vaes_available() {
+                    return BuiltinBackend::Vaes;
+                }
+            }
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if crate::aesni_available() {
+                    return BuiltinBackend::Aesni;
+                }
+                if crate::ssse3_available() {
+                    return BuiltinBackend::Ssse3;
+                }
+            }
+        #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            {
+                if crate::arm_aes_available() {
+                    return BuiltinBackend::Aarch64;
+                }
+            }
+        #[cfg(all(target_arch = "powerpc64", target_os = "linux", feature = "aesni", not(feature = "portable")))]
+            {
+                if crate::ppc_crypto_available() {
+                    return BuiltinBackend::Ppc64;
+                }
+            }
+        #[cfg(all(target_arch = "riscv64", feature = "aesni", not(feature = "portable")))]
+            {
+                if crate::riscv_aes_available() {
+                    return BuiltinBackend::Riscv64;
+                }
+            }
+        BuiltinBackend::Portable
+    }
+}
+
+impl Backend for BuiltinBackend {
+    fn name(&self) -> &'static str {
+        BuiltinBackend::name(*self)
+    }
+
+    fn is_available(&self) -> bool {
+        BuiltinBackend::is_available(*self)
+    }
+
+    fn init_scratchpad(&self, keccac: &[u8; 200], scratchpad: &mut [u8]) {
+        self.check(scratchpad);
+        // The hardware backends load the state as aligned vectors.
+        let keccac = A16(*keccac);
+        CryptoNight::init_scratchpad_with(*self, &keccac.0, scratchpad);
+    }
+
+    fn main_loop(&self, state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: &Tweaks) {
+        self.check(scratchpad);
+        assert!(scratchpad.len() >= tweaks.scratchpad_size());
+        CryptoNight::main_loop_with(*self, state, scratchpad, iterations, *tweaks);
+    }
+
+    fn finalize(&self, keccac: &mut [u8; 200], scratchpad: &[u8]) {
+        self.check(scratchpad);
+        let mut aligned = A16(*keccac);
+        CryptoNight::finalize_scratchpad_with(*self, &mut aligned.0, scratchpad);
+        *keccac = aligned.0;
+    }
+}
+
+impl fmt::Display for BuiltinBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Fast check to avoid taking the lock when no backend was installed.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static BACKEND: RwLock<Option<Arc<dyn Backend>>> = RwLock::new(None);
+static REGISTERED: RwLock<Vec<Arc<dyn Backend>>> = RwLock::new(Vec::new());
+
+/// Hash with `backend` process-wide, or return to the selected
+/// `BuiltinBackend` with `None`.
+///
+/// Returns the previously installed backend. While a backend is installed,
+/// hashes of several inputs at once are computed one after the other.
+///
+/// # Panics
+///
+/// If the backend is not available.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{set_backend, BuiltinBackend, CryptoNight, Digest};
+/// use std::sync::Arc;
+///
+/// set_backend(Some(Arc::new(BuiltinBackend::Portable)));
+/// CryptoNight::digest(b"Your data");
+/// # set_backend(None);
+/// ```
+pub fn set_backend(backend: Option<Arc<dyn Backend>>) -> Option<Arc<dyn Backend>> {
+    if let Some(backend) = &backend {
+        assert!(backend.is_available(), "the {} backend is not available", backend.name());
+    }
+
+    let mut current = BACKEND.write().unwrap_or_else(|e| e.into_inner());
+    INSTALLED.store(backend.is_some(), Ordering::Release);
+
+    std::mem::replace(&mut *current, backend)
+}
+
+/// Register a backend process-wide, so `backends` lists it.
+///
+/// Returns the previously registered backend with the same name.
+pub fn register_backend(backend: Arc<dyn Backend>) -> Option<Arc<dyn Backend>> {
+    let mut backends = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+
+    match backends.iter_mut().find(|b| b.name() == backend.name()) {
+        Some(existing) => Some(std::mem::replace(existing, backend)),
+        None => {
+            backends.push(backend);
+            None
+        }
+    }
+}
+
+/// The backends available on this machine: the built-in ones in order of
+/// preference, followed by the registered ones.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::backends;
+/// for backend in backends() {
+///     println!("{}", backend.name());
+/// }
+/// ```
+pub fn backends() -> Vec<Arc<dyn Backend>> {
+    let registered = REGISTERED.read().unwrap_or_else(|e| e.into_inner());
+
+    BuiltinBackend::all().iter()
+        .map(|&b| Arc::new(b) as Arc<dyn Backend>)
+        .chain(registered.iter().cloned())
+        .filter(|b| b.is_available())
+        .collect()
+}
+
+/// Hashes per second computed by `hash`, which returns how many hashes it
+/// computed, measured for at least `budget`.
+fn hash_rate<F: FnMut() -> usize>(budget: Duration, mut hash: F) -> f64 {
+    let start = Instant::now();
+    let mut hashes = 0;
+
+    while hashes == 0 || start.elapsed() < budget {
+        hashes += hash();
+    }
+
+    hashes as f64 / start.elapsed().as_secs_f64()
+}
+
+/// The backend installed with `set_backend`, if any.
+#[inline]
+pub(crate) fn installed() -> Option<Arc<dyn Backend>> {
+    if !INSTALLED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    BACKEND.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::Digest;
+
+    use super::*;
+
+    /// Serializes the tests that install a backend.
+    static INSTALLING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Counts the main loops run by the selected built-in backend.
+    struct Counting(AtomicUsize);
+
+    impl Backend for Counting {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn init_scratchpad(&self, keccac: &[u8; 200], scratchpad: &mut [u8]) {
+            BuiltinBackend::selected().init_scratchpad(keccac, scratchpad);
+        }
+
+        fn main_loop(&self, state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: &Tweaks) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            BuiltinBackend::selected().main_loop(state, scratchpad, iterations, tweaks);
+        }
+
+        fn finalize(&self, keccac: &mut [u8; 200], scratchpad: &[u8]) {
+            BuiltinBackend::selected().finalize(keccac, scratchpad);
+        }
+    }
+
+    /// A backend that can not run anywhere.
+    struct Unavailable;
+
+    impl Backend for Unavailable {
+        fn name(&self) -> &'static str {
+            "unavailable"
+        }
+
+        fn is_available(&self) -> bool {
+            false
+        }
+
+        fn init_scratchpad(&self, _: &[u8; 200], _: &mut [u8]) {
+            unreachable!()
+        }
+
+        fn main_loop(&self, _: &mut LoopState, _: &mut [u8], _: usize, _: &Tweaks) {
+            unreachable!()
+        }
+
+        fn finalize(&self, _: &mut [u8; 200], _: &[u8]) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_selected() {
+        let backend = BuiltinBackend::selected();
+        assert_eq!(backend, BuiltinBackend::selected());
+        assert_eq!(backend.to_string(), backend.name());
+
+        if cfg!(any(not(feature = "aesni"), feature = "portable")) {
+            assert_eq!(backend, BuiltinBackend::Portable);
+        }
+        if let Some(expected) = BuiltinBackend::STATIC {
+            assert_eq!(backend, expected);
+        }
+    }
+
+    #[test]
+    fn test_set_backend() {
+        let _lock = INSTALLING.lock().unwrap_or_else(|e| e.into_inner());
+        let expected = CryptoNight::digest(b"This is a test");
+        let counting = Arc::new(Counting(AtomicUsize::new(0)));
+
+        let previous = set_backend(Some(counting.clone()));
+        let digest = CryptoNight::digest(b"This is a test");
+        set_backend(previous);
+
+        assert_eq!(digest, expected);
+        // Hashes of other tests may run on the backend as well.
+        assert!(counting.0.load(Ordering::Relaxed) >= 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_unavailable_backend() {
+        set_backend(Some(Arc::new(Unavailable)));
+    }
+
+    #[test]
+    fn test_register_backend() {
+        assert!(register_backend(Arc::new(Counting(AtomicUsize::new(0)))).is_none());
+        assert!(register_backend(Arc::new(Counting(AtomicUsize::new(0)))).is_some());
+        register_backend(Arc::new(Unavailable));
+
+        let names: Vec<_> = backends().iter().map(|b| b.name()).collect();
+        assert_eq!(names.iter().filter(|&&n| n == "counting").count(), 1);
+        assert!(names.contains(&"portable"));
+        assert!(names.contains(&BuiltinBackend::selected().name()));
+        assert!(!names.contains(&"unavailable"));
+    }
+
+    #[test]
+    fn test_builtin_phases() {
+        let tweaks = Tweaks::plain(crate::Variant::Lite);
+        let mut expected = [0x5au8; 200];
+        let mut scratchpad = crate::Scratchpad::allocate(tweaks.scratchpad_size());
+        CryptoNight::digest_phases_with(BuiltinBackend::Portable, &mut expected, scratchpad.as_mut(), tweaks, |_| ());
+
+        for &backend in BuiltinBackend::all().iter().filter(|b| b.is_available()) {
+            let mut keccac = [0x5au8; 200];
+            let mut state = LoopState::new(&keccac);
+            let scratchpad = scratchpad.as_mut();
+
+            backend.init_scratchpad(&keccac, scratchpad);
+            backend.main_loop(&mut state, scratchpad, tweaks.iterations(), &tweaks);
+            backend.finalize(&mut keccac, scratchpad);
+
+            assert_eq!(keccac[..], expected[..], "{}", backend);
+        }
+    }
+
+    #[test]
+    fn test_auto_tune() {
+        let _lock = INSTALLING.lock().unwrap_or_else(|e| e.into_inner());
+        let expected = CryptoNight::digest(b"This is a test");
+
+        let tuning = BuiltinBackend::auto_tune(Variant::Upx2);
+        let digest = CryptoNight::digest(b"This is a test");
+        let installed = installed().map(|b| b.name());
+        set_backend(None);
+
+        assert!(tuning.backend.is_available());
+        assert!([1, 2, 4].contains(&tuning.lanes));
+        assert!(tuning.lanes <= tuning.backend.max_lanes());
+        if tuning.backend == BuiltinBackend::selected() {
+            assert_eq!(installed, None);
+        } else {
+            assert_eq!(installed, Some(tuning.backend.name()));
+        }
+        assert_eq!(digest, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_auto_tune_with_profile() {
+        let _lock = INSTALLING.lock().unwrap_or_else(|e| e.into_inner());
+        let path = std::env::temp_dir().join(format!("cryptonight-tuning-{}.json", std::process::id()));
+        let profile = |variant: &str, tuning: &str| format!(r#"{{"variant": "{}", "tuning": {}}}"#, variant, tuning);
+        let read = || serde_json::from_slice::<serde_json::Value>(&std::fs::read(&path).unwrap()).unwrap();
+
+        let tuning = BuiltinBackend::auto_tune_with_profile(Variant::Upx2, &path).unwrap();
+        assert_eq!(read()["variant"], "cn/upx2");
+        assert_eq!(read()["tuning"]["backend"], tuning.backend.name());
+
+        // A profile that measuring is unlikely to produce is used as it is.
+        let stored = r#"{"backend": "portable", "lanes": 1, "huge_pages": true}"#;
+        std::fs::write(&path, profile("cn/upx2", stored)).unwrap();
+        let tuning = BuiltinBackend::auto_tune_with_profile(Variant::Upx2, &path).unwrap();
+        let installed = installed().map(|b| b.name());
+        assert_eq!(tuning, Tuning { backend: BuiltinBackend::Portable, lanes: 1, huge_pages: true });
+        if BuiltinBackend::selected() != BuiltinBackend::Portable {
+            assert_eq!(installed, Some("portable"));
+        }
+
+        // Profiles for other variants, of unknown backends or that cannot be
+        // parsed are replaced.
+        for contents in [profile("cn-lite/0", stored), profile("cn/upx2", r#"{"backend": "gpu", "lanes": 1, "huge_pages": false}"#), "{".to_owned()] {
+            std::fs::write(&path, contents).unwrap();
+            let tuning = BuiltinBackend::auto_tune_with_profile(Variant::Upx2, &path).unwrap();
+            assert_eq!(read()["variant"], "cn/upx2");
+            assert_eq!(read()["tuning"], serde_json::to_value(tuning).unwrap());
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        set_backend(None);
+    }
+}
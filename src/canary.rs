@@ -0,0 +1,91 @@
+//! Overflow detection for backend development.
+//!
+//! With the `canary` feature enabled, the bytes of the caller's buffer past
+//! the logical end of the scratchpad, up to `CANARY_LEN` of them, are filled
+//! with a known pattern that is verified after every phase. A backend that
+//! addresses memory outside the scratchpad, for example because of a wrong
+//! address mask, is caught right after the offending phase. Interleaved
+//! hashes are checked once all of their phases are done.
+//!
+//! Only buffers longer than the scratchpad of the variant have room for the
+//! canary. Temporary scratchpads allocated by the crate are made long enough.
+use std::marker::PhantomData;
+use std::ptr;
+
+use crate::{Phase, Tweaks};
+
+/// Number of canary bytes after the scratchpad.
+pub const CANARY_LEN: usize = 1 << 16;
+
+/// Canary pattern byte for the given offset past the end of the scratchpad.
+fn pattern(offset: usize) -> u8 {
+    (offset as u8) ^ 0xA5
+}
+
+/// The canary bytes following a scratchpad.
+pub struct Canary<'a> {
+    ptr: *mut u8,
+    len: usize,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Canary<'a> {
+    /// Trim `buffer` to the scratchpad of `len` bytes and fill the rest with
+    /// the canary pattern.
+    pub fn arm(buffer: &mut &'a mut [u8], len: usize) -> Self {
+        let (scratchpad, canary) = std::mem::take(buffer).split_at_mut(len);
+        *buffer = scratchpad;
+
+        for (offset, byte) in canary.iter_mut().enumerate() {
+            *byte = pattern(offset);
+        }
+
+        // The canary may be written through pointers derived from the scratchpad,
+        // so it must only be inspected through raw pointers.
+        Canary { ptr: canary.as_mut_ptr(), len: canary.len(), _buffer: PhantomData }
+    }
+
+    /// Verify the canary after the given phase.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if any canary byte was changed.
+    pub fn check(&self, phase: Phase) {
+        unsafe { check(self.ptr, self.len, phase) }
+    }
+}
+
+/// Arm the canaries of the scratchpads of several hashes, see `Canary::arm`.
+pub fn arm_all<'a, const N: usize>(mut buffers: [&'a mut [u8]; N], tweaks: &[Tweaks; N]) -> ([&'a mut [u8]; N], [Canary<'a>; N]) {
+    let mut tweaks = tweaks.iter();
+    let canaries = buffers.each_mut().map(|buffer| Canary::arm(buffer, tweaks.next().unwrap().scratchpad_size()));
+
+    (buffers, canaries)
+}
+
+unsafe fn check(canary: *const u8, len: usize, phase: Phase) {
+    for offset in 0..len {
+        let byte = ptr::read_volatile(canary.add(offset));
+        if byte != pattern(offset) {
+            panic!("Scratchpad overflow by the end of the {:?} phase: canary overwritten {} bytes past the end",
+                   phase, offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "canary overwritten 3 bytes past the end")]
+    fn test_detect_overwrite() {
+        let mut buffer = vec![0u8; 16 + CANARY_LEN];
+        let mut scratchpad = &mut buffer[..];
+        let canary = Canary::arm(&mut scratchpad, 16);
+        assert_eq!(scratchpad.len(), 16);
+
+        unsafe { *canary.ptr.add(3) ^= 0xFF };
+        canary.check(Phase::MainLoop);
+    }
+}
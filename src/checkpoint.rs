@@ -0,0 +1,364 @@
+//! Pausing and resuming the memory-hard main loop.
+use std::io::{self, Read, Write};
+
+use digest::generic_array::GenericArray;
+
+use crate::{A16, CryptoNight, CustomVariant, FixedOutput, LoopState, Tweaks, Variant};
+
+/// Magic bytes at the start of a serialized checkpoint.
+const MAGIC: &[u8; 4] = b"CNCP";
+/// Version of the serialization format.
+const VERSION: u8 = 6;
+
+/// A CryptoNight hash paused during its main loop.
+///
+/// The checkpoint holds the hash state, but not the scratchpad. A hash can
+/// only be resumed with the same scratchpad contents it was paused with, so
+/// both must be kept, or transferred together using `Checkpoint::write_to`
+/// and `Checkpoint::read_from`.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{Checkpoint, CryptoNight, Digest};
+/// let mut scratchpad = CryptoNight::allocate_scratchpad();
+/// let mut checkpoint = CryptoNight::new().chain(b"Your data").start_resumable(scratchpad.as_mut());
+///
+/// while !checkpoint.is_finished() {
+///     // Do some work, and possibly save the checkpoint for later.
+///     checkpoint.advance(scratchpad.as_mut(), 100_000);
+/// }
+///
+/// let digest = checkpoint.finalize(scratchpad.as_mut());
+/// assert_eq!(digest, CryptoNight::digest(b"Your data"));
+/// ```
+#[derive(Clone)]
+pub struct Checkpoint {
+    state: A16<[u8; 200]>,
+    loop_state: LoopState,
+    iteration: usize,
+    tweaks: Tweaks,
+}
+
+impl CryptoNight {
+    /// Start a hash that can be paused during its main loop.
+    ///
+    /// This initializes the scratchpad and returns a checkpoint at the
+    /// first iteration of the main loop. See `fixed_result_with_buffer` for
+    /// the requirements on the scratchpad.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn start_resumable(self, scratchpad: &mut [u8]) -> Checkpoint {
+        let (state, tweaks) = self.into_core().into_parts();
+        let scratchpad = Self::scratchpad_for(scratchpad, tweaks);
+
+        Self::init_scratchpad(&state.0, scratchpad);
+
+        let loop_state = LoopState::new(&state.0);
+
+        Checkpoint { state, loop_state, iteration: 0, tweaks }
+    }
+}
+
+impl Checkpoint {
+    /// Number of iterations of the main loop, which depends on the variant.
+    pub fn iterations(&self) -> usize {
+        self.tweaks.iterations()
+    }
+
+    /// Number of main loop iterations completed so far.
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    /// Whether the main loop has been completed.
+    pub fn is_finished(&self) -> bool {
+        self.iteration == self.iterations()
+    }
+
+    /// Run up to `iterations` more iterations of the main loop.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn advance(&mut self, scratchpad: &mut [u8], iterations: usize) {
+        let scratchpad = CryptoNight::scratchpad_for(scratchpad, self.tweaks);
+
+        let iterations = iterations.min(self.iterations() - self.iteration);
+        CryptoNight::main_loop_partial(&mut self.loop_state, scratchpad, iterations, self.tweaks);
+        self.iteration += iterations;
+    }
+
+    /// Complete the hash, running any remaining iterations first.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn finalize(mut self, scratchpad: &mut [u8]) -> GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize> {
+        self.advance(scratchpad, self.iterations());
+
+        let scratchpad = CryptoNight::scratchpad_for(scratchpad, self.tweaks);
+        let state = &mut self.state.0;
+        CryptoNight::finalize_scratchpad(state, scratchpad);
+        let result = CryptoNight::finish(state);
+
+        CryptoNight::wipe(scratchpad);
+        CryptoNight::wipe(state);
+        result
+    }
+
+    /// Serialize the checkpoint, together with its scratchpad.
+    pub fn write_to<W: Write>(&self, scratchpad: &[u8], mut writer: W) -> io::Result<()> {
+        let scratchpad = &scratchpad[..self.tweaks.scratchpad_size()];
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(self.iteration as u64).to_le_bytes())?;
+        writer.write_all(&[variant_id(self.tweaks.variant)])?;
+        writer.write_all(&self.tweaks.tweak1_2.to_le_bytes())?;
+        writer.write_all(&self.tweaks.height.to_le_bytes())?;
+        match self.tweaks.custom {
+            Some(custom) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&(custom.scratchpad_size() as u64).to_le_bytes())?;
+                writer.write_all(&(custom.iterations() as u64).to_le_bytes())?;
+                writer.write_all(&(custom.address_mask() as u64).to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        writer.write_all(&self.state.0)?;
+        writer.write_all(&self.loop_state.a)?;
+        writer.write_all(&self.loop_state.b)?;
+        writer.write_all(&self.loop_state.b1)?;
+        writer.write_all(&self.loop_state.division_result.to_le_bytes())?;
+        writer.write_all(&self.loop_state.sqrt_result.to_le_bytes())?;
+        for register in &self.loop_state.random_math {
+            writer.write_all(&register.to_le_bytes())?;
+        }
+        for accumulator in &self.loop_state.conceal {
+            writer.write_all(&accumulator.to_le_bytes())?;
+        }
+        writer.write_all(&(scratchpad.len() as u64).to_le_bytes())?;
+        writer.write_all(scratchpad)
+    }
+
+    /// Read a checkpoint written by `write_to`, restoring its scratchpad.
+    ///
+    /// Checkpoints written by earlier versions of this crate, which only
+    /// supported variant 0, can be read as well.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn read_from<R: Read>(mut reader: R, scratchpad: &mut [u8]) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] == 0 || header[4] > VERSION {
+            return Err(invalid_data("not a supported checkpoint"));
+        }
+
+        let iteration = read_u64(&mut reader)?;
+
+        // Version 1 predates support for other variants.
+        let tweaks = if header[4] == 1 {
+            Tweaks::plain(Variant::V0)
+        } else {
+            let mut id = [0u8];
+            reader.read_exact(&mut id)?;
+            let variant = variant_from_id(id[0]).ok_or_else(|| invalid_data("unknown checkpoint variant"))?;
+
+            let tweak1_2 = read_u64(&mut reader)?;
+
+            // The block height was added with CryptoNight-R.
+            let height = if header[4] >= 4 { read_u64(&mut reader)? } else { 0 };
+
+            let custom = if header[4] >= 6 { read_custom(&mut reader, variant)? } else { None };
+
+            Tweaks { variant, tweak1_2, height, custom }
+        };
+
+        if iteration > tweaks.iterations() as u64 {
+            return Err(invalid_data("checkpoint iteration out of range"));
+        }
+
+        let mut state = A16([0u8; 200]);
+        let mut loop_state = LoopState::default();
+        reader.read_exact(&mut state.0)?;
+        reader.read_exact(&mut loop_state.a)?;
+        reader.read_exact(&mut loop_state.b)?;
+
+        // The remaining loop state was added with variant 2.
+        if header[4] >= 3 {
+            reader.read_exact(&mut loop_state.b1)?;
+            loop_state.division_result = read_u64(&mut reader)?;
+            loop_state.sqrt_result = read_u64(&mut reader)?;
+        }
+
+        if header[4] >= 4 {
+            for register in loop_state.random_math.iter_mut() {
+                let mut buffer = [0u8; 4];
+                reader.read_exact(&mut buffer)?;
+                *register = u32::from_le_bytes(buffer);
+            }
+        }
+
+        // The Conceal accumulators start at zero, so older checkpoints can
+        // leave them out.
+        if header[4] >= 5 {
+            for accumulator in loop_state.conceal.iter_mut() {
+                let mut buffer = [0u8; 4];
+                reader.read_exact(&mut buffer)?;
+                *accumulator = u32::from_le_bytes(buffer);
+            }
+        }
+
+        let scratchpad = CryptoNight::scratchpad_for(scratchpad, tweaks);
+        if read_u64(&mut reader)? != scratchpad.len() as u64 {
+            return Err(invalid_data("checkpoint scratchpad size mismatch"));
+        }
+        reader.read_exact(scratchpad)?;
+
+        Ok(Checkpoint { state, loop_state, iteration: iteration as usize, tweaks })
+    }
+}
+
+/// Identifier of a variant in the serialization format.
+pub(crate) fn variant_id(variant: Variant) -> u8 {
+    match variant {
+        Variant::V0 => 0,
+        Variant::V1 => 1,
+        Variant::V2 => 2,
+        Variant::R => 4,
+        // Other families of variants are numbered from 0x10 onwards.
+        Variant::Lite => 0x10,
+        Variant::Pico => 0x20,
+        Variant::Fast => 0x30,
+        Variant::Zls => 0x41,
+        Variant::Xao => 0x50,
+        Variant::Rto => 0x31,
+        Variant::Upx2 => 0x43,
+        Variant::Conceal => 0x51,
+        Variant::SoftShell => 0x70,
+        Variant::Wow => 0x80,
+    }
+}
+
+pub(crate) fn variant_from_id(id: u8) -> Option<Variant> {
+    match id {
+        0 => Some(Variant::V0),
+        1 => Some(Variant::V1),
+        2 => Some(Variant::V2),
+        4 => Some(Variant::R),
+        0x10 => Some(Variant::Lite),
+        0x20 => Some(Variant::Pico),
+        0x30 => Some(Variant::Fast),
+        0x41 => Some(Variant::Zls),
+        0x50 => Some(Variant::Xao),
+        0x31 => Some(Variant::Rto),
+        0x43 => Some(Variant::Upx2),
+        0x51 => Some(Variant::Conceal),
+        0x70 => Some(Variant::SoftShell),
+        0x80 => Some(Variant::Wow),
+        _ => None,
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// Read the parameters of a custom variant, validating them again.
+fn read_custom<R: Read>(reader: &mut R, base: Variant) -> io::Result<Option<CustomVariant>> {
+    let mut flag = [0u8];
+    reader.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        1 => {
+            let scratchpad_size = read_u64(reader)? as usize;
+            let iterations = read_u64(reader)? as usize;
+            let address_mask = read_u64(reader)? as usize;
+
+            CustomVariant::builder(base)
+                .scratchpad_size(scratchpad_size)
+                .iterations(iterations)
+                .address_mask(address_mask)
+                .build()
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+        _ => Err(invalid_data("invalid custom variant flag")),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CryptoNightSoftShell, Digest};
+
+    use super::*;
+
+    #[test]
+    fn test_resume_after_serialization() {
+        let mut scratchpad = CryptoNight::allocate_scratchpad();
+        let expected = CryptoNight::digest_with_buffer(b"checkpoint", scratchpad.as_mut());
+
+        let mut hasher = CryptoNight::default();
+        crate::Input::input(&mut hasher, b"checkpoint");
+        let mut checkpoint = hasher.start_resumable(scratchpad.as_mut());
+        checkpoint.advance(scratchpad.as_mut(), 1000);
+
+        let mut serialized = Vec::new();
+        checkpoint.write_to(scratchpad.as_mut(), &mut serialized).unwrap();
+
+        let mut other_scratchpad = CryptoNight::allocate_scratchpad();
+        let resumed = Checkpoint::read_from(&serialized[..], other_scratchpad.as_mut()).unwrap();
+        assert_eq!(resumed.iteration(), 1000);
+        assert_eq!(resumed.finalize(other_scratchpad.as_mut()), expected);
+    }
+
+    #[test]
+    fn test_resume_variants() {
+        let input = [7u8; 76];
+        let mut scratchpad = CryptoNight::allocate_scratchpad();
+
+        let hashers = [
+            CryptoNight::with_variant(Variant::V1),
+            CryptoNight::with_variant(Variant::V2),
+            CryptoNight::with_height(1_806_260),
+            CryptoNight::with_variant(Variant::Lite),
+            CryptoNight::with_variant(Variant::Pico),
+            CryptoNight::with_variant(Variant::Conceal),
+            CryptoNightSoftShell::with_height(1000).into_inner(),
+            CryptoNight { height: 1_806_260, ..CryptoNight::with_variant(Variant::Wow) },
+            CryptoNight::with_custom(CustomVariant::builder(Variant::V2).scratchpad_size(1 << 16).build().unwrap()),
+        ];
+
+        for hasher in hashers.iter() {
+            let variant = hasher.variant();
+            let expected = hasher.clone().chain(&input[..]).fixed_result_with_buffer(scratchpad.as_mut());
+
+            let mut checkpoint = hasher.clone().chain(&input[..]).start_resumable(scratchpad.as_mut());
+            checkpoint.advance(scratchpad.as_mut(), 1000);
+
+            let mut serialized = Vec::new();
+            checkpoint.write_to(scratchpad.as_mut(), &mut serialized).unwrap();
+
+            let resumed = Checkpoint::read_from(&serialized[..], scratchpad.as_mut()).unwrap();
+            assert_eq!(resumed.finalize(scratchpad.as_mut()), expected, "{}", variant);
+        }
+    }
+
+    #[test]
+    fn test_reject_garbage() {
+        let mut scratchpad = CryptoNight::allocate_scratchpad();
+
+        let error = Checkpoint::read_from(&b"garbage"[..], scratchpad.as_mut()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
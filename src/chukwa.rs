@@ -0,0 +1,157 @@
+//! Chukwa, the Argon2id based proof of work that replaced CryptoNight on TurtleCoin.
+use std::fmt;
+use std::io::{self, Write};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+
+use crate::{FixedOutput, Input, Reset};
+
+/// Number of input bytes used as the salt.
+const SALT_LEN: usize = 16;
+
+/// A version of Chukwa.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ChukwaVersion {
+    /// The first version, with 512 KB of memory and 3 iterations.
+    V1,
+    /// The second version, with 1 MB of memory and 4 iterations.
+    V2,
+}
+
+impl ChukwaVersion {
+    /// Memory used by Argon2id, in KiB.
+    pub fn memory(self) -> u32 {
+        match self {
+            ChukwaVersion::V1 => 512,
+            ChukwaVersion::V2 => 1024,
+        }
+    }
+
+    /// Number of passes of Argon2id over the memory.
+    pub fn iterations(self) -> u32 {
+        match self {
+            ChukwaVersion::V1 => 3,
+            ChukwaVersion::V2 => 4,
+        }
+    }
+}
+
+impl fmt::Display for ChukwaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChukwaVersion::V1 => f.write_str("chukwa"),
+            ChukwaVersion::V2 => f.write_str("chukwa/v2"),
+        }
+    }
+}
+
+/// Chukwa hasher.
+///
+/// Chukwa runs Argon2id on the input, with its first 16 bytes as the salt,
+/// so inputs must be at least that long. Block hashing blobs always are.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{Chukwa, ChukwaVersion, Digest};
+/// let input = [0u8; 76];
+///
+/// let v1 = Chukwa::digest(&input[..]);
+/// let v2 = Chukwa::with_version(ChukwaVersion::V2).chain(&input[..]).result();
+///
+/// assert_ne!(v1, v2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Chukwa {
+    version: ChukwaVersion,
+    data: Vec<u8>,
+}
+
+impl Chukwa {
+    /// Create a hasher for the given version.
+    pub fn with_version(version: ChukwaVersion) -> Self {
+        Chukwa { version, data: Vec::new() }
+    }
+
+    /// The version this hasher computes.
+    pub fn version(&self) -> ChukwaVersion {
+        self.version
+    }
+}
+
+impl Default for Chukwa {
+    fn default() -> Self {
+        Self::with_version(ChukwaVersion::V1)
+    }
+}
+
+impl Input for Chukwa {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.data.extend_from_slice(data.as_ref());
+    }
+}
+
+/// Writing to the hasher inputs the data. This never fails.
+impl Write for Chukwa {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Input::input(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Reset for Chukwa {
+    fn reset(&mut self) {
+        self.data.clear();
+    }
+}
+
+impl FixedOutput for Chukwa {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
+        assert!(self.data.len() >= SALT_LEN, "{} requires at least {} bytes of input", self.version, SALT_LEN);
+
+        let params = Params::new(self.version.memory(), self.version.iterations(), 1, Some(32))
+            .expect("Chukwa parameters are valid");
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut result = GenericArray::default();
+        argon2.hash_password_into(&self.data, &self.data[..SALT_LEN], &mut result)
+            .expect("Chukwa inputs are valid");
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn digest(version: ChukwaVersion, input: &[u8]) -> GenericArray<u8, U32> {
+        let mut hasher = Chukwa::with_version(version);
+        Input::input(&mut hasher, input);
+        hasher.fixed_result()
+    }
+
+    #[test]
+    fn test_vectors() {
+        // From the TurtleCoin test suite.
+        let input = hex!("0100fb8e8ac805899323371bb790db19218afd8db8e3755d8b90f39b3d5506a9abce4fa912244500000000ee8146d49fa93ee724deb57d12cbc6c6f3b924d946127c7a97418f9348828f0f02");
+
+        assert_eq!(digest(ChukwaVersion::V1, &input)[..], hex!("c0dad0eeb9c52e92a1c3aa5b76a3cb90bd7376c28dce191ceeb1096e3a390d2e")[..]);
+        assert_eq!(digest(ChukwaVersion::V2, &input)[..], hex!("3578c135261366a7bac407b8c0ff50f3ad96f096ec2813e9644e6e77a43f803d")[..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 16 bytes")]
+    fn test_short_input() {
+        digest(ChukwaVersion::V1, &[0u8; 15]);
+    }
+}
@@ -0,0 +1,218 @@
+//! The digest as a value, with its usual hex encoding.
+use std::fmt;
+use std::hint::black_box;
+use std::str::FromStr;
+
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+#[cfg(feature = "subtle")]
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::hex_input::digit_value;
+use crate::HexError;
+
+/// A 32 byte CryptoNight digest.
+///
+/// Digests are displayed and parsed as 64 hex digits, as in block explorers
+/// and pool interfaces. Parsing accepts both upper and lower case digits.
+///
+/// Comparing with `==` stops at the first differing byte. Use
+/// `CnHash::verify` to check untrusted hashes in constant time.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CnHash, CryptoNight, Digest};
+/// let hash = CnHash::from(CryptoNight::digest(b"This is a test"));
+/// let encoded = hash.to_string();
+///
+/// assert_eq!(encoded.len(), 64);
+/// assert_eq!(encoded.parse(), Ok(hash));
+/// ```
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CnHash(pub [u8; 32]);
+
+impl CnHash {
+    /// The bytes of the digest.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Check whether `expected` equals this digest, in constant time.
+    ///
+    /// All bytes are compared, whatever their contents, so the time taken
+    /// does not reveal how much of a submitted hash is correct. Only the
+    /// length of `expected` is not kept secret.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CnHash, CryptoNight, Digest};
+    /// let submitted = [0u8; 32];
+    /// let hash = CnHash::from(CryptoNight::digest(b"Block hashing blob"));
+    ///
+    /// assert!(!hash.verify(submitted));
+    /// assert!(hash.verify(hash));
+    /// ```
+    pub fn verify<B: AsRef<[u8]>>(&self, expected: B) -> bool {
+        let expected = expected.as_ref();
+        if expected.len() != self.0.len() {
+            return false;
+        }
+
+        // Keep the compiler from exiting the loop once a difference is found.
+        let difference = self.0.iter().zip(expected)
+            .fold(0u8, |difference, (a, b)| black_box(difference | (a ^ b)));
+        difference == 0
+    }
+}
+
+impl fmt::LowerHex for CnHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::UpperHex for CnHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays the digest as lower case hex digits.
+impl fmt::Display for CnHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::Debug for CnHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CnHash({:x})", self)
+    }
+}
+
+impl FromStr for CnHash {
+    type Err = HexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((index, character)) = s.char_indices().find(|&(_, c)| !c.is_ascii_hexdigit()) {
+            return Err(HexError::InvalidCharacter { character, index });
+        }
+
+        if s.len() != 64 {
+            return Err(HexError::InvalidLength(s.len()));
+        }
+
+        let mut hash = CnHash::default();
+        for (byte, digits) in hash.0.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+            // Digits have been validated above.
+            *byte = digit_value(digits[0]).unwrap() << 4 | digit_value(digits[1]).unwrap();
+        }
+
+        Ok(hash)
+    }
+}
+
+#[cfg(feature = "subtle")]
+impl ConstantTimeEq for CnHash {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0[..].ct_eq(&other.0[..])
+    }
+}
+
+impl AsRef<[u8; 32]> for CnHash {
+    fn as_ref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for CnHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for CnHash {
+    fn from(bytes: [u8; 32]) -> Self {
+        CnHash(bytes)
+    }
+}
+
+impl From<CnHash> for [u8; 32] {
+    fn from(hash: CnHash) -> Self {
+        hash.0
+    }
+}
+
+impl From<GenericArray<u8, U32>> for CnHash {
+    fn from(digest: GenericArray<u8, U32>) -> Self {
+        let mut hash = CnHash::default();
+        hash.0.copy_from_slice(&digest);
+        hash
+    }
+}
+
+impl From<CnHash> for GenericArray<u8, U32> {
+    fn from(hash: CnHash) -> Self {
+        GenericArray::clone_from_slice(&hash.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        let hash = CnHash(std::array::from_fn(|i| i as u8 * 8));
+        let lower = "0008101820283038404850586068707880889098a0a8b0b8c0c8d0d8e0e8f0f8";
+
+        assert_eq!(hash.to_string(), lower);
+        assert_eq!(format!("{:X}", hash), lower.to_uppercase());
+        assert_eq!(lower.parse(), Ok(hash));
+        assert_eq!(lower.to_uppercase().parse(), Ok(hash));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("00".parse::<CnHash>(), Err(HexError::InvalidLength(2)));
+        assert_eq!("0g".parse::<CnHash>(), Err(HexError::InvalidCharacter { character: 'g', index: 1 }));
+    }
+
+    #[test]
+    fn test_verify() {
+        let hash = CnHash([7; 32]);
+        let mut other = hash;
+        other.0[31] = 0;
+
+        assert!(hash.verify(hash));
+        assert!(hash.verify([7; 32]));
+        assert!(!hash.verify(other));
+        assert!(!hash.verify(&hash.0[..31]));
+    }
+
+    #[test]
+    #[cfg(feature = "subtle")]
+    fn test_constant_time_eq() {
+        let hash = CnHash([7; 32]);
+
+        assert!(bool::from(hash.ct_eq(&hash)));
+        assert!(!bool::from(hash.ct_eq(&CnHash::default())));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let hash = CnHash([7; 32]);
+        let digest: GenericArray<u8, U32> = hash.into();
+
+        assert_eq!(CnHash::from(digest), hash);
+        assert_eq!(<[u8; 32]>::from(hash), [7; 32]);
+    }
+}
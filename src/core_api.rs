@@ -0,0 +1,182 @@
+//! Low-level access to the CryptoNight computation.
+use digest::generic_array::GenericArray;
+
+use crate::{A16, CryptoNight, FixedOutput, KeccakState, scratchpad, ScratchpadSource, telemetry, Tweaks, Variant};
+
+/// The memory-hard part of CryptoNight, starting from an absorbed input.
+///
+/// `CryptoNight` absorbs its input into a `KeccakState` and pads it before
+/// handing the resulting state to this core. Building the state yourself
+/// allows other front-ends, such as inputs that were absorbed elsewhere or
+/// a different padding, without reimplementing the rest of the hash.
+///
+/// A core created from a Keccak state computes variant 0. Cores for other
+/// variants can be obtained through `CryptoNight::into_core`.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, CryptoNightCore, Digest, KeccakState};
+/// let mut keccak = KeccakState::new();
+/// keccak.absorb(b"Your data");
+/// keccak.pad();
+///
+/// let result = CryptoNightCore::new(&keccak).finalize();
+/// assert_eq!(result, CryptoNight::digest(b"Your data"));
+/// ```
+#[derive(Clone)]
+pub struct CryptoNightCore {
+    state: A16<[u8; 200]>,
+    tweaks: Tweaks,
+}
+
+impl CryptoNightCore {
+    /// Start from the state of a sponge that absorbed and padded the input.
+    ///
+    /// Any input absorbed after the last complete block is ignored, so the
+    /// sponge should be padded first.
+    pub fn new(keccak: &KeccakState) -> Self {
+        Self::from_state(keccak.to_bytes())
+    }
+
+    /// Start from a raw Keccak state.
+    pub fn from_state(state: [u8; 200]) -> Self {
+        Self::with_tweaks(state, Tweaks::plain(Variant::V0))
+    }
+
+    pub(crate) fn with_tweaks(state: [u8; 200], tweaks: Tweaks) -> Self {
+        CryptoNightCore { state: A16(state), tweaks }
+    }
+
+    /// Split the core into its state and tweaks.
+    pub(crate) fn into_parts(self) -> (A16<[u8; 200]>, Tweaks) {
+        (self.state, self.tweaks)
+    }
+
+    /// The Keccak state the computation starts from.
+    pub fn state(&self) -> &[u8; 200] {
+        &self.state.0
+    }
+
+    /// The variant this core computes.
+    pub fn variant(&self) -> Variant {
+        self.tweaks.variant
+    }
+
+    /// Compute the digest with a provided buffer.
+    ///
+    /// The same requirements as for `CryptoNight::fixed_result_with_buffer`
+    /// apply to the scratchpad.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn finalize_with_buffer(self, scratchpad: &mut [u8]) -> GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize> {
+        self.compute(scratchpad, ScratchpadSource::Buffer)
+    }
+
+    /// Compute the digest, temporarily allocating a scratchpad.
+    ///
+    /// With the `thread-local` feature, a scratchpad kept per thread is used
+    /// instead, as for `Digest::result`.
+    pub fn finalize(self) -> GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize> {
+        scratchpad::with_temporary(self.tweaks.scratchpad_size(), |scratchpad, source| self.compute(scratchpad, source))
+    }
+
+    pub(crate) fn compute(mut self, scratchpad: &mut [u8], source: ScratchpadSource) -> GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize> {
+        // Ensure that our alignment and size requirements are met.
+        let scratchpad = CryptoNight::digest_buffer_for(scratchpad, self.tweaks);
+
+        let tweaks = self.tweaks;
+        let result = telemetry::observe(CryptoNight::backend_name(), tweaks.variant, source, || {
+            let keccac = &mut self.state.0;
+
+            CryptoNight::digest_main(keccac, scratchpad, tweaks);
+
+            CryptoNight::finish(keccac)
+        });
+
+        CryptoNight::wipe(scratchpad);
+        CryptoNight::wipe(&mut self.state.0);
+        result
+    }
+
+    /// Compute the digests of two cores at once, see `CryptoNight::fixed_result2_with_buffers`.
+    pub(crate) fn compute2(mut self, mut other: Self, first_scratchpad: &mut [u8], second_scratchpad: &mut [u8], source: ScratchpadSource)
+        -> (GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize>, GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize>) {
+        let first_scratchpad = CryptoNight::digest_buffer_for(first_scratchpad, self.tweaks);
+        let second_scratchpad = CryptoNight::digest_buffer_for(second_scratchpad, other.tweaks);
+
+        let (first_tweaks, second_tweaks) = (self.tweaks, other.tweaks);
+        let variants = [first_tweaks.variant, second_tweaks.variant];
+        let result = telemetry::observe_all(CryptoNight::backend_name(), &variants, source, || {
+            let first = &mut self.state.0;
+            let second = &mut other.state.0;
+
+            CryptoNight::digest_main2(first, first_scratchpad, first_tweaks, second, second_scratchpad, second_tweaks);
+
+            let [first, second] = CryptoNight::finish_all([first, second]);
+            (first, second)
+        });
+
+        for buffer in [first_scratchpad, second_scratchpad, &mut self.state.0, &mut other.state.0] {
+            CryptoNight::wipe(buffer);
+        }
+        result
+    }
+
+    /// Compute the digests of four cores at once, see `CryptoNight::fixed_result4_with_buffers`.
+    pub(crate) fn compute4(cores: [Self; 4], scratchpads: [&mut [u8]; 4], source: ScratchpadSource)
+        -> [GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize>; 4] {
+        let [mut c0, mut c1, mut c2, mut c3] = cores;
+        let tweaks = [c0.tweaks, c1.tweaks, c2.tweaks, c3.tweaks];
+        let [s0, s1, s2, s3] = scratchpads;
+        let mut scratchpads = [
+            CryptoNight::digest_buffer_for(s0, tweaks[0]),
+            CryptoNight::digest_buffer_for(s1, tweaks[1]),
+            CryptoNight::digest_buffer_for(s2, tweaks[2]),
+            CryptoNight::digest_buffer_for(s3, tweaks[3]),
+        ];
+
+        let variants = [tweaks[0].variant, tweaks[1].variant, tweaks[2].variant, tweaks[3].variant];
+        let result = telemetry::observe_all(CryptoNight::backend_name(), &variants, source, || {
+            let keccacs = [&mut c0.state.0[..], &mut c1.state.0[..], &mut c2.state.0[..], &mut c3.state.0[..]];
+            CryptoNight::digest_main4(keccacs, scratchpads.each_mut().map(|s| &mut **s), tweaks);
+
+            CryptoNight::finish_all([&mut c0.state.0, &mut c1.state.0, &mut c2.state.0, &mut c3.state.0])
+        });
+
+        let states = [&mut c0.state.0[..], &mut c1.state.0[..], &mut c2.state.0[..], &mut c3.state.0[..]];
+        for buffer in IntoIterator::into_iter(scratchpads).chain(states) {
+            CryptoNight::wipe(buffer);
+        }
+        result
+    }
+
+    /// Compute the digests of `N` cores at once, see `CryptoNight::fixed_result_n_with_buffers`.
+    pub(crate) fn compute_n<const N: usize>(cores: [Self; N], scratchpads: [&mut [u8]; N], source: ScratchpadSource)
+        -> [GenericArray<u8, <CryptoNight as FixedOutput>::OutputSize>; N] {
+        let tweaks = cores.each_ref().map(|core| core.tweaks);
+        let mut states = cores.map(|core| core.state);
+        let mut tweaks_iter = tweaks.iter();
+        let mut scratchpads = scratchpads.map(|scratchpad| CryptoNight::digest_buffer_for(scratchpad, *tweaks_iter.next().unwrap()));
+
+        let variants = tweaks.map(|t| t.variant);
+        let result = telemetry::observe_all(CryptoNight::backend_name(), &variants, source, || {
+            CryptoNight::digest_main_n(states.each_mut().map(|state| &mut state.0[..]), scratchpads.each_mut().map(|s| &mut **s), tweaks);
+
+            CryptoNight::finish_all(states.each_mut().map(|state| &mut state.0))
+        });
+
+        let states = states.each_mut().map(|state| &mut state.0[..]);
+        for buffer in IntoIterator::into_iter(scratchpads).chain(states) {
+            CryptoNight::wipe(buffer);
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for CryptoNightCore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CryptoNightCore").finish()
+    }
+}
@@ -0,0 +1,226 @@
+//! Variants with parameters chosen at runtime.
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{CryptoNight, Variant};
+
+/// Largest number of iterations a custom variant may run.
+const MAX_ITERATIONS: usize = 1 << 24;
+
+/// A variant with custom parameters, for chains running CryptoNight with
+/// bespoke sizes.
+///
+/// The main loop is the one of the base variant, which selects the tweaks:
+/// `Variant::V0` for none, `Variant::V1` for the variant 1 tweak, or
+/// `Variant::V2` for the variant 2 math and shuffle. Use
+/// `CustomVariant::builder` to create one.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, CustomVariant, Digest, Variant};
+/// let custom = CustomVariant::builder(Variant::V2)
+///     .scratchpad_size(512 * 1024)
+///     .iterations(1 << 17)
+///     .build()
+///     .unwrap();
+///
+/// let result = CryptoNight::with_custom(custom).chain(b"Your data").result();
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CustomVariant {
+    base: Variant,
+    scratchpad_size: usize,
+    iterations: usize,
+    address_mask: usize,
+}
+
+impl CustomVariant {
+    /// Start building a variant on the given base, with its parameters.
+    pub fn builder(base: Variant) -> CustomVariantBuilder {
+        CustomVariantBuilder {
+            base,
+            scratchpad_size: base.scratchpad_size(),
+            iterations: base.iterations(),
+            address_mask: None,
+        }
+    }
+
+    /// The variant whose main loop this variant runs.
+    pub fn base(&self) -> Variant {
+        self.base
+    }
+
+    /// Size of the scratchpad in bytes.
+    pub fn scratchpad_size(&self) -> usize {
+        self.scratchpad_size
+    }
+
+    /// Number of iterations of the main loop.
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Mask applied to obtain a byte offset into the scratchpad.
+    pub fn address_mask(&self) -> usize {
+        self.address_mask
+    }
+}
+
+/// The parameters are validated again when deserialized.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for CustomVariant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "CustomVariant")]
+        struct Fields {
+            base: Variant,
+            scratchpad_size: usize,
+            iterations: usize,
+            address_mask: usize,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        CustomVariant::builder(fields.base)
+            .scratchpad_size(fields.scratchpad_size)
+            .iterations(fields.iterations)
+            .address_mask(fields.address_mask)
+            .build()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Builder for a `CustomVariant`.
+///
+/// Parameters that are not set are taken from the base variant, except the
+/// address mask, which covers the whole scratchpad by default.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CustomVariantBuilder {
+    base: Variant,
+    scratchpad_size: usize,
+    iterations: usize,
+    address_mask: Option<usize>,
+}
+
+impl CustomVariantBuilder {
+    /// Set the size of the scratchpad in bytes.
+    ///
+    /// It must be a multiple of 128 bytes, and at most `CryptoNight::SP_SIZE`.
+    pub fn scratchpad_size(mut self, scratchpad_size: usize) -> Self {
+        self.scratchpad_size = scratchpad_size;
+        self
+    }
+
+    /// Set the number of iterations of the main loop, at least 1 and at most 2^24.
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Set the address mask.
+    ///
+    /// Its lowest 4 bits must be clear, and it must be smaller than the
+    /// scratchpad size.
+    pub fn address_mask(mut self, address_mask: usize) -> Self {
+        self.address_mask = Some(address_mask);
+        self
+    }
+
+    /// Validate the parameters.
+    pub fn build(self) -> Result<CustomVariant, CustomVariantError> {
+        if !matches!(self.base, Variant::V0 | Variant::V1 | Variant::V2) {
+            return Err(CustomVariantError::Base(self.base));
+        }
+
+        let size = self.scratchpad_size;
+        if size == 0 || size & 127 != 0 || size > CryptoNight::SP_SIZE {
+            return Err(CustomVariantError::ScratchpadSize(size));
+        }
+
+        if self.iterations == 0 || self.iterations > MAX_ITERATIONS {
+            return Err(CustomVariantError::Iterations(self.iterations));
+        }
+
+        let address_mask = self.address_mask.unwrap_or(size - 16);
+        if address_mask & 15 != 0 || address_mask >= size {
+            return Err(CustomVariantError::AddressMask(address_mask));
+        }
+
+        Ok(CustomVariant {
+            base: self.base,
+            scratchpad_size: size,
+            iterations: self.iterations,
+            address_mask,
+        })
+    }
+}
+
+/// Errors that can occur when building a `CustomVariant`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CustomVariantError {
+    /// The base variant is not one of `V0`, `V1` or `V2`.
+    Base(Variant),
+    /// The scratchpad size is zero, not a multiple of 128 or too large.
+    ScratchpadSize(usize),
+    /// The number of iterations is zero or too large.
+    Iterations(usize),
+    /// The address mask is misaligned or exceeds the scratchpad.
+    AddressMask(usize),
+}
+
+impl fmt::Display for CustomVariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CustomVariantError::Base(base) => write!(f, "{} cannot be the base of a custom variant", base),
+            CustomVariantError::ScratchpadSize(size) => write!(f, "invalid scratchpad size {}", size),
+            CustomVariantError::Iterations(iterations) => write!(f, "invalid number of iterations {}", iterations),
+            CustomVariantError::AddressMask(mask) => write!(f, "invalid address mask {:#x}", mask),
+        }
+    }
+}
+
+impl Error for CustomVariantError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_from_base() {
+        let custom = CustomVariant::builder(Variant::V1).build().unwrap();
+
+        assert_eq!(custom.scratchpad_size(), Variant::V1.scratchpad_size());
+        assert_eq!(custom.iterations(), Variant::V1.iterations());
+        assert_eq!(custom.address_mask(), Variant::V1.address_mask());
+    }
+
+    #[test]
+    fn test_validation() {
+        let builder = CustomVariant::builder(Variant::V2);
+
+        assert_eq!(CustomVariant::builder(Variant::R).build(), Err(CustomVariantError::Base(Variant::R)));
+        assert_eq!(builder.scratchpad_size(1000).build(), Err(CustomVariantError::ScratchpadSize(1000)));
+        assert_eq!(builder.scratchpad_size(1 << 22).build(), Err(CustomVariantError::ScratchpadSize(1 << 22)));
+        assert_eq!(builder.iterations(0).build(), Err(CustomVariantError::Iterations(0)));
+        assert_eq!(builder.address_mask(0x1008).build(), Err(CustomVariantError::AddressMask(0x1008)));
+        assert_eq!(
+            builder.scratchpad_size(1 << 16).address_mask(1 << 16).build(),
+            Err(CustomVariantError::AddressMask(1 << 16))
+        );
+        assert!(builder.scratchpad_size(1 << 16).address_mask(0x7ff0).build().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_validates() {
+        let custom = CustomVariant::builder(Variant::V2).scratchpad_size(1 << 16).build().unwrap();
+        let saved = serde_json::to_string(&custom).unwrap();
+        assert_eq!(serde_json::from_str::<CustomVariant>(&saved).unwrap(), custom);
+
+        let invalid = saved.replace("65536", "1000");
+        assert!(serde_json::from_str::<CustomVariant>(&invalid).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Object-safe hashers, for selecting the algorithm at runtime.
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+
+use crate::{CryptoNight, FixedOutput, Input, ParseVariantError, Reset, Variant};
+
+/// An object-safe interface to the hashers of this crate.
+///
+/// Like `digest::DynDigest`, this allows holding a `Box<dyn DynCryptoNight>`
+/// whose algorithm is only known at runtime, for example from a
+/// configuration file, without making the surrounding code generic. It is
+/// implemented for every hasher with a 32 byte digest, including
+/// `CryptoNight`, `CryptoNightOf` and `CryptoNightSoftShell`.
+///
+/// As with `DynDigest`, calling `input` or `result` on a hasher that is not
+/// boxed is ambiguous when `Digest` is also in scope.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{cryptonight, dyn_hasher, Variant};
+/// let mut hasher = dyn_hasher("cn/2").unwrap();
+/// hasher.input(b"This is ");
+/// hasher.input(b"a test");
+///
+/// let result = hasher.result();
+/// assert_eq!(result[..], cryptonight(b"This is a test", Variant::V2, 0)[..]);
+/// ```
+pub trait DynCryptoNight: Send {
+    /// Input more data.
+    fn input(&mut self, data: &[u8]);
+
+    /// Compute the digest, consuming the hasher.
+    ///
+    /// This will temporarily allocate a scratchpad, like `Digest::result`.
+    fn result(self: Box<Self>) -> GenericArray<u8, U32>;
+
+    /// Compute the digest and reset the hasher, so it can be reused.
+    fn result_reset(&mut self) -> GenericArray<u8, U32>;
+
+    /// Reset the hasher to its initial state.
+    fn reset(&mut self);
+
+    /// Clone the hasher into a new box.
+    fn box_clone(&self) -> Box<dyn DynCryptoNight>;
+}
+
+impl<D> DynCryptoNight for D
+    where D: Input + FixedOutput<OutputSize=U32> + Reset + Clone + Send + 'static {
+    fn input(&mut self, data: &[u8]) {
+        Input::input(self, data);
+    }
+
+    fn result(self: Box<Self>) -> GenericArray<u8, U32> {
+        (*self).fixed_result()
+    }
+
+    fn result_reset(&mut self) -> GenericArray<u8, U32> {
+        let result = self.clone().fixed_result();
+        Reset::reset(self);
+        result
+    }
+
+    fn reset(&mut self) {
+        Reset::reset(self);
+    }
+
+    fn box_clone(&self) -> Box<dyn DynCryptoNight> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DynCryptoNight> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Create a boxed hasher for the variant with the given name.
+///
+/// Names are matched like `Variant::from_str`. CryptoNight-R and
+/// CryptoNight-WOW hashers use block height 0; box a hasher from
+/// `CryptoNight::with_height` to select another height.
+pub fn dyn_hasher(name: &str) -> Result<Box<dyn DynCryptoNight>, ParseVariantError> {
+    let variant: Variant = name.parse()?;
+
+    Ok(Box::new(CryptoNight::with_variant(variant)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cryptonight, CryptoNightOf, Digest};
+    use crate::params::Lite;
+
+    use super::*;
+
+    #[test]
+    fn test_hashers_agree() {
+        let hashers: Vec<Box<dyn DynCryptoNight>> = vec![
+            dyn_hasher("cn-lite/0").unwrap(),
+            Box::new(CryptoNightOf::<Lite>::new()),
+        ];
+        let expected = GenericArray::clone_from_slice(&cryptonight(b"This is a test", Variant::Lite, 0));
+
+        for mut hasher in hashers {
+            hasher.input(b"This is a test");
+            assert_eq!(hasher.box_clone().result(), expected);
+            assert_eq!(hasher.result_reset(), expected);
+
+            hasher.input(b"This is a test");
+            assert_eq!(hasher.result(), expected);
+        }
+    }
+
+    #[test]
+    fn test_unknown_name() {
+        assert_eq!(dyn_hasher("cn/unknown").err().unwrap().name(), "cn/unknown");
+    }
+}
@@ -0,0 +1,56 @@
+//! Hashing files through memory mapping.
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use digest::generic_array::GenericArray;
+use memmap::Mmap;
+
+use crate::{CryptoNight, Digest, FixedOutput, Input};
+
+/// Amount of data fed to the hasher at once.
+const CHUNK_SIZE: usize = 1 << 20;
+
+impl CryptoNight {
+    /// Compute the digest of a file.
+    ///
+    /// The file is memory mapped rather than read into a buffer, so large
+    /// files can be hashed without holding them in memory. The file must not
+    /// be modified while it is being hashed.
+    ///
+    /// This will temporarily allocate a scratchpad, like `Digest::result`.
+    pub fn digest_file<P: AsRef<Path>>(path: P) -> io::Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>> {
+        let file = File::open(path)?;
+        let mut hasher = Self::new();
+
+        // Empty files cannot be mapped.
+        if file.metadata()?.len() > 0 {
+            let map = unsafe { Mmap::map(&file)? };
+
+            for chunk in map.chunks(CHUNK_SIZE) {
+                Input::input(&mut hasher, chunk);
+            }
+        }
+
+        Ok(hasher.result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_digest_file() {
+        let contents: Vec<u8> = (0..CHUNK_SIZE + 1000).map(|i| i as u8).collect();
+        let path = std::env::temp_dir().join(format!("cryptonight-digest-file-{}", std::process::id()));
+        fs::write(&path, &contents).unwrap();
+
+        let result = CryptoNight::digest_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap(), CryptoNight::digest(&contents));
+    }
+}
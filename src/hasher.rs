@@ -0,0 +1,288 @@
+//! A reusable hasher that owns its scratchpad.
+use std::error::Error;
+use std::fmt;
+
+use crate::{backends, CryptoNight, CustomVariant, Input, MemoryOptions, Scratchpad, set_backend, Variant};
+
+/// A hasher that keeps its scratchpad, to hash one input after the other.
+///
+/// Unlike a `CryptoNight` hasher, which is consumed by `Digest::result`,
+/// this computes any number of digests with the same configuration and
+/// without allocating a scratchpad for each of them.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{cryptonight, CryptoNightHasher, Variant};
+/// let mut hasher = CryptoNightHasher::with_variant(Variant::V2);
+///
+/// for input in [&b"First"[..], b"Other"] {
+///     assert_eq!(hasher.hash(input), cryptonight(input, Variant::V2, 0));
+/// }
+/// ```
+pub struct CryptoNightHasher {
+    /// The configuration, cloned for every input.
+    template: CryptoNight,
+    scratchpad: Scratchpad,
+}
+
+impl CryptoNightHasher {
+    /// Create a hasher with the configuration of `hasher`.
+    ///
+    /// Input already given to `hasher` is hashed as a prefix of every input.
+    /// A scratchpad of the size the configuration needs is allocated.
+    pub fn new(hasher: CryptoNight) -> Self {
+        let scratchpad = Scratchpad::allocate(hasher.scratchpad_size());
+        Self::with_scratchpad(hasher, scratchpad)
+    }
+
+    /// Create a hasher for the given variant.
+    ///
+    /// See `CryptoNight::with_variant`.
+    pub fn with_variant(variant: Variant) -> Self {
+        Self::new(CryptoNight::with_variant(variant))
+    }
+
+    /// Create a hasher that uses the given scratchpad.
+    ///
+    /// This allows the scratchpad to be allocated with `Scratchpad::allocate_with`
+    /// or obtained elsewhere. If it is too small, it is replaced with a
+    /// scratchpad allocated with the same options on the first hash.
+    pub fn with_scratchpad(hasher: CryptoNight, scratchpad: Scratchpad) -> Self {
+        CryptoNightHasher { template: hasher, scratchpad }
+    }
+
+    /// Compute the digest of `data`.
+    pub fn hash<B: AsRef<[u8]>>(&mut self, data: B) -> [u8; 32] {
+        let len = self.template.scratchpad_size();
+        if self.scratchpad.len() < len {
+            self.scratchpad = Scratchpad::allocate_with(len, self.scratchpad.report().requested);
+        }
+
+        let mut hasher = self.template.clone();
+        Input::input(&mut hasher, data);
+
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&hasher.fixed_result_with_buffer(self.scratchpad.as_mut()));
+        digest
+    }
+
+    /// Use the given block height for the following hashes.
+    ///
+    /// This selects the program of CryptoNight-R and CryptoNight-WOW, and the
+    /// scratchpad size of the soft shell variant.
+    pub fn set_height(&mut self, height: u64) {
+        self.template.height = height;
+    }
+
+    /// The configuration of the hasher.
+    pub fn hasher(&self) -> &CryptoNight {
+        &self.template
+    }
+
+    /// The scratchpad, for example to inspect its `AllocationReport`.
+    pub fn scratchpad(&self) -> &Scratchpad {
+        &self.scratchpad
+    }
+
+    /// Keep the scratchpad, dropping the hasher.
+    pub fn into_scratchpad(self) -> Scratchpad {
+        self.scratchpad
+    }
+}
+
+/// Builder for a `CryptoNightHasher`.
+///
+/// This gathers the algorithm, the block height, the backend and how the
+/// scratchpad is obtained in one place, for example to configure a hasher
+/// from a configuration file.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{cryptonight, CryptoNightBuilder, MemoryOptions, Variant};
+/// let mut hasher = CryptoNightBuilder::new()
+///     .variant(Variant::R)
+///     .height(1_806_260)
+///     .backend("auto")
+///     .memory_options(MemoryOptions { huge_pages: true, ..MemoryOptions::default() })
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(hasher.hash(b"Your data"), cryptonight(b"Your data", Variant::R, 1_806_260));
+/// ```
+pub struct CryptoNightBuilder {
+    variant: Variant,
+    custom: Option<CustomVariant>,
+    height: u64,
+    backend: Option<String>,
+    options: MemoryOptions,
+    scratchpad: Option<Scratchpad>,
+}
+
+impl CryptoNightBuilder {
+    /// Start building a hasher for variant 0, with a scratchpad of regular memory.
+    pub fn new() -> Self {
+        CryptoNightBuilder {
+            variant: Variant::V0,
+            custom: None,
+            height: 0,
+            backend: None,
+            options: MemoryOptions::default(),
+            scratchpad: None,
+        }
+    }
+
+    /// Compute the given variant. This replaces any custom variant.
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self.custom = None;
+        self
+    }
+
+    /// Compute a variant with custom parameters.
+    pub fn custom(mut self, custom: CustomVariant) -> Self {
+        self.variant = custom.base();
+        self.custom = Some(custom);
+        self
+    }
+
+    /// Set the block height, used by CryptoNight-R, CryptoNight-WOW and the
+    /// soft shell variant.
+    pub fn height(mut self, height: u64) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Hash with the backend of the given name, as listed by `backends`.
+    ///
+    /// Backends are selected process-wide: building installs the backend
+    /// with `set_backend`, which affects every hasher. The name `auto`
+    /// removes any installed backend, returning to the fastest built-in one
+    /// the CPU supports. Without this, the backend is left as it is.
+    pub fn backend(mut self, name: &str) -> Self {
+        self.backend = Some(name.to_owned());
+        self
+    }
+
+    /// Allocate the scratchpad with the given options, such as huge pages.
+    ///
+    /// See `Scratchpad::allocate_with`.
+    pub fn memory_options(mut self, options: MemoryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Use the given scratchpad rather than allocating one.
+    ///
+    /// This allows scratchpads obtained elsewhere, such as one detached from
+    /// a `ScratchpadPool` or created with `Scratchpad::from_raw_parts`. If it
+    /// is too small, it is replaced on the first hash, see
+    /// `CryptoNightHasher::with_scratchpad`.
+    pub fn scratchpad(mut self, scratchpad: Scratchpad) -> Self {
+        self.scratchpad = Some(scratchpad);
+        self
+    }
+
+    /// Install the backend, if any, and create the hasher.
+    pub fn build(self) -> Result<CryptoNightHasher, UnknownBackendError> {
+        match self.backend.as_deref() {
+            None => (),
+            Some("auto") => {
+                set_backend(None);
+            }
+            Some(name) => {
+                let backend = backends().into_iter()
+                    .find(|backend| backend.name() == name)
+                    .ok_or_else(|| UnknownBackendError { name: name.to_owned() })?;
+                set_backend(Some(backend));
+            }
+        }
+
+        let hasher = CryptoNight {
+            height: self.height,
+            custom: self.custom,
+            ..CryptoNight::with_variant(self.variant)
+        };
+        let scratchpad = match self.scratchpad {
+            Some(scratchpad) => scratchpad,
+            None => Scratchpad::allocate_with(hasher.scratchpad_size(), self.options),
+        };
+
+        Ok(CryptoNightHasher::with_scratchpad(hasher, scratchpad))
+    }
+}
+
+impl Default for CryptoNightBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned when building a hasher with a backend that is not available.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UnknownBackendError {
+    name: String,
+}
+
+impl UnknownBackendError {
+    /// The name that was not recognized.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for UnknownBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown or unavailable backend {:?}", self.name)
+    }
+}
+
+impl Error for UnknownBackendError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cryptonight, CryptoNight, CustomVariant, Digest, Scratchpad, Variant};
+
+    use super::{CryptoNightBuilder, CryptoNightHasher};
+
+    #[test]
+    fn test_repeated_hashes() {
+        let mut hasher = CryptoNightHasher::new(CryptoNight::with_variant(Variant::Lite).chain(b"Prefix "));
+        let scratchpad = hasher.scratchpad().as_ref().as_ptr();
+
+        for input in [&b"First"[..], b"Other", b"First"] {
+            let expected = CryptoNight::with_variant(Variant::Lite).chain(b"Prefix ").chain(input).result();
+            assert_eq!(hasher.hash(input)[..], expected[..]);
+        }
+        assert_eq!(hasher.scratchpad().as_ref().as_ptr(), scratchpad);
+    }
+
+    #[test]
+    fn test_height() {
+        let mut hasher = CryptoNightHasher::new(CryptoNight::with_height(1_806_260));
+        assert_eq!(hasher.hash(b"CryptoNight-R"), cryptonight(b"CryptoNight-R", Variant::R, 1_806_260));
+
+        hasher.set_height(1_806_261);
+        assert_eq!(hasher.hash(b"CryptoNight-R"), cryptonight(b"CryptoNight-R", Variant::R, 1_806_261));
+    }
+
+    #[test]
+    fn test_small_scratchpad_replaced() {
+        let mut hasher = CryptoNightHasher::with_scratchpad(CryptoNight::with_variant(Variant::V0), Scratchpad::allocate(1024));
+
+        assert_eq!(hasher.hash(b"data"), cryptonight(b"data", Variant::V0, 0));
+        assert_eq!(hasher.into_scratchpad().len(), CryptoNight::SP_SIZE);
+    }
+
+    #[test]
+    fn test_builder() {
+        let custom = CustomVariant::builder(Variant::V2).iterations(1 << 10).build().unwrap();
+        let mut hasher = CryptoNightBuilder::new().variant(Variant::Lite).custom(custom).build().unwrap();
+
+        assert_eq!(hasher.hasher().custom(), Some(custom));
+        assert_eq!(hasher.scratchpad().len(), custom.scratchpad_size());
+        assert_eq!(hasher.hash(b"data")[..], CryptoNight::with_custom(custom).chain(b"data").result()[..]);
+
+        let error = CryptoNightBuilder::new().backend("gpu").build().err().unwrap();
+        assert_eq!(error.name(), "gpu");
+    }
+}
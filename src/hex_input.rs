@@ -0,0 +1,121 @@
+//! Hexadecimal input, as used by most RPC and stratum interfaces.
+use std::error::Error;
+use std::fmt;
+
+use digest::generic_array::GenericArray;
+
+use crate::{CryptoNight, FixedOutput, Input};
+
+/// Errors that can occur when inputting hex encoded data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HexError {
+    /// The input has an odd number of digits.
+    OddLength,
+    /// The input contains a character that is not a hex digit.
+    InvalidCharacter {
+        /// The offending character.
+        character: char,
+        /// Byte offset of the character in the input.
+        index: usize,
+    },
+    /// The input does not have the number of digits of a digest.
+    InvalidLength(usize),
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HexError::OddLength => f.write_str("odd number of hex digits"),
+            HexError::InvalidCharacter { character, index } => {
+                write!(f, "invalid hex character {:?} at position {}", character, index)
+            }
+            HexError::InvalidLength(length) => write!(f, "expected 64 hex digits, got {}", length),
+        }
+    }
+}
+
+impl Error for HexError {}
+
+pub(crate) fn digit_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl CryptoNight {
+    /// Input hex encoded data.
+    ///
+    /// Both upper and lower case digits are accepted. The input is validated
+    /// before anything is hashed, so on error the hasher is left unchanged.
+    pub fn input_hex(&mut self, s: &str) -> Result<(), HexError> {
+        if let Some((index, character)) = s.char_indices().find(|&(_, c)| !c.is_ascii_hexdigit()) {
+            return Err(HexError::InvalidCharacter { character, index });
+        }
+
+        if s.len() & 1 != 0 {
+            return Err(HexError::OddLength);
+        }
+
+        let mut buffer = [0u8; 64];
+        for chunk in s.as_bytes().chunks(2 * buffer.len()) {
+            let decoded = &mut buffer[..chunk.len() / 2];
+
+            for (byte, digits) in decoded.iter_mut().zip(chunk.chunks_exact(2)) {
+                // Digits have been validated above.
+                *byte = digit_value(digits[0]).unwrap() << 4 | digit_value(digits[1]).unwrap();
+            }
+
+            Input::input(self, decoded);
+        }
+
+        Ok(())
+    }
+
+    /// Compute the digest of hex encoded data.
+    ///
+    /// See also: `Digest::digest()`.
+    pub fn digest_hex_input(s: &str) -> Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>, HexError> {
+        let mut hasher: Self = Default::default();
+        hasher.input_hex(s)?;
+        Ok(hasher.fixed_result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keccak state of the hasher, which is cheap to compute compared to the full digest.
+    fn state(hasher: CryptoNight) -> Vec<u8> {
+        hasher.into_core().state().to_vec()
+    }
+
+    #[test]
+    fn test_input_hex() {
+        let data: Vec<u8> = (0..=255).collect();
+        let encoded: String = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+        let mut expected = CryptoNight::default();
+        Input::input(&mut expected, &data);
+
+        let mut hasher = CryptoNight::default();
+        hasher.input_hex(&encoded).unwrap();
+        assert_eq!(state(hasher), state(expected.clone()));
+
+        let mut hasher = CryptoNight::default();
+        hasher.input_hex(&encoded.to_lowercase()).unwrap();
+        assert_eq!(state(hasher), state(expected));
+    }
+
+    #[test]
+    fn test_input_hex_errors() {
+        let mut hasher = CryptoNight::default();
+
+        assert_eq!(hasher.input_hex("abc"), Err(HexError::OddLength));
+        assert_eq!(hasher.input_hex("00zz"), Err(HexError::InvalidCharacter { character: 'z', index: 2 }));
+        assert_eq!(state(hasher), state(CryptoNight::default()));
+    }
+}
@@ -0,0 +1,190 @@
+//! Compiling CryptoNight-R random math programs to x86_64 machine code.
+//!
+//! Interpreting the program dispatches on every instruction, 70 times per
+//! iteration of the main loop. The compiled program is straight-line code
+//! instead. The code is written to a private mapping, which is made
+//! executable and read-only before it is run, so it is never writable and
+//! executable at the same time.
+//!
+//! The generated function follows the System V calling convention and takes
+//! a pointer to the nine registers. Registers are kept in CPU registers:
+//!
+//! | Register | r0  | r1  | r2  | r3  | r4  | r5  | r6   | r7   | r8  |
+//! |----------|-----|-----|-----|-----|-----|-----|------|------|-----|
+//! | CPU      | eax | edx | ebx | esi | r8d | r9d | r10d | r11d | edi |
+//!
+//! `ecx` holds the count of rotations.
+use std::io;
+use std::ptr;
+
+use crate::random_math::{Opcode, Program};
+
+/// Encodings of the CPU registers holding the program registers.
+const REGISTERS: [u8; 9] = [0, 2, 3, 6, 8, 9, 10, 11, 7];
+const ECX: u8 = 1;
+const RDI: u8 = 7;
+
+/// Size of the code buffer, enough for the largest program.
+const CODE_SIZE: usize = 4096;
+
+/// A random math program compiled to native code.
+pub struct NativeProgram {
+    code: ptr::NonNull<u8>,
+}
+
+// The mapping is read-only once compiled.
+unsafe impl Send for NativeProgram {}
+
+unsafe impl Sync for NativeProgram {}
+
+impl NativeProgram {
+    /// Compile the program.
+    ///
+    /// Fails if the system refuses to map executable memory.
+    pub fn compile(program: &Program) -> io::Result<Self> {
+        let code = emit(program);
+        assert!(code.len() <= CODE_SIZE);
+
+        unsafe {
+            let buffer = libc::mmap(
+                ptr::null_mut(),
+                CODE_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if buffer == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Take ownership first, so the mapping is released on failure.
+            let native = NativeProgram { code: ptr::NonNull::new_unchecked(buffer as *mut u8) };
+            ptr::copy_nonoverlapping(code.as_ptr(), native.code.as_ptr(), code.len());
+
+            if libc::mprotect(buffer, CODE_SIZE, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(native)
+        }
+    }
+
+    /// Run the program on the registers.
+    #[inline]
+    pub fn execute(&self, r: &mut [u32; 9]) {
+        unsafe {
+            let function: extern "C" fn(*mut u32) = std::mem::transmute(self.code.as_ptr());
+            function(r.as_mut_ptr());
+        }
+    }
+}
+
+impl Drop for NativeProgram {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.code.as_ptr() as *mut libc::c_void, CODE_SIZE);
+        }
+    }
+}
+
+/// Generate the machine code for a program.
+fn emit(program: &Program) -> Vec<u8> {
+    let mut code = Vec::with_capacity(1024);
+
+    // push rbx; push rdi
+    code.extend_from_slice(&[0x53, 0x57]);
+
+    // The pointer is in r8's register, so it is loaded last.
+    for (i, &register) in REGISTERS.iter().enumerate() {
+        emit_memory(&mut code, 0x8B, register, 4 * i as u8);
+    }
+
+    for instruction in &program.code {
+        let dst = REGISTERS[instruction.dst];
+        let src = REGISTERS[instruction.src];
+
+        match instruction.opcode {
+            // imul dst, src
+            Opcode::Mul => emit_register(&mut code, &[0x0F, 0xAF], dst, src),
+            Opcode::Add => {
+                // add dst, src; add dst, C
+                emit_register(&mut code, &[0x01], src, dst);
+                emit_rex(&mut code, 0, dst);
+                code.extend_from_slice(&[0x81, 0xC0 | (dst & 7)]);
+                code.extend_from_slice(&instruction.c.to_le_bytes());
+            }
+            // sub dst, src
+            Opcode::Sub => emit_register(&mut code, &[0x29], src, dst),
+            Opcode::Ror | Opcode::Rol => {
+                // mov ecx, src; ror/rol dst, cl
+                emit_register(&mut code, &[0x89], src, ECX);
+                let operation = if instruction.opcode == Opcode::Ror { 1 } else { 0 };
+                emit_register(&mut code, &[0xD3], operation, dst);
+            }
+            // xor dst, src
+            Opcode::Xor => emit_register(&mut code, &[0x31], src, dst),
+        }
+    }
+
+    // pop rdi
+    code.push(0x5F);
+
+    // Only the first four registers change.
+    for (i, &register) in REGISTERS[..4].iter().enumerate() {
+        emit_memory(&mut code, 0x89, register, 4 * i as u8);
+    }
+
+    // pop rbx; ret
+    code.extend_from_slice(&[0x5B, 0xC3]);
+
+    code
+}
+
+/// Emit a REX prefix if either register needs one.
+fn emit_rex(code: &mut Vec<u8>, reg: u8, rm: u8) {
+    if reg >= 8 || rm >= 8 {
+        code.push(0x40 | ((reg >> 3) << 2) | (rm >> 3));
+    }
+}
+
+/// Emit an instruction with two register operands.
+fn emit_register(code: &mut Vec<u8>, opcode: &[u8], reg: u8, rm: u8) {
+    emit_rex(code, reg, rm);
+    code.extend_from_slice(opcode);
+    code.push(0xC0 | ((reg & 7) << 3) | (rm & 7));
+}
+
+/// Emit an instruction with a register operand and a memory operand `[rdi + offset]`.
+fn emit_memory(code: &mut Vec<u8>, opcode: u8, reg: u8, offset: u8) {
+    emit_rex(code, reg, RDI);
+    code.extend_from_slice(&[opcode, 0x40 | ((reg & 7) << 3) | RDI, offset]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_interpreter() {
+        let mut seed = 0x9E37_79B9u32;
+        for height in 1_806_260..1_806_360 {
+            let program = if height & 1 == 0 { Program::generate(height) } else { Program::generate_wow(height) };
+            let native = NativeProgram::compile(&program).unwrap();
+
+            let mut expected = [0u32; 9];
+            for register in expected.iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                *register = seed;
+            }
+            let mut actual = expected;
+
+            program.execute(&mut expected);
+            native.execute(&mut actual);
+
+            assert_eq!(actual, expected, "height {}", height);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Keccak-f[1600] on four states at once, using AVX2.
+//!
+//! Each 256-bit vector holds the same lane of the four states, so every step
+//! of the permutation is a single instruction for all of them. This is used
+//! to pad and finish the hashes of several inputs computed together.
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Rotate the 64-bit lanes of a vector left by a constant number of bits.
+macro_rules! rotate {
+    ($x:expr, $n:literal) => {{
+        let x = $x;
+        _mm256_or_si256(_mm256_slli_epi64(x, $n), _mm256_srli_epi64(x, 64 - $n))
+    }};
+}
+
+/// The rho and pi steps: move each lane to the next of the given lanes,
+/// rotated by the given offset.
+macro_rules! rho_pi {
+    ($a:ident, $last:ident, $lane:literal $offset:literal) => {
+        $a[$lane] = rotate!($last, $offset);
+    };
+    ($a:ident, $last:ident, $lane:literal $offset:literal, $($rest:tt)*) => {
+        let next = $a[$lane];
+        $a[$lane] = rotate!($last, $offset);
+        $last = next;
+        rho_pi!($a, $last, $($rest)*);
+    };
+}
+
+/// Round constants of the iota step.
+const RC: [u64; 24] = [
+    0x0000_0000_0000_0001, 0x0000_0000_0000_8082, 0x8000_0000_0000_808a, 0x8000_0000_8000_8000,
+    0x0000_0000_0000_808b, 0x0000_0000_8000_0001, 0x8000_0000_8000_8081, 0x8000_0000_0000_8009,
+    0x0000_0000_0000_008a, 0x0000_0000_0000_0088, 0x0000_0000_8000_8009, 0x0000_0000_8000_000a,
+    0x0000_0000_8000_808b, 0x8000_0000_0000_008b, 0x8000_0000_0000_8089, 0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002, 0x8000_0000_0000_0080, 0x0000_0000_0000_800a, 0x8000_0000_8000_000a,
+    0x8000_0000_8000_8081, 0x8000_0000_0000_8080, 0x0000_0000_8000_0001, 0x8000_0000_8000_8008,
+];
+
+/// Apply the Keccak-f[1600] permutation to up to four states.
+///
+/// # Safety
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn keccakf4(states: &mut [&mut [u64; 25]]) {
+    debug_assert!(states.len() <= 4);
+    let mut lanes = [_mm256_setzero_si256(); 25];
+    for (i, lane) in lanes.iter_mut().enumerate() {
+        let mut values = [0u64; 4];
+        for (value, state) in values.iter_mut().zip(states.iter()) {
+            *value = state[i];
+        }
+        *lane = _mm256_loadu_si256(values.as_ptr() as *const __m256i);
+    }
+
+    permute(&mut lanes);
+
+    for (i, lane) in lanes.iter().enumerate() {
+        let mut values = [0u64; 4];
+        _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, *lane);
+        for (value, state) in values.iter().zip(states.iter_mut()) {
+            state[i] = *value;
+        }
+    }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn permute(a: &mut [__m256i; 25]) {
+    for &round_constant in RC.iter() {
+        // Theta
+        let mut c = [_mm256_setzero_si256(); 5];
+        for (x, column) in c.iter_mut().enumerate() {
+            *column = _mm256_xor_si256(
+                _mm256_xor_si256(a[x], a[x + 5]),
+                _mm256_xor_si256(_mm256_xor_si256(a[x + 10], a[x + 15]), a[x + 20]),
+            );
+        }
+        for x in 0..5 {
+            let d = _mm256_xor_si256(c[(x + 4) % 5], rotate!(c[(x + 1) % 5], 1));
+            for y in 0..5 {
+                a[5 * y + x] = _mm256_xor_si256(a[5 * y + x], d);
+            }
+        }
+
+        // Rho and pi, visiting the lanes in the order of pi from lane 1.
+        let mut last = a[1];
+        rho_pi!(a, last,
+            10 1, 7 3, 11 6, 17 10, 18 15, 3 21, 5 28, 16 36, 8 45, 21 55, 24 2, 4 14,
+            15 27, 23 41, 19 56, 13 8, 12 25, 2 43, 20 62, 14 18, 22 39, 9 61, 6 20, 1 44);
+
+        // Chi
+        for y in 0..5 {
+            let row = [a[5 * y], a[5 * y + 1], a[5 * y + 2], a[5 * y + 3], a[5 * y + 4]];
+            for x in 0..5 {
+                a[5 * y + x] = _mm256_xor_si256(row[x], _mm256_andnot_si256(row[(x + 1) % 5], row[(x + 2) % 5]));
+            }
+        }
+
+        // Iota
+        a[0] = _mm256_xor_si256(a[0], _mm256_set1_epi64x(round_constant as i64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::avx2_available;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_tiny_keccak() {
+        if !avx2_available() {
+            return;
+        }
+
+        let mut n = 0x0123_4567_89ab_cdefu64;
+        let mut next = || {
+            n = n.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            n
+        };
+
+        for count in 1..=4 {
+            let mut states: Vec<[u64; 25]> = (0..count).map(|_| [(); 25].map(|_| next())).collect();
+            let mut expected = states.clone();
+            for state in expected.iter_mut() {
+                tiny_keccak::keccakf(state);
+            }
+
+            let mut refs: Vec<&mut [u64; 25]> = states.iter_mut().collect();
+            unsafe { keccakf4(&mut refs) };
+
+            assert_eq!(states, expected);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! The Keccak sponge CryptoNight starts and ends with.
+use std::convert::TryInto;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+mod avx2;
+
+/// Size of the Keccak state in bytes.
+const STATE_SIZE: usize = 200;
+
+/// A Keccak-f[1600] sponge with the parameters used by CryptoNight.
+///
+/// CryptoNight absorbs its input into this sponge with a rate of 136 bytes
+/// and the original Keccak padding, the same as Keccak-256. Rather than a
+/// digest, the whole 200 byte state is then used to initialize the hash.
+///
+/// The state can be cloned after absorbing a common prefix, so hashes of
+/// inputs sharing that prefix can start from there.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::KeccakState;
+/// let mut state = KeccakState::new();
+/// state.absorb(b"Your data");
+/// state.pad();
+///
+/// // The first 32 bytes are the Keccak-256 digest of the input.
+/// let keccak256 = &state.to_bytes()[..32];
+/// ```
+#[derive(Clone)]
+pub struct KeccakState {
+    lanes: [u64; 25],
+    buffer: [u8; KeccakState::RATE],
+    buffered: usize,
+}
+
+impl KeccakState {
+    /// Number of bytes absorbed per permutation.
+    pub const RATE: usize = 136;
+
+    /// Create an empty sponge.
+    pub fn new() -> Self {
+        KeccakState {
+            lanes: [0; 25],
+            buffer: [0; Self::RATE],
+            buffered: 0,
+        }
+    }
+
+    /// Create a sponge from a raw state, with nothing buffered.
+    pub fn from_bytes(bytes: &[u8; STATE_SIZE]) -> Self {
+        let mut state = Self::new();
+        for (lane, bytes) in state.lanes.iter_mut().zip(bytes.chunks_exact(8)) {
+            *lane = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        state
+    }
+
+    /// The raw state.
+    ///
+    /// Input that has been absorbed but not yet filled a complete block is
+    /// not part of the state until the sponge is padded.
+    pub fn to_bytes(&self) -> [u8; STATE_SIZE] {
+        let mut bytes = [0u8; STATE_SIZE];
+        for (lane, bytes) in self.lanes.iter().zip(bytes.chunks_exact_mut(8)) {
+            bytes.copy_from_slice(&lane.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Input that has been absorbed but not yet filled a complete block.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.buffer[..self.buffered]
+    }
+
+    /// Absorb data into the sponge.
+    pub fn absorb(&mut self, mut data: &[u8]) {
+        if self.buffered > 0 {
+            let take = data.len().min(Self::RATE - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+
+            if self.buffered < Self::RATE {
+                return;
+            }
+
+            let block = self.buffer;
+            self.absorb_block(&block);
+            self.buffered = 0;
+        }
+
+        let mut blocks = data.chunks_exact(Self::RATE);
+        for block in &mut blocks {
+            self.absorb_block(block);
+        }
+
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Finish absorbing by applying the Keccak padding.
+    ///
+    /// Afterwards the state holds the result of the sponge. Absorbing more
+    /// data after padding starts a new message on top of that state.
+    pub fn pad(&mut self) {
+        let block = self.padded_block();
+        self.absorb_block(&block);
+        self.buffered = 0;
+    }
+
+    /// Pad several sponges at once, see `pad`.
+    ///
+    /// With AVX2, the final permutations of four sponges are computed
+    /// together.
+    pub(crate) fn pad_all(states: &mut [&mut KeccakState]) {
+        for state in states.iter_mut() {
+            let block = state.padded_block();
+            state.xor_block(&block);
+            state.buffered = 0;
+        }
+
+        keccakf_all(&mut states.iter_mut().map(|state| &mut state.lanes).collect::<Vec<_>>());
+    }
+
+    /// Apply the Keccak-f[1600] permutation to the state.
+    pub fn permute(&mut self) {
+        tiny_keccak::keccakf(&mut self.lanes);
+    }
+
+    /// The buffered input followed by the Keccak padding.
+    fn padded_block(&self) -> [u8; Self::RATE] {
+        let mut block = [0u8; Self::RATE];
+        block[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+        block[self.buffered] ^= 0x01;
+        block[Self::RATE - 1] ^= 0x80;
+
+        block
+    }
+
+    fn absorb_block(&mut self, block: &[u8]) {
+        self.xor_block(block);
+        self.permute();
+    }
+
+    fn xor_block(&mut self, block: &[u8]) {
+        for (lane, bytes) in self.lanes.iter_mut().zip(block.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+    }
+}
+
+/// Apply the Keccak-f[1600] permutation to several raw states, see
+/// `keccakf_all`.
+pub(crate) fn permute_all(states: &mut [&mut [u8; STATE_SIZE]]) {
+    let mut sponges: Vec<_> = states.iter().map(|state| KeccakState::from_bytes(state)).collect();
+    keccakf_all(&mut sponges.iter_mut().map(|sponge| &mut sponge.lanes).collect::<Vec<_>>());
+
+    for (state, sponge) in states.iter_mut().zip(&sponges) {
+        **state = sponge.to_bytes();
+    }
+}
+
+/// Apply the Keccak-f[1600] permutation to several states, four at a time
+/// with AVX2.
+fn keccakf_all(states: &mut [&mut [u64; 25]]) {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+        {
+            // Two or three states are still cheaper in one go.
+            if states.len() > 1 && crate::avx2_available() {
+                for chunk in states.chunks_mut(4) {
+                    unsafe { avx2::keccakf4(chunk) };
+                }
+                return;
+            }
+        }
+    for state in states.iter_mut() {
+        tiny_keccak::keccakf(state);
+    }
+}
+
+impl Default for KeccakState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Besides the state, only the input that has not yet filled a block is
+/// serialized.
+#[cfg(feature = "serde")]
+impl Serialize for KeccakState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("KeccakState", 2)?;
+        state.serialize_field("lanes", &self.lanes)?;
+        state.serialize_field("buffered", &self.buffer[..self.buffered])?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for KeccakState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "KeccakState")]
+        struct Fields {
+            lanes: [u64; 25],
+            buffered: Vec<u8>,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        if fields.buffered.len() >= Self::RATE {
+            return Err(de::Error::invalid_length(fields.buffered.len(), &"less than a block of buffered input"));
+        }
+
+        let mut state = KeccakState { lanes: fields.lanes, ..Self::new() };
+        state.buffer[..fields.buffered.len()].copy_from_slice(&fields.buffered);
+        state.buffered = fields.buffered.len();
+
+        Ok(state)
+    }
+}
+
+impl std::fmt::Debug for KeccakState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("KeccakState").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn keccak256(parts: &[&[u8]]) -> [u8; 32] {
+        let mut state = KeccakState::new();
+        for part in parts {
+            state.absorb(part);
+        }
+        state.pad();
+
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&state.to_bytes()[..32]);
+        digest
+    }
+
+    #[test]
+    fn test_keccak256() {
+        assert_eq!(keccak256(&[]), hex!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"));
+        assert_eq!(keccak256(&[b"abc"]), hex!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"));
+    }
+
+    #[test]
+    fn test_absorb_in_parts() {
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+
+        let expected = keccak256(&[&data]);
+        assert_eq!(keccak256(&[&data[..1], &data[1..300], &data[300..]]), expected);
+        assert_eq!(keccak256(&[&data[..136], &data[136..272], &data[272..]]), expected);
+    }
+
+    #[test]
+    fn test_pad_all() {
+        let inputs: Vec<Vec<u8>> = (0..6).map(|i| vec![i as u8; 70 + 30 * i]).collect();
+        let mut states: Vec<KeccakState> = inputs.iter().map(|input| {
+            let mut state = KeccakState::new();
+            state.absorb(input);
+            state
+        }).collect();
+
+        let expected: Vec<_> = inputs.iter().map(|input| keccak256(&[input])).collect();
+        KeccakState::pad_all(&mut states.iter_mut().collect::<Vec<_>>());
+
+        for (state, expected) in states.iter().zip(expected) {
+            assert_eq!(state.to_bytes()[..32], expected);
+        }
+    }
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let mut state = KeccakState::new();
+        state.absorb(b"roundtrip");
+        state.pad();
+
+        assert_eq!(KeccakState::from_bytes(&state.to_bytes()).to_bytes()[..], state.to_bytes()[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        let mut state = KeccakState::new();
+        state.absorb(&[7u8; 200]);
+
+        let saved = serde_json::to_string(&state).unwrap();
+        let mut restored: KeccakState = serde_json::from_str(&saved).unwrap();
+        state.pad();
+        restored.pad();
+        assert_eq!(restored.to_bytes()[..], state.to_bytes()[..]);
+
+        let too_long = format!("{{\"lanes\":{:?},\"buffered\":{:?}}}", [0u64; 25], [0u8; KeccakState::RATE]);
+        assert!(serde_json::from_str::<KeccakState>(&too_long).is_err());
+    }
+}
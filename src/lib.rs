@@ -26,93 +26,583 @@
 //!
 //! [1]: https://cryptonote.org/cns/cns008.txt
 //! [2]: https://github.com/RustCrypto/hashes
-use std::alloc::{alloc, Layout};
+#![cfg_attr(feature = "safe", forbid(unsafe_code))]
+#[cfg(all(feature = "safe", any(feature = "guard-pages", feature = "canary", feature = "mmap", feature = "perf-event", feature = "allocator-api")))]
+compile_error!("The `safe` feature cannot be combined with `guard-pages`, `canary`, `mmap`, `perf-event` or `allocator-api`.");
+
+use std::convert::TryInto;
+use std::io::{self, IoSlice, Read, Write};
 
 use blake_hash::Blake256;
 pub use digest::{BlockInput, Digest, FixedOutput, Input, Reset};
 use digest::generic_array::GenericArray;
-use digest::generic_array::typenum::{U200, U32};
+use digest::generic_array::typenum::{U136, U32};
 use groestl::Groestl256;
 use jh_x86_64::Jh256;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
 use skein_hash::Skein512;
 
+pub use arena::{split_slab, ScratchpadArena};
+pub use backend::{Backend, backends, BuiltinBackend, register_backend, set_backend, Tuning};
+pub use checkpoint::Checkpoint;
+pub use cn_hash::CnHash;
+#[cfg(feature = "chukwa")]
+pub use chukwa::{Chukwa, ChukwaVersion};
+pub use core_api::CryptoNightCore;
+pub use custom::{CustomVariant, CustomVariantBuilder, CustomVariantError};
+pub use dyn_hasher::{dyn_hasher, DynCryptoNight};
+pub use hasher::{CryptoNightBuilder, CryptoNightHasher, UnknownBackendError};
+pub use hex_input::HexError;
+pub use keccak::KeccakState;
+pub use params::VariantParams;
+#[cfg(all(target_os = "linux", feature = "perf-event"))]
+pub use perf::{HashStats, PerfCounters};
+pub use pool::{PooledScratchpad, ScratchpadPool};
+pub use profile::{CoinProfile, coin_profile, PowAlgorithm, register_profile};
+pub use provider::ScratchpadProvider;
+pub use random_math::{RandomMathEngine, set_random_math_engine};
+#[cfg(feature = "randomx")]
+pub use randomx::{RandomX, RandomXCache};
+pub use scratchpad::{AllocationReport, ArrayScratchpad, memory_usage, MemoryOptions, MemoryUsage, Scratchpad, ScratchpadError};
+pub use soft_shell::CryptoNightSoftShell;
+pub use state::StateError;
+pub use telemetry::{HashEvent, HashObserver, ScratchpadSource, set_hash_observer};
+pub use typed::{CryptoNightFast, CryptoNightOf};
+pub use u64p::U64p;
+pub use variant::{ParseVariantError, Variant};
+
 mod aes;
-#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni"))]
+#[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+mod aarch64;
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
 mod aesni;
+mod arena;
+mod backend;
+#[cfg(feature = "canary")]
+mod canary;
+mod checkpoint;
+mod cn_hash;
+#[cfg(feature = "chukwa")]
+mod chukwa;
+mod core_api;
+mod custom;
+mod dyn_hasher;
+#[cfg(feature = "mmap")]
+mod file;
+mod hasher;
+mod hex_input;
+#[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+mod jit;
+mod keccak;
+pub mod monero;
+pub mod params;
+#[cfg(all(target_os = "linux", feature = "perf-event"))]
+mod perf;
+pub mod prelude;
+mod pool;
+mod profile;
+mod provider;
+mod random_math;
+#[cfg(feature = "randomx")]
+mod randomx;
+mod scratchpad;
+mod soft_shell;
+mod state;
+mod telemetry;
+#[cfg(feature = "serde")]
+mod tuning;
+mod typed;
+mod u64p;
+#[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+mod vaes;
+mod variant;
+pub mod wownero;
 
-const ROUNDS: usize = 524_288;
+/// Amount of data read at once by `CryptoNight::hash_reader`.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The phases of the main digest computation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    /// Filling the scratchpad from the initial state.
+    Init,
+    /// The memory-hard main loop.
+    MainLoop,
+    /// Folding the scratchpad back into the state.
+    Finalize,
+}
 
 #[repr(align(16))]
+#[derive(Clone)]
 /// Helper to enforce 16 byte alignment
 struct A16<T>(pub T);
 
-/// CryptoNight version 0 implementation.
-#[derive(Debug, Default, Clone)]
+/// Variant dependent inputs of the main loop.
+///
+/// Passed to `Backend::main_loop`, which can not run every variant the same
+/// way.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Tweaks {
+    variant: Variant,
+    /// The value mixed into the scratchpad by variant 1.
+    tweak1_2: u64,
+    /// The block height selecting the CryptoNight-R or CryptoNight-WOW program.
+    height: u64,
+    /// The parameters of a custom variant, whose base is `variant`.
+    custom: Option<CustomVariant>,
+}
+
+impl Tweaks {
+    /// Tweaks for the given variant that do not depend on the input.
+    fn plain(variant: Variant) -> Self {
+        Tweaks { variant, tweak1_2: 0, height: 0, custom: None }
+    }
+
+    /// Whether the main loops of hashes with these tweaks can run in
+    /// lockstep, with the same variant, iterations and scratchpad size.
+    fn lockstep(&self, other: &Tweaks) -> bool {
+        self.variant == other.variant
+            && self.iterations() == other.iterations()
+            && self.scratchpad_size() == other.scratchpad_size()
+    }
+
+    /// The variant, or the base variant of a custom one.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The value mixed into the scratchpad by variant 1.
+    pub fn tweak1_2(&self) -> u64 {
+        self.tweak1_2
+    }
+
+    /// The block height, used by CryptoNight-R, CryptoNight-WOW and the
+    /// soft shell variant.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// The parameters of a custom variant.
+    pub fn custom(&self) -> Option<CustomVariant> {
+        self.custom
+    }
+
+    /// Whether the parameters are only known at runtime.
+    fn is_dynamic(&self) -> bool {
+        self.custom.is_some() || self.variant == Variant::SoftShell
+    }
+
+    /// Size of the scratchpad, which depends on the height for the soft shell variant.
+    pub fn scratchpad_size(&self) -> usize {
+        match (self.custom, self.variant) {
+            (Some(custom), _) => custom.scratchpad_size(),
+            (None, Variant::SoftShell) => soft_shell::scratchpad_size(self.height),
+            (None, variant) => variant.scratchpad_size(),
+        }
+    }
+
+    /// Iterations of the main loop, which depend on the height for the soft shell variant.
+    pub fn iterations(&self) -> usize {
+        match (self.custom, self.variant) {
+            (Some(custom), _) => custom.iterations(),
+            (None, Variant::SoftShell) => soft_shell::iterations(self.height),
+            (None, variant) => variant.iterations(),
+        }
+    }
+
+    /// Address mask of the main loop, which depends on the height for the soft shell variant.
+    pub fn address_mask(&self) -> usize {
+        match (self.custom, self.variant) {
+            (Some(custom), _) => custom.address_mask(),
+            (None, Variant::SoftShell) => soft_shell::address_mask(self.height),
+            (None, variant) => variant.address_mask(),
+        }
+    }
+}
+
+/// The values carried between iterations of the main loop.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoopState {
+    /// The `a` register of the main loop.
+    pub a: [u8; 16],
+    /// The `b` register of the main loop.
+    pub b: [u8; 16],
+    /// The previous value of `b`, used by variant 2.
+    pub b1: [u8; 16],
+    /// Result of the variant 2 division step.
+    pub division_result: u64,
+    /// Result of the variant 2 square root step.
+    pub sqrt_result: u64,
+    /// The variable registers of the CryptoNight-R random math.
+    pub random_math: [u32; 4],
+    /// The floating point accumulators of the Conceal tweak, as bits.
+    pub conceal: [u32; 4],
+}
+
+impl LoopState {
+    /// The state at the start of the main loop.
+    fn new(keccac: &[u8]) -> Self {
+        let mut state = LoopState::default();
+        for i in 0..16 {
+            state.a[i] = keccac[i] ^ keccac[i + 32];
+            state.b[i] = keccac[i + 16] ^ keccac[i + 48];
+            state.b1[i] = keccac[i + 64] ^ keccac[i + 80];
+        }
+
+        state.division_result = u64::from_le_bytes(keccac[96..104].try_into().unwrap());
+        state.sqrt_result = u64::from_le_bytes(keccac[104..112].try_into().unwrap());
+        for (i, register) in state.random_math.iter_mut().enumerate() {
+            *register = u32::from_le_bytes(keccac[96 + 4 * i..100 + 4 * i].try_into().unwrap());
+        }
+
+        state
+    }
+}
+
+/// CryptoNight implementation.
+///
+/// By default this computes the original algorithm, variant 0. Use
+/// `CryptoNight::with_variant` to compute another variant.
+#[derive(Debug, Clone)]
 pub struct CryptoNight {
-    internal_hasher: sha3::Keccak256Full,
+    keccak: KeccakState,
+    variant: Variant,
+    /// Number of bytes input so far, if the hasher saw the start of the input.
+    input_len: Option<usize>,
+    /// Bytes 35 to 43 of the input, used by variant 1.
+    tweak_input: [u8; 8],
+    /// The block height, used by CryptoNight-R and CryptoNight-WOW.
+    height: u64,
+    /// The parameters of a custom variant, whose base is `variant`.
+    custom: Option<CustomVariant>,
 }
 
 
 impl CryptoNight {
     /// Alignment requirement for the scratch pad.
     pub const SP_ALIGNMENT: usize = 16;
-    /// Scratch pad size.
-    pub const SP_SIZE: usize = 1 << 21;
+    /// Scratch pad size, large enough for every variant.
+    ///
+    /// Some variants need less, see `Variant::scratchpad_size`.
+    pub const SP_SIZE: usize = params::V0::SCRATCHPAD_SIZE;
 
     /// Compute a digest with a provided buffer.
     ///
-    /// This method performs no allocations.
-    ///
     /// This method performs no allocations, as opposed to the
-    /// `fixed_result` method. However, the scratchpad should be of
-    /// proper length and alignment. See the `SP_ALIGNMENT` and `SP_SIZE`
-    /// constants for the exact requirements.
+    /// `fixed_result` method. However, the scratchpad should be aligned to
+    /// `SP_ALIGNMENT` and hold at least the scratchpad of the variant,
+    /// which `SP_SIZE` does for every variant. Only the start of longer
+    /// buffers is used. With the `zeroize` feature, the part of the buffer
+    /// that was used is overwritten with zeros before returning.
     ///
     /// See also: `Digest::fixed_result()`.
     ///
     /// # Panics
     ///
-    /// If the buffer provided is not acceptable, this method will panic.
+    /// If the buffer provided is not acceptable, this method will panic. See
+    /// `try_fixed_result_with_buffer` for a method that returns an error
+    /// instead.
     pub fn fixed_result_with_buffer(self, scratchpad: &mut [u8]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
-        // Ensure that our alignment requirements are met.
-        assert_eq!(scratchpad.as_ptr() as usize & (Self::SP_ALIGNMENT - 1), 0);
-        assert_eq!(scratchpad.len(), Self::SP_SIZE);
-
-        let mut keccac = A16(self.internal_hasher.fixed_result());
-        let keccac = &mut keccac.0;
-
-        Self::digest_main(keccac, scratchpad);
-
-        #[allow(clippy::cast_ptr_alignment)]
-            tiny_keccak::keccakf(unsafe { &mut *(keccac as *mut GenericArray<u8, U200> as *mut [u64; 25]) });
+        self.try_fixed_result_with_buffer(scratchpad).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        Self::hash_final_state(&keccac)
+    /// Compute a digest with a provided buffer, if the buffer is acceptable.
+    ///
+    /// This is `fixed_result_with_buffer`, returning an error rather than
+    /// panicking when the buffer is misaligned or too small for the variant.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest, ScratchpadError};
+    /// let mut buffer = vec![0u8; 1024];
+    /// let error = CryptoNight::new().chain(b"Your data").try_fixed_result_with_buffer(&mut buffer).unwrap_err();
+    ///
+    /// assert!(matches!(error, ScratchpadError::WrongLength { .. } | ScratchpadError::Misaligned { .. }));
+    /// ```
+    pub fn try_fixed_result_with_buffer(self, scratchpad: &mut [u8])
+        -> Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>, ScratchpadError> {
+        scratchpad::check_buffer(scratchpad, self.scratchpad_size())?;
+        Ok(self.compute(scratchpad, ScratchpadSource::Buffer))
     }
 
     /// Compute a digest with a provided buffer.
     ///
-    /// This method performs no allocations.
-    ///
     /// This method performs no allocations, as opposed to the
-    /// `fixed_result` method. However, the scratchpad should be of
-    /// proper length and alignment. See the `SP_ALIGNMENT` and `SP_SIZE`
-    /// constants for the exact requirements.
+    /// `digest` method. The same requirements as for
+    /// `fixed_result_with_buffer` apply to the scratchpad.
     ///
     /// See also: `Digest::digest()`.
     ///
     /// # Panics
     ///
-    /// If the buffer provided is not acceptable, this method will panic.
+    /// If the buffer provided is not acceptable, this method will panic. See
+    /// `try_digest_with_buffer` for a method that returns an error instead.
     pub fn digest_with_buffer<B>(data: B, scratchpad: &mut [u8]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize>
+        where B: AsRef<[u8]> {
+        Self::try_digest_with_buffer(data, scratchpad).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Compute a digest with a provided buffer, if the buffer is acceptable.
+    ///
+    /// See `try_fixed_result_with_buffer`.
+    pub fn try_digest_with_buffer<B>(data: B, scratchpad: &mut [u8])
+        -> Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>, ScratchpadError>
         where B: AsRef<[u8]> {
         let mut hasher: Self = Default::default();
         Input::input(&mut hasher, data);
-        hasher.fixed_result_with_buffer(scratchpad)
+        hasher.try_fixed_result_with_buffer(scratchpad)
+    }
+
+    /// Compute the digests of two hashers at once with provided buffers.
+    ///
+    /// The main loops of the two hashes are interleaved in a single thread,
+    /// so that the memory latency of one hides behind the work of the
+    /// other. This gives more throughput per core than computing the
+    /// digests one after the other, which is what happens for hashers of
+    /// different variants, on the AArch64 backend or with a backend
+    /// installed by `set_backend`.
+    ///
+    /// Both scratchpads have the same requirements as for
+    /// `fixed_result_with_buffer`. Use `split_slab` to take them from a
+    /// single allocation.
+    ///
+    /// # Panics
+    ///
+    /// If either buffer provided is not acceptable, this method will panic.
+    pub fn fixed_result2_with_buffers(self, other: Self, first_scratchpad: &mut [u8], second_scratchpad: &mut [u8])
+        -> (GenericArray<u8, <Self as FixedOutput>::OutputSize>, GenericArray<u8, <Self as FixedOutput>::OutputSize>) {
+        let [first, second] = Self::into_cores([self, other]);
+        first.compute2(second, first_scratchpad, second_scratchpad, ScratchpadSource::Buffer)
+    }
+
+    /// Compute the digests of two inputs at once with provided buffers.
+    ///
+    /// See `fixed_result2_with_buffers`.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{split_slab, CryptoNight, Digest, Scratchpad};
+    /// let mut slab = Scratchpad::allocate(2 * CryptoNight::SP_SIZE);
+    /// let mut scratchpads = split_slab(slab.as_mut(), 2);
+    /// let (first, second) = (scratchpads.next().unwrap(), scratchpads.next().unwrap());
+    ///
+    /// let (a, b) = CryptoNight::digest2_with_buffers(b"First", b"Second", first, second);
+    /// assert_eq!(a, CryptoNight::digest(b"First"));
+    /// assert_eq!(b, CryptoNight::digest(b"Second"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If either buffer provided is not acceptable, this method will panic.
+    pub fn digest2_with_buffers<A, B>(first: A, second: B, first_scratchpad: &mut [u8], second_scratchpad: &mut [u8])
+        -> (GenericArray<u8, <Self as FixedOutput>::OutputSize>, GenericArray<u8, <Self as FixedOutput>::OutputSize>)
+        where A: AsRef<[u8]>, B: AsRef<[u8]> {
+        let mut first_hasher: Self = Default::default();
+        Input::input(&mut first_hasher, first);
+        let mut second_hasher: Self = Default::default();
+        Input::input(&mut second_hasher, second);
+        first_hasher.fixed_result2_with_buffers(second_hasher, first_scratchpad, second_scratchpad)
+    }
+
+    /// Compute the digests of four hashers at once with provided buffers.
+    ///
+    /// With the `vaes` feature, on CPUs with the VAES and AVX-512
+    /// extensions, the scratchpads are filled and folded back with 512-bit
+    /// AES instructions and the four main loops are interleaved. Otherwise
+    /// this computes the digests two at a time, as
+    /// `fixed_result2_with_buffers` does. Hashers of different variants,
+    /// or with scratchpads of different sizes, are not computed four at
+    /// once.
+    ///
+    /// The scratchpads have the same requirements as for
+    /// `fixed_result_with_buffer`. Use `split_slab` to take them from a
+    /// single allocation.
+    ///
+    /// # Panics
+    ///
+    /// If any buffer provided is not acceptable, this method will panic.
+    pub fn fixed_result4_with_buffers(hashers: [Self; 4], scratchpads: [&mut [u8]; 4])
+        -> [GenericArray<u8, <Self as FixedOutput>::OutputSize>; 4] {
+        CryptoNightCore::compute4(Self::into_cores(hashers), scratchpads, ScratchpadSource::Buffer)
+    }
+
+    /// Compute the digests of four inputs at once with provided buffers.
+    ///
+    /// See `fixed_result4_with_buffers`.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{split_slab, CryptoNight, Digest, Scratchpad};
+    /// let mut slab = Scratchpad::allocate(4 * CryptoNight::SP_SIZE);
+    /// let mut scratchpads = split_slab(slab.as_mut(), 4);
+    /// let scratchpads = [(); 4].map(|_| scratchpads.next().unwrap());
+    ///
+    /// let digests = CryptoNight::digest4_with_buffers([b"One", b"Two", b"Six", b"Ten"], scratchpads);
+    /// assert_eq!(digests[2], CryptoNight::digest(b"Six"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If any buffer provided is not acceptable, this method will panic.
+    pub fn digest4_with_buffers<B: AsRef<[u8]>>(inputs: [B; 4], scratchpads: [&mut [u8]; 4])
+        -> [GenericArray<u8, <Self as FixedOutput>::OutputSize>; 4] {
+        let hashers = inputs.map(|input| {
+            let mut hasher: Self = Default::default();
+            Input::input(&mut hasher, input);
+            hasher
+        });
+        Self::fixed_result4_with_buffers(hashers, scratchpads)
+    }
+
+    /// Compute the digests of `N` hashers at once with provided buffers.
+    ///
+    /// The main loops of the hashes are processed round-robin in one
+    /// thread, each hiding the memory latency of the others, on the same
+    /// backends as `fixed_result2_with_buffers`. The best number of lanes
+    /// depends on the cache size and memory latency of the machine. Unlike
+    /// `fixed_result4_with_buffers`, this does not use VAES. Hashers of
+    /// different variants, or with scratchpads of different sizes, are
+    /// computed one at a time.
+    ///
+    /// The scratchpads have the same requirements as for
+    /// `fixed_result_with_buffer`. Use `split_slab` to take them from a
+    /// single allocation.
+    ///
+    /// # Panics
+    ///
+    /// If any buffer provided is not acceptable, this method will panic.
+    pub fn fixed_result_n_with_buffers<const N: usize>(hashers: [Self; N], scratchpads: [&mut [u8]; N])
+        -> [GenericArray<u8, <Self as FixedOutput>::OutputSize>; N] {
+        CryptoNightCore::compute_n(Self::into_cores(hashers), scratchpads, ScratchpadSource::Buffer)
+    }
+
+    /// Compute the digests of `N` inputs at once with provided buffers.
+    ///
+    /// See `fixed_result_n_with_buffers`.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{split_slab, CryptoNight, Digest, Scratchpad};
+    /// let mut slab = Scratchpad::allocate(3 * CryptoNight::SP_SIZE);
+    /// let mut scratchpads = split_slab(slab.as_mut(), 3);
+    /// let scratchpads = [(); 3].map(|_| scratchpads.next().unwrap());
+    ///
+    /// let digests = CryptoNight::digest_n(&[b"One", b"Two", b"Six"], scratchpads);
+    /// assert_eq!(digests[1], CryptoNight::digest(b"Two"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If any buffer provided is not acceptable, this method will panic.
+    pub fn digest_n<const N: usize, B: AsRef<[u8]>>(inputs: &[B; N], scratchpads: [&mut [u8]; N])
+        -> [GenericArray<u8, <Self as FixedOutput>::OutputSize>; N] {
+        let mut inputs = inputs.iter();
+        let hashers = [(); N].map(|_| {
+            let mut hasher: Self = Default::default();
+            Input::input(&mut hasher, inputs.next().unwrap());
+            hasher
+        });
+        Self::fixed_result_n_with_buffers(hashers, scratchpads)
+    }
+
+    /// Input several parts of data at once.
+    ///
+    /// This is equivalent to calling `Input::input` for every part in order,
+    /// and avoids concatenating the parts into a temporary buffer. For
+    /// `IoSlice`s, use `Write::write_vectored`.
+    pub fn input_vectored<B: AsRef<[u8]>>(&mut self, parts: &[B]) {
+        for part in parts {
+            Input::input(self, part);
+        }
+    }
+
+    /// Compute the digest of the concatenation of several parts of data.
+    ///
+    /// See also: `Digest::digest()`.
+    pub fn digest_vectored<B: AsRef<[u8]>>(parts: &[B]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
+        let mut hasher: Self = Default::default();
+        hasher.input_vectored(parts);
+        hasher.fixed_result()
+    }
+
+    /// Input all data read from `reader` and compute the digest.
+    ///
+    /// The data is read in chunks of 64 KiB and input as it arrives, so large
+    /// inputs such as files are never held in memory at once. Reads that are
+    /// interrupted are retried; any other error is returned.
+    ///
+    /// This will temporarily allocate a scratchpad, like `Digest::result`.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest};
+    /// let data = &b"Your data"[..];
+    /// let result = CryptoNight::new().hash_reader(data).unwrap();
+    ///
+    /// assert_eq!(result, CryptoNight::digest(data));
+    /// ```
+    pub fn hash_reader<R: Read>(self, reader: R) -> io::Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>> {
+        self.hash_reader_with_buffer_size(reader, READ_BUFFER_SIZE)
+    }
+
+    /// Like `hash_reader`, reading chunks of `buffer_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `buffer_size` is zero.
+    pub fn hash_reader_with_buffer_size<R: Read>(mut self, mut reader: R, buffer_size: usize)
+        -> io::Result<GenericArray<u8, <Self as FixedOutput>::OutputSize>> {
+        assert!(buffer_size > 0, "Buffer size cannot be zero");
+        let mut buffer = vec![0; buffer_size];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => Input::input(&mut self, &buffer[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(self.fixed_result())
+    }
+
+    /// Compute a digest with a scratchpad from the given provider.
+    ///
+    /// See also: `Digest::fixed_result()`.
+    ///
+    /// # Panics
+    ///
+    /// If the provider returns an unacceptable buffer, this method will panic.
+    pub fn fixed_result_with_provider<P>(self, provider: &mut P) -> GenericArray<u8, <Self as FixedOutput>::OutputSize>
+        where P: ScratchpadProvider + ?Sized {
+        let len = self.scratchpad_size();
+        self.compute(provider.scratchpad(len), ScratchpadSource::Provider)
+    }
+
+    /// Compute a digest with a scratchpad from the given provider.
+    ///
+    /// See also: `Digest::digest()`.
+    ///
+    /// # Panics
+    ///
+    /// If the provider returns an unacceptable buffer, this method will panic.
+    pub fn digest_with_provider<B, P>(data: B, provider: &mut P) -> GenericArray<u8, <Self as FixedOutput>::OutputSize>
+        where B: AsRef<[u8]>, P: ScratchpadProvider + ?Sized {
+        let mut hasher: Self = Default::default();
+        Input::input(&mut hasher, data);
+        hasher.fixed_result_with_provider(provider)
     }
 
     /// Allocate a reusable scratchpad for use with the `_with_buffer` methods.
     ///
-    /// The resulting buffer is guaranteed to be on the heap. Its contents are undefinded.
+    /// The scratchpad is `CryptoNight::SP_SIZE` bytes, enough for every
+    /// variant, and properly aligned. It is freed when dropped. Its contents
+    /// are undefined. See `Scratchpad::allocate_with` for other kinds of
+    /// memory.
     ///
     /// # Usage
     /// ```
@@ -121,23 +611,489 @@ impl CryptoNight {
     ///
     /// CryptoNight::digest_with_buffer(b"Your data", buffer.as_mut());
     /// ```
-    pub fn allocate_scratchpad() -> impl AsMut<[u8]> {
-        unsafe {
-            let buffer = alloc(Layout::from_size_align_unchecked(Self::SP_SIZE, Self::SP_ALIGNMENT));
-            Vec::from_raw_parts(buffer, Self::SP_SIZE, Self::SP_SIZE)
+    pub fn allocate_scratchpad() -> Scratchpad {
+        Scratchpad::allocate(Self::SP_SIZE)
+    }
+
+    /// Allocate a scratchpad of exactly the size `variant` requires.
+    ///
+    /// This is `Variant::scratchpad_size` bytes, which for the lighter
+    /// variants is much less than `CryptoNight::SP_SIZE`. For the soft shell
+    /// variant it is the largest size the variant needs at any height.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest, Variant};
+    /// let mut buffer = CryptoNight::allocate_scratchpad_for(Variant::Lite);
+    /// assert_eq!(buffer.len(), Variant::Lite.scratchpad_size());
+    ///
+    /// CryptoNight::with_variant(Variant::Lite).chain(b"Your data").fixed_result_with_buffer(buffer.as_mut());
+    /// ```
+    pub fn allocate_scratchpad_for(variant: Variant) -> Scratchpad {
+        Scratchpad::allocate(variant.scratchpad_size())
+    }
+
+    /// Run `f` with a scratchpad for the `_with_buffer` methods.
+    ///
+    /// The scratchpad is `CryptoNight::SP_SIZE` bytes and properly aligned,
+    /// so it is accepted for every variant. It is allocated for the call and
+    /// freed afterwards, or with the `thread-local` feature, borrowed from the
+    /// scratchpad kept for the current thread.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest};
+    /// let digests = CryptoNight::with_scratchpad(|scratchpad| {
+    ///     [b"First", b"Other"].map(|input| CryptoNight::digest_with_buffer(input, scratchpad))
+    /// });
+    ///
+    /// assert_eq!(digests[1], CryptoNight::digest(b"Other"));
+    /// ```
+    pub fn with_scratchpad<R, F: FnOnce(&mut [u8]) -> R>(f: F) -> R {
+        scratchpad::with_temporary(Self::SP_SIZE, |scratchpad, _| f(&mut scratchpad[..Self::SP_SIZE]))
+    }
+
+    /// Create a hasher for the given variant.
+    ///
+    /// CryptoNight-R and CryptoNight-WOW hashers created this way use block
+    /// height 0. Use `CryptoNight::with_height` or `cryptonight` to select
+    /// the height.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest, Variant};
+    /// let mut hasher = CryptoNight::with_variant(Variant::V1);
+    /// hasher.input(&[0u8; 76][..]);
+    ///
+    /// let result = hasher.result();
+    /// ```
+    pub fn with_variant(variant: Variant) -> Self {
+        CryptoNight {
+            keccak: KeccakState::new(),
+            variant,
+            input_len: Some(0),
+            tweak_input: [0; 8],
+            height: 0,
+            custom: None,
+        }
+    }
+
+    /// Create a hasher for a variant with custom parameters.
+    ///
+    /// The hasher reports the base of the custom variant as its variant.
+    pub fn with_custom(custom: CustomVariant) -> Self {
+        CryptoNight {
+            custom: Some(custom),
+            ..Self::with_variant(custom.base())
+        }
+    }
+
+    /// Create a CryptoNight-R hasher for a block at the given height.
+    ///
+    /// The height selects the random math program of the main loop, so the
+    /// same input hashes differently at different heights.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest, Variant};
+    /// let mut hasher = CryptoNight::with_height(1_806_260);
+    /// hasher.input(b"Block header");
+    ///
+    /// assert_eq!(hasher.variant(), Variant::R);
+    /// let result = hasher.result();
+    /// ```
+    pub fn with_height(height: u64) -> Self {
+        CryptoNight {
+            height,
+            ..Self::with_variant(Variant::R)
+        }
+    }
+
+    /// Compute the CryptoNight-R digest of the data for a block at the given height.
+    ///
+    /// See also: `Digest::digest()`.
+    pub fn digest_with_height<B: AsRef<[u8]>>(data: B, height: u64) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
+        let mut hasher = Self::with_height(height);
+        Input::input(&mut hasher, data);
+        hasher.fixed_result()
+    }
+
+    /// The variant this hasher computes.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The custom variant this hasher computes, if created with `CryptoNight::with_custom`.
+    pub fn custom(&self) -> Option<CustomVariant> {
+        self.custom
+    }
+
+    /// The block height used by CryptoNight-R, CryptoNight-WOW and the soft
+    /// shell variant.
+    ///
+    /// This is 0 unless set with `CryptoNight::with_height` or
+    /// `CryptoNightSoftShell::with_height`.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Pad the input absorbed so far and hand it to the low-level core.
+    ///
+    /// # Panics
+    ///
+    /// If the input is too short for the variant, see `Variant::min_input_len`.
+    pub fn into_core(mut self) -> CryptoNightCore {
+        self.keccak.pad();
+        let state = self.keccak.to_bytes();
+        let tweaks = self.tweaks(&state);
+
+        CryptoNightCore::with_tweaks(state, tweaks)
+    }
+
+    /// Hand several hashers to the low-level core at once, see `into_core`.
+    ///
+    /// The Keccak states of the hashers are padded together, see
+    /// `KeccakState::pad_all`.
+    fn into_cores<const N: usize>(mut hashers: [Self; N]) -> [CryptoNightCore; N] {
+        KeccakState::pad_all(&mut hashers.iter_mut().map(|hasher| &mut hasher.keccak).collect::<Vec<_>>());
+
+        hashers.map(|hasher| {
+            let state = hasher.keccak.to_bytes();
+            let tweaks = hasher.tweaks(&state);
+
+            CryptoNightCore::with_tweaks(state, tweaks)
+        })
+    }
+
+    fn tweaks(&self, state: &[u8; 200]) -> Tweaks {
+        let mut tweaks = Tweaks::plain(self.variant);
+        tweaks.height = self.height;
+        tweaks.custom = self.custom;
+
+        if self.variant.base() == Variant::V1 {
+            let input_len = self.input_len.unwrap_or_else(|| panic!("{} needs to see the start of the input", self.variant));
+            let min_input_len = self.variant.min_input_len();
+            assert!(input_len >= min_input_len, "{} requires at least {} bytes of input", self.variant, min_input_len);
+
+            tweaks.tweak1_2 = u64::from_le_bytes(self.tweak_input)
+                ^ u64::from_le_bytes(state[192..].try_into().unwrap());
+        }
+
+        tweaks
+    }
+
+    /// Record the input bytes that variant 1 depends on.
+    fn record_tweak_input(&mut self, data: &[u8]) {
+        const TWEAK_INPUT: std::ops::Range<usize> = 35..43;
+
+        if let Some(input_len) = self.input_len.as_mut() {
+            if *input_len < TWEAK_INPUT.end {
+                for (position, &byte) in (*input_len..TWEAK_INPUT.end).zip(data) {
+                    if position >= TWEAK_INPUT.start {
+                        self.tweak_input[position - TWEAK_INPUT.start] = byte;
+                    }
+                }
+            }
+
+            *input_len += data.len();
+        }
+    }
+
+    /// Size of the scratchpad this hasher needs.
+    fn scratchpad_size(&self) -> usize {
+        Tweaks { height: self.height, custom: self.custom, ..Tweaks::plain(self.variant) }.scratchpad_size()
+    }
+
+    /// The part of the buffer used as the scratchpad of the variant.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer is misaligned or too small for the variant.
+    fn scratchpad_for(scratchpad: &mut [u8], tweaks: Tweaks) -> &mut [u8] {
+        let len = tweaks.scratchpad_size();
+        scratchpad::check_buffer(scratchpad, len).unwrap_or_else(|e| panic!("{}", e));
+
+        &mut scratchpad[..len]
+    }
+
+    /// Overwrite memory holding data derived from the input, with the `zeroize` feature.
+    #[inline]
+    fn wipe(buffer: &mut [u8]) {
+        if cfg!(feature = "zeroize") {
+            buffer.fill(0);
+            // The buffer is not read again, so keep the writes from being optimized out.
+            std::hint::black_box(buffer);
+        }
+    }
+
+    fn compute(self, scratchpad: &mut [u8], source: ScratchpadSource) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
+        self.into_core().compute(scratchpad, source)
+    }
+
+    /// Name of the backend used by `digest_phases`.
+    fn backend_name() -> &'static str {
+        match backend::installed() {
+            Some(backend) => backend.name(),
+            None => BuiltinBackend::selected().name(),
+        }
+    }
+
+    /// The part of the buffer the digest runs on.
+    ///
+    /// This is the scratchpad of the variant, followed with the `canary`
+    /// feature by as much of the rest of the buffer as the canary can use.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer is misaligned or too small for the variant.
+    fn digest_buffer_for(scratchpad: &mut [u8], tweaks: Tweaks) -> &mut [u8] {
+        let len = tweaks.scratchpad_size();
+        scratchpad::check_buffer(scratchpad, len).unwrap_or_else(|e| panic!("{}", e));
+        #[cfg(feature = "canary")]
+        let len = scratchpad.len().min(len + canary::CANARY_LEN);
+
+        &mut scratchpad[..len]
+    }
+
+    fn digest_main(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks) {
+        #[cfg(feature = "canary")]
+        {
+            let mut scratchpad = scratchpad;
+            let canary = canary::Canary::arm(&mut scratchpad, tweaks.scratchpad_size());
+            return Self::digest_phases(keccac, scratchpad, tweaks, |phase| canary.check(phase));
         }
+
+        #[allow(unreachable_code)]
+        Self::digest_phases(keccac, scratchpad, tweaks, |_| ())
+    }
+
+    /// Compute two hashes at once, interleaving their main loops if the backend supports it.
+    fn digest_main2(
+        first: &mut [u8], first_scratchpad: &mut [u8], first_tweaks: Tweaks,
+        second: &mut [u8], second_scratchpad: &mut [u8], second_tweaks: Tweaks,
+    ) {
+        Self::digest_main_n([first, second], [first_scratchpad, second_scratchpad], [first_tweaks, second_tweaks]);
     }
 
-    fn digest_main(keccac: &mut [u8], scratchpad: &mut [u8]) {
-        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni"))]
+    /// Compute `N` hashes at once, interleaving their main loops if the backend supports it.
+    fn digest_main_n<const N: usize>(keccacs: [&mut [u8]; N], scratchpads: [&mut [u8]; N], tweaks: [Tweaks; N]) {
+        // The interleaved main loops run in lockstep.
+        let lockstep = tweaks.iter().all(|t| t.lockstep(&tweaks[0]));
+        let backend = BuiltinBackend::selected();
+        if N > 1 && lockstep && backend::installed().is_none() && backend != BuiltinBackend::Aarch64 {
+            #[cfg(feature = "canary")]
+            let (scratchpads, canaries) = canary::arm_all(scratchpads, &tweaks);
+
+            Self::digest_interleaved(backend, keccacs, scratchpads, tweaks);
+
+            #[cfg(feature = "canary")]
+            for canary in &canaries {
+                canary.check(Phase::Finalize);
+            }
+            return;
+        }
+        let hashes = IntoIterator::into_iter(keccacs).zip(scratchpads).zip(tweaks);
+        for ((keccac, scratchpad), tweaks) in hashes {
+            Self::digest_main(keccac, scratchpad, tweaks);
+        }
+    }
+
+    /// Compute `N` hashes with the interleaved main loop of a built-in backend.
+    fn digest_interleaved<const N: usize>(backend: BuiltinBackend, keccacs: [&mut [u8]; N], scratchpads: [&mut [u8]; N], tweaks: [Tweaks; N]) {
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if backend.has_aesni() {
+                    return unsafe { aesni::digest_main_n(keccacs, scratchpads, tweaks) };
+                }
+            }
+        let _ = backend;
+        aes::digest_main_n(keccacs, scratchpads, tweaks);
+    }
+
+    /// Compute four hashes at once, with the `vaes` backend if possible.
+    fn digest_main4(keccacs: [&mut [u8]; 4], scratchpads: [&mut [u8]; 4], tweaks: [Tweaks; 4]) {
+        #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
             {
-                if is_x86_feature_detected!("aes") && is_x86_feature_detected!("sse4.1") {
-                    return unsafe { aesni::digest_main(keccac, scratchpad) };
+                let lockstep = tweaks.iter().all(|t| t.lockstep(&tweaks[0]));
+                let interleave = BuiltinBackend::selected() == BuiltinBackend::VaesAvx512 && backend::installed().is_none();
+                if interleave && lockstep {
+                    #[cfg(feature = "canary")]
+                    let (scratchpads, canaries) = canary::arm_all(scratchpads, &tweaks);
+
+                    unsafe { vaes::digest_main4(keccacs, scratchpads, tweaks) };
+
+                    #[cfg(feature = "canary")]
+                    for canary in &canaries {
+                        canary.check(Phase::Finalize);
+                    }
+                    return;
                 }
             }
-        aes::digest_main(keccac, scratchpad);
+        let [k0, k1, k2, k3] = keccacs;
+        let [s0, s1, s2, s3] = scratchpads;
+        Self::digest_main2(k0, s0, tweaks[0], k1, s1, tweaks[1]);
+        Self::digest_main2(k2, s2, tweaks[2], k3, s3, tweaks[3]);
     }
 
+    /// Run the installed or the selected backend, calling `after_phase` whenever a phase completes.
+    fn digest_phases<F: FnMut(Phase)>(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, mut after_phase: F) {
+        let backend = match backend::installed() {
+            Some(backend) => backend,
+            None => return Self::digest_phases_with(BuiltinBackend::selected(), keccac, scratchpad, tweaks, after_phase),
+        };
+        let keccac: &mut [u8; 200] = keccac.try_into().unwrap();
+
+        backend.init_scratchpad(keccac, scratchpad);
+        after_phase(Phase::Init);
+
+        backend.main_loop(&mut LoopState::new(keccac), scratchpad, tweaks.iterations(), &tweaks);
+        after_phase(Phase::MainLoop);
+
+        backend.finalize(keccac, scratchpad);
+        after_phase(Phase::Finalize);
+    }
+
+    /// Run a built-in backend, calling `after_phase` whenever a phase completes.
+    #[inline]
+    fn digest_phases_with<F: FnMut(Phase)>(backend: BuiltinBackend, keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, after_phase: F) {
+        #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Vaes || backend == BuiltinBackend::VaesAvx512 {
+                    return unsafe { vaes::digest_main(keccac, scratchpad, tweaks, backend == BuiltinBackend::VaesAvx512, after_phase) };
+                }
+            }
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aesni {
+                    return unsafe { aesni::digest_main(keccac, scratchpad, tweaks, after_phase) };
+                }
+            }
+        #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aarch64 {
+                    return unsafe { aarch64::digest_main(keccac, scratchpad, tweaks, after_phase) };
+                }
+            }
+        let _ = backend;
+        aes::digest_main(keccac, scratchpad, tweaks, after_phase);
+    }
+
+    /// Fill the scratchpad from the initial state using the installed or the selected backend.
+    fn init_scratchpad(keccac: &[u8; 200], scratchpad: &mut [u8]) {
+        match backend::installed() {
+            Some(backend) => backend.init_scratchpad(keccac, scratchpad),
+            None => Self::init_scratchpad_with(BuiltinBackend::selected(), keccac, scratchpad),
+        }
+    }
+
+    /// Fill the scratchpad from the initial state using a built-in backend.
+    ///
+    /// The backend must be available.
+    #[inline]
+    fn init_scratchpad_with(backend: BuiltinBackend, keccac: &[u8], scratchpad: &mut [u8]) {
+        #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Vaes || backend == BuiltinBackend::VaesAvx512 {
+                    return unsafe { vaes::init(keccac, scratchpad, backend == BuiltinBackend::VaesAvx512) };
+                }
+            }
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aesni {
+                    return unsafe { aesni::init(keccac, scratchpad) };
+                }
+            }
+        #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aarch64 {
+                    return unsafe { aarch64::init(keccac, scratchpad) };
+                }
+            }
+        let _ = backend;
+        aes::init_scratchpad(keccac, scratchpad);
+    }
+
+    /// Run part of the main loop using the installed or the selected backend.
+    fn main_loop_partial(state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+        match backend::installed() {
+            Some(backend) => backend.main_loop(state, scratchpad, iterations, &tweaks),
+            None => Self::main_loop_with(BuiltinBackend::selected(), state, scratchpad, iterations, tweaks),
+        }
+    }
+
+    /// Run part of the main loop using a built-in backend.
+    ///
+    /// The backend must be available.
+    #[inline]
+    fn main_loop_with(backend: BuiltinBackend, state: &mut LoopState, scratchpad: &mut [u8], iterations: usize, tweaks: Tweaks) {
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if backend.has_aesni() {
+                    return unsafe { aesni::main_loop_partial(state, scratchpad, iterations, tweaks) };
+                }
+            }
+        #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aarch64 {
+                    return unsafe { aarch64::main_loop_partial(state, scratchpad, iterations, tweaks) };
+                }
+            }
+        let _ = backend;
+        aes::main_loop_partial(state, scratchpad, iterations, tweaks);
+    }
+
+    /// Fold the scratchpad back into the state using the installed or the selected backend.
+    fn finalize_scratchpad(keccac: &mut [u8; 200], scratchpad: &[u8]) {
+        match backend::installed() {
+            Some(backend) => backend.finalize(keccac, scratchpad),
+            None => Self::finalize_scratchpad_with(BuiltinBackend::selected(), keccac, scratchpad),
+        }
+    }
+
+    /// Fold the scratchpad back into the state using a built-in backend.
+    ///
+    /// The backend must be available.
+    #[inline]
+    fn finalize_scratchpad_with(backend: BuiltinBackend, keccac: &mut [u8], scratchpad: &[u8]) {
+        #[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Vaes || backend == BuiltinBackend::VaesAvx512 {
+                    return unsafe { vaes::finalize(keccac, scratchpad, backend == BuiltinBackend::VaesAvx512) };
+                }
+            }
+        #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aesni {
+                    return unsafe { aesni::finalize(keccac, scratchpad) };
+                }
+            }
+        #[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+            {
+                if backend == BuiltinBackend::Aarch64 {
+                    return unsafe { aarch64::finalize(keccac, scratchpad) };
+                }
+            }
+        let _ = backend;
+        aes::finalize_state(keccac, scratchpad);
+    }
+
+    /// Permute the final state and compute the resulting digest.
+    fn finish(keccac: &mut [u8; 200]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
+        let mut state = KeccakState::from_bytes(keccac);
+        state.permute();
+        *keccac = state.to_bytes();
+
+        Self::hash_final_state(keccac)
+    }
+
+    /// Permute several final states at once and compute the resulting digests.
+    fn finish_all<const N: usize>(mut keccacs: [&mut [u8; 200]; N]) -> [GenericArray<u8, <Self as FixedOutput>::OutputSize>; N] {
+        keccak::permute_all(&mut keccacs);
+
+        keccacs.map(|keccac| Self::hash_final_state(keccac))
+    }
+
+    #[cfg(not(feature = "constant-time"))]
     fn hash_final_state(state: &[u8]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
         match state[0] & 3 {
             0 => Blake256::digest(&state),
@@ -147,30 +1103,234 @@ impl CryptoNight {
             x => unreachable!("Invalid output option {}", x)
         }
     }
+
+    /// Select the final hash without branching on the state.
+    ///
+    /// All four candidate hashes are computed and the right one is picked
+    /// with masks, so the choice does not show up in the timing. Note that
+    /// the memory access pattern of the main loop still depends on the input.
+    #[cfg(feature = "constant-time")]
+    fn hash_final_state(state: &[u8]) -> GenericArray<u8, <Self as FixedOutput>::OutputSize> {
+        let candidates = [
+            Blake256::digest(state),
+            Groestl256::digest(state),
+            Jh256::digest(state),
+            Skein512::digest(state),
+        ];
+        let selection = state[0] & 3;
+
+        let mut result = GenericArray::default();
+        for (option, candidate) in candidates.iter().enumerate() {
+            // 0xFF if this is the selected option, 0x00 otherwise.
+            let mask = (u16::from(selection ^ option as u8).wrapping_sub(1) >> 8) as u8;
+
+            for (out, byte) in result.iter_mut().zip(candidate.iter()) {
+                *out |= byte & mask;
+            }
+        }
+
+        result
+    }
+}
+
+/// Whether the CPU supports the extensions needed by the `aesni` module.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+fn aesni_available() -> bool {
+    is_x86_feature_detected!("aes") && is_x86_feature_detected!("sse2")
+}
+
+/// Whether the CPU supports the extensions needed by `aes::ssse3`.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+fn ssse3_available() -> bool {
+    is_x86_feature_detected!("ssse3")
+}
+
+/// Whether the CPU supports the extensions needed by `keccak::avx2`.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "aesni", not(feature = "portable")))]
+fn avx2_available() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+/// Whether the CPU supports the extensions needed by the `vaes` module.
+#[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+fn vaes_available() -> bool {
+    aesni_available() && is_x86_feature_detected!("vaes") && is_x86_feature_detected!("avx2")
+}
+
+/// Whether the CPU also supports the 512-bit instructions of the `vaes` module.
+#[cfg(all(target_arch = "x86_64", feature = "vaes", not(feature = "portable")))]
+fn avx512_available() -> bool {
+    vaes_available() && is_x86_feature_detected!("avx512f")
+}
+
+/// Whether the CPU supports the extensions needed by the `aarch64` module.
+#[cfg(all(target_arch = "aarch64", feature = "aesni", not(feature = "portable")))]
+fn arm_aes_available() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes") && std::arch::is_aarch64_feature_detected!("neon")
+}
+
+/// Whether the CPU supports the vector crypto instructions used by `aes::ppc64`.
+#[cfg(all(target_arch = "powerpc64", target_os = "linux", feature = "aesni", not(feature = "portable")))]
+fn ppc_crypto_available() -> bool {
+    /// `PPC_FEATURE2_VEC_CRYPTO` in the `AT_HWCAP2` entry of the auxiliary vector.
+    const VEC_CRYPTO: libc::c_ulong = 0x0200_0000;
+
+    unsafe { libc::getauxval(libc::AT_HWCAP2) & VEC_CRYPTO != 0 }
+}
+
+/// Whether the CPU supports the Zkne extension used by `aes::riscv64`.
+#[cfg(all(target_arch = "riscv64", feature = "aesni", not(feature = "portable")))]
+fn riscv_aes_available() -> bool {
+    std::arch::is_riscv_feature_detected!("zkne")
 }
 
 impl Input for CryptoNight {
     fn input<B: AsRef<[u8]>>(&mut self, data: B) {
-        Input::input(&mut self.internal_hasher, data);
+        let data = data.as_ref();
+        self.record_tweak_input(data);
+        self.keccak.absorb(data);
+    }
+}
+
+/// Writing to the hasher inputs the data. This never fails.
+impl Write for CryptoNight {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Input::input(self, buf);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let mut written = 0;
+        for buf in bufs {
+            Input::input(self, &**buf);
+            written += buf.len();
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Default for CryptoNight {
+    fn default() -> Self {
+        Self::with_variant(Variant::V0)
+    }
+}
+
+/// Continue hashing from a sponge that already absorbed part of the input.
+///
+/// This allows a common prefix to be absorbed once and reused. Since the
+/// hasher does not see the start of the input, it only computes variant 0.
+impl From<KeccakState> for CryptoNight {
+    fn from(keccak: KeccakState) -> Self {
+        CryptoNight {
+            keccak,
+            input_len: None,
+            ..Self::default()
+        }
+    }
+}
+
+/// Hashers are serialized with the input absorbed so far, so hashing can be
+/// resumed later, possibly in another process.
+#[cfg(feature = "serde")]
+impl Serialize for CryptoNight {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hasher = serializer.serialize_struct("CryptoNight", 6)?;
+        hasher.serialize_field("variant", &self.variant)?;
+        hasher.serialize_field("height", &self.height)?;
+        hasher.serialize_field("custom", &self.custom)?;
+        hasher.serialize_field("input_len", &self.input_len)?;
+        hasher.serialize_field("tweak_input", &self.tweak_input)?;
+        hasher.serialize_field("keccak", &self.keccak)?;
+        hasher.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for CryptoNight {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "CryptoNight")]
+        struct Fields {
+            variant: Variant,
+            height: u64,
+            custom: Option<CustomVariant>,
+            input_len: Option<usize>,
+            tweak_input: [u8; 8],
+            keccak: KeccakState,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        if let Some(custom) = fields.custom {
+            if custom.base() != fields.variant {
+                return Err(de::Error::custom("custom variant does not match the variant"));
+            }
+        }
+
+        Ok(CryptoNight {
+            keccak: fields.keccak,
+            variant: fields.variant,
+            input_len: fields.input_len,
+            tweak_input: fields.tweak_input,
+            height: fields.height,
+            custom: fields.custom,
+        })
     }
 }
 
 impl Reset for CryptoNight {
     fn reset(&mut self) {
-        Reset::reset(&mut self.internal_hasher);
+        *self = CryptoNight {
+            height: self.height,
+            custom: self.custom,
+            ..Self::with_variant(self.variant)
+        };
     }
 }
 
 impl BlockInput for CryptoNight {
-    type BlockSize = <sha3::Keccak256Full as BlockInput>::BlockSize;
+    type BlockSize = U136;
 }
 
 impl FixedOutput for CryptoNight {
     type OutputSize = U32;
 
     fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
-        let mut scratchpad = Self::allocate_scratchpad();
-
-        self.fixed_result_with_buffer(scratchpad.as_mut())
+        scratchpad::with_temporary(self.scratchpad_size(), |scratchpad, source| self.compute(scratchpad, source))
     }
 }
+
+/// Compute the digest of the data with a variant selected at runtime.
+///
+/// The height is used by the variants that depend on it, CryptoNight-R,
+/// CryptoNight-WOW and the soft shell variant, and ignored by the others.
+/// Parse the variant from its name to select it by configuration or from a
+/// pool job.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{cryptonight, CryptoNight, Digest, Variant};
+/// let variant: Variant = "cn/r".parse().unwrap();
+/// let result = cryptonight(b"Block header", variant, 1_806_260);
+///
+/// assert_eq!(result[..], CryptoNight::digest_with_height(b"Block header", 1_806_260)[..]);
+/// ```
+///
+/// # Panics
+///
+/// If the input is too short for the variant, see `Variant::min_input_len`.
+pub fn cryptonight(data: &[u8], variant: Variant, height: u64) -> [u8; 32] {
+    let mut hasher = CryptoNight {
+        height,
+        ..CryptoNight::with_variant(variant)
+    };
+    Input::input(&mut hasher, data);
+
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.fixed_result());
+    digest
+}
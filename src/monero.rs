@@ -0,0 +1,100 @@
+//! Monero's proof of work, selecting the variant from the block height.
+//!
+//! Monero changed its proof of work at several hard forks. These helpers
+//! follow the mainnet schedule, so verifiers do not need their own copy of
+//! the fork heights.
+//!
+//! ```
+//! use cryptonight_hash::{monero, Variant};
+//!
+//! assert_eq!(monero::variant_at(1_700_000), Some(Variant::V2));
+//! ```
+use crate::{CoinProfile, Variant};
+
+/// First block of hard fork version 7, which introduced variant 1.
+pub const VARIANT_1_HEIGHT: u64 = 1_546_000;
+/// First block of hard fork version 8, which introduced variant 2.
+pub const VARIANT_2_HEIGHT: u64 = 1_685_555;
+/// First block of hard fork version 10, which introduced CryptoNight-R.
+pub const R_HEIGHT: u64 = 1_788_000;
+/// First block of hard fork version 12, which replaced CryptoNight with RandomX.
+pub const RANDOMX_HEIGHT: u64 = 1_978_433;
+
+/// Block whose proof of work hash is fixed by consensus, working around a
+/// bug at the time it was mined.
+const WORKAROUND_HEIGHT: u64 = 202_612;
+const WORKAROUND_HASH: [u8; 32] = [
+    0x84, 0xf6, 0x47, 0x66, 0x47, 0x5d, 0x51, 0x83, 0x7a, 0xc9, 0xef, 0xbe, 0xf1, 0x92, 0x64, 0x86,
+    0xe5, 0x85, 0x63, 0xc9, 0x5a, 0x19, 0xfe, 0xf4, 0xae, 0xc3, 0x25, 0x4f, 0x03, 0x00, 0x00, 0x00,
+];
+
+/// The CryptoNight variant of Monero's proof of work at the given height.
+///
+/// Returns `None` from the RandomX fork on, as that proof of work is not
+/// CryptoNight.
+pub fn variant_at(height: u64) -> Option<Variant> {
+    match height {
+        h if h >= RANDOMX_HEIGHT => None,
+        h if h >= R_HEIGHT => Some(Variant::R),
+        h if h >= VARIANT_2_HEIGHT => Some(Variant::V2),
+        h if h >= VARIANT_1_HEIGHT => Some(Variant::V1),
+        _ => Some(Variant::V0),
+    }
+}
+
+/// Monero's proof of work rules as a coin profile.
+///
+/// It is also available as `coin_profile("monero")`.
+pub fn profile() -> CoinProfile {
+    CoinProfile::new("monero")
+        .fork(0, Variant::V0)
+        .fork(VARIANT_1_HEIGHT, Variant::V1)
+        .fork(VARIANT_2_HEIGHT, Variant::V2)
+        .fork(R_HEIGHT, Variant::R)
+        .end(RANDOMX_HEIGHT)
+        .fixed_hash(WORKAROUND_HEIGHT, WORKAROUND_HASH)
+}
+
+/// Compute the proof of work hash of a block from its hashing blob.
+///
+/// Returns `None` for heights from the RandomX fork on, see `variant_at`.
+pub fn pow_hash(blob: &[u8], height: u64) -> Option<[u8; 32]> {
+    profile().pow_hash(blob, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cryptonight;
+
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        assert_eq!(variant_at(0), Some(Variant::V0));
+        assert_eq!(variant_at(VARIANT_1_HEIGHT - 1), Some(Variant::V0));
+        assert_eq!(variant_at(VARIANT_1_HEIGHT), Some(Variant::V1));
+        assert_eq!(variant_at(VARIANT_2_HEIGHT), Some(Variant::V2));
+        assert_eq!(variant_at(R_HEIGHT - 1), Some(Variant::V2));
+        assert_eq!(variant_at(R_HEIGHT), Some(Variant::R));
+        assert_eq!(variant_at(RANDOMX_HEIGHT - 1), Some(Variant::R));
+        assert_eq!(variant_at(RANDOMX_HEIGHT), None);
+    }
+
+    #[test]
+    fn test_profile_matches_schedule() {
+        let profile = profile();
+
+        for &height in &[0, VARIANT_1_HEIGHT, VARIANT_2_HEIGHT - 1, VARIANT_2_HEIGHT, R_HEIGHT, RANDOMX_HEIGHT] {
+            assert_eq!(profile.algorithm_at(height), variant_at(height).map(Into::into));
+        }
+    }
+
+    #[test]
+    fn test_pow_hash() {
+        let blob = [0u8; 76];
+
+        assert_eq!(pow_hash(&blob, WORKAROUND_HEIGHT), Some(WORKAROUND_HASH));
+        assert_eq!(pow_hash(&blob, R_HEIGHT), Some(cryptonight(&blob, Variant::R, R_HEIGHT)));
+        assert_eq!(pow_hash(&blob, RANDOMX_HEIGHT), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Compile-time parameters of the CryptoNight variants.
+//!
+//! These constants are the source of truth for this crate's implementation.
+//! They are public so that implementations elsewhere, such as OpenCL or CUDA
+//! kernels, can be parameterized against the same values.
+//!
+//! ```
+//! use cryptonight_hash::params::{V0, VariantParams};
+//!
+//! assert_eq!(V0::SCRATCHPAD_SIZE, 2 * 1024 * 1024);
+//! ```
+use crate::Variant;
+
+/// The parameters that distinguish CryptoNight variants.
+pub trait VariantParams {
+    /// The runtime description of the variant.
+    const VARIANT: Variant;
+
+    /// Conventional name of the variant, e.g. `cn/0`.
+    const NAME: &'static str;
+
+    /// Number of iterations of the main loop.
+    ///
+    /// Every iteration performs two reads and two writes on the scratchpad.
+    /// Note that the reference implementation counts both halves of an
+    /// iteration separately, giving twice this number.
+    const ITERATIONS: usize;
+
+    /// Size of the scratchpad in bytes.
+    const SCRATCHPAD_SIZE: usize;
+
+    /// Mask applied to a 64 bit value to obtain a byte offset into the
+    /// scratchpad. The lowest 4 bits are clear, so offsets are 16 byte aligned.
+    const ADDRESS_MASK: usize;
+
+    /// Number of 128 byte blocks written to the scratchpad during
+    /// initialization, and read back during finalization.
+    const INIT_BLOCKS: usize;
+}
+
+/// The original CryptoNight algorithm, as described by CryptoNote standard 8.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct V0;
+
+impl VariantParams for V0 {
+    const VARIANT: Variant = Variant::V0;
+    const NAME: &'static str = "cn/0";
+    const ITERATIONS: usize = 1 << 19;
+    const SCRATCHPAD_SIZE: usize = 1 << 21;
+    const ADDRESS_MASK: usize = Self::SCRATCHPAD_SIZE - 16;
+    const INIT_BLOCKS: usize = Self::SCRATCHPAD_SIZE / 128;
+}
+
+/// CryptoNight variant 1, also known as the Monero v7 tweak.
+///
+/// Variant 1 changes the main loop only, so it shares its sizes with the
+/// original algorithm.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct V1;
+
+impl VariantParams for V1 {
+    const VARIANT: Variant = Variant::V1;
+    const NAME: &'static str = "cn/1";
+    const ITERATIONS: usize = V0::ITERATIONS;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight variant 2, used by Monero from v8.
+///
+/// Variant 2 adds a division and square root step and shuffles the
+/// neighbouring scratchpad blocks in the main loop.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct V2;
+
+impl VariantParams for V2 {
+    const VARIANT: Variant = Variant::V2;
+    const NAME: &'static str = "cn/2";
+    const ITERATIONS: usize = V0::ITERATIONS;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-R, also known as variant 4, used by Monero from v10.
+///
+/// CryptoNight-R builds on variant 2, replacing the division and square root
+/// step with a random math program that changes with the block height.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct R;
+
+impl VariantParams for R {
+    const VARIANT: Variant = Variant::R;
+    const NAME: &'static str = "cn/r";
+    const ITERATIONS: usize = V0::ITERATIONS;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-Lite, with half the scratchpad and half the iterations.
+///
+/// This is the original algorithm with smaller parameters, also known as
+/// `cn-lite/0`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Lite;
+
+impl VariantParams for Lite {
+    const VARIANT: Variant = Variant::Lite;
+    const NAME: &'static str = "cn-lite/0";
+    const ITERATIONS: usize = 1 << 18;
+    const SCRATCHPAD_SIZE: usize = 1 << 20;
+    const ADDRESS_MASK: usize = Self::SCRATCHPAD_SIZE - 16;
+    const INIT_BLOCKS: usize = Self::SCRATCHPAD_SIZE / 128;
+}
+
+/// CryptoNight-Pico as used by TurtleCoin, also known as `cn-pico/trtl`.
+///
+/// This is variant 2 with a 256 KB scratchpad, which fits in the L2 cache
+/// of most CPUs. Its address mask only covers half of the scratchpad.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Pico;
+
+impl VariantParams for Pico {
+    const VARIANT: Variant = Variant::Pico;
+    const NAME: &'static str = "cn-pico/trtl";
+    const ITERATIONS: usize = 1 << 16;
+    const SCRATCHPAD_SIZE: usize = 1 << 18;
+    const ADDRESS_MASK: usize = (Self::SCRATCHPAD_SIZE / 2) - 16;
+    const INIT_BLOCKS: usize = Self::SCRATCHPAD_SIZE / 128;
+}
+
+/// CryptoNight-Fast as used by Masari, also known as `cn/msr`.
+///
+/// This is variant 1 with half the iterations.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Fast;
+
+impl VariantParams for Fast {
+    const VARIANT: Variant = Variant::Fast;
+    const NAME: &'static str = "cn/fast";
+    const ITERATIONS: usize = V0::ITERATIONS / 2;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-ZLS as used by Zelerius, also known as `cn/zls`.
+///
+/// This is variant 2 with three quarters of the iterations.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Zls;
+
+impl VariantParams for Zls {
+    const VARIANT: Variant = Variant::Zls;
+    const NAME: &'static str = "cn/zls";
+    const ITERATIONS: usize = V0::ITERATIONS / 4 * 3;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-XAO as used by Alloy, also known as `cn/xao`.
+///
+/// This is the original algorithm with twice the iterations.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Xao;
+
+impl VariantParams for Xao {
+    const VARIANT: Variant = Variant::Xao;
+    const NAME: &'static str = "cn/xao";
+    const ITERATIONS: usize = V0::ITERATIONS * 2;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-RTO as used by Arto, also known as `cn/rto`.
+///
+/// This is variant 1, except that the second tweak also mixes the low half
+/// of the stored block into its high half.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Rto;
+
+impl VariantParams for Rto {
+    const VARIANT: Variant = Variant::Rto;
+    const NAME: &'static str = "cn/rto";
+    const ITERATIONS: usize = V0::ITERATIONS;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// CryptoNight-UPX2 as used by uPlexa, also known as `cn/upx2`.
+///
+/// This is variant 2 with a 128 KB scratchpad and 16384 iterations, which
+/// shuffles the neighbouring blocks in the opposite direction.
+/// Unlike `Pico`, its address mask covers the whole scratchpad.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Upx2;
+
+impl VariantParams for Upx2 {
+    const VARIANT: Variant = Variant::Upx2;
+    const NAME: &'static str = "cn/upx2";
+    const ITERATIONS: usize = 1 << 14;
+    const SCRATCHPAD_SIZE: usize = 1 << 17;
+    const ADDRESS_MASK: usize = Self::SCRATCHPAD_SIZE - 16;
+    const INIT_BLOCKS: usize = Self::SCRATCHPAD_SIZE / 128;
+}
+
+/// CryptoNight-Conceal as used by Conceal, also known as `cn/ccx`.
+///
+/// This is the original algorithm with half the iterations. Before the
+/// first transfer, each block read from the scratchpad is mixed with single
+/// precision floating point accumulators that are carried between
+/// iterations.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Conceal;
+
+impl VariantParams for Conceal {
+    const VARIANT: Variant = Variant::Conceal;
+    const NAME: &'static str = "cn/ccx";
+    const ITERATIONS: usize = V0::ITERATIONS / 2;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
+
+/// TurtleCoin's soft shell CryptoNight, also known as `cn-soft-shell/0`.
+///
+/// This is the original algorithm with a scratchpad between 256 KB and
+/// about 1.6 MB, growing and shrinking again over a cycle of 4096 blocks,
+/// with the iterations following along. Like `Pico`, it only addresses
+/// half of the scratchpad.
+///
+/// The parameters depend on the block height, so these constants are the
+/// largest values of the cycle. See `CryptoNightSoftShell` for the values
+/// at a height.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SoftShell;
+
+impl VariantParams for SoftShell {
+    const VARIANT: Variant = Variant::SoftShell;
+    const NAME: &'static str = "cn-soft-shell/0";
+    const ITERATIONS: usize = 414_720;
+    const SCRATCHPAD_SIZE: usize = 1_658_880;
+    const ADDRESS_MASK: usize = (Self::SCRATCHPAD_SIZE / 2) - 16;
+    const INIT_BLOCKS: usize = Self::SCRATCHPAD_SIZE / 128;
+}
+
+/// CryptoNight-WOW, as used by Wownero.
+///
+/// This is an earlier form of CryptoNight-R, with a different random math
+/// program for each height. Its programs never read register 8, and their
+/// result is not mixed into `a`, nor the shuffled blocks into `c`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Wow;
+
+impl VariantParams for Wow {
+    const VARIANT: Variant = Variant::Wow;
+    const NAME: &'static str = "cn/wow";
+    const ITERATIONS: usize = V0::ITERATIONS;
+    const SCRATCHPAD_SIZE: usize = V0::SCRATCHPAD_SIZE;
+    const ADDRESS_MASK: usize = V0::ADDRESS_MASK;
+    const INIT_BLOCKS: usize = V0::INIT_BLOCKS;
+}
@@ -0,0 +1,222 @@
+//! Hardware performance counters for tuning, through Linux' `perf_event_open`.
+//!
+//! The counters measure the calling thread only, in user space only, which
+//! works with the default `perf_event_paranoid` setting on most systems.
+use std::io;
+use std::mem::size_of;
+
+use digest::generic_array::GenericArray;
+
+use crate::{CryptoNight, FixedOutput, Input};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+
+const PERF_COUNT_HW_CACHE_LL: u64 = 2;
+const PERF_COUNT_HW_CACHE_DTLB: u64 = 3;
+const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+
+const FLAG_DISABLED: u64 = 1;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+/// The first published version of `struct perf_event_attr`.
+///
+/// The kernel accepts older, smaller versions of the struct, so there is
+/// no need to mirror the current one.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// Read miss counter for a cache.
+const fn cache_miss(cache: u64) -> u64 {
+    cache | PERF_COUNT_HW_CACHE_OP_READ << 8 | PERF_COUNT_HW_CACHE_RESULT_MISS << 16
+}
+
+/// A single hardware counter.
+struct Counter(libc::c_int);
+
+impl Counter {
+    fn open(kind: u32, config: u64) -> io::Result<Self> {
+        let attr = PerfEventAttr {
+            kind,
+            size: size_of::<PerfEventAttr>() as u32,
+            config,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+
+        let fd = unsafe {
+            libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr, 0, -1, -1, 0)
+        };
+
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Counter(fd as libc::c_int))
+        }
+    }
+
+    fn ioctl(&self, request: libc::c_ulong) {
+        unsafe { libc::ioctl(self.0, request as _, 0) };
+    }
+
+    fn read(&self) -> Option<u64> {
+        let mut value = 0u64;
+        let read = unsafe { libc::read(self.0, &mut value as *mut u64 as *mut libc::c_void, size_of::<u64>()) };
+
+        if read == size_of::<u64>() as isize { Some(value) } else { None }
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Hardware counter values for a single measurement.
+///
+/// Counters that are not available on this machine, or that could not be
+/// opened due to permissions, are `None`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct HashStats {
+    /// CPU cycles.
+    pub cycles: Option<u64>,
+    /// Retired instructions.
+    pub instructions: Option<u64>,
+    /// Last level cache read misses.
+    pub llc_misses: Option<u64>,
+    /// Data TLB read misses.
+    pub dtlb_misses: Option<u64>,
+}
+
+/// A set of hardware counters for the calling thread.
+///
+/// Opening the counters is relatively expensive, so reuse them between
+/// measurements. The counters are bound to the thread that created them.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, PerfCounters};
+/// let counters = PerfCounters::new();
+/// let mut scratchpad = CryptoNight::allocate_scratchpad();
+///
+/// let (digest, stats) = CryptoNight::digest_with_stats(b"Your data", scratchpad.as_mut(), &counters);
+/// if let Some(cycles) = stats.cycles {
+///     println!("Hash took {} cycles", cycles);
+/// }
+/// ```
+pub struct PerfCounters {
+    cycles: Option<Counter>,
+    instructions: Option<Counter>,
+    llc_misses: Option<Counter>,
+    dtlb_misses: Option<Counter>,
+}
+
+impl PerfCounters {
+    /// Open all counters that are available.
+    pub fn new() -> Self {
+        PerfCounters {
+            cycles: Counter::open(PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES).ok(),
+            instructions: Counter::open(PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS).ok(),
+            llc_misses: Counter::open(PERF_TYPE_HW_CACHE, cache_miss(PERF_COUNT_HW_CACHE_LL)).ok(),
+            dtlb_misses: Counter::open(PERF_TYPE_HW_CACHE, cache_miss(PERF_COUNT_HW_CACHE_DTLB)).ok(),
+        }
+    }
+
+    /// Run `f` and report the counter values for its execution.
+    pub fn measure<F: FnOnce() -> R, R>(&self, f: F) -> (R, HashStats) {
+        for counter in self.counters() {
+            counter.ioctl(PERF_EVENT_IOC_RESET);
+            counter.ioctl(PERF_EVENT_IOC_ENABLE);
+        }
+
+        let result = f();
+
+        for counter in self.counters() {
+            counter.ioctl(PERF_EVENT_IOC_DISABLE);
+        }
+
+        let stats = HashStats {
+            cycles: self.cycles.as_ref().and_then(Counter::read),
+            instructions: self.instructions.as_ref().and_then(Counter::read),
+            llc_misses: self.llc_misses.as_ref().and_then(Counter::read),
+            dtlb_misses: self.dtlb_misses.as_ref().and_then(Counter::read),
+        };
+
+        (result, stats)
+    }
+
+    fn counters(&self) -> impl Iterator<Item=&Counter> {
+        self.cycles.iter()
+            .chain(self.instructions.iter())
+            .chain(self.llc_misses.iter())
+            .chain(self.dtlb_misses.iter())
+    }
+}
+
+impl Default for PerfCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CryptoNight {
+    /// Compute a digest with a provided buffer and measure it with hardware counters.
+    ///
+    /// Only the hash computation itself is measured. See `digest_with_buffer`
+    /// for the requirements on the buffer.
+    ///
+    /// # Panics
+    ///
+    /// If the buffer provided is not acceptable, this method will panic.
+    pub fn digest_with_stats<B>(data: B, scratchpad: &mut [u8], counters: &PerfCounters)
+                                -> (GenericArray<u8, <Self as FixedOutput>::OutputSize>, HashStats)
+        where B: AsRef<[u8]> {
+        let mut hasher: Self = Default::default();
+        Input::input(&mut hasher, data);
+
+        counters.measure(|| hasher.fixed_result_with_buffer(scratchpad))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_size() {
+        // PERF_ATTR_SIZE_VER0
+        assert_eq!(size_of::<PerfEventAttr>(), 64);
+    }
+
+    #[test]
+    fn test_measure_without_permissions() {
+        // Whether counters are available depends on the host, but measuring
+        // must work either way.
+        let counters = PerfCounters::new();
+        let (result, _) = counters.measure(|| 42);
+
+        assert_eq!(result, 42);
+    }
+}
@@ -0,0 +1,195 @@
+//! Reusable scratchpads shared between threads.
+use std::sync::Mutex;
+
+use crate::{CryptoNight, MemoryOptions, Scratchpad, ScratchpadProvider};
+
+/// A thread-safe pool of reusable scratchpads.
+///
+/// Worker threads take a scratchpad from the pool for as long as they need
+/// one, and it returns to the pool when dropped. Scratchpads are only
+/// allocated when the pool runs empty, so a pool shared by a fixed number of
+/// threads stops allocating once every thread has taken one.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, Digest, ScratchpadPool};
+/// let pool = ScratchpadPool::new(CryptoNight::SP_SIZE);
+///
+/// std::thread::scope(|scope| {
+///     for i in 0..4u8 {
+///         let pool = &pool;
+///         scope.spawn(move || {
+///             let mut scratchpad = pool.get();
+///             CryptoNight::digest_with_buffer([i], scratchpad.as_mut())
+///         });
+///     }
+/// });
+///
+/// assert!(pool.idle() <= 4);
+/// ```
+pub struct ScratchpadPool {
+    idle: Mutex<Vec<Scratchpad>>,
+    len: usize,
+    options: MemoryOptions,
+}
+
+impl ScratchpadPool {
+    /// Create an empty pool of scratchpads of `len` bytes.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero.
+    pub fn new(len: usize) -> Self {
+        Self::with_options(len, MemoryOptions::default())
+    }
+
+    /// Create an empty pool that allocates its scratchpads with the given options.
+    ///
+    /// See `Scratchpad::allocate_with`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero.
+    pub fn with_options(len: usize, options: MemoryOptions) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+
+        ScratchpadPool {
+            idle: Mutex::new(Vec::new()),
+            len,
+            options,
+        }
+    }
+
+    /// Take a scratchpad from the pool, allocating one if none is idle.
+    pub fn get(&self) -> PooledScratchpad<'_> {
+        let scratchpad = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop()
+            .unwrap_or_else(|| Scratchpad::allocate_with(self.len, self.options));
+
+        PooledScratchpad { scratchpad: Some(scratchpad), pool: self }
+    }
+
+    /// Size of the scratchpads in the pool.
+    pub fn scratchpad_len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of scratchpads waiting in the pool to be reused.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Free all idle scratchpads.
+    ///
+    /// Scratchpads that are in use return to the pool as usual.
+    pub fn shrink(&self) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Pools of `CryptoNight::SP_SIZE` byte scratchpads, enough for every variant.
+impl Default for ScratchpadPool {
+    fn default() -> Self {
+        Self::new(CryptoNight::SP_SIZE)
+    }
+}
+
+/// A scratchpad taken from a `ScratchpadPool`, which returns to it when dropped.
+pub struct PooledScratchpad<'a> {
+    scratchpad: Option<Scratchpad>,
+    pool: &'a ScratchpadPool,
+}
+
+impl PooledScratchpad<'_> {
+    fn inner(&self) -> &Scratchpad {
+        self.scratchpad.as_ref().unwrap()
+    }
+
+    fn inner_mut(&mut self) -> &mut Scratchpad {
+        self.scratchpad.as_mut().unwrap()
+    }
+
+    /// Keep the scratchpad, rather than returning it to the pool.
+    pub fn detach(mut self) -> Scratchpad {
+        self.scratchpad.take().unwrap()
+    }
+}
+
+impl AsRef<[u8]> for PooledScratchpad<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.inner().as_ref()
+    }
+}
+
+impl AsMut<[u8]> for PooledScratchpad<'_> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.inner_mut().as_mut()
+    }
+}
+
+/// Like `Scratchpad`, reallocates when a different size is requested.
+/// Scratchpads that no longer have the size of the pool are freed instead
+/// of returned.
+impl ScratchpadProvider for PooledScratchpad<'_> {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        self.inner_mut().scratchpad(len)
+    }
+}
+
+impl Drop for PooledScratchpad<'_> {
+    fn drop(&mut self) {
+        if let Some(scratchpad) = self.scratchpad.take() {
+            if scratchpad.len() == self.pool.len {
+                self.pool.idle.lock().unwrap_or_else(|e| e.into_inner()).push(scratchpad);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let pool = ScratchpadPool::new(1024);
+
+        let first = pool.get().as_ref().as_ptr();
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(pool.get().as_ref().as_ptr(), first);
+
+        let detached = pool.get().detach();
+        assert_eq!(detached.len(), 1024);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn test_resized_not_returned() {
+        let pool = ScratchpadPool::new(1024);
+
+        pool.get().scratchpad(2048);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn test_threads() {
+        const THREADS: usize = 4;
+        let pool = ScratchpadPool::new(1024);
+        let barrier = Barrier::new(THREADS);
+
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    let mut scratchpad = pool.get();
+                    scratchpad.as_mut().fill(1);
+                    // Hold on to the scratchpads, so every thread gets its own.
+                    barrier.wait();
+                });
+            }
+        });
+
+        assert_eq!(pool.idle(), THREADS);
+    }
+}
@@ -0,0 +1,10 @@
+//! The items needed for typical use of the crate.
+//!
+//! ```
+//! use cryptonight_hash::prelude::*;
+//!
+//! let mut scratchpad = Scratchpad::allocate(CryptoNight::SP_SIZE);
+//! let result = CryptoNight::digest_with_buffer(b"Your data", scratchpad.as_mut());
+//! ```
+pub use crate::{CryptoNight, CryptoNightFast, CryptoNightOf, CryptoNightSoftShell, MemoryOptions, Scratchpad, ScratchpadProvider, Variant, VariantParams};
+pub use crate::{BlockInput, Digest, FixedOutput, Input, Reset};
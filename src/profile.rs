@@ -0,0 +1,216 @@
+//! Proof of work rules of coins, selecting the algorithm from the block height.
+use std::sync::RwLock;
+
+use crate::{monero, wownero, CryptoNight, CustomVariant, FixedOutput, Input, Variant};
+
+/// The proof of work algorithm of a coin from some height on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PowAlgorithm {
+    /// One of the supported variants.
+    Variant(Variant),
+    /// A variant with custom parameters.
+    Custom(CustomVariant),
+}
+
+impl PowAlgorithm {
+    /// Create a hasher for a block at the given height.
+    pub fn hasher(self, height: u64) -> CryptoNight {
+        let hasher = match self {
+            PowAlgorithm::Variant(variant) => CryptoNight::with_variant(variant),
+            PowAlgorithm::Custom(custom) => CryptoNight::with_custom(custom),
+        };
+
+        CryptoNight { height, ..hasher }
+    }
+}
+
+impl From<Variant> for PowAlgorithm {
+    fn from(variant: Variant) -> Self {
+        PowAlgorithm::Variant(variant)
+    }
+}
+
+impl From<CustomVariant> for PowAlgorithm {
+    fn from(custom: CustomVariant) -> Self {
+        PowAlgorithm::Custom(custom)
+    }
+}
+
+/// The proof of work rules of a coin.
+///
+/// A profile lists the heights at which the coin changed its algorithm,
+/// the height from which it stopped using CryptoNight, if any, and blocks
+/// whose hash is fixed by consensus. Register it with `register_profile`
+/// to look it up by name elsewhere.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CoinProfile, Variant};
+/// let profile = CoinProfile::new("example")
+///     .fork(0, Variant::V0)
+///     .fork(100_000, Variant::V2)
+///     .end(200_000);
+///
+/// assert_eq!(profile.algorithm_at(150_000), Some(Variant::V2.into()));
+/// assert_eq!(profile.pow_hash(b"Block hashing blob", 200_000), None);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoinProfile {
+    name: String,
+    /// Algorithm from each height on, sorted by height. `None` marks the end.
+    forks: Vec<(u64, Option<PowAlgorithm>)>,
+    fixed_hashes: Vec<(u64, [u8; 32])>,
+}
+
+impl CoinProfile {
+    /// Create a profile without any algorithm.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        CoinProfile {
+            name: name.into(),
+            forks: Vec::new(),
+            fixed_hashes: Vec::new(),
+        }
+    }
+
+    /// Use the given algorithm from the given height on.
+    ///
+    /// This replaces any other change at the same height.
+    pub fn fork<A: Into<PowAlgorithm>>(self, height: u64, algorithm: A) -> Self {
+        self.transition(height, Some(algorithm.into()))
+    }
+
+    /// Stop using CryptoNight from the given height on.
+    pub fn end(self, height: u64) -> Self {
+        self.transition(height, None)
+    }
+
+    /// Fix the proof of work hash of the block at the given height.
+    pub fn fixed_hash(mut self, height: u64, hash: [u8; 32]) -> Self {
+        self.fixed_hashes.retain(|&(h, _)| h != height);
+        self.fixed_hashes.push((height, hash));
+        self
+    }
+
+    fn transition(mut self, height: u64, algorithm: Option<PowAlgorithm>) -> Self {
+        self.forks.retain(|&(h, _)| h != height);
+        self.forks.push((height, algorithm));
+        self.forks.sort_by_key(|&(h, _)| h);
+        self
+    }
+
+    /// Name of the coin.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The algorithm at the given height.
+    ///
+    /// Returns `None` before the first fork and after the end.
+    pub fn algorithm_at(&self, height: u64) -> Option<PowAlgorithm> {
+        self.forks.iter()
+            .rev()
+            .find(|&&(h, _)| h <= height)
+            .and_then(|&(_, algorithm)| algorithm)
+    }
+
+    /// Compute the proof of work hash of a block from its hashing blob.
+    ///
+    /// Returns `None` at heights without an algorithm.
+    pub fn pow_hash(&self, blob: &[u8], height: u64) -> Option<[u8; 32]> {
+        if let Some(&(_, hash)) = self.fixed_hashes.iter().find(|&&(h, _)| h == height) {
+            return Some(hash);
+        }
+
+        self.algorithm_at(height).map(|algorithm| {
+            let mut hasher = algorithm.hasher(height);
+            Input::input(&mut hasher, blob);
+
+            let mut digest = [0u8; 32];
+            digest.copy_from_slice(&hasher.fixed_result());
+            digest
+        })
+    }
+}
+
+static PROFILES: RwLock<Vec<CoinProfile>> = RwLock::new(Vec::new());
+
+/// Profiles that are available without registering them.
+fn builtin_profile(name: &str) -> Option<CoinProfile> {
+    if name.eq_ignore_ascii_case("monero") {
+        Some(monero::profile())
+    } else if name.eq_ignore_ascii_case("wownero") {
+        Some(wownero::profile())
+    } else {
+        None
+    }
+}
+
+/// Register a profile process-wide, so `coin_profile` can find it by name.
+///
+/// Returns the previously registered profile with the same name, compared
+/// case-insensitively. Registered profiles take precedence over built-in
+/// ones.
+pub fn register_profile(profile: CoinProfile) -> Option<CoinProfile> {
+    let mut profiles = PROFILES.write().unwrap_or_else(|e| e.into_inner());
+
+    match profiles.iter_mut().find(|p| p.name.eq_ignore_ascii_case(&profile.name)) {
+        Some(existing) => Some(std::mem::replace(existing, profile)),
+        None => {
+            profiles.push(profile);
+            None
+        }
+    }
+}
+
+/// Look up a registered or built-in profile by name, case-insensitively.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{coin_profile, Variant};
+/// let monero = coin_profile("monero").unwrap();
+///
+/// assert_eq!(monero.algorithm_at(1_700_000), Some(Variant::V2.into()));
+/// ```
+pub fn coin_profile(name: &str) -> Option<CoinProfile> {
+    let profiles = PROFILES.read().unwrap_or_else(|e| e.into_inner());
+
+    profiles.iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+        .cloned()
+        .or_else(|| builtin_profile(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let profile = CoinProfile::new("test")
+            .fork(100, Variant::V1)
+            .fork(0, Variant::V0)
+            .end(300)
+            .fork(100, Variant::V2);
+
+        assert_eq!(profile.algorithm_at(0), Some(PowAlgorithm::Variant(Variant::V0)));
+        assert_eq!(profile.algorithm_at(99), Some(PowAlgorithm::Variant(Variant::V0)));
+        assert_eq!(profile.algorithm_at(100), Some(PowAlgorithm::Variant(Variant::V2)));
+        assert_eq!(profile.algorithm_at(300), None);
+
+        assert_eq!(CoinProfile::new("empty").algorithm_at(0), None);
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(coin_profile("registry test").is_none());
+        assert!(coin_profile("Monero").is_some());
+
+        let first = CoinProfile::new("Registry Test").fork(0, Variant::V0);
+        let second = CoinProfile::new("registry test").fork(0, Variant::V1);
+
+        assert_eq!(register_profile(first.clone()), None);
+        assert_eq!(coin_profile("registry test"), Some(first.clone()));
+        assert_eq!(register_profile(second.clone()), Some(first));
+        assert_eq!(coin_profile("REGISTRY TEST"), Some(second));
+    }
+}
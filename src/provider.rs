@@ -0,0 +1,97 @@
+//! Abstraction over where scratchpads come from.
+use crate::{ArrayScratchpad, CryptoNight, Scratchpad};
+
+/// A source of scratchpad memory.
+///
+/// Hashers borrow a scratchpad from the provider for the duration of a
+/// single hash. Implement this trait to integrate memory that is managed
+/// elsewhere, such as shared memory or buffers owned by foreign code.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, Scratchpad, ScratchpadProvider};
+/// // A scratchpad is itself a provider, and is reused between hashes.
+/// let mut provider = Scratchpad::allocate(CryptoNight::SP_SIZE);
+///
+/// CryptoNight::digest_with_provider(b"Your data", &mut provider);
+/// ```
+pub trait ScratchpadProvider {
+    /// Lend out a scratchpad of exactly `len` bytes.
+    ///
+    /// The returned memory must be aligned to `CryptoNight::SP_ALIGNMENT`.
+    /// Its contents may be arbitrary.
+    ///
+    /// # Panics
+    ///
+    /// Implementations may panic if they cannot provide the requested size.
+    fn scratchpad(&mut self, len: usize) -> &mut [u8];
+}
+
+/// Owned scratchpads reallocate themselves, with the same options, when a
+/// different size is requested.
+impl ScratchpadProvider for Scratchpad {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        if self.len() != len {
+            *self = Scratchpad::allocate_with(len, self.report().requested);
+        }
+
+        self.as_mut()
+    }
+}
+
+/// Borrowed buffers, such as the slots of a `ScratchpadArena`, are used as
+/// is. Buffers larger than requested are cut to size, so buffers of
+/// `CryptoNight::SP_SIZE` bytes work for every variant.
+impl ScratchpadProvider for [u8] {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        assert!(self.len() >= len, "Provided scratchpad is too small");
+        assert_eq!(self.as_ptr() as usize & (CryptoNight::SP_ALIGNMENT - 1), 0,
+                   "Provided scratchpad is not properly aligned");
+        &mut self[..len]
+    }
+}
+
+/// Inline scratchpads are cut to size like borrowed buffers.
+impl<const N: usize> ScratchpadProvider for ArrayScratchpad<N> {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        self.as_mut().scratchpad(len)
+    }
+}
+
+impl<P: ScratchpadProvider + ?Sized> ScratchpadProvider for &mut P {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        (**self).scratchpad(len)
+    }
+}
+
+impl<P: ScratchpadProvider + ?Sized> ScratchpadProvider for Box<P> {
+    fn scratchpad(&mut self, len: usize) -> &mut [u8] {
+        (**self).scratchpad(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ScratchpadArena;
+
+    use super::*;
+
+    #[test]
+    fn test_scratchpad_reallocates() {
+        let mut scratchpad = Scratchpad::allocate(1024);
+
+        assert_eq!(scratchpad.scratchpad(2048).len(), 2048);
+        assert_eq!(scratchpad.len(), 2048);
+    }
+
+    #[test]
+    fn test_dyn_provider() {
+        let mut arena = ScratchpadArena::new(2, 1024);
+        let mut providers: Vec<Box<dyn ScratchpadProvider>> = vec![Box::new(Scratchpad::allocate(1024))];
+        providers.extend(arena.iter_mut().map(|slot| Box::new(slot) as Box<dyn ScratchpadProvider>));
+
+        for provider in &mut providers {
+            assert_eq!(provider.scratchpad(1024).len(), 1024);
+        }
+    }
+}
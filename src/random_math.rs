@@ -0,0 +1,455 @@
+//! The random math programs of CryptoNight-R.
+//!
+//! Every block height selects a different program of 60 to 70 simple
+//! arithmetic instructions, which the main loop runs on every iteration.
+//! Programs are generated from the height with the same deterministic
+//! procedure as the reference implementation, which also aims for a fixed
+//! latency on typical CPUs.
+//!
+//! CryptoNight-WOW generates its programs from another seed, and never
+//! reads register 8 in them.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blake_hash::Blake256;
+use digest::Digest;
+
+#[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+use crate::jit::NativeProgram;
+
+/// Minimal latency of a program, equivalent to 15 multiplications.
+const TOTAL_LATENCY: usize = 15 * 3;
+/// Minimal number of instructions in a program.
+const NUM_INSTRUCTIONS_MIN: usize = 60;
+/// Maximal number of instructions in a program.
+const NUM_INSTRUCTIONS_MAX: usize = 70;
+/// Number of ALUs available for multiplications.
+const ALU_COUNT_MUL: usize = 1;
+/// Number of ALUs available for other instructions.
+const ALU_COUNT: usize = 3;
+
+/// How the random math programs are run.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum RandomMathEngine {
+    /// Interpret the program, one instruction at a time.
+    ///
+    /// The interpreter is written in safe Rust and works on every platform.
+    Interpreter,
+    /// Compile the program to native code. This is the default.
+    ///
+    /// This requires the `jit` feature on x86_64 unix-like platforms.
+    /// Elsewhere, or if the system refuses to map executable memory, the
+    /// interpreter is used instead. Both give the same results.
+    Jit,
+}
+
+/// Whether the interpreter was selected over the JIT.
+static INTERPRET: AtomicBool = AtomicBool::new(false);
+
+/// Select how CryptoNight-R random math programs are run, process-wide.
+///
+/// Returns the previously selected engine. Hashes that are already running
+/// keep their engine.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{set_random_math_engine, CryptoNight, RandomMathEngine};
+/// // Use the interpreter, for example to audit the JIT.
+/// let previous = set_random_math_engine(RandomMathEngine::Interpreter);
+///
+/// CryptoNight::digest_with_height(b"Your data", 1_806_260);
+/// # set_random_math_engine(previous);
+/// ```
+pub fn set_random_math_engine(engine: RandomMathEngine) -> RandomMathEngine {
+    if INTERPRET.swap(engine == RandomMathEngine::Interpreter, Ordering::AcqRel) {
+        RandomMathEngine::Interpreter
+    } else {
+        RandomMathEngine::Jit
+    }
+}
+
+/// The operations of the random math programs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Opcode {
+    /// `dst * src`
+    Mul,
+    /// `dst + src + C`
+    Add,
+    /// `dst - src`
+    Sub,
+    /// `dst` rotated right by `src` bits
+    Ror,
+    /// `dst` rotated left by `src` bits
+    Rol,
+    /// `dst ^ src`
+    Xor,
+}
+
+impl Opcode {
+    /// Latency in cycles on a typical CPU.
+    fn latency(self) -> usize {
+        match self {
+            Opcode::Mul => 3,
+            Opcode::Add | Opcode::Ror | Opcode::Rol => 2,
+            Opcode::Sub | Opcode::Xor => 1,
+        }
+    }
+
+    /// Latency in cycles on a theoretical ASIC.
+    fn asic_latency(self) -> usize {
+        match self {
+            Opcode::Mul => 3,
+            _ => 1,
+        }
+    }
+
+    /// Number of ALUs that can execute the instruction.
+    fn alus(self) -> usize {
+        match self {
+            Opcode::Mul => ALU_COUNT_MUL,
+            _ => ALU_COUNT,
+        }
+    }
+
+    fn is_rotation(self) -> bool {
+        self == Opcode::Ror || self == Opcode::Rol
+    }
+}
+
+/// A single instruction, operating on the registers `r[dst]` and `r[src]`.
+///
+/// Registers 0 to 3 are variable, registers 4 to 8 are set from the state
+/// of the main loop before every run.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub dst: usize,
+    pub src: usize,
+    /// The constant of an `Add`, zero for other instructions.
+    pub c: u32,
+}
+
+/// The random math program for a block height.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Program {
+    pub(crate) code: Vec<Instruction>,
+}
+
+/// A program ready to be run by the main loop, using the selected engine.
+pub struct Compiled {
+    program: Program,
+    #[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+    native: Option<NativeProgram>,
+}
+
+impl Compiled {
+    /// Run the program on the registers.
+    #[inline]
+    pub fn execute(&self, r: &mut [u32; 9]) {
+        #[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+            {
+                if let Some(native) = self.native.as_ref() {
+                    return native.execute(r);
+                }
+            }
+        self.program.execute(r)
+    }
+}
+
+/// Source of random bytes for the program generator.
+struct Data {
+    bytes: [u8; 32],
+    index: usize,
+}
+
+impl Data {
+    fn new(height: u64, wow: bool) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&height.to_le_bytes());
+        // Change the seed, which CryptoNight-WOW did not do yet.
+        if !wow {
+            bytes[20] = -38i8 as u8;
+        }
+
+        // Start past the end, so the seed is hashed before use.
+        Data { bytes, index: 32 }
+    }
+
+    /// Take `N` bytes, hashing the data to get new bytes if there are not enough.
+    fn take(&mut self, n: usize) -> &[u8] {
+        if self.index + n > self.bytes.len() {
+            let hash = Blake256::digest(&self.bytes);
+            self.bytes.copy_from_slice(&hash);
+            self.index = 0;
+        }
+
+        self.index += n;
+        &self.bytes[self.index - n..self.index]
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+}
+
+impl Program {
+    /// Generate the program for a block height.
+    pub fn generate(height: u64) -> Self {
+        Self::generate_with(height, false)
+    }
+
+    /// Generate the CryptoNight-WOW program for a block height.
+    pub fn generate_wow(height: u64) -> Self {
+        Self::generate_with(height, true)
+    }
+
+    fn generate_with(height: u64, wow: bool) -> Self {
+        let mut data = Data::new(height, wow);
+
+        loop {
+            let (code, r8_used) = Self::generate_candidate(&mut data, wow);
+
+            // In rare cases the program does not use register 8, or ends up
+            // with the wrong length, and the generator tries again.
+            if r8_used && code.len() >= NUM_INSTRUCTIONS_MIN && code.len() <= NUM_INSTRUCTIONS_MAX {
+                return Program { code };
+            }
+        }
+    }
+
+    fn generate_candidate(data: &mut Data, wow: bool) -> (Vec<Instruction>, bool) {
+        let mut code = Vec::with_capacity(NUM_INSTRUCTIONS_MAX);
+        let mut latency = [0usize; 9];
+        let mut asic_latency = [0usize; 9];
+
+        // For registers 0 to 3, tracks the last instruction writing to them:
+        // byte 0 is the current value of the register, byte 1 the opcode and
+        // byte 2 the value of the source register. Registers 4 to 8 are
+        // constant and are treated as having the same value.
+        let mut inst_data: [u32; 9] = [0, 1, 2, 3, 0xFF_FFFF, 0xFF_FFFF, 0xFF_FFFF, 0xFF_FFFF, 0xFF_FFFF];
+
+        let mut alu_busy = [[false; ALU_COUNT]; TOTAL_LATENCY + 1];
+        let mut rotated = [false; 4];
+        let mut rotate_count = 0;
+        let mut num_retries = 0;
+        let mut total_iterations = 0;
+        // CryptoNight-WOW programs don't have to use register 8.
+        let mut r8_used = wow;
+
+        // Generate code until every register reaches the required latency.
+        while latency[..4].iter().any(|&l| l < TOTAL_LATENCY) && num_retries < 64 {
+            // Guarantee termination.
+            total_iterations += 1;
+            if total_iterations > 256 {
+                break;
+            }
+
+            let c = data.byte();
+
+            let opcode = match c & 7 {
+                0..=2 => Opcode::Mul,
+                3 => Opcode::Add,
+                4 => Opcode::Sub,
+                5 => if (data.byte() as i8) >= 0 { Opcode::Ror } else { Opcode::Rol },
+                _ => Opcode::Xor,
+            };
+
+            let dst = usize::from((c >> 3) & 3);
+            let mut src = usize::from((c >> 5) & 7);
+
+            // Don't do ADD/SUB/XOR with the same register, use register 8
+            // instead, or for CryptoNight-WOW the constant register paired
+            // with the destination.
+            if (opcode == Opcode::Add || opcode == Opcode::Sub || opcode == Opcode::Xor) && dst == src {
+                src = if wow { dst + 4 } else { 8 };
+            }
+
+            // Two rotations in a row are equivalent to a single one.
+            if opcode.is_rotation() && rotated[dst] {
+                continue;
+            }
+
+            // Repeating an instruction other than MUL with the same source
+            // value can be optimized into a single instruction.
+            let signature = ((opcode as u32) << 8) + ((inst_data[src] & 255) << 16);
+            if opcode != Opcode::Mul && (inst_data[dst] & 0xFF_FF00) == signature {
+                continue;
+            }
+
+            // Find the first cycle an ALU is available for this instruction.
+            let mut next_latency = latency[dst].max(latency[src]);
+            let mut alu_index = None;
+            while next_latency < TOTAL_LATENCY {
+                for i in (0..opcode.alus()).rev() {
+                    if alu_busy[next_latency][i] {
+                        continue;
+                    }
+
+                    // ADD is two 1-cycle instructions on a real CPU.
+                    if opcode == Opcode::Add && alu_busy[next_latency + 1][i] {
+                        continue;
+                    }
+
+                    // A rotation can only start when the previous one has finished.
+                    if opcode.is_rotation() && next_latency < rotate_count * opcode.latency() {
+                        continue;
+                    }
+
+                    alu_index = Some(i);
+                    break;
+                }
+
+                if alu_index.is_some() {
+                    break;
+                }
+                next_latency += 1;
+            }
+
+            // Don't leave a register unchanged for more than 7 cycles.
+            if next_latency > latency[dst] + 7 {
+                continue;
+            }
+
+            next_latency += opcode.latency();
+
+            if next_latency > TOTAL_LATENCY {
+                num_retries += 1;
+                continue;
+            }
+
+            let alu_index = alu_index.expect("An ALU is available below the total latency");
+            if opcode.is_rotation() {
+                rotate_count += 1;
+            }
+
+            // ALUs are pipelined, so they are only busy during the first cycle.
+            let start = next_latency - opcode.latency();
+            alu_busy[start][alu_index] = true;
+            latency[dst] = next_latency;
+            asic_latency[dst] = asic_latency[dst].max(asic_latency[src]) + opcode.asic_latency();
+            rotated[dst] = opcode.is_rotation();
+            inst_data[dst] = code.len() as u32 + signature;
+
+            if src == 8 {
+                r8_used = true;
+            }
+
+            let mut instruction = Instruction { opcode, dst, src, c: 0 };
+            if opcode == Opcode::Add {
+                alu_busy[start + 1][alu_index] = true;
+
+                let mut constant = [0u8; 4];
+                constant.copy_from_slice(data.take(4));
+                instruction.c = u32::from_le_bytes(constant);
+            }
+
+            code.push(instruction);
+            if code.len() >= NUM_INSTRUCTIONS_MIN {
+                break;
+            }
+        }
+
+        // An ASIC can extract more parallelism from the code, so add MUL and
+        // ROR instructions until one register reaches the required latency.
+        let prev_len = code.len();
+        while code.len() < NUM_INSTRUCTIONS_MAX && asic_latency[..4].iter().all(|&l| l < TOTAL_LATENCY) {
+            let mut min = 0;
+            let mut max = 0;
+            for i in 1..4 {
+                if asic_latency[i] < asic_latency[min] {
+                    min = i;
+                }
+                if asic_latency[i] > asic_latency[max] {
+                    max = i;
+                }
+            }
+
+            let opcode = [Opcode::Ror, Opcode::Mul, Opcode::Mul][(code.len() - prev_len) % 3];
+            latency[min] = latency[max] + opcode.latency();
+            asic_latency[min] = asic_latency[max] + opcode.asic_latency();
+
+            code.push(Instruction { opcode, dst: min, src: max, c: 0 });
+        }
+
+        (code, r8_used)
+    }
+
+    /// Prepare the program to be run by the main loop.
+    pub fn compile(self) -> Compiled {
+        #[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+        let native = if INTERPRET.load(Ordering::Acquire) {
+            None
+        } else {
+            NativeProgram::compile(&self).ok()
+        };
+
+        Compiled {
+            #[cfg(all(target_arch = "x86_64", unix, feature = "jit", not(feature = "portable")))]
+            native,
+            program: self,
+        }
+    }
+
+    /// Run the program on the registers.
+    pub fn execute(&self, r: &mut [u32; 9]) {
+        for instruction in &self.code {
+            let src = r[instruction.src];
+            let dst = &mut r[instruction.dst];
+
+            *dst = match instruction.opcode {
+                Opcode::Mul => dst.wrapping_mul(src),
+                Opcode::Add => dst.wrapping_add(src).wrapping_add(instruction.c),
+                Opcode::Sub => dst.wrapping_sub(src),
+                Opcode::Ror => dst.rotate_right(src),
+                Opcode::Rol => dst.rotate_left(src),
+                Opcode::Xor => *dst ^ src,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        for height in 0..100 {
+            let program = Program::generate(height);
+            let code = &program.code;
+
+            assert!(code.len() >= NUM_INSTRUCTIONS_MIN && code.len() <= NUM_INSTRUCTIONS_MAX);
+            assert!(code.iter().any(|instruction| instruction.src == 8));
+            assert!(code.iter().all(|instruction| instruction.dst < 4));
+            assert_eq!(program, Program::generate(height));
+        }
+    }
+
+    #[test]
+    fn test_generate_wow() {
+        for height in 0..100 {
+            let program = Program::generate_wow(height);
+            let code = &program.code;
+
+            assert!(code.len() >= NUM_INSTRUCTIONS_MIN && code.len() <= NUM_INSTRUCTIONS_MAX);
+            assert!(code.iter().all(|instruction| instruction.src < 8 && instruction.dst < 4));
+            assert_ne!(program, Program::generate(height));
+        }
+    }
+
+    #[test]
+    fn test_execute() {
+        let program = Program {
+            code: vec![
+                Instruction { opcode: Opcode::Add, dst: 0, src: 1, c: 10 },
+                Instruction { opcode: Opcode::Mul, dst: 0, src: 2, c: 0 },
+                Instruction { opcode: Opcode::Ror, dst: 1, src: 0, c: 0 },
+                Instruction { opcode: Opcode::Xor, dst: 2, src: 8, c: 0 },
+            ],
+        };
+
+        let mut r = [1, 2, 3, 0, 0, 0, 0, 0, 0xFF];
+        program.execute(&mut r);
+
+        assert_eq!(r[..3], [39, 2u32.rotate_right(39), 3 ^ 0xFF]);
+    }
+}
@@ -0,0 +1,130 @@
+//! The AES based generators and hash of RandomX.
+//!
+//! The four 16 byte columns of the 64 byte state are processed
+//! independently, alternating between encryption and decryption rounds.
+use crate::aes::{aes_round, aes_round_inverse};
+
+/// Keys of AesGenerator1R, the first 64 bytes of
+/// Blake2b-512("RandomX AesGenerator1R keys").
+const GENERATOR_1R_KEYS: [u128; 4] = [
+    0xb4f4_4917_dbb5_552b_6271_6609_6dac_a553,
+    0x0da1_dc4e_1725_d378_846a_710d_6d7c_af07,
+    0x3e20_e345_f4c0_794f_9f94_7ec6_3f12_62f1,
+    0x4916_9154_1631_4c88_b1ba_317c_6aef_8135,
+];
+
+/// Keys of AesGenerator4R, from Blake2b-512("RandomX AesGenerator4R keys 0-3")
+/// and Blake2b-512("RandomX AesGenerator4R keys 4-7").
+const GENERATOR_4R_KEYS: [u128; 8] = [
+    0x99e5_d23f_2f54_6d2b_d183_3ddb_6421_aadd,
+    0xa5df_cde5_06f7_9d53_b691_3f55_b20e_3450,
+    0x171c_02bf_0aa4_679f_515e_7baf_5c3e_d904,
+    0xd8de_d291_cd67_3785_e78f_5d08_8562_3763,
+    0x229e_ffb4_3d51_8b6d_e3d6_a7a6_b582_6f73,
+    0xb272_b7d2_e902_4d4e_9c10_b3d9_c756_6bf3,
+    0xf63b_efa7_2ba9_660a_f765_a38b_f273_c9e7,
+    0xc0b0_762d_0c06_d1fd_9158_39de_7a7c_d609,
+];
+
+/// Initial state of AesHash1R, Blake2b-512("RandomX AesHash1R state").
+const HASH_STATE: [u128; 4] = [
+    0xd798_3aad_cc82_db47_9fa8_56de_92b5_2c0d,
+    0xace7_8057_f59e_125a_15c7_b798_338d_996e,
+    0xe8a0_7ce4_5079_506b_ae62_c7d0_6a77_0017,
+    0x7e99_4948_79a1_0005_07ad_828d_630a_240c,
+];
+
+/// Keys of the final rounds of AesHash1R, Blake2b-256("RandomX AesHash1R xkeys").
+const HASH_XKEYS: [u128; 2] = [
+    0x0689_0201_90dc_56bf_8b24_949f_f6fa_8389,
+    0xed18_f99b_ee10_43c6_51f4_e03c_61b2_63d1,
+];
+
+/// Run one round on every column, decrypting the columns in `decrypt`.
+fn round(state: &mut [u8; 64], keys: [&[u8; 16]; 4], decrypt: [bool; 4]) {
+    for ((column, key), &decrypt) in state.chunks_exact_mut(16).zip(keys.iter()).zip(decrypt.iter()) {
+        if decrypt {
+            aes_round_inverse(column, &key[..]);
+        } else {
+            aes_round(column, &key[..]);
+        }
+    }
+}
+
+/// Fill `output` with AesGenerator1R, continuing from `state` and updating it.
+pub fn fill_1r(state: &mut [u8; 64], output: &mut [u8]) {
+    let keys: Vec<[u8; 16]> = GENERATOR_1R_KEYS.iter().map(|key| key.to_le_bytes()).collect();
+
+    for chunk in output.chunks_exact_mut(64) {
+        round(state, [&keys[0], &keys[1], &keys[2], &keys[3]], [true, false, true, false]);
+        chunk.copy_from_slice(state);
+    }
+}
+
+/// Fill `output` with AesGenerator4R, starting from `seed`.
+pub fn fill_4r(seed: &[u8; 64], output: &mut [u8]) {
+    let keys: Vec<[u8; 16]> = GENERATOR_4R_KEYS.iter().map(|key| key.to_le_bytes()).collect();
+    let mut state = *seed;
+
+    for chunk in output.chunks_exact_mut(64) {
+        // The first two columns use keys 0 to 3, the others keys 4 to 7.
+        for i in 0..4 {
+            round(&mut state, [&keys[i], &keys[i], &keys[i + 4], &keys[i + 4]], [true, false, true, false]);
+        }
+        chunk.copy_from_slice(&state);
+    }
+}
+
+/// Compute AesHash1R of `input`, whose length must be a multiple of 64 bytes.
+pub fn hash_1r(input: &[u8]) -> [u8; 64] {
+    let mut state = [0u8; 64];
+    for (column, value) in state.chunks_exact_mut(16).zip(HASH_STATE.iter()) {
+        column.copy_from_slice(&value.to_le_bytes());
+    }
+
+    let decrypt = [false, true, false, true];
+    for chunk in input.chunks_exact(64) {
+        let mut columns = [[0u8; 16]; 4];
+        for (column, input) in columns.iter_mut().zip(chunk.chunks_exact(16)) {
+            column.copy_from_slice(input);
+        }
+
+        round(&mut state, [&columns[0], &columns[1], &columns[2], &columns[3]], decrypt);
+    }
+
+    // Two extra rounds for full diffusion.
+    for key in HASH_XKEYS.iter() {
+        let key = key.to_le_bytes();
+        round(&mut state, [&key, &key, &key, &key], decrypt);
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::blake2b;
+    use super::*;
+
+    fn derive(label: &str, len: usize) -> Vec<u128> {
+        let mut bytes = vec![0u8; len];
+        blake2b(label.as_bytes(), &mut bytes);
+
+        bytes.chunks_exact(16)
+            .map(|key| {
+                let mut value = [0u8; 16];
+                value.copy_from_slice(key);
+                u128::from_le_bytes(value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(derive("RandomX AesGenerator1R keys", 64), GENERATOR_1R_KEYS);
+        assert_eq!(derive("RandomX AesGenerator4R keys 0-3", 64), GENERATOR_4R_KEYS[..4]);
+        assert_eq!(derive("RandomX AesGenerator4R keys 4-7", 64), GENERATOR_4R_KEYS[4..]);
+        assert_eq!(derive("RandomX AesHash1R state", 64), HASH_STATE);
+        assert_eq!(derive("RandomX AesHash1R xkeys", 32), HASH_XKEYS);
+    }
+}
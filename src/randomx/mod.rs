@@ -0,0 +1,258 @@
+//! RandomX, the proof of work that replaced CryptoNight on Monero.
+//!
+//! This is the light mode of RandomX, meant for verifying hashes: dataset
+//! items are computed from the 256 MiB cache as they are needed, rather than
+//! from a precomputed 2 GiB dataset. Programs are interpreted, and the
+//! floating point rounding modes are emulated in software, so it is
+//! portable but far too slow for mining.
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use argon2::{Algorithm, Argon2, Block, Params, Version};
+use blake2::Blake2bVar;
+use blake2::digest::{Update, VariableOutput};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+
+use crate::{FixedOutput, Input, Reset};
+
+use self::superscalar::{Blake2Generator, Program};
+use self::vm::Vm;
+
+mod aes_hash;
+mod superscalar;
+mod vm;
+
+/// Argon2 salt of the cache.
+const ARGON_SALT: &[u8] = b"RandomX\x03";
+/// Size of the cache in 1 KiB Argon2 blocks.
+const CACHE_BLOCKS: u32 = 262_144;
+/// Argon2 passes over the cache.
+const CACHE_ITERATIONS: u32 = 3;
+/// SuperscalarHash programs run for each dataset item.
+const CACHE_ACCESSES: usize = 8;
+/// Mask selecting a 64 byte line of the cache.
+const CACHE_LINE_MASK: u64 = (CACHE_BLOCKS as u64) * 16 - 1;
+/// Programs run for each hash.
+const PROGRAM_COUNT: usize = 8;
+
+const SUPERSCALAR_MUL_0: u64 = 6_364_136_223_846_793_005;
+const SUPERSCALAR_ADD: [u64; 7] = [
+    9_298_411_001_130_361_340,
+    12_065_312_585_734_608_966,
+    9_306_329_213_124_626_780,
+    5_281_919_268_842_080_866,
+    10_536_153_434_571_861_004,
+    3_398_623_926_847_679_864,
+    9_549_104_520_008_361_294,
+];
+
+/// Blake2b with an output of up to 64 bytes.
+fn blake2b(input: &[u8], output: &mut [u8]) {
+    let mut hasher = Blake2bVar::new(output.len()).expect("valid Blake2b output size");
+    hasher.update(input);
+    hasher.finalize_variable(output).expect("output has the requested size");
+}
+
+/// The RandomX cache for one key.
+///
+/// Computing the cache takes a while and 256 MiB of memory, and is only
+/// needed when the key changes, every 2048 blocks on Monero. Share it
+/// between hashers with an `Arc`.
+pub struct RandomXCache {
+    key: Vec<u8>,
+    memory: Vec<Block>,
+    programs: Vec<Program>,
+}
+
+impl RandomXCache {
+    /// Compute the cache for the given key.
+    pub fn new(key: &[u8]) -> Self {
+        let params = Params::new(CACHE_BLOCKS, CACHE_ITERATIONS, 1, None).expect("RandomX parameters are valid");
+        let argon2 = Argon2::new(Algorithm::Argon2d, Version::V0x13, params);
+
+        let mut memory = vec![Block::default(); CACHE_BLOCKS as usize];
+        argon2.fill_memory(key, ARGON_SALT, &mut memory[..]).expect("RandomX inputs are valid");
+
+        let mut gen = Blake2Generator::new(key, 0);
+        let programs = (0..CACHE_ACCESSES).map(|_| Program::generate(&mut gen)).collect();
+
+        RandomXCache {
+            key: key.to_vec(),
+            memory,
+            programs,
+        }
+    }
+
+    /// The key of this cache.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Compute the RandomX hash of the input.
+    pub fn hash(&self, input: &[u8]) -> [u8; 32] {
+        let mut seed = [0u8; 64];
+        blake2b(input, &mut seed);
+
+        let mut vm = Vm::new(self, &mut seed);
+        for _ in 1..PROGRAM_COUNT {
+            vm.run(&seed);
+            blake2b(&vm.register_file(), &mut seed);
+        }
+        vm.run(&seed);
+
+        let mut result = [0u8; 32];
+        blake2b(&vm.final_register_file(), &mut result);
+        result
+    }
+
+    fn cache_line(&self, line: u64) -> &[u64] {
+        let line = line as usize;
+        let block = self.memory[line / 16].as_ref();
+        &block[(line % 16) * 8..][..8]
+    }
+
+    /// Compute an item of the dataset.
+    fn dataset_item(&self, item: u64) -> [u64; 8] {
+        let mut r = [0u64; 8];
+        r[0] = (item + 1).wrapping_mul(SUPERSCALAR_MUL_0);
+        for (i, &add) in SUPERSCALAR_ADD.iter().enumerate() {
+            r[i + 1] = r[0] ^ add;
+        }
+
+        let mut register_value = item;
+        for program in &self.programs {
+            let line = self.cache_line(register_value & CACHE_LINE_MASK);
+            program.execute(&mut r);
+
+            for (r, &mix) in r.iter_mut().zip(line) {
+                *r ^= mix;
+            }
+            register_value = r[program.address_register()];
+        }
+
+        r
+    }
+}
+
+impl fmt::Debug for RandomXCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RandomXCache")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+/// RandomX hasher in light mode.
+///
+/// Unlike the CryptoNight hashers, this needs a cache for the key to hash
+/// with, so it does not implement `Default` or `Digest`.
+///
+/// # Usage
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use cryptonight_hash::{FixedOutput, Input, RandomX, RandomXCache};
+/// let cache = Arc::new(RandomXCache::new(b"Seed block hash"));
+///
+/// let mut hasher = RandomX::new(cache.clone());
+/// hasher.input(b"Block hashing blob");
+///
+/// assert_eq!(hasher.fixed_result(), RandomX::digest_with_key(b"Seed block hash", b"Block hashing blob"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RandomX {
+    cache: Arc<RandomXCache>,
+    data: Vec<u8>,
+}
+
+impl RandomX {
+    /// Create a hasher using the given cache.
+    pub fn new(cache: Arc<RandomXCache>) -> Self {
+        RandomX { cache, data: Vec::new() }
+    }
+
+    /// The cache this hasher uses.
+    pub fn cache(&self) -> &Arc<RandomXCache> {
+        &self.cache
+    }
+
+    /// Hash data with the given key in one go.
+    ///
+    /// This computes the cache for the key, so prefer keeping a
+    /// `RandomXCache` around when hashing more than once with a key.
+    pub fn digest_with_key<B: AsRef<[u8]>>(key: &[u8], data: B) -> GenericArray<u8, U32> {
+        let cache = RandomXCache::new(key);
+        GenericArray::clone_from_slice(&cache.hash(data.as_ref()))
+    }
+}
+
+impl Input for RandomX {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.data.extend_from_slice(data.as_ref());
+    }
+}
+
+/// Writing to the hasher inputs the data. This never fails.
+impl Write for RandomX {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Input::input(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Reset for RandomX {
+    fn reset(&mut self) {
+        self.data.clear();
+    }
+}
+
+impl FixedOutput for RandomX {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
+        GenericArray::clone_from_slice(&self.cache.hash(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let cache = RandomXCache::new(b"test key 000");
+
+        assert_eq!(cache.memory[0].as_ref()[0], 0x191e_0e1d_23c0_2186);
+        assert_eq!(cache.memory[1_568_413 / 128].as_ref()[1_568_413 % 128], 0xf1b6_2fe6_210b_f8b1);
+        assert_eq!(cache.memory[33_554_431 / 128].as_ref()[33_554_431 % 128], 0x1f47_f056_d05c_d99b);
+
+        assert_eq!(cache.dataset_item(0)[0], 0x6805_88a8_5ae2_22db);
+        assert_eq!(cache.dataset_item(10_000_000)[0], 0x7943_a1f6_186f_fb72);
+        assert_eq!(cache.dataset_item(20_000_000)[0], 0x9035_244d_7180_95e1);
+        assert_eq!(cache.dataset_item(30_000_000)[0], 0x145a_5091_f785_3099);
+
+        assert_eq!(cache.hash(b"This is a test"), hex!("639183aae1bf4c9a35884cb46b09cad9175f04efd7684e7262a0ac1c2f0b4e3f"));
+        assert_eq!(cache.hash(b"Lorem ipsum dolor sit amet"), hex!("300a0adb47603dedb42228ccb2b211104f4da45af709cd7547cd049e9489c969"));
+        assert_eq!(
+            cache.hash(b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua"),
+            hex!("c36d4ed4191e617309867ed66a443be4075014e2b061bcdaf9ce7b721d2b77a8")
+        );
+    }
+
+    #[test]
+    fn test_digest_with_key() {
+        let input = b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua";
+
+        assert_eq!(
+            RandomX::digest_with_key(b"test key 001", &input[..])[..],
+            hex!("e9ff4503201c0c2cca26d285c93ae883f9b1d30c9eb240b820756f2d5a7905fc")[..]
+        );
+    }
+}
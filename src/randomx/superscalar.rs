@@ -0,0 +1,669 @@
+//! SuperscalarHash, the programs computing the dataset items of RandomX from
+//! the cache.
+//!
+//! The programs are generated by simulating the scheduling of their
+//! instructions on a simple model of a superscalar CPU, so the generator has
+//! to follow the reference implementation step by step.
+use super::blake2b;
+
+/// Target latency of a program, in cycles.
+const LATENCY: usize = 170;
+/// Cycles that can be scheduled, including some slack past the latency.
+const CYCLE_MAP_SIZE: usize = LATENCY + 4;
+/// Cycles to look ahead for a ready source or destination register.
+const LOOK_FORWARD_CYCLES: usize = 4;
+/// Instructions that may be discarded in a row before giving up on a slot.
+const MAX_THROWAWAY_COUNT: usize = 256;
+/// Largest number of instructions in a program.
+const MAX_SIZE: usize = 3 * LATENCY + 2;
+/// IADD_RS cannot write this register, which would need a displacement.
+const REGISTER_NEEDS_DISPLACEMENT: usize = 5;
+
+/// Pseudo-random bytes from repeated Blake2b hashing of a seed.
+pub struct Blake2Generator {
+    data: [u8; 64],
+    index: usize,
+}
+
+impl Blake2Generator {
+    /// Create a generator from a seed, of which at most 60 bytes are used.
+    pub fn new(seed: &[u8], nonce: u32) -> Self {
+        let mut data = [0u8; 64];
+        let len = seed.len().min(60);
+        data[..len].copy_from_slice(&seed[..len]);
+        data[60..].copy_from_slice(&nonce.to_le_bytes());
+
+        Blake2Generator { data, index: 64 }
+    }
+
+    fn refill(&mut self, needed: usize) {
+        if self.index + needed > self.data.len() {
+            let data = self.data;
+            blake2b(&data, &mut self.data);
+            self.index = 0;
+        }
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.refill(1);
+        self.index += 1;
+        self.data[self.index - 1]
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        self.refill(4);
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.data[self.index..self.index + 4]);
+        self.index += 4;
+        u32::from_le_bytes(bytes)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Opcode {
+    ISubR,
+    IXorR,
+    IAddRs,
+    IMulR,
+    IRorC,
+    IAddC7,
+    IXorC7,
+    IAddC8,
+    IXorC8,
+    IAddC9,
+    IXorC9,
+    IMulhR,
+    ISmulhR,
+    IMulRcp,
+}
+
+impl Opcode {
+    fn is_multiplication(self) -> bool {
+        matches!(self, Opcode::IMulR | Opcode::IMulhR | Opcode::ISmulhR | Opcode::IMulRcp)
+    }
+}
+
+/// Execution ports a micro-op can be issued to.
+const P0: u8 = 1;
+const P1: u8 = 2;
+const P5: u8 = 4;
+const P01: u8 = P0 | P1;
+const P05: u8 = P0 | P5;
+const P015: u8 = P0 | P1 | P5;
+
+/// An x86 instruction, made of up to two micro-ops.
+#[derive(Copy, Clone)]
+struct MacroOp {
+    latency: usize,
+    uop1: u8,
+    uop2: u8,
+    dependent: bool,
+}
+
+impl MacroOp {
+    const fn new(latency: usize, uop1: u8, uop2: u8) -> Self {
+        MacroOp { latency, uop1, uop2, dependent: false }
+    }
+}
+
+const SUB_RR: MacroOp = MacroOp::new(1, P015, 0);
+const XOR_RR: MacroOp = MacroOp::new(1, P015, 0);
+const LEA_SIB: MacroOp = MacroOp::new(1, P01, 0);
+const IMUL_RR: MacroOp = MacroOp::new(3, P1, 0);
+const ROR_RI: MacroOp = MacroOp::new(1, P05, 0);
+const ADD_RI: MacroOp = MacroOp::new(1, P015, 0);
+const XOR_RI: MacroOp = MacroOp::new(1, P015, 0);
+/// Eliminated by register renaming.
+const MOV_RR: MacroOp = MacroOp::new(0, 0, 0);
+const MUL_R: MacroOp = MacroOp::new(4, P1, P5);
+const IMUL_R: MacroOp = MacroOp::new(4, P1, P5);
+const MOV_RI64: MacroOp = MacroOp::new(1, P015, 0);
+const IMUL_RR_DEPENDENT: MacroOp = MacroOp { dependent: true, ..IMUL_RR };
+
+/// How an instruction maps onto macro-ops.
+struct InstructionInfo {
+    ops: &'static [MacroOp],
+    /// Macro-op that produces the result.
+    result_op: usize,
+    /// Macro-op at which the destination register is chosen.
+    dst_op: usize,
+    /// Macro-op at which the source register is chosen, if any.
+    src_op: Option<usize>,
+}
+
+impl InstructionInfo {
+    const fn single(op: &'static [MacroOp], src_op: Option<usize>) -> Self {
+        InstructionInfo { ops: op, result_op: 0, dst_op: 0, src_op }
+    }
+}
+
+fn info(opcode: Opcode) -> &'static InstructionInfo {
+    static ISUB_R: InstructionInfo = InstructionInfo::single(&[SUB_RR], Some(0));
+    static IXOR_R: InstructionInfo = InstructionInfo::single(&[XOR_RR], Some(0));
+    static IADD_RS: InstructionInfo = InstructionInfo::single(&[LEA_SIB], Some(0));
+    static IMUL_R_INFO: InstructionInfo = InstructionInfo::single(&[IMUL_RR], Some(0));
+    static IROR_C: InstructionInfo = InstructionInfo::single(&[ROR_RI], None);
+    static IADD_C: InstructionInfo = InstructionInfo::single(&[ADD_RI], None);
+    static IXOR_C: InstructionInfo = InstructionInfo::single(&[XOR_RI], None);
+    static IMULH_R: InstructionInfo = InstructionInfo {
+        ops: &[MOV_RR, MUL_R, MOV_RR],
+        result_op: 1,
+        dst_op: 0,
+        src_op: Some(1),
+    };
+    static ISMULH_R: InstructionInfo = InstructionInfo {
+        ops: &[MOV_RR, IMUL_R, MOV_RR],
+        result_op: 1,
+        dst_op: 0,
+        src_op: Some(1),
+    };
+    static IMUL_RCP: InstructionInfo = InstructionInfo {
+        ops: &[MOV_RI64, IMUL_RR_DEPENDENT],
+        result_op: 1,
+        dst_op: 1,
+        src_op: None,
+    };
+
+    match opcode {
+        Opcode::ISubR => &ISUB_R,
+        Opcode::IXorR => &IXOR_R,
+        Opcode::IAddRs => &IADD_RS,
+        Opcode::IMulR => &IMUL_R_INFO,
+        Opcode::IRorC => &IROR_C,
+        Opcode::IAddC7 | Opcode::IAddC8 | Opcode::IAddC9 => &IADD_C,
+        Opcode::IXorC7 | Opcode::IXorC8 | Opcode::IXorC9 => &IXOR_C,
+        Opcode::IMulhR => &IMULH_R,
+        Opcode::ISmulhR => &ISMULH_R,
+        Opcode::IMulRcp => &IMUL_RCP,
+    }
+}
+
+/// The instruction sizes a decoder cycle can handle, 16 bytes in total.
+struct DecoderBuffer {
+    index: usize,
+    slots: &'static [usize],
+}
+
+const BUFFER_484: DecoderBuffer = DecoderBuffer { index: 0, slots: &[4, 8, 4] };
+const BUFFER_7333: DecoderBuffer = DecoderBuffer { index: 1, slots: &[7, 3, 3, 3] };
+const BUFFER_3733: DecoderBuffer = DecoderBuffer { index: 2, slots: &[3, 7, 3, 3] };
+const BUFFER_493: DecoderBuffer = DecoderBuffer { index: 3, slots: &[4, 9, 3] };
+const BUFFER_4444: DecoderBuffer = DecoderBuffer { index: 4, slots: &[4, 4, 4, 4] };
+const BUFFER_3310: DecoderBuffer = DecoderBuffer { index: 5, slots: &[3, 3, 10] };
+
+fn fetch_next(current: Option<Opcode>, cycle: usize, mul_count: usize, gen: &mut Blake2Generator) -> &'static DecoderBuffer {
+    // The high multiplications need the three byte slots for their moves.
+    if let Some(Opcode::IMulhR) | Some(Opcode::ISmulhR) = current {
+        return &BUFFER_3310;
+    }
+
+    // Keep up at least one multiplication per cycle.
+    if mul_count < cycle + 1 {
+        return &BUFFER_4444;
+    }
+
+    if current == Some(Opcode::IMulRcp) {
+        return if gen.byte() & 1 != 0 { &BUFFER_484 } else { &BUFFER_493 };
+    }
+
+    match gen.byte() & 3 {
+        0 => &BUFFER_484,
+        1 => &BUFFER_7333,
+        2 => &BUFFER_3733,
+        _ => &BUFFER_493,
+    }
+}
+
+#[derive(Copy, Clone)]
+struct RegisterInfo {
+    /// Cycle at which the register is ready.
+    latency: usize,
+    last_group: Option<Opcode>,
+    last_group_par: i64,
+}
+
+impl Default for RegisterInfo {
+    fn default() -> Self {
+        RegisterInfo { latency: 0, last_group: None, last_group_par: -1 }
+    }
+}
+
+/// An instruction being generated.
+struct Candidate {
+    opcode: Opcode,
+    info: &'static InstructionInfo,
+    src: Option<usize>,
+    dst: usize,
+    modifier: u8,
+    imm32: u32,
+    group: Opcode,
+    group_par: i64,
+    can_reuse: bool,
+    group_par_is_source: bool,
+}
+
+impl Candidate {
+    /// Choose an instruction fitting a decoder slot of the given size.
+    fn for_slot(gen: &mut Blake2Generator, size: usize, buffer: &DecoderBuffer, is_last: bool) -> Self {
+        let opcode = match size {
+            3 if is_last => [Opcode::ISubR, Opcode::IXorR, Opcode::IMulhR, Opcode::ISmulhR][(gen.byte() & 3) as usize],
+            3 => [Opcode::ISubR, Opcode::IXorR][(gen.byte() & 1) as usize],
+            4 if buffer.index == BUFFER_4444.index && !is_last => Opcode::IMulR,
+            4 => [Opcode::IRorC, Opcode::IAddRs][(gen.byte() & 1) as usize],
+            7 => [Opcode::IXorC7, Opcode::IAddC7][(gen.byte() & 1) as usize],
+            8 => [Opcode::IXorC8, Opcode::IAddC8][(gen.byte() & 1) as usize],
+            9 => [Opcode::IXorC9, Opcode::IAddC9][(gen.byte() & 1) as usize],
+            10 => Opcode::IMulRcp,
+            _ => unreachable!("no instruction has size {}", size),
+        };
+
+        let mut candidate = Candidate {
+            opcode,
+            info: info(opcode),
+            src: None,
+            dst: 0,
+            modifier: 0,
+            imm32: 0,
+            group: opcode,
+            group_par: -1,
+            can_reuse: false,
+            group_par_is_source: false,
+        };
+
+        match opcode {
+            Opcode::ISubR => {
+                candidate.group = Opcode::IAddRs;
+                candidate.group_par_is_source = true;
+            }
+            Opcode::IXorR | Opcode::IMulR => candidate.group_par_is_source = true,
+            Opcode::IAddRs => {
+                candidate.modifier = gen.byte();
+                candidate.group_par_is_source = true;
+            }
+            Opcode::IRorC => {
+                while candidate.imm32 == 0 {
+                    candidate.imm32 = u32::from(gen.byte() & 63);
+                }
+            }
+            Opcode::IAddC7 | Opcode::IAddC8 | Opcode::IAddC9 => {
+                candidate.imm32 = gen.u32();
+                candidate.group = Opcode::IAddC7;
+            }
+            Opcode::IXorC7 | Opcode::IXorC8 | Opcode::IXorC9 => {
+                candidate.imm32 = gen.u32();
+                candidate.group = Opcode::IXorC7;
+            }
+            Opcode::IMulhR | Opcode::ISmulhR => {
+                candidate.can_reuse = true;
+                candidate.group_par = i64::from(gen.u32() as i32);
+            }
+            Opcode::IMulRcp => {
+                candidate.imm32 = gen.u32();
+                while candidate.imm32 == 0 || candidate.imm32.is_power_of_two() {
+                    candidate.imm32 = gen.u32();
+                }
+            }
+        }
+
+        candidate
+    }
+
+    fn select_source(&mut self, cycle: usize, registers: &[RegisterInfo; 8], gen: &mut Blake2Generator) -> bool {
+        let available: Vec<usize> = (0..8).filter(|&i| registers[i].latency <= cycle).collect();
+
+        // With two candidates, prefer the one IADD_RS cannot use as destination.
+        if available.len() == 2 && self.opcode == Opcode::IAddRs && available.contains(&REGISTER_NEEDS_DISPLACEMENT) {
+            self.src = Some(REGISTER_NEEDS_DISPLACEMENT);
+            self.group_par = REGISTER_NEEDS_DISPLACEMENT as i64;
+            return true;
+        }
+
+        match select_register(&available, gen) {
+            Some(register) => {
+                self.src = Some(register);
+                if self.group_par_is_source {
+                    self.group_par = register as i64;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn select_destination(
+        &mut self,
+        cycle: usize,
+        allow_chained_mul: bool,
+        registers: &[RegisterInfo; 8],
+        gen: &mut Blake2Generator,
+    ) -> bool {
+        let available: Vec<usize> = (0..8)
+            .filter(|&i| {
+                let register = &registers[i];
+
+                register.latency <= cycle
+                    && (self.can_reuse || Some(i) != self.src)
+                    && (allow_chained_mul || self.group != Opcode::IMulR || register.last_group != Some(Opcode::IMulR))
+                    && (register.last_group != Some(self.group) || register.last_group_par != self.group_par)
+                    && (self.opcode != Opcode::IAddRs || i != REGISTER_NEEDS_DISPLACEMENT)
+            })
+            .collect();
+
+        match select_register(&available, gen) {
+            Some(register) => {
+                self.dst = register;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish(&self) -> Instruction {
+        let operation = match self.opcode {
+            Opcode::ISubR => Operation::Sub,
+            Opcode::IXorR => Operation::Xor,
+            Opcode::IAddRs => Operation::AddShifted(u32::from(self.modifier >> 2) & 3),
+            Opcode::IMulR => Operation::Mul,
+            Opcode::IRorC => Operation::RotateRight(self.imm32),
+            Opcode::IAddC7 | Opcode::IAddC8 | Opcode::IAddC9 => Operation::AddConstant(sign_extend(self.imm32)),
+            Opcode::IXorC7 | Opcode::IXorC8 | Opcode::IXorC9 => Operation::XorConstant(sign_extend(self.imm32)),
+            Opcode::IMulhR => Operation::MulHigh,
+            Opcode::ISmulhR => Operation::SignedMulHigh,
+            Opcode::IMulRcp => Operation::MulConstant(reciprocal(self.imm32)),
+        };
+
+        // Instructions without a source read their destination.
+        Instruction { dst: self.dst, src: self.src.unwrap_or(self.dst), operation }
+    }
+}
+
+fn select_register(available: &[usize], gen: &mut Blake2Generator) -> Option<usize> {
+    match available.len() {
+        0 => None,
+        1 => Some(available[0]),
+        len => Some(available[gen.u32() as usize % len]),
+    }
+}
+
+/// Find the first cycle from `cycle` on at which a port for `uop` is free.
+fn schedule_uop(uop: u8, port_busy: &mut [[u8; 3]; CYCLE_MAP_SIZE], mut cycle: usize, commit: bool) -> Option<usize> {
+    while cycle < CYCLE_MAP_SIZE {
+        // Port 5 first, as it is the least used.
+        for &(port, index) in &[(P5, 2), (P0, 0), (P1, 1)] {
+            if uop & port != 0 && port_busy[cycle][index] == 0 {
+                if commit {
+                    port_busy[cycle][index] = uop;
+                }
+                return Some(cycle);
+            }
+        }
+        cycle += 1;
+    }
+
+    None
+}
+
+fn schedule_mop(
+    mop: MacroOp,
+    port_busy: &mut [[u8; 3]; CYCLE_MAP_SIZE],
+    mut cycle: usize,
+    dep_cycle: usize,
+    commit: bool,
+) -> Option<usize> {
+    if mop.dependent {
+        cycle = cycle.max(dep_cycle);
+    }
+
+    if mop.uop1 == 0 {
+        return Some(cycle);
+    }
+
+    if mop.uop2 == 0 {
+        return schedule_uop(mop.uop1, port_busy, cycle, commit);
+    }
+
+    // Both micro-ops have to be issued in the same cycle.
+    while cycle < CYCLE_MAP_SIZE {
+        let cycle1 = schedule_uop(mop.uop1, port_busy, cycle, false);
+        let cycle2 = schedule_uop(mop.uop2, port_busy, cycle, false);
+
+        if let (Some(cycle1), Some(cycle2)) = (cycle1, cycle2) {
+            if cycle1 == cycle2 {
+                if commit {
+                    schedule_uop(mop.uop1, port_busy, cycle1, true);
+                    schedule_uop(mop.uop2, port_busy, cycle2, true);
+                }
+                return Some(cycle1);
+            }
+        }
+        cycle += 1;
+    }
+
+    None
+}
+
+fn sign_extend(imm32: u32) -> u64 {
+    imm32 as i32 as i64 as u64
+}
+
+/// Compute 2^x / divisor for the largest x keeping the result in 64 bits,
+/// for a divisor that is not a power of 2.
+pub fn reciprocal(divisor: u32) -> u64 {
+    let divisor = u64::from(divisor);
+    let p2exp63 = 1u64 << 63;
+    let quotient = p2exp63 / divisor;
+    let remainder = p2exp63 % divisor;
+    let shift = 64 - divisor.leading_zeros();
+
+    (quotient << shift) + ((remainder << shift) / divisor)
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Operation {
+    Sub,
+    Xor,
+    AddShifted(u32),
+    Mul,
+    RotateRight(u32),
+    AddConstant(u64),
+    XorConstant(u64),
+    MulHigh,
+    SignedMulHigh,
+    MulConstant(u64),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Instruction {
+    dst: usize,
+    src: usize,
+    operation: Operation,
+}
+
+/// A generated SuperscalarHash program.
+#[derive(Clone, Debug)]
+pub struct Program {
+    code: Vec<Instruction>,
+    address_register: usize,
+}
+
+impl Program {
+    /// Generate the next program from the generator.
+    pub fn generate(gen: &mut Blake2Generator) -> Self {
+        let mut port_busy = [[0u8; 3]; CYCLE_MAP_SIZE];
+        let mut registers = [RegisterInfo::default(); 8];
+        let mut code = Vec::new();
+
+        let mut current: Option<Candidate> = None;
+        let mut mop_index = 0;
+        let mut cycle = 0;
+        let mut dep_cycle = 0;
+        let mut mul_count = 0;
+        let mut throw_away_count = 0;
+        let mut ports_saturated = false;
+
+        let mut decode_cycle = 0;
+        while decode_cycle < LATENCY && !ports_saturated && code.len() < MAX_SIZE {
+            let buffer = fetch_next(current.as_ref().map(|c| c.opcode), decode_cycle, mul_count, gen);
+            let mut buffer_index = 0;
+
+            while buffer_index < buffer.slots.len() {
+                let top_cycle = cycle;
+
+                if mop_index >= current.as_ref().map_or(0, |c| c.info.ops.len()) {
+                    if ports_saturated || code.len() >= MAX_SIZE {
+                        break;
+                    }
+
+                    let is_last = buffer_index + 1 == buffer.slots.len();
+                    current = Some(Candidate::for_slot(gen, buffer.slots[buffer_index], buffer, is_last));
+                    mop_index = 0;
+                }
+
+                let instruction = current.as_mut().unwrap();
+                let mop = instruction.info.ops[mop_index];
+
+                let mut schedule_cycle = match schedule_mop(mop, &mut port_busy, cycle, dep_cycle, false) {
+                    Some(schedule_cycle) => schedule_cycle,
+                    None => {
+                        ports_saturated = true;
+                        break;
+                    }
+                };
+
+                if instruction.info.src_op == Some(mop_index) {
+                    let mut forward = 0;
+                    while forward < LOOK_FORWARD_CYCLES && !instruction.select_source(schedule_cycle, &registers, gen) {
+                        schedule_cycle += 1;
+                        cycle += 1;
+                        forward += 1;
+                    }
+
+                    if forward == LOOK_FORWARD_CYCLES {
+                        if throw_away_count < MAX_THROWAWAY_COUNT {
+                            throw_away_count += 1;
+                            mop_index = instruction.info.ops.len();
+                            continue;
+                        }
+                        current = None;
+                        break;
+                    }
+                }
+
+                if instruction.info.dst_op == mop_index {
+                    let mut forward = 0;
+                    while forward < LOOK_FORWARD_CYCLES
+                        && !instruction.select_destination(schedule_cycle, throw_away_count > 0, &registers, gen)
+                    {
+                        schedule_cycle += 1;
+                        cycle += 1;
+                        forward += 1;
+                    }
+
+                    if forward == LOOK_FORWARD_CYCLES {
+                        if throw_away_count < MAX_THROWAWAY_COUNT {
+                            throw_away_count += 1;
+                            mop_index = instruction.info.ops.len();
+                            continue;
+                        }
+                        current = None;
+                        break;
+                    }
+                }
+                throw_away_count = 0;
+
+                schedule_cycle = match schedule_mop(mop, &mut port_busy, schedule_cycle, schedule_cycle, true) {
+                    Some(schedule_cycle) => schedule_cycle,
+                    None => {
+                        ports_saturated = true;
+                        break;
+                    }
+                };
+                dep_cycle = schedule_cycle + mop.latency;
+
+                if instruction.info.result_op == mop_index {
+                    let register = &mut registers[instruction.dst];
+                    register.latency = dep_cycle;
+                    register.last_group = Some(instruction.group);
+                    register.last_group_par = instruction.group_par;
+                }
+
+                buffer_index += 1;
+                mop_index += 1;
+                if schedule_cycle >= LATENCY {
+                    ports_saturated = true;
+                }
+                cycle = top_cycle;
+
+                if mop_index >= instruction.info.ops.len() {
+                    code.push(instruction.finish());
+                    if instruction.opcode.is_multiplication() {
+                        mul_count += 1;
+                    }
+                }
+            }
+
+            cycle += 1;
+            decode_cycle += 1;
+        }
+
+        Program {
+            address_register: address_register(&code),
+            code,
+        }
+    }
+
+    /// The register whose value selects the next cache line.
+    pub fn address_register(&self) -> usize {
+        self.address_register
+    }
+
+    /// Run the program on the registers.
+    pub fn execute(&self, r: &mut [u64; 8]) {
+        for instruction in &self.code {
+            let src = r[instruction.src];
+            let dst = &mut r[instruction.dst];
+
+            *dst = match instruction.operation {
+                Operation::Sub => dst.wrapping_sub(src),
+                Operation::Xor => *dst ^ src,
+                Operation::AddShifted(shift) => dst.wrapping_add(src << shift),
+                Operation::Mul => dst.wrapping_mul(src),
+                Operation::RotateRight(amount) => dst.rotate_right(amount),
+                Operation::AddConstant(imm) => dst.wrapping_add(imm),
+                Operation::XorConstant(imm) => *dst ^ imm,
+                Operation::MulHigh => ((u128::from(*dst) * u128::from(src)) >> 64) as u64,
+                Operation::SignedMulHigh => ((i128::from(*dst as i64) * i128::from(src as i64)) >> 64) as u64,
+                Operation::MulConstant(rcp) => dst.wrapping_mul(rcp),
+            };
+        }
+    }
+}
+
+/// The register with the longest dependency chain on an ASIC, which executes
+/// each instruction in one cycle, taking the first one on ties.
+fn address_register(code: &[Instruction]) -> usize {
+    let mut latencies = [0usize; 8];
+
+    for instruction in code {
+        latencies[instruction.dst] = latencies[instruction.dst].max(latencies[instruction.src]) + 1;
+    }
+
+    let max = latencies.iter().copied().max().unwrap_or(0);
+    latencies.iter().position(|&latency| latency == max).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reciprocal() {
+        assert_eq!(reciprocal(3), 12297829382473034410);
+        assert_eq!(reciprocal(13), 11351842506898185609);
+        assert_eq!(reciprocal(33), 17887751829051686415);
+        assert_eq!(reciprocal(65537), 18446462603027742720);
+        assert_eq!(reciprocal(15000001), 10316166306300415204);
+        assert_eq!(reciprocal(3845182035), 10302264209224146340);
+        assert_eq!(reciprocal(0xffff_ffff), 9223372039002259456);
+    }
+}
@@ -0,0 +1,511 @@
+//! The RandomX virtual machine, interpreting the random programs.
+use super::aes_hash;
+use super::superscalar::reciprocal;
+use super::RandomXCache;
+
+/// Size of the scratchpad in bytes.
+pub const SCRATCHPAD_SIZE: usize = 2 * 1024 * 1024;
+/// Instructions in a program.
+const PROGRAM_SIZE: usize = 256;
+/// Times each program is executed.
+const PROGRAM_ITERATIONS: usize = 2048;
+/// Extra dataset items beyond the base size, selecting the dataset offset.
+const DATASET_EXTRA_ITEMS: u64 = 524_287;
+
+const L1_MASK: u64 = 0x3ff8;
+const L2_MASK: u64 = 0x3_fff8;
+const L3_MASK: u64 = 0x1f_fff8;
+/// Mask aligning scratchpad addresses to 64 bytes.
+const L3_MASK_64: u64 = 0x1f_ffc0;
+/// Mask aligning dataset addresses to a cache line.
+const CACHE_LINE_ALIGN_MASK: u32 = 0x7fff_ffc0;
+/// ISTORE writes to the whole scratchpad from this condition on.
+const STORE_L3_CONDITION: u8 = 14;
+/// Lowest condition bit tested by CBRANCH.
+const JUMP_OFFSET: u8 = 8;
+/// Bits flipped by FSCAL_R.
+const SCALE_MASK: u64 = 0x80f0_0000_0000_0000;
+/// Mantissa and the low exponent bits that E registers take from memory.
+const DYNAMIC_MANTISSA_MASK: u64 = (1 << 56) - 1;
+
+const ROUND_DOWN: u32 = 1;
+const ROUND_UP: u32 = 2;
+const ROUND_TO_ZERO: u32 = 3;
+
+#[derive(Copy, Clone, Debug)]
+enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    MulHigh,
+    SignedMulHigh,
+    Xor,
+    RotateRight,
+    RotateLeft,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Address {
+    /// Register added to the offset, or none for absolute addresses.
+    base: Option<usize>,
+    offset: u64,
+    mask: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    Register(usize),
+    Immediate(u64),
+    Memory(Address),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum FloatSource {
+    Register(usize),
+    Memory(Address),
+}
+
+/// A decoded instruction.
+#[derive(Copy, Clone, Debug)]
+enum Instruction {
+    AddShifted { dst: usize, src: usize, shift: u32, imm: u64 },
+    Integer { op: IntOp, dst: usize, src: Source },
+    Negate { dst: usize },
+    Swap { dst: usize, src: usize },
+    /// Swap the halves of an F register, or of an E register from 4 on.
+    FloatSwap { dst: usize },
+    FloatAdd { dst: usize, src: FloatSource },
+    FloatSub { dst: usize, src: FloatSource },
+    FloatScale { dst: usize },
+    FloatMul { dst: usize, src: usize },
+    FloatDiv { dst: usize, src: Address },
+    FloatSqrt { dst: usize },
+    /// Jump to `resume` if the condition bits of the register are zero.
+    Branch { register: usize, imm: u64, mask: u64, resume: usize },
+    SetRounding { src: usize, rotate: u32 },
+    Store { dst: usize, src: usize, imm: u64, mask: u64 },
+    Nop,
+}
+
+fn sign_extend(imm32: u32) -> u64 {
+    imm32 as i32 as i64 as u64
+}
+
+/// Decode a program, resolving the branch targets.
+fn compile(code: &[u8]) -> Vec<Instruction> {
+    // Last instruction that modified each register.
+    let mut register_usage: [Option<usize>; 8] = [None; 8];
+
+    code.chunks_exact(8)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let opcode = bytes[0];
+            let dst = usize::from(bytes[1] & 7);
+            let src = usize::from(bytes[2] & 7);
+            let modifier = bytes[3];
+            let imm32 = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            let imm = sign_extend(imm32);
+
+            let mem_mask = if modifier & 3 != 0 { L1_MASK } else { L2_MASK };
+            let memory = Address { base: Some(src), offset: imm, mask: mem_mask };
+            // Integer instructions use the whole scratchpad or an immediate
+            // instead of their destination as source.
+            let int_memory = if src != dst {
+                memory
+            } else {
+                Address { base: None, offset: imm, mask: L3_MASK }
+            };
+            let int_operand = if src != dst { Source::Register(src) } else { Source::Immediate(imm) };
+
+            let integer = |op, src| Instruction::Integer { op, dst, src };
+
+            let instruction = match opcode {
+                0..=15 => Instruction::AddShifted {
+                    dst,
+                    src,
+                    shift: u32::from(modifier >> 2) & 3,
+                    // The displacement is only encodable with r5 as destination.
+                    imm: if dst == 5 { imm } else { 0 },
+                },
+                16..=22 => integer(IntOp::Add, Source::Memory(int_memory)),
+                23..=38 => integer(IntOp::Sub, int_operand),
+                39..=45 => integer(IntOp::Sub, Source::Memory(int_memory)),
+                46..=61 => integer(IntOp::Mul, int_operand),
+                62..=65 => integer(IntOp::Mul, Source::Memory(int_memory)),
+                66..=69 => integer(IntOp::MulHigh, Source::Register(src)),
+                70 => integer(IntOp::MulHigh, Source::Memory(int_memory)),
+                71..=74 => integer(IntOp::SignedMulHigh, Source::Register(src)),
+                75 => integer(IntOp::SignedMulHigh, Source::Memory(int_memory)),
+                76..=83 => {
+                    if imm32 == 0 || imm32.is_power_of_two() {
+                        Instruction::Nop
+                    } else {
+                        integer(IntOp::Mul, Source::Immediate(reciprocal(imm32)))
+                    }
+                }
+                84..=85 => Instruction::Negate { dst },
+                86..=100 => integer(IntOp::Xor, int_operand),
+                101..=105 => integer(IntOp::Xor, Source::Memory(int_memory)),
+                106..=113 => integer(IntOp::RotateRight, int_operand),
+                114..=115 => integer(IntOp::RotateLeft, int_operand),
+                116..=119 => {
+                    if src != dst {
+                        register_usage[src] = Some(i);
+                        Instruction::Swap { dst, src }
+                    } else {
+                        Instruction::Nop
+                    }
+                }
+                120..=123 => Instruction::FloatSwap { dst },
+                124..=139 => Instruction::FloatAdd { dst: dst & 3, src: FloatSource::Register(src & 3) },
+                140..=144 => Instruction::FloatAdd { dst: dst & 3, src: FloatSource::Memory(memory) },
+                145..=160 => Instruction::FloatSub { dst: dst & 3, src: FloatSource::Register(src & 3) },
+                161..=165 => Instruction::FloatSub { dst: dst & 3, src: FloatSource::Memory(memory) },
+                166..=171 => Instruction::FloatScale { dst: dst & 3 },
+                172..=203 => Instruction::FloatMul { dst: dst & 3, src: src & 3 },
+                204..=207 => Instruction::FloatDiv { dst: dst & 3, src: memory },
+                208..=213 => Instruction::FloatSqrt { dst: dst & 3 },
+                214..=238 => {
+                    let shift = (modifier >> 4) + JUMP_OFFSET;
+                    let imm = (imm | (1 << shift)) & !(1 << (shift - 1));
+                    let resume = register_usage[dst].map_or(0, |target| target + 1);
+
+                    register_usage = [Some(i); 8];
+                    return Instruction::Branch { register: dst, imm, mask: 0xff << shift, resume };
+                }
+                239 => Instruction::SetRounding { src, rotate: imm32 & 63 },
+                240..=255 => {
+                    let mask = if modifier >> 4 >= STORE_L3_CONDITION { L3_MASK } else { mem_mask };
+                    Instruction::Store { dst, src, imm, mask }
+                }
+            };
+
+            match instruction {
+                Instruction::AddShifted { .. }
+                | Instruction::Integer { .. }
+                | Instruction::Negate { .. }
+                | Instruction::Swap { .. } => register_usage[dst] = Some(i),
+                _ => (),
+            }
+
+            instruction
+        })
+        .collect()
+}
+
+/// Bits of a float in [1, 2^32), for the A registers.
+fn small_positive_float_bits(entropy: u64) -> u64 {
+    let exponent = ((entropy >> 59) + 1023) & 0x7ff;
+    (exponent << 52) | (entropy & ((1 << 52) - 1))
+}
+
+/// The exponent and low mantissa bits that E registers are masked with.
+fn float_mask(entropy: u64) -> u64 {
+    let exponent = 0x300 | ((entropy >> 60) << 4);
+    (entropy & ((1 << 22) - 1)) | (exponent << 52)
+}
+
+fn next_up(x: f64) -> f64 {
+    if x == 0.0 {
+        f64::from_bits(1)
+    } else if x > 0.0 {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+fn next_down(x: f64) -> f64 {
+    -next_up(-x)
+}
+
+/// Round a result computed to nearest in the given mode, where `error` has
+/// the sign of the exact result minus the rounded one.
+fn round(result: f64, error: f64, mode: u32) -> f64 {
+    match mode {
+        ROUND_DOWN if error < 0.0 => next_down(result),
+        ROUND_UP if error > 0.0 => next_up(result),
+        ROUND_TO_ZERO if error < 0.0 && result > 0.0 => next_down(result),
+        ROUND_TO_ZERO if error > 0.0 && result < 0.0 => next_up(result),
+        _ => result,
+    }
+}
+
+// The operations below emulate the directed rounding modes, as Rust only
+// computes in round to nearest. The exact error of the rounded result is
+// recovered with an error free transformation to decide the direction.
+
+fn add(a: f64, b: f64, mode: u32) -> f64 {
+    let sum = a + b;
+    if sum == 0.0 {
+        // Exact zeros are negative when rounding down, unless both are positive.
+        return if mode == ROUND_DOWN && (a.is_sign_negative() || b.is_sign_negative()) { -0.0 } else { sum };
+    }
+
+    let b_virtual = sum - a;
+    let error = (a - (sum - b_virtual)) + (b - b_virtual);
+    round(sum, error, mode)
+}
+
+fn mul(a: f64, b: f64, mode: u32) -> f64 {
+    let product = a * b;
+    round(product, a.mul_add(b, -product), mode)
+}
+
+fn div(a: f64, b: f64, mode: u32) -> f64 {
+    let quotient = a / b;
+    let remainder = (-quotient).mul_add(b, a);
+    round(quotient, if b < 0.0 { -remainder } else { remainder }, mode)
+}
+
+fn sqrt(a: f64, mode: u32) -> f64 {
+    let root = a.sqrt();
+    round(root, (-root).mul_add(root, a), mode)
+}
+
+fn float_pair(operation: impl Fn(f64, f64) -> f64, a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [operation(a[0], b[0]), operation(a[1], b[1])]
+}
+
+fn xor_pair(a: [f64; 2], mask: [u64; 2]) -> [f64; 2] {
+    [f64::from_bits(a[0].to_bits() ^ mask[0]), f64::from_bits(a[1].to_bits() ^ mask[1])]
+}
+
+/// The virtual machine of one hash, in light mode.
+pub struct Vm<'a> {
+    cache: &'a RandomXCache,
+    scratchpad: Vec<u8>,
+    r: [u64; 8],
+    f: [[f64; 2]; 4],
+    e: [[f64; 2]; 4],
+    a: [[f64; 2]; 4],
+    rounding: u32,
+}
+
+impl<'a> Vm<'a> {
+    /// Create a machine with its scratchpad filled from the seed, which is
+    /// updated to seed the first program.
+    pub fn new(cache: &'a RandomXCache, seed: &mut [u8; 64]) -> Self {
+        let mut scratchpad = vec![0u8; SCRATCHPAD_SIZE];
+        aes_hash::fill_1r(seed, &mut scratchpad);
+
+        Vm {
+            cache,
+            scratchpad,
+            r: [0; 8],
+            f: [[0.0; 2]; 4],
+            e: [[0.0; 2]; 4],
+            a: [[0.0; 2]; 4],
+            rounding: 0,
+        }
+    }
+
+    fn load64(&self, address: u64) -> u64 {
+        let address = address as usize;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.scratchpad[address..address + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn store64(&mut self, address: u64, value: u64) {
+        let address = address as usize;
+        self.scratchpad[address..address + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Load two 32 bit signed integers as floats.
+    fn load_float_pair(&self, address: u64) -> [f64; 2] {
+        let value = self.load64(address);
+        [f64::from(value as i32), f64::from((value >> 32) as i32)]
+    }
+
+    fn address(&self, address: Address) -> u64 {
+        address.base.map_or(0, |base| self.r[base]).wrapping_add(address.offset) & address.mask
+    }
+
+    fn source(&self, source: Source) -> u64 {
+        match source {
+            Source::Register(src) => self.r[src],
+            Source::Immediate(imm) => imm,
+            Source::Memory(address) => self.load64(self.address(address)),
+        }
+    }
+
+    fn float_source(&self, source: FloatSource) -> [f64; 2] {
+        match source {
+            FloatSource::Register(src) => self.a[src],
+            FloatSource::Memory(address) => self.load_float_pair(self.address(address)),
+        }
+    }
+
+    /// Generate a program from the seed and run it.
+    pub fn run(&mut self, seed: &[u8; 64]) {
+        let mut buffer = [0u8; 128 + 8 * PROGRAM_SIZE];
+        aes_hash::fill_4r(seed, &mut buffer);
+
+        let entropy = |i: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buffer[8 * i..8 * i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        for (i, a) in self.a.iter_mut().enumerate() {
+            *a = [
+                f64::from_bits(small_positive_float_bits(entropy(2 * i))),
+                f64::from_bits(small_positive_float_bits(entropy(2 * i + 1))),
+            ];
+        }
+
+        let mut ma = entropy(8) as u32 & CACHE_LINE_ALIGN_MASK;
+        let mut mx = entropy(10) as u32;
+        let read_registers = entropy(12);
+        let read_register = |i: usize| 2 * i + ((read_registers >> i) & 1) as usize;
+        let dataset_offset = (entropy(13) % (DATASET_EXTRA_ITEMS + 1)) * 64;
+        let e_mask = [float_mask(entropy(14)), float_mask(entropy(15))];
+
+        let program = compile(&buffer[128..]);
+
+        self.r = [0; 8];
+        let mut sp_addr0 = u64::from(mx);
+        let mut sp_addr1 = u64::from(ma);
+
+        for _ in 0..PROGRAM_ITERATIONS {
+            let sp_mix = self.r[read_register(0)] ^ self.r[read_register(1)];
+            sp_addr0 = (sp_addr0 ^ sp_mix) & L3_MASK_64;
+            sp_addr1 = (sp_addr1 ^ (sp_mix >> 32)) & L3_MASK_64;
+
+            for i in 0..8 {
+                self.r[i] ^= self.load64(sp_addr0 + 8 * i as u64);
+            }
+
+            for i in 0..4 {
+                self.f[i] = self.load_float_pair(sp_addr1 + 8 * i as u64);
+                let e = self.load_float_pair(sp_addr1 + 8 * (i as u64 + 4));
+                self.e[i] = [
+                    f64::from_bits((e[0].to_bits() & DYNAMIC_MANTISSA_MASK) | e_mask[0]),
+                    f64::from_bits((e[1].to_bits() & DYNAMIC_MANTISSA_MASK) | e_mask[1]),
+                ];
+            }
+
+            self.execute(&program, e_mask);
+
+            mx ^= (self.r[read_register(2)] ^ self.r[read_register(3)]) as u32;
+            mx &= CACHE_LINE_ALIGN_MASK;
+
+            let item = self.cache.dataset_item((dataset_offset + u64::from(ma)) / 64);
+            for (r, item) in self.r.iter_mut().zip(item.iter()) {
+                *r ^= item;
+            }
+            std::mem::swap(&mut mx, &mut ma);
+
+            for i in 0..8 {
+                self.store64(sp_addr1 + 8 * i as u64, self.r[i]);
+            }
+
+            // The F registers keep the mixed values, which end up in the register file.
+            for i in 0..4 {
+                self.f[i] = xor_pair(self.f[i], [self.e[i][0].to_bits(), self.e[i][1].to_bits()]);
+                self.store64(sp_addr0 + 16 * i as u64, self.f[i][0].to_bits());
+                self.store64(sp_addr0 + 16 * i as u64 + 8, self.f[i][1].to_bits());
+            }
+
+            sp_addr0 = 0;
+            sp_addr1 = 0;
+        }
+    }
+
+    fn execute(&mut self, program: &[Instruction], e_mask: [u64; 2]) {
+        let mut pc = 0;
+
+        while pc < program.len() {
+            match program[pc] {
+                Instruction::AddShifted { dst, src, shift, imm } => {
+                    self.r[dst] = self.r[dst].wrapping_add((self.r[src] << shift).wrapping_add(imm));
+                }
+                Instruction::Integer { op, dst, src } => {
+                    let src = self.source(src);
+                    let dst = &mut self.r[dst];
+
+                    *dst = match op {
+                        IntOp::Add => dst.wrapping_add(src),
+                        IntOp::Sub => dst.wrapping_sub(src),
+                        IntOp::Mul => dst.wrapping_mul(src),
+                        IntOp::MulHigh => ((u128::from(*dst) * u128::from(src)) >> 64) as u64,
+                        IntOp::SignedMulHigh => ((i128::from(*dst as i64) * i128::from(src as i64)) >> 64) as u64,
+                        IntOp::Xor => *dst ^ src,
+                        IntOp::RotateRight => dst.rotate_right(src as u32 & 63),
+                        IntOp::RotateLeft => dst.rotate_left(src as u32 & 63),
+                    };
+                }
+                Instruction::Negate { dst } => self.r[dst] = self.r[dst].wrapping_neg(),
+                Instruction::Swap { dst, src } => self.r.swap(dst, src),
+                Instruction::FloatSwap { dst } => {
+                    let register = if dst < 4 { &mut self.f[dst] } else { &mut self.e[dst - 4] };
+                    register.swap(0, 1);
+                }
+                Instruction::FloatAdd { dst, src } => {
+                    let (src, mode) = (self.float_source(src), self.rounding);
+                    self.f[dst] = float_pair(|a, b| add(a, b, mode), self.f[dst], src);
+                }
+                Instruction::FloatSub { dst, src } => {
+                    let (src, mode) = (self.float_source(src), self.rounding);
+                    self.f[dst] = float_pair(|a, b| add(a, -b, mode), self.f[dst], src);
+                }
+                Instruction::FloatScale { dst } => self.f[dst] = xor_pair(self.f[dst], [SCALE_MASK; 2]),
+                Instruction::FloatMul { dst, src } => {
+                    let mode = self.rounding;
+                    self.e[dst] = float_pair(|a, b| mul(a, b, mode), self.e[dst], self.a[src]);
+                }
+                Instruction::FloatDiv { dst, src } => {
+                    let loaded = self.load_float_pair(self.address(src));
+                    let divisor = [
+                        f64::from_bits((loaded[0].to_bits() & DYNAMIC_MANTISSA_MASK) | e_mask[0]),
+                        f64::from_bits((loaded[1].to_bits() & DYNAMIC_MANTISSA_MASK) | e_mask[1]),
+                    ];
+                    let mode = self.rounding;
+                    self.e[dst] = float_pair(|a, b| div(a, b, mode), self.e[dst], divisor);
+                }
+                Instruction::FloatSqrt { dst } => {
+                    let mode = self.rounding;
+                    self.e[dst] = [sqrt(self.e[dst][0], mode), sqrt(self.e[dst][1], mode)];
+                }
+                Instruction::Branch { register, imm, mask, resume } => {
+                    self.r[register] = self.r[register].wrapping_add(imm);
+                    if self.r[register] & mask == 0 {
+                        pc = resume;
+                        continue;
+                    }
+                }
+                Instruction::SetRounding { src, rotate } => self.rounding = (self.r[src].rotate_right(rotate) & 3) as u32,
+                Instruction::Store { dst, src, imm, mask } => {
+                    let address = self.r[dst].wrapping_add(imm) & mask;
+                    self.store64(address, self.r[src]);
+                }
+                Instruction::Nop => (),
+            }
+
+            pc += 1;
+        }
+    }
+
+    /// The register file, as hashed between programs.
+    pub fn register_file(&self) -> [u8; 256] {
+        let mut bytes = [0u8; 256];
+        let words = self.r.iter()
+            .copied()
+            .chain(self.f.iter().chain(self.e.iter()).chain(self.a.iter()).flatten().map(|x| x.to_bits()));
+
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// The register file with the A registers replaced by the hash of the
+    /// scratchpad, which the final hash is computed from.
+    pub fn final_register_file(&self) -> [u8; 256] {
+        let mut bytes = self.register_file();
+        bytes[192..].copy_from_slice(&aes_hash::hash_1r(&self.scratchpad));
+        bytes
+    }
+}
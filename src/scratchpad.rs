@@ -0,0 +1,1238 @@
+//! Owned scratchpad memory with configurable backing.
+//!
+//! The scratchpad is by far the largest allocation CryptoNight needs, and
+//! how it is backed has a measurable impact on performance. This module
+//! allows allocating it on huge pages, locked into RAM or in memory shared
+//! with child processes where the platform supports it.
+//!
+//! With the `guard-pages` feature enabled on unix-like platforms, every
+//! scratchpad is surrounded by inaccessible pages, so out of bounds accesses
+//! fault instead of silently corrupting other memory. This is meant for
+//! developing new backends and is not suitable for production use.
+#[cfg(not(feature = "safe"))]
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::error::Error;
+use std::fmt;
+use std::io;
+#[cfg(not(feature = "safe"))]
+use std::ptr::NonNull;
+#[cfg(not(feature = "safe"))]
+use std::slice;
+#[cfg(feature = "thread-local")]
+use std::cell::Cell;
+#[cfg(feature = "allocator-api")]
+use allocator_api2::alloc::Allocator;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{CryptoNight, ScratchpadSource, Variant};
+
+/// Options for allocating a scratchpad.
+///
+/// All options are best effort: if the platform does not support them, or
+/// the system refuses them, the allocation falls back to regular memory.
+/// Use `Scratchpad::report` to check what was granted.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MemoryOptions {
+    /// Back the scratchpad with huge pages.
+    pub huge_pages: bool,
+    /// Lock the scratchpad into RAM, so it is never swapped out.
+    ///
+    /// Besides steadier performance, this keeps the data derived from the
+    /// input out of swap. Only the scratchpad is locked: the Keccak state is
+    /// kept in the hasher and on the stack, which are not covered.
+    pub lock: bool,
+    /// Map the scratchpad as shared memory.
+    ///
+    /// A shared scratchpad stays shared with processes forked after it was
+    /// allocated, instead of being copied on write. This allows a supervisor
+    /// to set up the scratchpads, for instance as a `ScratchpadArena` on
+    /// huge pages, and hand them to forked worker processes.
+    ///
+    /// This is only supported on unix-like platforms. Windows cannot fork,
+    /// so scratchpads stay private there and the report does not grant it.
+    pub shared: bool,
+}
+
+/// What happened when allocating a scratchpad.
+///
+/// An error code is only present if the system refused the option. If an
+/// option was requested but neither granted nor refused, the platform does
+/// not support it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct AllocationReport {
+    /// The options that were asked for.
+    pub requested: MemoryOptions,
+    /// The options that were actually applied.
+    pub granted: MemoryOptions,
+    /// OS error code for the failed huge page allocation, if any.
+    pub huge_pages_errno: Option<i32>,
+    /// OS error code for the failed memory lock, if any.
+    pub lock_errno: Option<i32>,
+    /// Whether the kernel was advised to back the scratchpad with transparent
+    /// huge pages, because explicit huge pages were refused. Linux only.
+    ///
+    /// The kernel may still use regular pages, for instance when
+    /// transparent huge pages are disabled or memory is fragmented.
+    pub transparent_huge_pages: bool,
+}
+
+impl AllocationReport {
+    /// Whether any requested option was not granted.
+    pub fn is_degraded(&self) -> bool {
+        self.requested != self.granted
+    }
+}
+
+impl fmt::Display for AllocationReport {
+    /// Describe the options that were not granted, and why.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn describe(f: &mut fmt::Formatter, what: &str, errno: Option<i32>) -> fmt::Result {
+            match errno {
+                Some(errno) => write!(f, "{} failed: {}", what, io::Error::from_raw_os_error(errno)),
+                None => write!(f, "{} not supported on this platform", what),
+            }
+        }
+
+        if !self.is_degraded() {
+            return f.write_str("all requested options granted");
+        }
+
+        let missing = [
+            (self.requested.huge_pages && !self.granted.huge_pages, "huge pages", self.huge_pages_errno),
+            (self.requested.lock && !self.granted.lock, "locking memory", self.lock_errno),
+            (self.requested.shared && !self.granted.shared, "shared memory", None),
+        ];
+
+        let mut separator = "";
+        for &(_, what, errno) in missing.iter().filter(|(missing, _, _)| *missing) {
+            f.write_str(separator)?;
+            describe(f, what, errno)?;
+            if what == "huge pages" && self.transparent_huge_pages {
+                f.write_str(", advised transparent huge pages instead")?;
+            }
+            separator = "; ";
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a buffer cannot be used as a scratchpad.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ScratchpadError {
+    /// The buffer is smaller than the variant needs.
+    WrongLength {
+        /// Number of bytes the variant needs.
+        required: usize,
+        /// Length of the buffer.
+        actual: usize,
+    },
+    /// The buffer does not start at a multiple of `CryptoNight::SP_ALIGNMENT`.
+    Misaligned {
+        /// Address of the start of the buffer.
+        address: usize,
+    },
+}
+
+impl fmt::Display for ScratchpadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScratchpadError::WrongLength { required, actual } => {
+                write!(f, "scratchpad of {} bytes is too small, {} bytes are required", actual, required)
+            }
+            ScratchpadError::Misaligned { address } => {
+                write!(f, "scratchpad at {:#x} is not aligned to {} bytes", address, CryptoNight::SP_ALIGNMENT)
+            }
+        }
+    }
+}
+
+impl Error for ScratchpadError {}
+
+/// Check that `buffer` can be used as a scratchpad of `len` bytes.
+pub(crate) fn check_buffer(buffer: &[u8], len: usize) -> Result<(), ScratchpadError> {
+    let address = buffer.as_ptr() as usize;
+    if address & (CryptoNight::SP_ALIGNMENT - 1) != 0 {
+        Err(ScratchpadError::Misaligned { address })
+    } else if buffer.len() < len {
+        Err(ScratchpadError::WrongLength { required: len, actual: buffer.len() })
+    } else {
+        Ok(())
+    }
+}
+
+/// Scratchpad memory currently held by the crate.
+///
+/// This covers every live `Scratchpad`, including those returned by
+/// `CryptoNight::allocate_scratchpad`, those backing a `ScratchpadArena`
+/// and the temporary buffers used by `Digest::result`. Caller-provided
+/// buffers are not included.
+///
+/// Sizes are what was actually obtained from the system, which may be
+/// more than requested due to rounding to the page size.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MemoryUsage {
+    /// Number of live scratchpads.
+    pub scratchpads: usize,
+    /// Total number of bytes held.
+    pub bytes: usize,
+    /// Number of bytes backed by huge pages.
+    pub huge_page_bytes: usize,
+    /// Number of bytes locked into RAM.
+    pub locked_bytes: usize,
+}
+
+static SCRATCHPADS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static HUGE_PAGE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Report how much scratchpad memory is currently held.
+///
+/// The counters are read individually, so the result may be slightly
+/// inconsistent while other threads allocate or free scratchpads.
+pub fn memory_usage() -> MemoryUsage {
+    MemoryUsage {
+        scratchpads: SCRATCHPADS.load(Ordering::Relaxed),
+        bytes: BYTES.load(Ordering::Relaxed),
+        huge_page_bytes: HUGE_PAGE_BYTES.load(Ordering::Relaxed),
+        locked_bytes: LOCKED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(feature = "thread-local")]
+thread_local! {
+    /// The scratchpad reused by `with_temporary` on this thread, while it is not in use.
+    static CACHED: Cell<Option<Scratchpad>> = const { Cell::new(None) };
+}
+
+/// Run `f` with a scratchpad of at least `len` bytes that nobody else holds.
+///
+/// The scratchpad is allocated for the call. With the `thread-local` feature,
+/// a scratchpad of `CryptoNight::SP_SIZE` bytes is kept per thread and reused
+/// instead, so that only the first call on each thread allocates.
+pub(crate) fn with_temporary<R>(len: usize, f: impl FnOnce(&mut [u8], ScratchpadSource) -> R) -> R {
+    // Leave room for the canary after the scratchpad.
+    #[cfg(feature = "canary")]
+    let len = len + crate::canary::CANARY_LEN;
+
+    #[cfg(feature = "thread-local")]
+        {
+            // The scratchpad is taken out of the cell while in use, so nested
+            // hashes, say from a hash observer, allocate their own.
+            let cached = CACHED.try_with(Cell::take).ok().flatten().filter(|s| s.len() >= len);
+            let (mut scratchpad, source) = match cached {
+                Some(scratchpad) => (scratchpad, ScratchpadSource::ThreadLocal),
+                None => (Scratchpad::allocate(len.max(CryptoNight::SP_SIZE)), ScratchpadSource::Allocated),
+            };
+
+            let result = f(scratchpad.as_mut(), source);
+            // Fails if the thread is exiting, which frees the scratchpad.
+            let _ = CACHED.try_with(|cached| cached.set(Some(scratchpad)));
+            return result;
+        }
+
+    #[allow(unreachable_code)]
+    {
+        let mut scratchpad = Scratchpad::allocate(len);
+        f(scratchpad.as_mut(), ScratchpadSource::Allocated)
+    }
+}
+
+/// How the memory of a scratchpad was obtained.
+enum Backing {
+    /// Allocated through the global allocator with the given layout.
+    #[cfg(not(feature = "safe"))]
+    Heap(Layout),
+    /// Part of a vector, starting at an aligned offset. Used by safe builds.
+    #[cfg(feature = "safe")]
+    Vec { buffer: Vec<u8>, offset: usize },
+    /// Allocated with the given layout from a custom allocator.
+    #[cfg(feature = "allocator-api")]
+    Custom { layout: Layout, allocator: Box<dyn Allocator + Send + Sync> },
+    /// Mapped directly from the operating system, starting at `base`.
+    #[cfg(all(any(unix, windows), not(feature = "safe")))]
+    Mapped { base: NonNull<u8>, map_len: usize },
+    /// Owned by the caller, see `Scratchpad::from_raw_parts`. Not freed.
+    #[cfg(not(feature = "safe"))]
+    External,
+}
+
+/// An owned, properly aligned scratchpad buffer.
+///
+/// The contents of a freshly allocated scratchpad are undefined.
+pub struct Scratchpad {
+    #[cfg(not(feature = "safe"))]
+    ptr: NonNull<u8>,
+    len: usize,
+    backing: Backing,
+    report: AllocationReport,
+}
+
+// The scratchpad exclusively owns its memory, like a Box<[u8]> would.
+#[cfg(not(feature = "safe"))]
+unsafe impl Send for Scratchpad {}
+
+#[cfg(not(feature = "safe"))]
+unsafe impl Sync for Scratchpad {}
+
+impl Scratchpad {
+    /// Allocate a scratchpad of `len` bytes from the global allocator.
+    ///
+    /// With the `guard-pages` feature, the scratchpad is mapped between
+    /// guard pages instead.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero.
+    pub fn allocate(len: usize) -> Self {
+        if cfg!(feature = "guard-pages") {
+            Self::allocate_with(len, MemoryOptions::default())
+        } else {
+            Self::allocate_heap(len)
+        }
+    }
+
+    #[cfg(not(feature = "safe"))]
+    fn allocate_heap(len: usize) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+        let layout = Layout::from_size_align(len, CryptoNight::SP_ALIGNMENT)
+            .expect("Scratchpad size overflows");
+
+        let ptr = unsafe { alloc(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+
+        Scratchpad {
+            ptr,
+            len,
+            backing: Backing::Heap(layout),
+            report: AllocationReport::default(),
+        }.tracked()
+    }
+
+    #[cfg(feature = "safe")]
+    fn allocate_heap(len: usize) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+
+        // Over-allocate so an aligned start can be found without unsafe code.
+        let buffer = vec![0u8; len + CryptoNight::SP_ALIGNMENT - 1];
+        let offset = buffer.as_ptr().align_offset(CryptoNight::SP_ALIGNMENT);
+
+        Scratchpad {
+            len,
+            backing: Backing::Vec { buffer, offset },
+            report: AllocationReport::default(),
+        }.tracked()
+    }
+
+    /// Allocate a scratchpad of `len` bytes from a custom allocator.
+    ///
+    /// This puts scratchpads in memory managed elsewhere, such as an arena
+    /// shared with a GPU runtime. The allocator is kept with the scratchpad
+    /// and frees it when dropped. When used as a `ScratchpadProvider`, the
+    /// scratchpad is reallocated from the global allocator if resized.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero. If the allocator fails,
+    /// `handle_alloc_error` is called.
+    #[cfg(feature = "allocator-api")]
+    pub fn allocate_in<A: Allocator + Send + Sync + 'static>(len: usize, allocator: A) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+        let layout = Layout::from_size_align(len, CryptoNight::SP_ALIGNMENT)
+            .expect("Scratchpad size overflows");
+
+        let ptr = allocator.allocate(layout).unwrap_or_else(|_| handle_alloc_error(layout));
+
+        Scratchpad {
+            ptr: ptr.cast(),
+            len,
+            backing: Backing::Custom { layout, allocator: Box::new(allocator) },
+            report: AllocationReport::default(),
+        }.tracked()
+    }
+
+    /// Use memory owned elsewhere as a scratchpad, without copying.
+    ///
+    /// This allows FFI callers, pinned host buffers of a GPU runtime or
+    /// shared memory segments to serve as scratchpads. The memory is not
+    /// freed when the scratchpad is dropped, and is not included in
+    /// `memory_usage`. When used as a `ScratchpadProvider`, the scratchpad
+    /// is replaced by a newly allocated one if resized.
+    ///
+    /// An error is returned if `ptr` is not aligned to
+    /// `CryptoNight::SP_ALIGNMENT`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes of `len` bytes for as long
+    /// as the scratchpad exists, from any thread it is sent to, and must not
+    /// be accessed through any other pointer during that time.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `ptr` is null or `len` is zero.
+    #[cfg(not(feature = "safe"))]
+    pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize) -> Result<Self, ScratchpadError> {
+        let ptr = NonNull::new(ptr).expect("Scratchpad pointer is null");
+        assert!(len > 0, "Scratchpad cannot be empty");
+
+        let address = ptr.as_ptr() as usize;
+        if address & (CryptoNight::SP_ALIGNMENT - 1) != 0 {
+            return Err(ScratchpadError::Misaligned { address });
+        }
+
+        Ok(Scratchpad {
+            ptr,
+            len,
+            backing: Backing::External,
+            report: AllocationReport::default(),
+        })
+    }
+
+    /// Allocate a scratchpad large enough for every variant.
+    ///
+    /// This is `CryptoNight::SP_SIZE` bytes. Servers hashing jobs of
+    /// several variants can keep one such scratchpad per worker and use the
+    /// start of it for each job, see `Scratchpad::for_variant`.
+    pub fn allocate_max() -> Self {
+        Self::allocate(CryptoNight::SP_SIZE)
+    }
+
+    /// Allocate a scratchpad of `len` bytes on huge pages, if possible.
+    ///
+    /// A CryptoNight scratchpad fits a single 2 MB huge page, which avoids
+    /// nearly all TLB misses in the main loop. If huge pages cannot be
+    /// obtained, the scratchpad is allocated normally. See
+    /// `Scratchpad::huge_pages` to find out which happened.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero.
+    pub fn allocate_huge(len: usize) -> Self {
+        Self::allocate_with(len, MemoryOptions { huge_pages: true, ..Default::default() })
+    }
+
+    /// Allocate a scratchpad of `len` bytes with the given options.
+    ///
+    /// Huge pages are supported on Linux and Android, and on Windows as large
+    /// pages, for which the user needs the "Lock pages in memory" privilege.
+    /// Locking is supported on all unix-like platforms and Windows, and shared
+    /// memory only on unix-like platforms. None of them are available with the
+    /// `safe` feature. When no options are requested this is equivalent to
+    /// `Scratchpad::allocate`.
+    ///
+    /// Options that could not be applied are recorded in the report, see
+    /// `Scratchpad::report`. Huge pages are never granted when the
+    /// `guard-pages` feature is enabled.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `len` is zero.
+    pub fn allocate_with(len: usize, options: MemoryOptions) -> Self {
+        #[cfg(all(unix, not(feature = "safe")))]
+            {
+                if options.huge_pages || options.lock || options.shared || cfg!(feature = "guard-pages") {
+                    return Self::allocate_mapped(len, options);
+                }
+            }
+
+        #[cfg(all(windows, not(feature = "safe")))]
+            {
+                if options.huge_pages || options.lock {
+                    return Self::allocate_virtual(len, options);
+                }
+            }
+
+        let mut scratchpad = Self::allocate_heap(len);
+        scratchpad.report.requested = options;
+        scratchpad
+    }
+
+    /// Allocate a scratchpad directly from the Windows virtual memory manager.
+    #[cfg(all(windows, not(feature = "safe")))]
+    fn allocate_virtual(len: usize, options: MemoryOptions) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+
+        let mut report = AllocationReport { requested: options, ..Default::default() };
+
+        let huge_mapping = if options.huge_pages {
+            map_huge(len).map_err(|e| report.huge_pages_errno = e.raw_os_error()).ok()
+        } else {
+            None
+        };
+        report.granted.huge_pages = huge_mapping.is_some();
+
+        let (ptr, map_len) = huge_mapping.unwrap_or_else(|| {
+            let map_len = round_up(len, page_size());
+            let ptr = map(map_len, 0).unwrap_or_else(|_| {
+                handle_alloc_error(Layout::from_size_align(map_len, CryptoNight::SP_ALIGNMENT).unwrap())
+            });
+            (ptr, map_len)
+        });
+
+        if options.lock {
+            if report.granted.huge_pages {
+                // Large pages are never paged out.
+                report.granted.lock = true;
+            } else {
+                lock(ptr, map_len, &mut report);
+            }
+        }
+
+        Scratchpad {
+            ptr,
+            len,
+            backing: Backing::Mapped { base: ptr, map_len },
+            report,
+        }.tracked()
+    }
+
+    #[cfg(all(unix, not(feature = "safe")))]
+    fn allocate_mapped(len: usize, options: MemoryOptions) -> Self {
+        assert!(len > 0, "Scratchpad cannot be empty");
+
+        let mut report = AllocationReport { requested: options, ..Default::default() };
+
+        if cfg!(feature = "guard-pages") {
+            return Self::allocate_guarded(len, options);
+        }
+
+        let huge_mapping = if options.huge_pages {
+            map_huge(len, options.shared).map_err(|e| report.huge_pages_errno = e.raw_os_error()).ok()
+        } else {
+            None
+        };
+        report.granted.huge_pages = huge_mapping.is_some();
+        report.granted.shared = options.shared;
+
+        let (ptr, map_len) = huge_mapping.unwrap_or_else(|| {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+                {
+                    if options.huge_pages {
+                        let (ptr, map_len, advised) = map_transparent_huge(len, options.shared);
+                        report.transparent_huge_pages = advised;
+                        return (ptr, map_len);
+                    }
+                }
+
+            let map_len = round_up(len, page_size());
+            let ptr = map(map_len, options.shared, 0).unwrap_or_else(|_| {
+                handle_alloc_error(Layout::from_size_align(map_len, CryptoNight::SP_ALIGNMENT).unwrap())
+            });
+            (ptr, map_len)
+        });
+
+        if options.lock {
+            lock(ptr, map_len, &mut report);
+        }
+
+        Scratchpad {
+            ptr,
+            len,
+            backing: Backing::Mapped { base: ptr, map_len },
+            report,
+        }.tracked()
+    }
+
+    /// Map a scratchpad between two inaccessible guard pages.
+    ///
+    /// The scratchpad is placed to end as close to the trailing guard page
+    /// as its alignment allows.
+    #[cfg(all(unix, not(feature = "safe")))]
+    fn allocate_guarded(len: usize, options: MemoryOptions) -> Self {
+        let mut report = AllocationReport { requested: options, ..Default::default() };
+        report.granted.shared = options.shared;
+
+        let page = page_size();
+        let data_len = round_up(len, page);
+        let map_len = data_len + 2 * page;
+        let base = map(map_len, options.shared, 0).unwrap_or_else(|_| {
+            handle_alloc_error(Layout::from_size_align(map_len, CryptoNight::SP_ALIGNMENT).unwrap())
+        });
+
+        let ptr = unsafe {
+            let guard_before = base.as_ptr();
+            let guard_after = guard_before.add(page + data_len);
+
+            for guard in &[guard_before, guard_after] {
+                if libc::mprotect(*guard as *mut libc::c_void, page, libc::PROT_NONE) != 0 {
+                    panic!("Could not protect guard page: {}", io::Error::last_os_error());
+                }
+            }
+
+            let offset = (data_len - len) & !(CryptoNight::SP_ALIGNMENT - 1);
+            NonNull::new_unchecked(guard_before.add(page + offset))
+        };
+
+        if options.lock {
+            lock(ptr, len, &mut report);
+        }
+
+        Scratchpad {
+            ptr,
+            len,
+            backing: Backing::Mapped { base, map_len },
+            report,
+        }.tracked()
+    }
+
+    /// Add this scratchpad to the memory usage counters.
+    fn tracked(self) -> Self {
+        self.update_usage(AtomicUsize::fetch_add);
+        self
+    }
+
+    fn update_usage(&self, update: fn(&AtomicUsize, usize, Ordering) -> usize) {
+        #[cfg(not(feature = "safe"))]
+            {
+                if let Backing::External = self.backing {
+                    return;
+                }
+            }
+
+        let bytes = self.allocated_len();
+
+        update(&SCRATCHPADS, 1, Ordering::Relaxed);
+        update(&BYTES, bytes, Ordering::Relaxed);
+        if self.huge_pages() {
+            update(&HUGE_PAGE_BYTES, bytes, Ordering::Relaxed);
+        }
+        if self.locked() {
+            update(&LOCKED_BYTES, bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of bytes actually obtained for this scratchpad.
+    fn allocated_len(&self) -> usize {
+        match self.backing {
+            #[cfg(not(feature = "safe"))]
+            Backing::Heap(layout) => layout.size(),
+            #[cfg(feature = "safe")]
+            Backing::Vec { ref buffer, .. } => buffer.capacity(),
+            #[cfg(feature = "allocator-api")]
+            Backing::Custom { layout, .. } => layout.size(),
+            #[cfg(all(any(unix, windows), not(feature = "safe")))]
+            Backing::Mapped { map_len, .. } => map_len,
+            #[cfg(not(feature = "safe"))]
+            Backing::External => self.len,
+        }
+    }
+
+    /// The start of the scratchpad, cut to the size `variant` needs.
+    ///
+    /// For the soft shell variant, whose scratchpad size depends on the
+    /// height, this is the largest size it needs.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the scratchpad is too small for `variant`.
+    pub fn for_variant(&mut self, variant: Variant) -> &mut [u8] {
+        let len = variant.scratchpad_size();
+        assert!(self.len >= len, "Scratchpad is too small for {}", variant);
+
+        &mut self.as_mut()[..len]
+    }
+
+    /// Length of the scratchpad in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the scratchpad has a length of zero. This is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the scratchpad is backed by huge pages.
+    pub fn huge_pages(&self) -> bool {
+        self.report.granted.huge_pages
+    }
+
+    /// Whether the kernel was advised to back the scratchpad with transparent
+    /// huge pages instead. See `AllocationReport::transparent_huge_pages`.
+    pub fn transparent_huge_pages(&self) -> bool {
+        self.report.transparent_huge_pages
+    }
+
+    /// Whether the scratchpad is locked into RAM.
+    pub fn locked(&self) -> bool {
+        self.report.granted.lock
+    }
+
+    /// Whether the scratchpad is shared with processes forked after its allocation.
+    pub fn shared(&self) -> bool {
+        self.report.granted.shared
+    }
+
+    /// Report on the options requested and granted for this scratchpad.
+    pub fn report(&self) -> AllocationReport {
+        self.report
+    }
+}
+
+/// A scratchpad of `N` bytes held inline, without allocating.
+///
+/// This can live on the stack or in a static, for verifying hashes of the
+/// variants with small scratchpads where allocating is not an option. Use
+/// the scratchpad size of the variant as `N`, see
+/// `VariantParams::SCRATCHPAD_SIZE`. Keep the stack size of the thread in
+/// mind: even the smallest variants need 128 KB.
+///
+/// # Usage
+/// ```
+/// # use std::sync::Mutex;
+/// # use cryptonight_hash::{ArrayScratchpad, CryptoNight, Digest, Variant};
+/// # use cryptonight_hash::params::{Upx2, VariantParams};
+/// static SCRATCHPAD: Mutex<ArrayScratchpad<{ Upx2::SCRATCHPAD_SIZE }>> = Mutex::new(ArrayScratchpad::new());
+///
+/// let mut scratchpad = SCRATCHPAD.lock().unwrap();
+/// let digest = CryptoNight::with_variant(Variant::Upx2)
+///     .chain(b"Your data")
+///     .fixed_result_with_buffer(scratchpad.as_mut());
+/// ```
+#[repr(align(16))]
+pub struct ArrayScratchpad<const N: usize>([u8; N]);
+
+impl<const N: usize> ArrayScratchpad<N> {
+    /// Create a zeroed scratchpad.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `N` is zero or not a multiple of 128, the size
+    /// of the blocks the scratchpad is filled with.
+    pub const fn new() -> Self {
+        assert!(N > 0 && N & 127 == 0, "Scratchpad size must be a non-zero multiple of 128");
+        ArrayScratchpad([0; N])
+    }
+}
+
+impl<const N: usize> Default for ArrayScratchpad<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for ArrayScratchpad<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> AsMut<[u8]> for ArrayScratchpad<N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[cfg(not(feature = "safe"))]
+impl AsRef<[u8]> for Scratchpad {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(not(feature = "safe"))]
+impl AsMut<[u8]> for Scratchpad {
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(feature = "safe")]
+impl AsRef<[u8]> for Scratchpad {
+    fn as_ref(&self) -> &[u8] {
+        let Backing::Vec { ref buffer, offset } = self.backing;
+        &buffer[offset..offset + self.len]
+    }
+}
+
+#[cfg(feature = "safe")]
+impl AsMut<[u8]> for Scratchpad {
+    fn as_mut(&mut self) -> &mut [u8] {
+        let Backing::Vec { ref mut buffer, offset } = self.backing;
+        &mut buffer[offset..offset + self.len]
+    }
+}
+
+impl Drop for Scratchpad {
+    fn drop(&mut self) {
+        self.update_usage(AtomicUsize::fetch_sub);
+
+        match self.backing {
+            #[cfg(not(feature = "safe"))]
+            Backing::Heap(layout) => unsafe { dealloc(self.ptr.as_ptr(), layout) },
+            #[cfg(feature = "safe")]
+            Backing::Vec { .. } => (),
+            #[cfg(feature = "allocator-api")]
+            Backing::Custom { layout, ref allocator } => unsafe { allocator.deallocate(self.ptr, layout) },
+            #[cfg(all(unix, not(feature = "safe")))]
+            Backing::Mapped { base, map_len } => unsafe {
+                // Unmapping also releases any lock on the memory.
+                libc::munmap(base.as_ptr() as *mut libc::c_void, map_len);
+            },
+            #[cfg(all(windows, not(feature = "safe")))]
+            Backing::Mapped { base, .. } => unsafe {
+                use winapi::um::memoryapi::VirtualFree;
+                use winapi::um::winnt::MEM_RELEASE;
+
+                VirtualFree(base.as_ptr() as *mut _, 0, MEM_RELEASE);
+            },
+            #[cfg(not(feature = "safe"))]
+            Backing::External => (),
+        }
+    }
+}
+
+/// Size of a (default) huge page.
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "safe")))]
+const HUGE_PAGE_SIZE: usize = 1 << 21;
+
+#[cfg(all(unix, not(feature = "safe")))]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(all(windows, not(feature = "safe")))]
+fn page_size() -> usize {
+    use winapi::um::sysinfoapi::{GetSystemInfo, SYSTEM_INFO};
+
+    unsafe {
+        let mut info: SYSTEM_INFO = std::mem::zeroed();
+        GetSystemInfo(&mut info);
+        info.dwPageSize as usize
+    }
+}
+
+/// Round `len` up to a multiple of `multiple`, which must be a power of two.
+#[cfg(all(any(unix, windows), not(feature = "safe")))]
+fn round_up(len: usize, multiple: usize) -> usize {
+    debug_assert!(multiple.is_power_of_two());
+    (len + multiple - 1) & !(multiple - 1)
+}
+
+/// Map at least `len` bytes backed by huge pages.
+///
+/// Returns the mapping and its actual length.
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "safe")))]
+fn map_huge(len: usize, shared: bool) -> io::Result<(NonNull<u8>, usize)> {
+    let map_len = round_up(len, HUGE_PAGE_SIZE);
+    map(map_len, shared, libc::MAP_HUGETLB).map(|ptr| (ptr, map_len))
+}
+
+/// Map at least `len` bytes aligned to a huge page, and advise the kernel to
+/// back them with transparent huge pages.
+///
+/// Returns the mapping, its length and whether the advice was accepted.
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "safe")))]
+fn map_transparent_huge(len: usize, shared: bool) -> (NonNull<u8>, usize, bool) {
+    let map_len = round_up(len, HUGE_PAGE_SIZE);
+    // Map an extra huge page to find an aligned start in, then unmap the rest.
+    let padded_len = map_len + HUGE_PAGE_SIZE;
+    let base = map(padded_len, shared, 0).unwrap_or_else(|_| {
+        handle_alloc_error(Layout::from_size_align(padded_len, HUGE_PAGE_SIZE).unwrap())
+    });
+
+    unsafe {
+        let start = base.as_ptr();
+        let offset = start.align_offset(HUGE_PAGE_SIZE);
+        let ptr = start.add(offset);
+
+        if offset > 0 {
+            libc::munmap(start as *mut libc::c_void, offset);
+        }
+        libc::munmap(ptr.add(map_len) as *mut libc::c_void, HUGE_PAGE_SIZE - offset);
+
+        let advised = libc::madvise(ptr as *mut libc::c_void, map_len, libc::MADV_HUGEPAGE) == 0;
+
+        (NonNull::new_unchecked(ptr), map_len, advised)
+    }
+}
+
+// `io::Error::other` is too recent for the toolchains the crate supports.
+#[allow(clippy::io_other_error)]
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android")), not(feature = "safe")))]
+fn map_huge(_len: usize, _shared: bool) -> io::Result<(NonNull<u8>, usize)> {
+    Err(io::Error::new(io::ErrorKind::Other, "huge pages are not supported"))
+}
+
+/// Allocate at least `len` bytes backed by large pages.
+///
+/// Returns the allocation and its actual length. Large pages can only be
+/// allocated with the "Lock pages in memory" privilege enabled, so this
+/// enables it for the process first.
+#[allow(clippy::io_other_error)]
+#[cfg(all(windows, not(feature = "safe")))]
+fn map_huge(len: usize) -> io::Result<(NonNull<u8>, usize)> {
+    use winapi::um::memoryapi::GetLargePageMinimum;
+    use winapi::um::winnt::MEM_LARGE_PAGES;
+
+    let large_page = unsafe { GetLargePageMinimum() };
+    if large_page == 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, "large pages are not supported"));
+    }
+
+    enable_lock_memory_privilege()?;
+
+    let map_len = round_up(len, large_page);
+    map(map_len, MEM_LARGE_PAGES).map(|ptr| (ptr, map_len))
+}
+
+/// Enable the "Lock pages in memory" privilege in the token of the process.
+///
+/// This only succeeds if the privilege was granted to the user.
+#[cfg(all(windows, not(feature = "safe")))]
+fn enable_lock_memory_privilege() -> io::Result<()> {
+    use std::mem;
+
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+    use winapi::um::winbase::LookupPrivilegeValueA;
+    use winapi::um::winnt::{SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY};
+
+    unsafe {
+        let mut token = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut privileges: TOKEN_PRIVILEGES = mem::zeroed();
+        privileges.PrivilegeCount = 1;
+        privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+
+        let name = b"SeLockMemoryPrivilege\0";
+        let luid = &mut privileges.Privileges[0].Luid;
+        let result = if LookupPrivilegeValueA(std::ptr::null(), name.as_ptr() as *const _, luid) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            AdjustTokenPrivileges(token, 0, &mut privileges, 0, std::ptr::null_mut(), std::ptr::null_mut());
+
+            // This reports success with ERROR_NOT_ALL_ASSIGNED if the user does
+            // not have the privilege, so only the error code tells.
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(ERROR_SUCCESS as i32) => Ok(()),
+                e => Err(e),
+            }
+        };
+
+        CloseHandle(token);
+        result
+    }
+}
+
+/// Lock `len` bytes at `ptr` into RAM and record the outcome in `report`.
+#[cfg(all(unix, not(feature = "safe")))]
+fn lock(ptr: NonNull<u8>, len: usize, report: &mut AllocationReport) {
+    if unsafe { libc::mlock(ptr.as_ptr() as *const libc::c_void, len) } == 0 {
+        report.granted.lock = true;
+    } else {
+        report.lock_errno = io::Error::last_os_error().raw_os_error();
+    }
+}
+
+/// Lock `len` bytes at `ptr` into RAM and record the outcome in `report`.
+///
+/// Windows limits locked memory to the minimum working set of the process,
+/// which is raised to fit the scratchpad first.
+#[cfg(all(windows, not(feature = "safe")))]
+fn lock(ptr: NonNull<u8>, len: usize, report: &mut AllocationReport) {
+    use winapi::um::memoryapi::VirtualLock;
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::winbase::{GetProcessWorkingSetSize, SetProcessWorkingSetSize};
+
+    let locked = unsafe {
+        let process = GetCurrentProcess();
+        let (mut minimum, mut maximum) = (0, 0);
+
+        GetProcessWorkingSetSize(process, &mut minimum, &mut maximum) != 0
+            && SetProcessWorkingSetSize(process, minimum + len, maximum.max(minimum + len)) != 0
+            && VirtualLock(ptr.as_ptr() as *mut _, len) != 0
+    };
+
+    if locked {
+        report.granted.lock = true;
+    } else {
+        report.lock_errno = io::Error::last_os_error().raw_os_error();
+    }
+}
+
+/// Commit `len` bytes of memory, with extra allocation flags.
+#[cfg(all(windows, not(feature = "safe")))]
+fn map(len: usize, extra_flags: winapi::shared::minwindef::DWORD) -> io::Result<NonNull<u8>> {
+    use winapi::um::memoryapi::VirtualAlloc;
+    use winapi::um::winnt::{MEM_COMMIT, MEM_RESERVE, PAGE_READWRITE};
+
+    let ptr = unsafe {
+        VirtualAlloc(std::ptr::null_mut(), len, MEM_COMMIT | MEM_RESERVE | extra_flags, PAGE_READWRITE)
+    };
+
+    NonNull::new(ptr as *mut u8).ok_or_else(io::Error::last_os_error)
+}
+
+/// Map `len` bytes of anonymous memory, either private or shared.
+#[cfg(all(unix, not(feature = "safe")))]
+fn map(len: usize, shared: bool, extra_flags: libc::c_int) -> io::Result<NonNull<u8>> {
+    let sharing = if shared { libc::MAP_SHARED } else { libc::MAP_PRIVATE };
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            sharing | libc::MAP_ANONYMOUS | extra_flags,
+            -1,
+            0,
+        )
+    };
+
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(NonNull::new(ptr as *mut u8).expect("mmap returned null"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_with_fallback() {
+        let options = MemoryOptions { huge_pages: true, lock: true, shared: false };
+        let mut scratchpad = Scratchpad::allocate_with(CryptoNight::SP_SIZE, options);
+
+        assert_eq!(scratchpad.len(), CryptoNight::SP_SIZE);
+        assert_eq!(scratchpad.as_ref().as_ptr() as usize % CryptoNight::SP_ALIGNMENT, 0);
+
+        // Memory must be usable regardless of what was granted.
+        scratchpad.as_mut()[CryptoNight::SP_SIZE - 1] = 42;
+        assert_eq!(scratchpad.as_ref()[CryptoNight::SP_SIZE - 1], 42);
+
+        let report = scratchpad.report();
+        assert_eq!(report.requested, options);
+        assert_eq!(report.is_degraded(), !(scratchpad.huge_pages() && scratchpad.locked()));
+    }
+
+    #[test]
+    fn test_allocate_huge() {
+        let mut scratchpad = Scratchpad::allocate_huge(CryptoNight::SP_SIZE);
+
+        assert_eq!(scratchpad.len(), CryptoNight::SP_SIZE);
+        assert_eq!(scratchpad.as_ref().as_ptr() as usize % CryptoNight::SP_ALIGNMENT, 0);
+        assert!(scratchpad.report().requested.huge_pages);
+        assert_eq!(scratchpad.report().is_degraded(), !scratchpad.huge_pages());
+
+        scratchpad.as_mut()[CryptoNight::SP_SIZE - 1] = 42;
+        assert_eq!(scratchpad.as_ref()[CryptoNight::SP_SIZE - 1], 42);
+    }
+
+    #[test]
+    #[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "safe"), not(feature = "guard-pages")))]
+    fn test_transparent_huge_page_fallback() {
+        let scratchpad = Scratchpad::allocate_huge(CryptoNight::SP_SIZE);
+        if scratchpad.huge_pages() {
+            // Explicit huge pages were available, so there was no fallback.
+            assert!(!scratchpad.transparent_huge_pages());
+            return;
+        }
+
+        // The fallback mapping is aligned to a huge page either way.
+        assert_eq!(scratchpad.as_ref().as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+        assert_eq!(scratchpad.allocated_len() % HUGE_PAGE_SIZE, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api")]
+    fn test_allocate_in() {
+        use std::alloc::Layout;
+        use std::sync::Arc;
+
+        use allocator_api2::alloc::{AllocError, Global};
+
+        use crate::Digest;
+
+        /// Counts the bytes it has outstanding.
+        struct Counting(Arc<AtomicUsize>);
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(layout.size(), Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(layout.size(), Ordering::Relaxed);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let outstanding = Arc::new(AtomicUsize::new(0));
+        let len = Variant::Upx2.scratchpad_size();
+        let mut scratchpad = Scratchpad::allocate_in(len, Counting(outstanding.clone()));
+        assert_eq!(outstanding.load(Ordering::Relaxed), len);
+        assert_eq!(scratchpad.as_ref().as_ptr() as usize % CryptoNight::SP_ALIGNMENT, 0);
+
+        let digest = CryptoNight::with_variant(Variant::Upx2).chain(b"data").fixed_result_with_buffer(scratchpad.as_mut());
+        assert_eq!(digest, CryptoNight::with_variant(Variant::Upx2).chain(b"data").result());
+
+        drop(scratchpad);
+        assert_eq!(outstanding.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_array_scratchpad() {
+        use crate::Digest;
+        use crate::params::{Pico, VariantParams};
+
+        let mut scratchpad = ArrayScratchpad::<{ Pico::SCRATCHPAD_SIZE }>::new();
+        assert_eq!(scratchpad.as_ref().as_ptr() as usize % CryptoNight::SP_ALIGNMENT, 0);
+
+        let digest = CryptoNight::with_variant(Variant::Pico).chain(b"data").fixed_result_with_buffer(scratchpad.as_mut());
+        assert_eq!(digest, CryptoNight::with_variant(Variant::Pico).chain(b"data").result());
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
+    fn test_from_raw_parts() {
+        use crate::Digest;
+
+        let mut memory = Scratchpad::allocate(Variant::Pico.scratchpad_size() + 16);
+        let ptr = memory.as_mut().as_mut_ptr();
+
+        let error = unsafe { Scratchpad::from_raw_parts(ptr.add(1), 1024) }.err();
+        assert_eq!(error, Some(ScratchpadError::Misaligned { address: ptr as usize + 1 }));
+
+        let mut scratchpad = unsafe { Scratchpad::from_raw_parts(ptr, Variant::Pico.scratchpad_size()) }.unwrap();
+        assert_eq!(scratchpad.as_ref().as_ptr(), ptr as *const u8);
+
+        let digest = CryptoNight::with_variant(Variant::Pico).chain(b"data").fixed_result_with_buffer(scratchpad.as_mut());
+        assert_eq!(digest, CryptoNight::with_variant(Variant::Pico).chain(b"data").result());
+
+        drop(scratchpad);
+        // The memory is still owned by, and freed with, the original scratchpad.
+        memory.as_mut().fill(0);
+    }
+
+    #[test]
+    fn test_allocate_max() {
+        use crate::Digest;
+
+        let mut scratchpad = Scratchpad::allocate_max();
+        for &variant in Variant::all() {
+            assert_eq!(scratchpad.for_variant(variant).len(), variant.scratchpad_size(), "{}", variant);
+        }
+
+        let digest = CryptoNight::with_variant(Variant::Upx2).chain(b"data").fixed_result_with_buffer(scratchpad.for_variant(Variant::Upx2));
+        assert_eq!(digest, CryptoNight::with_variant(Variant::Upx2).chain(b"data").result());
+    }
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn test_for_variant_too_small() {
+        Scratchpad::allocate(Variant::Lite.scratchpad_size()).for_variant(Variant::V2);
+    }
+
+    #[test]
+    fn test_check_buffer() {
+        let mut scratchpad = Scratchpad::allocate(1024);
+
+        assert_eq!(check_buffer(scratchpad.as_ref(), 1024), Ok(()));
+        assert_eq!(check_buffer(scratchpad.as_ref(), 2048),
+                   Err(ScratchpadError::WrongLength { required: 2048, actual: 1024 }));
+
+        let misaligned = &scratchpad.as_mut()[1..];
+        assert_eq!(check_buffer(misaligned, 512),
+                   Err(ScratchpadError::Misaligned { address: misaligned.as_ptr() as usize }));
+    }
+
+    #[test]
+    fn test_report_display() {
+        let report = AllocationReport {
+            requested: MemoryOptions { huge_pages: true, lock: true, shared: false },
+            granted: MemoryOptions::default(),
+            huge_pages_errno: None,
+            lock_errno: Some(1),
+            transparent_huge_pages: false,
+        };
+
+        let message = report.to_string();
+        assert!(message.starts_with("huge pages not supported on this platform; locking memory failed: "));
+        assert_eq!(AllocationReport::default().to_string(), "all requested options granted");
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "guard-pages", not(feature = "safe")))]
+    fn test_guard_page_placement() {
+        let len = CryptoNight::SP_SIZE - 16;
+        let scratchpad = Scratchpad::allocate(len);
+        let end = scratchpad.as_ref().as_ptr() as usize + len;
+
+        // The scratchpad ends right at the trailing guard page.
+        assert_eq!(end % page_size(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "thread-local")]
+    fn test_thread_local_reused() {
+        let first = with_temporary(1024, |scratchpad, _| scratchpad.as_ptr());
+        let (second, source) = with_temporary(1024, |scratchpad, source| {
+            // Nested uses get a scratchpad of their own.
+            let nested = with_temporary(1024, |nested, _| nested.as_ptr());
+            assert_ne!(nested, scratchpad.as_ptr());
+
+            (scratchpad.as_ptr(), source)
+        });
+
+        assert_eq!(first, second);
+        assert_eq!(source, ScratchpadSource::ThreadLocal);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        // Other tests allocate concurrently, so only lower bounds are exact.
+        let scratchpad = Scratchpad::allocate(CryptoNight::SP_SIZE);
+        let usage = memory_usage();
+
+        assert!(usage.scratchpads >= 1);
+        assert!(usage.bytes >= scratchpad.len());
+        assert!(usage.bytes >= usage.huge_page_bytes);
+        assert!(usage.bytes >= usage.locked_bytes);
+    }
+
+    #[test]
+    #[cfg(all(unix, not(feature = "safe")))]
+    fn test_shared_survives_fork() {
+        let options = MemoryOptions { shared: true, ..Default::default() };
+        let mut scratchpad = Scratchpad::allocate_with(CryptoNight::SP_SIZE, options);
+        assert!(scratchpad.shared());
+        scratchpad.as_mut()[0] = 0;
+
+        unsafe {
+            let child = libc::fork();
+            assert!(child >= 0, "fork failed: {}", io::Error::last_os_error());
+            if child == 0 {
+                scratchpad.as_mut()[0] = 42;
+                libc::_exit(0);
+            }
+
+            let mut status = 0;
+            assert_eq!(libc::waitpid(child, &mut status, 0), child);
+        }
+
+        assert_eq!(scratchpad.as_ref()[0], 42);
+    }
+
+    #[test]
+    #[cfg(all(windows, not(feature = "safe")))]
+    fn test_shared_not_granted() {
+        for &lock in &[false, true] {
+            let options = MemoryOptions { shared: true, lock, ..Default::default() };
+            let scratchpad = Scratchpad::allocate_with(CryptoNight::SP_SIZE, options);
+
+            assert!(!scratchpad.shared());
+            assert!(scratchpad.report().is_degraded());
+            assert!(scratchpad.report().to_string().contains("shared memory not supported"));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! TurtleCoin's soft shell CryptoNight, whose parameters follow the block height.
+use std::io::{self, Write};
+
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::{U136, U32};
+
+use crate::{BlockInput, CryptoNight, FixedOutput, Input, Reset, Variant};
+
+/// Scratchpad size at the start of the cycle.
+const MEMORY: usize = 256 * 1024;
+/// Number of blocks over which the scratchpad grows, and then shrinks.
+const WINDOW: u64 = 2048;
+/// Growth of the scratchpad per block.
+const PAD_MULTIPLIER: usize = WINDOW as usize / 3;
+/// Growth of the iterations per block.
+const ITERATIONS_MULTIPLIER: usize = PAD_MULTIPLIER / 2;
+
+/// Position of the height in the cycle, rising from 0 to `WINDOW` and back.
+fn offset(height: u64) -> usize {
+    let position = height % (2 * WINDOW);
+    if position < WINDOW {
+        position as usize
+    } else {
+        (2 * WINDOW - position) as usize
+    }
+}
+
+/// Size of the scratchpad at the given height, a multiple of 128 bytes.
+pub(crate) fn scratchpad_size(height: u64) -> usize {
+    (MEMORY + offset(height) * PAD_MULTIPLIER) / 128 * 128
+}
+
+/// Iterations of the main loop at the given height.
+pub(crate) fn iterations(height: u64) -> usize {
+    (MEMORY / 2 + offset(height) * ITERATIONS_MULTIPLIER) / 2
+}
+
+/// Address mask at the given height, covering half of the scratchpad.
+///
+/// Sizes are not powers of two, so not every block of that half can be
+/// addressed. This matches the reference implementation.
+pub(crate) fn address_mask(height: u64) -> usize {
+    scratchpad_size(height) / 2 - 16
+}
+
+/// TurtleCoin's soft shell CryptoNight.
+///
+/// The scratchpad size and the number of iterations change with the block
+/// height, so hashers are created for a height. Hashers created with
+/// `Default` use height 0, where the scratchpad is smallest.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::CryptoNightSoftShell;
+/// let result = CryptoNightSoftShell::digest_with_height(b"Block header", 1_000_000);
+///
+/// assert_eq!(CryptoNightSoftShell::scratchpad_size(1_000_000), 654_976);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CryptoNightSoftShell(CryptoNight);
+
+impl CryptoNightSoftShell {
+    /// Create a hasher for a block at the given height.
+    pub fn with_height(height: u64) -> Self {
+        CryptoNightSoftShell(CryptoNight {
+            height,
+            ..CryptoNight::with_variant(Variant::SoftShell)
+        })
+    }
+
+    /// Compute the digest of the data for a block at the given height.
+    ///
+    /// See also: `Digest::digest()`.
+    pub fn digest_with_height<B: AsRef<[u8]>>(data: B, height: u64) -> GenericArray<u8, U32> {
+        let mut hasher = Self::with_height(height);
+        Input::input(&mut hasher, data);
+        hasher.fixed_result()
+    }
+
+    /// Size of the scratchpad used at the given height, in bytes.
+    pub fn scratchpad_size(height: u64) -> usize {
+        scratchpad_size(height)
+    }
+
+    /// Number of iterations of the main loop at the given height.
+    pub fn iterations(height: u64) -> usize {
+        iterations(height)
+    }
+
+    /// The underlying hasher.
+    pub fn into_inner(self) -> CryptoNight {
+        self.0
+    }
+}
+
+impl Default for CryptoNightSoftShell {
+    fn default() -> Self {
+        Self::with_height(0)
+    }
+}
+
+impl Input for CryptoNightSoftShell {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        Input::input(&mut self.0, data);
+    }
+}
+
+impl Write for CryptoNightSoftShell {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Reset for CryptoNightSoftShell {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl BlockInput for CryptoNightSoftShell {
+    type BlockSize = U136;
+}
+
+impl FixedOutput for CryptoNightSoftShell {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
+        self.0.fixed_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::params::{SoftShell, VariantParams};
+
+    use super::*;
+
+    #[test]
+    fn test_cycle() {
+        assert_eq!(scratchpad_size(0), MEMORY);
+        assert_eq!(iterations(0), MEMORY / 4);
+
+        // The parameters rise to the end of the window, then fall again.
+        assert_eq!(scratchpad_size(WINDOW), SoftShell::SCRATCHPAD_SIZE);
+        assert_eq!(iterations(WINDOW), SoftShell::ITERATIONS);
+        assert_eq!(scratchpad_size(WINDOW - 1), scratchpad_size(WINDOW + 1));
+        assert_eq!(scratchpad_size(2 * WINDOW), MEMORY);
+
+        for height in 0..2 * WINDOW {
+            assert_eq!(scratchpad_size(height) % 128, 0);
+            assert!(scratchpad_size(height) <= SoftShell::SCRATCHPAD_SIZE);
+            assert!(address_mask(height) < scratchpad_size(height));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Saving and restoring hashers that have absorbed part of their input.
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::{CryptoNight, CustomVariant, CustomVariantError, KeccakState};
+use crate::checkpoint::{variant_from_id, variant_id};
+
+/// Version of the format of `CryptoNight::state_bytes`.
+const VERSION: u8 = 1;
+
+/// Offsets of the fields in the saved state.
+const VARIANT: usize = 1;
+const HEIGHT: usize = 2;
+const CUSTOM: usize = 10;
+const INPUT_LEN: usize = 35;
+const TWEAK_INPUT: usize = 44;
+const LANES: usize = 52;
+const BUFFERED: usize = 252;
+
+impl CryptoNight {
+    /// Size of the state saved by `CryptoNight::state_bytes`.
+    pub const STATE_BYTES: usize = 389;
+
+    /// Save the state of the hasher, including the input absorbed so far.
+    ///
+    /// The state can be restored with `CryptoNight::from_state_bytes`, for
+    /// example after a restart or in another process, to continue hashing.
+    /// The format is independent of the platform.
+    ///
+    /// # Usage
+    /// ```
+    /// # use cryptonight_hash::{CryptoNight, Digest};
+    /// let saved = CryptoNight::new().chain(b"This is ").state_bytes();
+    ///
+    /// let hasher = CryptoNight::from_state_bytes(&saved).unwrap();
+    /// assert_eq!(hasher.chain(b"a test").result(), CryptoNight::digest(b"This is a test"));
+    /// ```
+    pub fn state_bytes(&self) -> [u8; Self::STATE_BYTES] {
+        let mut bytes = [0u8; Self::STATE_BYTES];
+
+        bytes[0] = VERSION;
+        bytes[VARIANT] = variant_id(self.variant);
+        bytes[HEIGHT..CUSTOM].copy_from_slice(&self.height.to_le_bytes());
+        if let Some(custom) = self.custom {
+            bytes[CUSTOM] = 1;
+            let parameters = [custom.scratchpad_size(), custom.iterations(), custom.address_mask()];
+            for (parameter, bytes) in parameters.iter().zip(bytes[CUSTOM + 1..INPUT_LEN].chunks_exact_mut(8)) {
+                bytes.copy_from_slice(&(*parameter as u64).to_le_bytes());
+            }
+        }
+        if let Some(input_len) = self.input_len {
+            bytes[INPUT_LEN] = 1;
+            bytes[INPUT_LEN + 1..TWEAK_INPUT].copy_from_slice(&(input_len as u64).to_le_bytes());
+        }
+        bytes[TWEAK_INPUT..LANES].copy_from_slice(&self.tweak_input);
+        bytes[LANES..BUFFERED].copy_from_slice(&self.keccak.to_bytes());
+
+        let buffered = self.keccak.buffered();
+        bytes[BUFFERED] = buffered.len() as u8;
+        bytes[BUFFERED + 1..BUFFERED + 1 + buffered.len()].copy_from_slice(buffered);
+
+        bytes
+    }
+
+    /// Restore a hasher saved with `CryptoNight::state_bytes`.
+    pub fn from_state_bytes(bytes: &[u8; Self::STATE_BYTES]) -> Result<Self, StateError> {
+        if bytes[0] != VERSION {
+            return Err(StateError::Version(bytes[0]));
+        }
+
+        let variant = variant_from_id(bytes[VARIANT]).ok_or(StateError::Variant(bytes[VARIANT]))?;
+        let height = read_u64(&bytes[HEIGHT..CUSTOM]);
+        let custom = match bytes[CUSTOM] {
+            0 => None,
+            1 => {
+                let parameters = &bytes[CUSTOM + 1..INPUT_LEN];
+                let custom = CustomVariant::builder(variant)
+                    .scratchpad_size(read_u64(&parameters[..8]) as usize)
+                    .iterations(read_u64(&parameters[8..16]) as usize)
+                    .address_mask(read_u64(&parameters[16..]) as usize)
+                    .build()
+                    .map_err(StateError::Custom)?;
+                Some(custom)
+            }
+            _ => return Err(StateError::Corrupt),
+        };
+        let input_len = match bytes[INPUT_LEN] {
+            0 => None,
+            1 => Some(read_u64(&bytes[INPUT_LEN + 1..TWEAK_INPUT]) as usize),
+            _ => return Err(StateError::Corrupt),
+        };
+
+        let buffered = bytes[BUFFERED] as usize;
+        if buffered >= KeccakState::RATE {
+            return Err(StateError::Corrupt);
+        }
+        let mut keccak = KeccakState::from_bytes(bytes[LANES..BUFFERED].try_into().unwrap());
+        keccak.absorb(&bytes[BUFFERED + 1..BUFFERED + 1 + buffered]);
+
+        Ok(CryptoNight {
+            keccak,
+            variant,
+            input_len,
+            tweak_input: bytes[TWEAK_INPUT..LANES].try_into().unwrap(),
+            height,
+            custom,
+        })
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// Errors that can occur when restoring a hasher with `CryptoNight::from_state_bytes`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StateError {
+    /// The state was saved in an unsupported format version.
+    Version(u8),
+    /// The identifier of the variant is unknown.
+    Variant(u8),
+    /// The parameters of the custom variant are invalid.
+    Custom(CustomVariantError),
+    /// The state is malformed.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::Version(version) => write!(f, "unsupported state version {}", version),
+            StateError::Variant(id) => write!(f, "unknown variant identifier {:#x}", id),
+            StateError::Custom(error) => write!(f, "invalid custom variant: {}", error),
+            StateError::Corrupt => f.write_str("corrupt state"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Digest, Variant};
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let input: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let custom = CustomVariant::builder(Variant::V1).iterations(1 << 10).build().unwrap();
+
+        for &split in &[0, 20, 40, 136, 200] {
+            let (head, tail) = input.split_at(split);
+
+            for hasher in [CryptoNight::with_variant(Variant::V1), CryptoNight::with_height(1_806_260), CryptoNight::with_custom(custom)] {
+                let expected = hasher.clone().chain(&input).result();
+
+                let saved = hasher.chain(head).state_bytes();
+                let restored = CryptoNight::from_state_bytes(&saved).unwrap();
+                assert_eq!(restored.chain(tail).result(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let saved = CryptoNight::with_custom(CustomVariant::builder(Variant::V2).build().unwrap()).state_bytes();
+
+        let mut bytes = saved;
+        bytes[0] = 0;
+        assert_eq!(CryptoNight::from_state_bytes(&bytes).unwrap_err(), StateError::Version(0));
+
+        let mut bytes = saved;
+        bytes[VARIANT] = 0xff;
+        assert_eq!(CryptoNight::from_state_bytes(&bytes).unwrap_err(), StateError::Variant(0xff));
+
+        let mut bytes = saved;
+        bytes[VARIANT] = variant_id(Variant::R);
+        assert_eq!(CryptoNight::from_state_bytes(&bytes).unwrap_err(), StateError::Custom(CustomVariantError::Base(Variant::R)));
+
+        let mut bytes = saved;
+        bytes[BUFFERED] = KeccakState::RATE as u8;
+        assert_eq!(CryptoNight::from_state_bytes(&bytes).unwrap_err(), StateError::Corrupt);
+    }
+}
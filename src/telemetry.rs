@@ -0,0 +1,133 @@
+//! Hooks for observing every hash computed by the crate.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::Variant;
+
+/// Where the scratchpad for a hash came from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ScratchpadSource {
+    /// Allocated by the crate for this hash, as in `Digest::result`.
+    Allocated,
+    /// Passed in by the caller, as in `CryptoNight::digest_with_buffer`.
+    Buffer,
+    /// Taken from a `ScratchpadProvider`.
+    Provider,
+    /// Reused from an earlier hash on the same thread, as in `Digest::result`
+    /// with the `thread-local` feature.
+    ThreadLocal,
+}
+
+/// Information about a completed hash.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct HashEvent {
+    /// Time spent computing the hash, excluding scratchpad allocation.
+    pub duration: Duration,
+    /// Name of the backend that computed the hash, e.g. `aesni`.
+    pub backend: &'static str,
+    /// The variant that was computed.
+    pub variant: Variant,
+    /// Where the scratchpad came from.
+    pub scratchpad: ScratchpadSource,
+}
+
+/// Receives a `HashEvent` after every hash.
+///
+/// Observers are called on the thread that computed the hash, so they
+/// should be quick. Any closure taking a `&HashEvent` is an observer.
+pub trait HashObserver: Send + Sync {
+    /// Called after a hash has been computed.
+    fn on_hash(&self, event: &HashEvent);
+}
+
+impl<F: Fn(&HashEvent) + Send + Sync> HashObserver for F {
+    fn on_hash(&self, event: &HashEvent) {
+        self(event)
+    }
+}
+
+/// Fast check to avoid taking the lock and the time when nobody listens.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static OBSERVER: RwLock<Option<Arc<dyn HashObserver>>> = RwLock::new(None);
+
+/// Install a process-wide observer for all hashes, or remove it with `None`.
+///
+/// Returns the previously installed observer. Hashes computed through a
+/// `Checkpoint` are not reported.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{set_hash_observer, CryptoNight, Digest, HashEvent};
+/// use std::sync::Arc;
+///
+/// set_hash_observer(Some(Arc::new(|event: &HashEvent| {
+///     println!("{} hash took {:?} on {}", event.variant, event.duration, event.backend);
+/// })));
+///
+/// CryptoNight::digest(b"Your data");
+/// # set_hash_observer(None);
+/// ```
+pub fn set_hash_observer(observer: Option<Arc<dyn HashObserver>>) -> Option<Arc<dyn HashObserver>> {
+    let mut current = OBSERVER.write().unwrap_or_else(|e| e.into_inner());
+    ENABLED.store(observer.is_some(), Ordering::Release);
+
+    std::mem::replace(&mut *current, observer)
+}
+
+/// Run `hash`, and report it to the observer if there is one.
+pub(crate) fn observe<F: FnOnce() -> R, R>(backend: &'static str, variant: Variant, scratchpad: ScratchpadSource, hash: F) -> R {
+    observe_all(backend, &[variant], scratchpad, hash)
+}
+
+/// Run `hash`, which computes a hash for each of the variants at once.
+///
+/// Every hash is reported with the duration of the whole computation.
+pub(crate) fn observe_all<F: FnOnce() -> R, R>(backend: &'static str, variants: &[Variant], scratchpad: ScratchpadSource, hash: F) -> R {
+    if !ENABLED.load(Ordering::Acquire) {
+        return hash();
+    }
+
+    let start = Instant::now();
+    let result = hash();
+    let duration = start.elapsed();
+
+    let observer = OBSERVER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(observer) = observer {
+        for &variant in variants {
+            observer.on_hash(&HashEvent {
+                duration,
+                backend,
+                variant,
+                scratchpad,
+            });
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+
+        let previous = set_hash_observer(Some(Arc::new(move |event: &HashEvent| {
+            if event.backend == "test" {
+                assert_eq!(event.scratchpad, ScratchpadSource::Buffer);
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        })));
+
+        assert_eq!(observe("test", Variant::V0, ScratchpadSource::Buffer, || 42), 42);
+        set_hash_observer(previous);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
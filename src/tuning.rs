@@ -0,0 +1,93 @@
+//! Keeping the outcome of the auto-tuner between runs.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{BuiltinBackend, Tuning, Variant};
+
+/// The contents of a profile file.
+#[derive(Serialize, Deserialize)]
+struct Profile {
+    variant: Variant,
+    tuning: Tuning,
+}
+
+impl BuiltinBackend {
+    /// Like `BuiltinBackend::auto_tune`, but keep the outcome in a profile
+    /// file, to skip the measurements on the next start.
+    ///
+    /// If the file at `path` holds a tuning for `variant` that can be used
+    /// on this machine, it is applied with `Tuning::apply`. Otherwise, the
+    /// backends are measured and the outcome is written to the file as JSON,
+    /// replacing what was there. Remove the file to measure again, for
+    /// example after moving it to another machine with the same backends.
+    ///
+    /// # Usage
+    /// ```no_run
+    /// # use cryptonight_hash::{BuiltinBackend, Variant};
+    /// let tuning = BuiltinBackend::auto_tune_with_profile(Variant::Lite, "tuning.json")?;
+    /// println!("Hashing {} at a time with {}", tuning.lanes, tuning.backend);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn auto_tune_with_profile<P: AsRef<Path>>(variant: Variant, path: P) -> io::Result<Tuning> {
+        let path = path.as_ref();
+
+        if let Some(tuning) = load(path, variant)? {
+            tuning.apply();
+            return Ok(tuning);
+        }
+
+        let tuning = BuiltinBackend::auto_tune(variant);
+        fs::write(path, serde_json::to_vec_pretty(&Profile { variant, tuning })?)?;
+
+        Ok(tuning)
+    }
+}
+
+/// Read the tuning for `variant` from a profile, if it exists and is usable.
+fn load(path: &Path, variant: Variant) -> io::Result<Option<Tuning>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // Profiles that cannot be parsed, such as those naming a backend this
+    // version does not know, are measured again.
+    let profile: Profile = match serde_json::from_slice(&contents) {
+        Ok(profile) => profile,
+        Err(_) => return Ok(None),
+    };
+
+    let Profile { variant: tuned, tuning } = profile;
+    let backend = tuning.backend;
+    // Interleaving is only used with the selected backend.
+    let lanes_usable = tuning.lanes == 1
+        || (backend == BuiltinBackend::selected() && [2, 4].contains(&tuning.lanes) && tuning.lanes <= backend.max_lanes());
+
+    if tuned == variant && backend.is_available() && lanes_usable {
+        Ok(Some(tuning))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Built-in backends are serialized by name.
+impl Serialize for BuiltinBackend {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for BuiltinBackend {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+
+        BuiltinBackend::all().iter()
+            .copied()
+            .find(|backend| backend.name() == name)
+            .ok_or_else(|| de::Error::custom(format_args!("unknown backend {:?}", name)))
+    }
+}
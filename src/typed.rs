@@ -0,0 +1,107 @@
+//! Digest types fixed to a variant at compile time.
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::{U136, U32};
+
+use crate::{BlockInput, CryptoNight, FixedOutput, Input, Reset};
+use crate::params::{Fast, VariantParams};
+
+/// A `CryptoNight` hasher for the variant `V`.
+///
+/// This is for code that selects the algorithm through the `Digest` type,
+/// for example as a type parameter. The hasher runs the same engine as
+/// `CryptoNight::with_variant(V::VARIANT)`.
+///
+/// Variants that depend on the block height are not supported, as `Digest`
+/// has no way to provide it. Use `CryptoNight::with_height` for
+/// CryptoNight-R, `cryptonight` for CryptoNight-WOW, and
+/// `CryptoNightSoftShell` for the soft shell variant.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, CryptoNightOf, Digest, Variant};
+/// use cryptonight_hash::params::V2;
+///
+/// let input = b"This is a test";
+/// let result = CryptoNightOf::<V2>::digest(input);
+///
+/// assert_eq!(result, CryptoNight::with_variant(Variant::V2).chain(input).result());
+/// ```
+pub struct CryptoNightOf<V: VariantParams> {
+    inner: CryptoNight,
+    variant: PhantomData<V>,
+}
+
+/// CryptoNight-Fast, as used by Masari.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::{CryptoNight, CryptoNightFast, Digest, Variant};
+/// let input = [0u8; 76];
+/// let result = CryptoNightFast::digest(&input[..]);
+///
+/// assert_eq!(result, CryptoNight::with_variant(Variant::Fast).chain(&input[..]).result());
+/// ```
+pub type CryptoNightFast = CryptoNightOf<Fast>;
+
+impl<V: VariantParams> CryptoNightOf<V> {
+    /// The underlying hasher.
+    pub fn into_inner(self) -> CryptoNight {
+        self.inner
+    }
+}
+
+impl<V: VariantParams> Default for CryptoNightOf<V> {
+    fn default() -> Self {
+        CryptoNightOf { inner: CryptoNight::with_variant(V::VARIANT), variant: PhantomData }
+    }
+}
+
+impl<V: VariantParams> Clone for CryptoNightOf<V> {
+    fn clone(&self) -> Self {
+        CryptoNightOf { inner: self.inner.clone(), variant: PhantomData }
+    }
+}
+
+impl<V: VariantParams> fmt::Debug for CryptoNightOf<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CryptoNightOf").field(&self.inner).finish()
+    }
+}
+
+impl<V: VariantParams> Input for CryptoNightOf<V> {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        Input::input(&mut self.inner, data);
+    }
+}
+
+impl<V: VariantParams> Write for CryptoNightOf<V> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<V: VariantParams> Reset for CryptoNightOf<V> {
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+impl<V: VariantParams> BlockInput for CryptoNightOf<V> {
+    type BlockSize = U136;
+}
+
+impl<V: VariantParams> FixedOutput for CryptoNightOf<V> {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
+        self.inner.fixed_result()
+    }
+}
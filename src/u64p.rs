@@ -0,0 +1,176 @@
+//! The 128 bit arithmetic used in CryptoNight's main loop.
+use std::convert::TryFrom;
+use std::array::TryFromSliceError;
+use std::ops::{Add, Mul, BitXor};
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+/// A pair of 64 bit unsigned integers
+///
+/// This type is used to implement the operations on the scratch pad in
+/// CryptoNight's main loop, and exposes the exact semantics of those
+/// operations for external tooling:
+///
+/// - `+` is the CryptoNight 8byte_add: both halves are added separately,
+///   wrapping on overflow.
+/// - `*` is the CryptoNight 8byte_mul: the low halves are multiplied to a
+///   128 bit product, with the high 64 bits of the product in the low half
+///   of the result and the low 64 bits in the high half.
+/// - `^` is a plain bitwise xor.
+///
+/// CryptoNight reads its 16 byte blocks as two little endian integers, the
+/// low half first. The pair is stored in that form, so it can be viewed as a
+/// block through `AsRef<[u8]>`. Use `from_be_bytes` and `to_be_bytes` for
+/// other data.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::U64p;
+/// use std::convert::TryFrom;
+///
+/// let a = U64p::new(3, 1);
+/// let b = U64p::try_from(&[2u8; 16][..]).unwrap();
+///
+/// assert_eq!((a * b).to_le_bytes(), U64p::new(0, 3 * 0x0202020202020202).to_le_bytes());
+/// ```
+#[repr(transparent)]
+pub struct U64p([u8; 16]);
+
+impl U64p {
+    /// Create a pair from its low and high halves.
+    pub fn new(low: u64, high: u64) -> Self {
+        U64p(join(low.to_le_bytes(), high.to_le_bytes()))
+    }
+
+    /// The low half, stored in the first 8 bytes of a block.
+    pub fn low(self) -> u64 {
+        u64::from_le_bytes(split(self.0).0)
+    }
+
+    /// The high half, stored in the last 8 bytes of a block.
+    pub fn high(self) -> u64 {
+        u64::from_le_bytes(split(self.0).1)
+    }
+
+    /// Read a block as CryptoNight does: two little endian integers.
+    pub fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        U64p(bytes)
+    }
+
+    /// Read a block of two big endian integers.
+    pub fn from_be_bytes(bytes: [u8; 16]) -> Self {
+        let (low, high) = split(bytes);
+        U64p::new(u64::from_be_bytes(low), u64::from_be_bytes(high))
+    }
+
+    /// Write the pair as a CryptoNight block: two little endian integers.
+    pub fn to_le_bytes(self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Write the pair as two big endian integers.
+    pub fn to_be_bytes(self) -> [u8; 16] {
+        join(self.low().to_be_bytes(), self.high().to_be_bytes())
+    }
+}
+
+fn split(bytes: [u8; 16]) -> ([u8; 8], [u8; 8]) {
+    let mut low = [0u8; 8];
+    let mut high = [0u8; 8];
+    low.copy_from_slice(&bytes[..8]);
+    high.copy_from_slice(&bytes[8..]);
+    (low, high)
+}
+
+fn join(low: [u8; 8], high: [u8; 8]) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&low);
+    bytes[8..].copy_from_slice(&high);
+    bytes
+}
+
+/// Read a 16 byte block as two little endian integers.
+impl TryFrom<&[u8]> for U64p {
+    type Error = TryFromSliceError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        <[u8; 16]>::try_from(bytes).map(Self::from_le_bytes)
+    }
+}
+
+/// The pair as a CryptoNight block.
+impl AsRef<[u8]> for U64p {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The pair as a CryptoNight block.
+impl AsMut<[u8]> for U64p {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// Perform the cn 8byte_add operation.
+impl Add for U64p {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        U64p::new(self.low().wrapping_add(rhs.low()), self.high().wrapping_add(rhs.high()))
+    }
+}
+
+/// Perform the cn 8byte_mul operation.
+impl Mul for U64p {
+    type Output = U64p;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let a = u128::from(self.low());
+        let b = u128::from(rhs.low());
+
+        let r = a * b;
+
+        #[allow(clippy::suspicious_arithmetic_impl)]
+        U64p::new((r >> 64) as u64, r as u64)
+    }
+}
+
+impl BitXor for U64p {
+    type Output = U64p;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        U64p::new(self.low() ^ rhs.low(), self.high() ^ rhs.high())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_order() {
+        let mut bytes = [0u8; 16];
+        bytes[0] = 1;
+        bytes[15] = 2;
+
+        let le = U64p::from_le_bytes(bytes);
+        assert_eq!((le.low(), le.high()), (1, 2 << 56));
+        assert_eq!(le.to_le_bytes(), bytes);
+
+        let be = U64p::from_be_bytes(bytes);
+        assert_eq!((be.low(), be.high()), (1 << 56, 2));
+        assert_eq!(be.to_be_bytes(), bytes);
+
+        assert!(U64p::try_from(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let max = U64p::new(u64::MAX, u64::MAX);
+
+        assert_eq!(max + U64p::new(2, 1), U64p::new(1, 0));
+        // (2^64 - 1)^2 = 2^128 - 2^65 + 1
+        assert_eq!(max * max, U64p::new(u64::MAX - 1, 1));
+        assert_eq!(max ^ U64p::new(1, 2), U64p::new(!1, !2));
+    }
+}
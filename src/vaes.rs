@@ -0,0 +1,370 @@
+//! Module implementing the scratchpad stages using the VAES extension.
+//!
+//! Filling the scratchpad and folding it back encrypts eight independent
+//! blocks at a time. The wide AES instructions of VAES encrypt two or four
+//! of them at once, which cuts the fixed cost of every hash. The main loop
+//! is left to the `aesni` module.
+//!
+//! With AVX-512, this module also computes four hashes at once, with the
+//! scratchpad stages of all four in 512-bit instructions and their main
+//! loops interleaved.
+//!
+//! This module requires the following CPU extensions on top of those of the
+//! `aesni` module:
+//!
+//! * VAES (for the wide AES instructions)
+//! * AVX2 (for the 256-bit vector operations)
+//! * AVX-512F (for the 512-bit vector operations, if available)
+use std::arch::x86_64::*;
+use std::slice;
+
+use slice_cast::cast_mut;
+
+use crate::aesni::{self, KeysType};
+use crate::{LoopState, Phase, Tweaks};
+
+/// Number of 512-bit vectors in the blocks that fill the scratchpad.
+const VECTORS: usize = 2;
+
+/// Compute a hash, using 512-bit instructions for the scratchpad stages if
+/// `avx512` is set, or 256-bit ones otherwise.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn digest_main<F: FnMut(Phase)>(keccac: &mut [u8], scratchpad: &mut [u8], tweaks: Tweaks, avx512: bool, mut after_phase: F) {
+    let mut state = LoopState::new(keccac);
+
+    init(keccac, scratchpad, avx512);
+    after_phase(Phase::Init);
+    aesni::main_loop_partial(&mut state, scratchpad, tweaks.iterations(), tweaks);
+    after_phase(Phase::MainLoop);
+    finalize(keccac, scratchpad, avx512);
+    after_phase(Phase::Finalize);
+}
+
+/// Fill the scratchpad from the initial state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn init(keccac: &[u8], scratchpad: &mut [u8], avx512: bool) {
+    let scratchpad: &mut [__m128i] = cast_mut(scratchpad);
+    let keccac = slice::from_raw_parts(keccac.as_ptr() as *const __m128i, 12);
+
+    if avx512 {
+        init_scratchpad_512(keccac, scratchpad);
+    } else {
+        init_scratchpad_256(keccac, scratchpad);
+    }
+}
+
+/// Fold the scratchpad back into the state.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn finalize(keccac: &mut [u8], scratchpad: &[u8], avx512: bool) {
+    let scratchpad = slice::from_raw_parts(scratchpad.as_ptr() as *const __m128i, scratchpad.len() / 16);
+    let keccac: &mut [__m128i] = cast_mut(&mut keccac[..192]);
+
+    if avx512 {
+        finalize_state_512(keccac, scratchpad);
+    } else {
+        finalize_state_256(keccac, scratchpad);
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx2")]
+unsafe fn init_scratchpad_256(keccac: &[__m128i], scratchpad: &mut [__m128i]) {
+    let keys = broadcast_keys_256(&aesni::derive_key(keccac[0], keccac[1]));
+    let mut blocks = [_mm256_setzero_si256(); 4];
+    for (j, block) in blocks.iter_mut().enumerate() {
+        *block = _mm256_loadu_si256(keccac[4 + 2 * j..].as_ptr() as *const __m256i);
+    }
+
+    for scratchpad_chunk in scratchpad.chunks_exact_mut(8) {
+        for block in blocks.iter_mut() {
+            for key in keys.iter() {
+                *block = _mm256_aesenc_epi128(*block, *key);
+            }
+        }
+
+        let destination = scratchpad_chunk.as_mut_ptr() as *mut __m256i;
+        for (j, block) in blocks.iter().enumerate() {
+            _mm256_storeu_si256(destination.add(j), *block);
+        }
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx512f")]
+unsafe fn init_scratchpad_512(keccac: &[__m128i], scratchpad: &mut [__m128i]) {
+    let keys = broadcast_keys(&aesni::derive_key(keccac[0], keccac[1]));
+    let mut blocks = load_blocks(keccac);
+
+    for scratchpad_chunk in scratchpad.chunks_exact_mut(8) {
+        for block in blocks.iter_mut() {
+            for key in keys.iter() {
+                *block = _mm512_aesenc_epi128(*block, *key);
+            }
+        }
+
+        let destination = scratchpad_chunk.as_mut_ptr() as *mut __m512i;
+        for (j, block) in blocks.iter().enumerate() {
+            _mm512_storeu_si512(destination.add(j) as *mut _, *block);
+        }
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx2")]
+unsafe fn finalize_state_256(keccac: &mut [__m128i], scratchpad: &[__m128i]) {
+    let keys = broadcast_keys_256(&aesni::derive_key(keccac[2], keccac[3]));
+    let mut blocks = [_mm256_setzero_si256(); 4];
+    for (j, block) in blocks.iter_mut().enumerate() {
+        *block = _mm256_loadu_si256(keccac[4 + 2 * j..].as_ptr() as *const __m256i);
+    }
+
+    for scratchpad_chunk in scratchpad.chunks_exact(8) {
+        let source = scratchpad_chunk.as_ptr() as *const __m256i;
+        for (j, block) in blocks.iter_mut().enumerate() {
+            *block = _mm256_xor_si256(*block, _mm256_loadu_si256(source.add(j)));
+            for key in keys.iter() {
+                *block = _mm256_aesenc_epi128(*block, *key);
+            }
+        }
+    }
+
+    let destination = keccac[4..].as_mut_ptr() as *mut __m256i;
+    for (j, block) in blocks.iter().enumerate() {
+        _mm256_storeu_si256(destination.add(j), *block);
+    }
+}
+
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx512f")]
+unsafe fn finalize_state_512(keccac: &mut [__m128i], scratchpad: &[__m128i]) {
+    let keys = broadcast_keys(&aesni::derive_key(keccac[2], keccac[3]));
+    let mut blocks = load_blocks(keccac);
+
+    for scratchpad_chunk in scratchpad.chunks_exact(8) {
+        let source = scratchpad_chunk.as_ptr() as *const __m512i;
+        for (j, block) in blocks.iter_mut().enumerate() {
+            *block = _mm512_xor_si512(*block, _mm512_loadu_si512(source.add(j) as *const _));
+            for key in keys.iter() {
+                *block = _mm512_aesenc_epi128(*block, *key);
+            }
+        }
+    }
+
+    let destination = keccac[4..].as_mut_ptr() as *mut __m512i;
+    for (j, block) in blocks.iter().enumerate() {
+        _mm512_storeu_si512(destination.add(j) as *mut _, *block);
+    }
+}
+
+/// Compute four hashes of the same variant, number of iterations and
+/// scratchpad size at once.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "sse2")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx512f")]
+pub unsafe fn digest_main4(keccacs: [&mut [u8]; 4], scratchpads: [&mut [u8]; 4], tweaks: [Tweaks; 4]) {
+    debug_assert!(tweaks.iter().all(|t| t.lockstep(&tweaks[0])));
+    let mut states = [LoopState::default(); 4];
+    for (state, keccac) in states.iter_mut().zip(keccacs.iter()) {
+        *state = LoopState::new(keccac);
+    }
+
+    // Cast to SSE types
+    let mut keccacs: [&mut [__m128i]; 4] = keccacs.map(|keccac| cast_mut(&mut keccac[..192]));
+    let mut scratchpads: [&mut [__m128i]; 4] = scratchpads.map(|scratchpad| cast_mut(scratchpad));
+
+    init_scratchpads(&keccacs, &mut scratchpads);
+    {
+        let [s0, s1, s2, s3] = &mut states;
+        let [sp0, sp1, sp2, sp3] = &mut scratchpads;
+        aesni::main_loop_variant_interleaved([
+            (s0, &mut **sp0, tweaks[0]),
+            (s1, &mut **sp1, tweaks[1]),
+            (s2, &mut **sp2, tweaks[2]),
+            (s3, &mut **sp3, tweaks[3]),
+        ]);
+    }
+    finalize_states(&mut keccacs, &scratchpads);
+}
+
+/// Broadcast each round key to both 128-bit lanes.
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn broadcast_keys_256(keys: &KeysType) -> [__m256i; 10] {
+    let mut wide = [_mm256_setzero_si256(); 10];
+    for (wide, key) in wide.iter_mut().zip(keys.iter()) {
+        *wide = _mm256_broadcastsi128_si256(*key);
+    }
+
+    wide
+}
+
+/// Broadcast each round key to all four 128-bit lanes.
+#[inline]
+#[target_feature(enable = "avx512f")]
+unsafe fn broadcast_keys(keys: &KeysType) -> [__m512i; 10] {
+    let mut wide = [_mm512_setzero_si512(); 10];
+    for (wide, key) in wide.iter_mut().zip(keys.iter()) {
+        *wide = _mm512_broadcast_i32x4(*key);
+    }
+
+    wide
+}
+
+/// Load the 128 bytes of blocks starting at `keccac[4]`.
+#[inline]
+#[target_feature(enable = "avx512f")]
+unsafe fn load_blocks(keccac: &[__m128i]) -> [__m512i; VECTORS] {
+    [
+        _mm512_loadu_si512(keccac[4..].as_ptr() as *const _),
+        _mm512_loadu_si512(keccac[8..].as_ptr() as *const _),
+    ]
+}
+
+/// Fill the four scratchpads from their initial states.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx512f")]
+unsafe fn init_scratchpads(keccacs: &[&mut [__m128i]; 4], scratchpads: &mut [&mut [__m128i]; 4]) {
+    let mut keys = [[_mm512_setzero_si512(); 10]; 4];
+    let mut blocks = [[_mm512_setzero_si512(); VECTORS]; 4];
+    for (i, keccac) in keccacs.iter().enumerate() {
+        keys[i] = broadcast_keys(&aesni::derive_key(keccac[0], keccac[1]));
+        blocks[i] = load_blocks(keccac);
+    }
+
+    let chunks = scratchpads[0].len() / (VECTORS * 4);
+    for chunk in 0..chunks {
+        for (i, blocks) in blocks.iter_mut().enumerate() {
+            for block in blocks.iter_mut() {
+                for key in keys[i].iter() {
+                    *block = _mm512_aesenc_epi128(*block, *key);
+                }
+            }
+        }
+
+        for (scratchpad, blocks) in scratchpads.iter_mut().zip(blocks.iter()) {
+            let chunk = scratchpad.as_mut_ptr().add(chunk * VECTORS * 4) as *mut __m512i;
+            for (j, block) in blocks.iter().enumerate() {
+                _mm512_storeu_si512(chunk.add(j) as *mut _, *block);
+            }
+        }
+    }
+}
+
+/// Fold the four scratchpads back into their states.
+#[target_feature(enable = "aes")]
+#[target_feature(enable = "vaes")]
+#[target_feature(enable = "avx512f")]
+unsafe fn finalize_states(keccacs: &mut [&mut [__m128i]; 4], scratchpads: &[&mut [__m128i]; 4]) {
+    let mut keys = [[_mm512_setzero_si512(); 10]; 4];
+    let mut blocks = [[_mm512_setzero_si512(); VECTORS]; 4];
+    for (i, keccac) in keccacs.iter().enumerate() {
+        keys[i] = broadcast_keys(&aesni::derive_key(keccac[2], keccac[3]));
+        blocks[i] = load_blocks(keccac);
+    }
+
+    let chunks = scratchpads[0].len() / (VECTORS * 4);
+    for chunk in 0..chunks {
+        for (i, blocks) in blocks.iter_mut().enumerate() {
+            let source = scratchpads[i].as_ptr().add(chunk * VECTORS * 4) as *const __m512i;
+            for (j, block) in blocks.iter_mut().enumerate() {
+                *block = _mm512_xor_si512(*block, _mm512_loadu_si512(source.add(j) as *const _));
+                for key in keys[i].iter() {
+                    *block = _mm512_aesenc_epi128(*block, *key);
+                }
+            }
+        }
+    }
+
+    for (keccac, blocks) in keccacs.iter_mut().zip(blocks.iter()) {
+        let destination = keccac[4..].as_mut_ptr() as *mut __m512i;
+        for (j, block) in blocks.iter().enumerate() {
+            _mm512_storeu_si512(destination.add(j) as *mut _, *block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{aes, aesni, avx512_available, vaes_available, CryptoNight, Scratchpad, Variant};
+
+    use super::*;
+
+    #[test]
+    fn test_scratchpad_stages_match_portable() {
+        if !vaes_available() {
+            return;
+        }
+
+        let keccac: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut expected = Scratchpad::allocate(1 << 16);
+        aes::init_scratchpad(&keccac, expected.as_mut());
+        let mut expected_keccac = keccac.clone();
+        aes::finalize_state(&mut expected_keccac, expected.as_ref());
+
+        for &avx512 in &[false, true] {
+            if avx512 && !avx512_available() {
+                continue;
+            }
+
+            let mut actual = Scratchpad::allocate(1 << 16);
+            let mut actual_keccac = keccac.clone();
+            unsafe {
+                init(&actual_keccac, actual.as_mut(), avx512);
+                finalize(&mut actual_keccac, actual.as_ref(), avx512);
+            }
+
+            assert!(actual.as_ref() == expected.as_ref(), "avx512: {}", avx512);
+            assert_eq!(actual_keccac, expected_keccac, "avx512: {}", avx512);
+        }
+    }
+
+    #[test]
+    fn test_four_way_matches_aesni() {
+        if !avx512_available() {
+            return;
+        }
+
+        let mut slab = Scratchpad::allocate(8 * CryptoNight::SP_SIZE);
+        let mut scratchpads = slab.as_mut().chunks_exact_mut(CryptoNight::SP_SIZE);
+        let mut actual = [(); 4].map(|_| scratchpads.next().unwrap());
+        let mut expected = [(); 4].map(|_| scratchpads.next().unwrap());
+
+        for &variant in &[Variant::V0, Variant::V2, Variant::Lite] {
+            let tweaks = Tweaks::plain(variant);
+            let len = tweaks.scratchpad_size();
+            let mut keccacs = [0usize, 1, 2, 3].map(|i| (0..200).map(|j| (i * 200 + j) as u8).collect::<Vec<_>>());
+            let mut expected_keccacs = keccacs.clone();
+
+            for (keccac, scratchpad) in expected_keccacs.iter_mut().zip(expected.iter_mut()) {
+                unsafe { aesni::digest_main(keccac, &mut scratchpad[..len], tweaks, |_| ()) };
+            }
+            let [k0, k1, k2, k3] = &mut keccacs;
+            let [s0, s1, s2, s3] = &mut actual;
+            unsafe {
+                digest_main4(
+                    [k0, k1, k2, k3],
+                    [&mut s0[..len], &mut s1[..len], &mut s2[..len], &mut s3[..len]],
+                    [tweaks; 4],
+                )
+            };
+
+            assert_eq!(keccacs, expected_keccacs, "{}", variant);
+            for (actual, expected) in actual.iter().zip(expected.iter()) {
+                assert!(actual[..len] == expected[..len], "{}", variant);
+            }
+        }
+    }
+}
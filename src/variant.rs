@@ -0,0 +1,303 @@
+//! Runtime descriptions of the supported CryptoNight variants.
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::params::{Conceal, Fast, Lite, Pico, R, Rto, SoftShell, Upx2, V0, V1, V2, Wow, Xao, Zls, VariantParams};
+
+/// A CryptoNight variant supported by this crate.
+///
+/// This type is meant for discovering what the crate can do at runtime, for
+/// example to advertise supported algorithms to a pool or orchestration
+/// tool.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Variant {
+    /// The original CryptoNight algorithm, as described by CryptoNote standard 8.
+    V0,
+    /// Variant 1, also known as the Monero v7 tweak.
+    ///
+    /// Its main loop mixes in bytes 35 to 43 of the input, so it requires
+    /// inputs of at least 43 bytes.
+    V1,
+    /// Variant 2, used by Monero from v8.
+    V2,
+    /// CryptoNight-R, also known as variant 4, used by Monero from v10.
+    ///
+    /// Its main loop runs a random math program selected by the block
+    /// height, so hashers need the height, see `CryptoNight::with_height`.
+    R,
+    /// CryptoNight-Lite, the original algorithm with a 1 MB scratchpad.
+    Lite,
+    /// CryptoNight-Pico as used by TurtleCoin, variant 2 with a 256 KB scratchpad.
+    Pico,
+    /// CryptoNight-Fast as used by Masari, variant 1 with half the iterations.
+    ///
+    /// Like variant 1, it requires inputs of at least 43 bytes.
+    Fast,
+    /// CryptoNight-ZLS as used by Zelerius, variant 2 with three quarters of
+    /// the iterations.
+    Zls,
+    /// CryptoNight-XAO as used by Alloy, the original algorithm with twice
+    /// the iterations.
+    Xao,
+    /// CryptoNight-RTO as used by Arto, variant 1 with a stronger second
+    /// tweak.
+    ///
+    /// Like variant 1, it requires inputs of at least 43 bytes.
+    Rto,
+    /// CryptoNight-UPX2 as used by uPlexa, variant 2 with a 128 KB scratchpad
+    /// and the shuffle reversed.
+    Upx2,
+    /// CryptoNight-Conceal as used by Conceal, the original algorithm with
+    /// half the iterations and a floating point tweak.
+    Conceal,
+    /// TurtleCoin's soft shell CryptoNight, whose scratchpad size and
+    /// iterations follow a cycle over the block height.
+    ///
+    /// Hashers need the height, see `CryptoNightSoftShell::with_height`.
+    /// `scratchpad_size` and `iterations` return the largest values of the
+    /// cycle.
+    SoftShell,
+    /// CryptoNight-WOW, as used by Wownero, an earlier form of CryptoNight-R.
+    ///
+    /// Like CryptoNight-R, it depends on the block height.
+    Wow,
+}
+
+impl Variant {
+    /// All variants supported by this crate, in no particular order.
+    pub fn all() -> &'static [Variant] {
+        &[
+            Variant::V0,
+            Variant::V1,
+            Variant::V2,
+            Variant::R,
+            Variant::Lite,
+            Variant::Pico,
+            Variant::Fast,
+            Variant::Zls,
+            Variant::Xao,
+            Variant::Rto,
+            Variant::Upx2,
+            Variant::Conceal,
+            Variant::SoftShell,
+            Variant::Wow,
+        ]
+    }
+
+    /// Conventional name of the variant.
+    ///
+    /// Names follow the scheme used by common mining software, e.g. `cn/0`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::V0 => V0::NAME,
+            Variant::V1 => V1::NAME,
+            Variant::V2 => V2::NAME,
+            Variant::R => R::NAME,
+            Variant::Lite => Lite::NAME,
+            Variant::Pico => Pico::NAME,
+            Variant::Fast => Fast::NAME,
+            Variant::Zls => Zls::NAME,
+            Variant::Xao => Xao::NAME,
+            Variant::Rto => Rto::NAME,
+            Variant::Upx2 => Upx2::NAME,
+            Variant::Conceal => Conceal::NAME,
+            Variant::SoftShell => SoftShell::NAME,
+            Variant::Wow => Wow::NAME,
+        }
+    }
+
+    /// Size of the scratchpad required by this variant, in bytes.
+    pub fn scratchpad_size(self) -> usize {
+        match self {
+            Variant::V0 => V0::SCRATCHPAD_SIZE,
+            Variant::V1 => V1::SCRATCHPAD_SIZE,
+            Variant::V2 => V2::SCRATCHPAD_SIZE,
+            Variant::R => R::SCRATCHPAD_SIZE,
+            Variant::Lite => Lite::SCRATCHPAD_SIZE,
+            Variant::Pico => Pico::SCRATCHPAD_SIZE,
+            Variant::Fast => Fast::SCRATCHPAD_SIZE,
+            Variant::Zls => Zls::SCRATCHPAD_SIZE,
+            Variant::Xao => Xao::SCRATCHPAD_SIZE,
+            Variant::Rto => Rto::SCRATCHPAD_SIZE,
+            Variant::Upx2 => Upx2::SCRATCHPAD_SIZE,
+            Variant::Conceal => Conceal::SCRATCHPAD_SIZE,
+            Variant::SoftShell => SoftShell::SCRATCHPAD_SIZE,
+            Variant::Wow => Wow::SCRATCHPAD_SIZE,
+        }
+    }
+
+    /// Number of iterations of the main loop.
+    pub fn iterations(self) -> usize {
+        match self {
+            Variant::V0 => V0::ITERATIONS,
+            Variant::V1 => V1::ITERATIONS,
+            Variant::V2 => V2::ITERATIONS,
+            Variant::R => R::ITERATIONS,
+            Variant::Lite => Lite::ITERATIONS,
+            Variant::Pico => Pico::ITERATIONS,
+            Variant::Fast => Fast::ITERATIONS,
+            Variant::Zls => Zls::ITERATIONS,
+            Variant::Xao => Xao::ITERATIONS,
+            Variant::Rto => Rto::ITERATIONS,
+            Variant::Upx2 => Upx2::ITERATIONS,
+            Variant::Conceal => Conceal::ITERATIONS,
+            Variant::SoftShell => SoftShell::ITERATIONS,
+            Variant::Wow => Wow::ITERATIONS,
+        }
+    }
+
+    /// Mask to obtain a scratchpad byte offset, see `VariantParams::ADDRESS_MASK`.
+    pub fn address_mask(self) -> usize {
+        match self {
+            Variant::V0 => V0::ADDRESS_MASK,
+            Variant::V1 => V1::ADDRESS_MASK,
+            Variant::V2 => V2::ADDRESS_MASK,
+            Variant::R => R::ADDRESS_MASK,
+            Variant::Lite => Lite::ADDRESS_MASK,
+            Variant::Pico => Pico::ADDRESS_MASK,
+            Variant::Fast => Fast::ADDRESS_MASK,
+            Variant::Zls => Zls::ADDRESS_MASK,
+            Variant::Xao => Xao::ADDRESS_MASK,
+            Variant::Rto => Rto::ADDRESS_MASK,
+            Variant::Upx2 => Upx2::ADDRESS_MASK,
+            Variant::Conceal => Conceal::ADDRESS_MASK,
+            Variant::SoftShell => SoftShell::ADDRESS_MASK,
+            Variant::Wow => Wow::ADDRESS_MASK,
+        }
+    }
+
+    /// Number of 128 byte blocks in the scratchpad initialization.
+    pub fn init_blocks(self) -> usize {
+        match self {
+            Variant::V0 => V0::INIT_BLOCKS,
+            Variant::V1 => V1::INIT_BLOCKS,
+            Variant::V2 => V2::INIT_BLOCKS,
+            Variant::R => R::INIT_BLOCKS,
+            Variant::Lite => Lite::INIT_BLOCKS,
+            Variant::Pico => Pico::INIT_BLOCKS,
+            Variant::Fast => Fast::INIT_BLOCKS,
+            Variant::Zls => Zls::INIT_BLOCKS,
+            Variant::Xao => Xao::INIT_BLOCKS,
+            Variant::Rto => Rto::INIT_BLOCKS,
+            Variant::Upx2 => Upx2::INIT_BLOCKS,
+            Variant::Conceal => Conceal::INIT_BLOCKS,
+            Variant::SoftShell => SoftShell::INIT_BLOCKS,
+            Variant::Wow => Wow::INIT_BLOCKS,
+        }
+    }
+
+    /// Minimum length of the input in bytes.
+    pub fn min_input_len(self) -> usize {
+        match self {
+            Variant::V1 | Variant::Fast | Variant::Rto => 43,
+            _ => 0,
+        }
+    }
+
+    /// The variant whose main loop this variant runs, with its own parameters.
+    pub(crate) fn base(self) -> Variant {
+        match self {
+            Variant::Lite
+            | Variant::Xao
+            | Variant::Conceal
+            | Variant::SoftShell => Variant::V0,
+            Variant::Pico
+            | Variant::Zls
+            | Variant::Upx2 => Variant::V2,
+            Variant::Fast | Variant::Rto => Variant::V1,
+            Variant::Wow => Variant::R,
+            variant => variant,
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses the conventional name of a variant, as returned by `Variant::name`.
+///
+/// Names are compared ignoring ASCII case.
+///
+/// # Usage
+/// ```
+/// # use cryptonight_hash::Variant;
+/// assert_eq!("cn/r".parse(), Ok(Variant::R));
+/// assert!("cn/unknown".parse::<Variant>().is_err());
+/// ```
+impl FromStr for Variant {
+    type Err = ParseVariantError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::all().iter()
+            .find(|variant| variant.name().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| ParseVariantError { name: s.to_owned() })
+    }
+}
+
+/// Error returned when parsing the name of an unsupported variant.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParseVariantError {
+    name: String,
+}
+
+impl ParseVariantError {
+    /// The name that was not recognized.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for ParseVariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown CryptoNight variant {:?}", self.name)
+    }
+}
+
+impl Error for ParseVariantError {}
+
+/// Variants are serialized by name.
+#[cfg(feature = "serde")]
+impl Serialize for Variant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_names_unique() {
+        let names: HashSet<_> = Variant::all().iter().map(|v| v.name()).collect();
+        assert_eq!(names.len(), Variant::all().len());
+    }
+
+    #[test]
+    fn test_parse_names() {
+        for &variant in Variant::all() {
+            assert_eq!(variant.name().parse(), Ok(variant));
+            assert_eq!(variant.name().to_uppercase().parse(), Ok(variant));
+        }
+
+        assert_eq!("cn/unknown".parse::<Variant>().unwrap_err().name(), "cn/unknown");
+    }
+}
@@ -0,0 +1,25 @@
+//! Wownero's CryptoNight proof of work.
+//!
+//! Wownero hashed its blocks with CryptoNight-WOW, whose random math
+//! program depends on the block height, before it moved on to RandomWOW.
+//! Only the CryptoNight-WOW rules are covered here, at every height.
+//!
+//! ```
+//! use cryptonight_hash::{cryptonight, wownero, Variant};
+//!
+//! let blob = [0u8; 76];
+//! assert_eq!(wownero::pow_hash(&blob, 1_806_260), Some(cryptonight(&blob, Variant::Wow, 1_806_260)));
+//! ```
+use crate::{CoinProfile, Variant};
+
+/// Wownero's CryptoNight-WOW proof of work as a coin profile.
+///
+/// It is also available as `coin_profile("wownero")`.
+pub fn profile() -> CoinProfile {
+    CoinProfile::new("wownero").fork(0, Variant::Wow)
+}
+
+/// Compute the CryptoNight-WOW hash of a block from its hashing blob.
+pub fn pow_hash(blob: &[u8], height: u64) -> Option<[u8; 32]> {
+    profile().pow_hash(blob, height)
+}
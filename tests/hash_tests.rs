@@ -1,7 +1,10 @@
+use std::io::{self, IoSlice, Read, Write};
+
 use digest::Digest;
 use hex_literal::hex;
 
-use cryptonight_hash::CryptoNight;
+use cryptonight_hash::{coin_profile, cryptonight, CryptoNight, CryptoNightFast, CryptoNightOf, CryptoNightSoftShell, CustomVariant, RandomMathEngine, Scratchpad, set_random_math_engine, split_slab, Variant};
+use cryptonight_hash::params::{self, VariantParams};
 
 /// Sample inputs for version 0 of the algorithm.
 ///
@@ -47,3 +50,387 @@ fn validate_with_buffer() {
         assert_eq!(result[..], output[..]);
     }
 }
+
+#[test]
+fn validate_vectored() {
+    for (i, (&input, &output)) in INPUTS.iter().zip(OUTPUTS.iter()).enumerate() {
+        println!("{}: {}", i, hex::encode(input));
+        let (head, tail) = input.split_at(input.len() / 2);
+
+        assert_eq!(CryptoNight::digest_vectored(&[head, tail])[..], output[..]);
+
+        let mut hasher = CryptoNight::new();
+        let written = hasher.write_vectored(&[IoSlice::new(head), IoSlice::new(tail)]).unwrap();
+        assert_eq!(written, head.len() + tail.len());
+        assert_eq!(hasher.result()[..], output[..]);
+    }
+}
+
+#[test]
+fn hash_reader_matches_digest() {
+    /// Reads the data a few bytes at a time, interrupting every other read.
+    struct Trickle<'a>(&'a [u8], bool);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+
+            let read = buf.len().min(self.0.len()).min(3);
+            buf[..read].copy_from_slice(&self.0[..read]);
+            self.0 = &self.0[read..];
+            Ok(read)
+        }
+    }
+
+    for &input in INPUTS.iter() {
+        let expected = CryptoNight::digest(input);
+
+        assert_eq!(CryptoNight::new().hash_reader(input).unwrap(), expected);
+        assert_eq!(CryptoNight::new().hash_reader_with_buffer_size(Trickle(input, false), 2).unwrap(), expected);
+    }
+
+    let lite = CryptoNight::with_variant(Variant::Lite).hash_reader(&b"This is a test"[..]).unwrap();
+    assert_eq!(lite, CryptoNight::with_variant(Variant::Lite).chain(b"This is a test").result());
+}
+
+/// Known answers for variant 1, from monero/tests/hash/tests-slow-1.txt.
+const V1_VECTORS: &[(&[u8], [u8; 32])] = &[
+    (&[0; 43], hex!("b5a7f63abb94d07d1a6445c36c07c7e8327fe61b1647e391b4c7edae5de57a3d")),
+    (&[0; 76], hex!("80563c40ed46575a9e44820d93ee095e2851aa22483fd67837118c6cd951ba61")),
+    (
+        &hex!("8519e039172b0d70e5ca7b3383d6b3167315a422747b73f019cf9528f0fde341fd0f2a63030ba6450525cf6de31837669af6f1df8131faf50aaab8d3a7405589"),
+        hex!("5bb40c5880cef2f739bdb6aaaf16161eaae55530e7b10d7ea996b751a299e949"),
+    ),
+    (
+        &hex!("37a636d7dafdf259b7287eddca2f58099e98619d2f99bdb8969d7b14498102cc065201c8be90bd777323f449848b215d2977c92c4c1c2da36ab46b2e389689ed97c18fec08cd3b03235c5e4c62a37ad88c7b67932495a71090e85dd4020a9300"),
+        hex!("613e638505ba1fd05f428d5c9f8e08f8165614342dac419adc6a47dce257eb3e"),
+    ),
+    (
+        &hex!("38274c97c45a172cfc97679870422e3a1ab0784960c60514d816271415c306ee3a3ed1a77e31f6a885c3cb"),
+        hex!("ed082e49dbd5bbe34a3726a0d1dad981146062b39d36d62c71eb1ed8ab49459b"),
+    ),
+];
+
+/// Known answers for variant 2, from monero/tests/hash/tests-slow-2.txt.
+const V2_VECTORS: &[(&[u8], [u8; 32])] = &[
+    (
+        b"This is a test This is a test This is a test",
+        hex!("353fdc068fd47b03c04b9431e005e00b68c2168a3cc7335c8b9b308156591a4f"),
+    ),
+    (
+        b"Lorem ipsum dolor sit amet, consectetur adipiscing",
+        hex!("72f134fc50880c330fe65a2cb7896d59b2e708a0221c6a9da3f69b3a702d8682"),
+    ),
+    (
+        b"elit, sed do eiusmod tempor incididunt ut labore",
+        hex!("410919660ec540fc49d8695ff01f974226a2a28dbbac82949c12f541b9a62d2f"),
+    ),
+    (
+        b"et dolore magna aliqua. Ut enim ad minim veniam,",
+        hex!("4472fecfeb371e8b7942ce0378c0ba5e6d0c6361b669c587807365c787ae652d"),
+    ),
+];
+
+/// Sample inputs for CryptoNight-R, with the block height each is hashed at.
+///
+/// Taken from monero/tests/hash/tests-slow-4.txt.
+const R_INPUTS: &[(&[u8], u64)] = &[
+    (b"This is a test This is a test This is a test", 1_806_260),
+    (b"Lorem ipsum dolor sit amet, consectetur adipiscing", 1_806_261),
+    (b"elit, sed do eiusmod tempor incididunt ut labore", 1_806_262),
+    (b"et dolore magna aliqua. Ut enim ad minim veniam,", 1_806_263),
+    (b"quis nostrud exercitation ullamco laboris nisi", 1_806_264),
+    (b"ut aliquip ex ea commodo consequat. Duis aute", 1_806_265),
+    (b"irure dolor in reprehenderit in voluptate velit", 1_806_266),
+    (b"esse cillum dolore eu fugiat nulla pariatur.", 1_806_267),
+    (b"Excepteur sint occaecat cupidatat non proident,", 1_806_268),
+    (b"sunt in culpa qui officia deserunt mollit anim id est laborum.", 1_806_269),
+];
+
+const R_OUTPUTS: &[[u8; 32]] = &[
+    hex!("f759588ad57e758467295443a9bd71490abff8e9dad1b95b6bf2f5d0d78387bc"),
+    hex!("5bb833deca2bdd7252a9ccd7b4ce0b6a4854515794b56c207262f7a5b9bdb566"),
+    hex!("1ee6728da60fbd8d7d55b2b1ade487a3cf52a2c3ac6f520db12c27d8921f6cab"),
+    hex!("6969fe2ddfb758438d48049f302fc2108a4fcc93e37669170e6db4b0b9b4c4cb"),
+    hex!("7f3048b4e90d0cbe7a57c0394f37338a01fae3adfdc0e5126d863a895eb04e02"),
+    hex!("1d290443a4b542af04a82f6b2494a6ee7f20f2754c58e0849032483a56e8e2ef"),
+    hex!("c43cc6567436a86afbd6aa9eaa7c276e9806830334b614b2bee23cc76634f6fd"),
+    hex!("87be2479c0c4e8edfdfaa5603e93f4265b3f8224c1c5946feb424819d18990a4"),
+    hex!("dd9d6a6d8e47465cceac0877ef889b93e7eba979557e3935d7f86dce11b070f3"),
+    hex!("75c6f2ae49a20521de97285b431e717125847fb8935ed84a61e7f8d36a2c3d8e"),
+];
+
+#[test]
+fn validate_variant_1() {
+    for (i, &(input, output)) in V1_VECTORS.iter().enumerate() {
+        println!("{}: {}", i, hex::encode(input));
+
+        assert_eq!(cryptonight(input, Variant::V1, 0), output);
+    }
+}
+
+#[test]
+fn validate_variant_2() {
+    for (i, &(input, output)) in V2_VECTORS.iter().enumerate() {
+        println!("{}: {}", i, hex::encode(input));
+
+        assert_eq!(cryptonight(input, Variant::V2, 0), output);
+    }
+}
+
+#[test]
+fn validate_cn_r() {
+    for (i, (&(input, height), &output)) in R_INPUTS.iter().zip(R_OUTPUTS.iter()).enumerate() {
+        println!("{}: {} at {}", i, hex::encode(input), height);
+
+        assert_eq!(CryptoNight::digest_with_height(input, height)[..], output[..]);
+        assert_eq!(CryptoNight::with_height(height).chain(input).result()[..], output[..]);
+    }
+}
+
+/// The block hashed by the tests of xmrig.
+const XMRIG_BLOB: &[u8] = &hex!("0305a0dbd6bf05cf16e503f3a66f78007cbf34144332ecbfc22ed95c8700383b309ace1923a0964b00000008ba939a62724c0d7581fce5761e9d8a0e6a1c3f924fdd8493d1115649c05eb601");
+
+/// Digests of `XMRIG_BLOB` from the tests of xmrig.
+const XMRIG_OUTPUTS: &[(Variant, [u8; 32])] = &[
+    (Variant::V0, hex!("1a3ffbee909b420d91f7be6e5fb56db71b3110d886011e877ee5786afd080100")),
+    (Variant::V1, hex!("f22d3d6203d2a08b41d9027278d8bcc983acada9b68e52e3c689692a50e921d9")),
+    (Variant::V2, hex!("97378282cf10e7ad033f7b8074c40e14d06e7f609dddda787680b58c05f43d21")),
+    (Variant::Lite, hex!("3695b4b53bb00358b0ad38dc160feb9e004eece09b83a72ef6ba9864d3510c88")),
+    (Variant::Pico, hex!("08f421d7833117300eda66e98f4a2569093df300500173944efc401e9a4a17af")),
+    (Variant::Fast, hex!("3c7a61084c5eb865b498ab2f5a1ac52c49c177c2d0133442d65ed514335c82c5")),
+    (Variant::Zls, hex!("516e33c6e446abbccdad18c04cd9a25e64102853b20a42dfdeaa8b599ecf40e2")),
+    (Variant::Xao, hex!("9a29d0c4afdc639b6553b1c83735114c5d77162142975cb850c0a51f6407bd33")),
+    (Variant::Rto, hex!("82661e1c6e6436668406327a9bb11319a5561615dfec1c9ee3884a6c1ceb76a5")),
+    (Variant::Upx2, hex!("aabbb8ed14a835fa22cfb1b5dea872b0a1d6cbd846f4391c0f01f3875e3a3761")),
+    (Variant::Conceal, hex!("b3a16786d2c985ecadc45f910527c7a196f0e1e97c8709381d7d419335f81672")),
+];
+
+#[test]
+fn validate_xmrig_vectors() {
+    for &(variant, output) in XMRIG_OUTPUTS {
+        assert_eq!(cryptonight(XMRIG_BLOB, variant, 0), output, "{}", variant);
+    }
+}
+
+#[test]
+fn fast_hasher_matches_variant() {
+    let fast = CryptoNightFast::digest(XMRIG_BLOB);
+
+    assert_eq!(fast, CryptoNight::with_variant(Variant::Fast).chain(XMRIG_BLOB).result());
+}
+
+#[test]
+fn typed_hashers_match_variants() {
+    fn typed<V: VariantParams>(input: &[u8]) {
+        let expected = CryptoNight::with_variant(V::VARIANT).chain(input).result();
+        assert_eq!(CryptoNightOf::<V>::digest(input), expected, "{}", V::NAME);
+    }
+
+    let input = [0u8; 76];
+    typed::<params::V1>(&input);
+    typed::<params::Lite>(&input);
+    typed::<params::Conceal>(&input);
+}
+
+#[test]
+fn custom_variant_matches_builtin() {
+    let input = b"This is a test";
+    let pico = CustomVariant::builder(Variant::V2)
+        .scratchpad_size(Variant::Pico.scratchpad_size())
+        .iterations(Variant::Pico.iterations())
+        .address_mask(Variant::Pico.address_mask())
+        .build()
+        .unwrap();
+
+    let expected = CryptoNight::with_variant(Variant::Pico).chain(input).result();
+    assert_eq!(CryptoNight::with_custom(pico).chain(input).result(), expected);
+}
+
+#[test]
+fn soft_shell_follows_height_cycle() {
+    let input = b"This is a test";
+    let start = CryptoNightSoftShell::digest_with_height(input, 0);
+
+    assert_ne!(start, CryptoNightSoftShell::digest_with_height(input, 1024));
+    assert_eq!(start, CryptoNightSoftShell::digest_with_height(input, 4096));
+}
+
+/// Digests of `R_INPUTS` for CryptoNight-WOW, at the same heights, from the
+/// tests of xmrig.
+const WOW_OUTPUTS: &[[u8; 32]] = &[
+    hex!("9d47bf4c41b7e8e727e681715acb47fa1677cdba9ca7bcb05ad8cc8abd5daa66"),
+    hex!("0d4a495cb844a3ca8ba4edb8e6bcf829ef1c06d9cdea2b62ca46c2a21b8b0a79"),
+    hex!("a1d6d848b5c5915fccd2f64cf216c6b1a02cf7c77bc80d8d4e51b419e88ff0dd"),
+    hex!("af3a8544a0221a148c2ac90484b19861e3afca33fe17021efb8ad6496b567915"),
+    hex!("313399e0963ae8a99dab8af66d343e097dae0c0feb08dbc43ccdafef5515f413"),
+    hex!("6021c6ef90bff9ae94a7506d623d3a7a86c1756d655f50dd558f716d64622a34"),
+    hex!("2b13000535f3db5f9b9b84a65c4351f386cd2cdedebb8c3ad2eab086e6a3fee5"),
+    hex!("fc0e1dad8e895749dc90eb690bc1ba059a1cd772afaaf65a106bf9e5e6b80503"),
+    hex!("b60b0afe144deff7d903ed2d5545e77ebe66a3c51fee7016eeb8fee9eb630c0f"),
+    hex!("64774b27e7d5fec862fc4c0c13ac6bf09123b6f05bb0e4b75c97f379a2b3a679"),
+];
+
+#[test]
+fn validate_cn_wow() {
+    for (i, (&(input, height), &output)) in R_INPUTS.iter().zip(WOW_OUTPUTS.iter()).enumerate() {
+        println!("{}: {} at {}", i, hex::encode(input), height);
+
+        assert_eq!(cryptonight(input, Variant::Wow, height), output);
+    }
+
+    // Coins select it through a profile.
+    let (input, height) = R_INPUTS[0];
+    let profile = coin_profile("wownero").unwrap();
+    assert_eq!(profile.pow_hash(input, height), Some(WOW_OUTPUTS[0]));
+}
+
+#[test]
+#[should_panic(expected = "at least 43 bytes")]
+fn variant_1_short_input() {
+    CryptoNight::with_variant(Variant::V1).chain(&[0u8; 42][..]).result();
+}
+
+#[test]
+fn cn_r_engines_agree() {
+    let input = b"CryptoNight-R";
+
+    let previous = set_random_math_engine(RandomMathEngine::Interpreter);
+    let interpreted = CryptoNight::digest_with_height(input, 1_806_260);
+    set_random_math_engine(RandomMathEngine::Jit);
+    let compiled = CryptoNight::digest_with_height(input, 1_806_260);
+    set_random_math_engine(previous);
+
+    assert_eq!(interpreted, compiled);
+}
+
+#[test]
+fn small_variants_use_smaller_scratchpads() {
+    let input = b"This is a test";
+
+    for &variant in &[Variant::Lite, Variant::Pico, Variant::Upx2] {
+        let mut scratchpad = Scratchpad::allocate(variant.scratchpad_size());
+        let digest = CryptoNight::with_variant(variant).chain(input).fixed_result_with_buffer(scratchpad.as_mut());
+
+        assert_eq!(digest, CryptoNight::with_variant(variant).chain(input).result(), "{}", variant);
+    }
+}
+
+#[test]
+fn interleaved_hashes_match() {
+    let mut slab = Scratchpad::allocate(2 * CryptoNight::SP_SIZE);
+    let mut scratchpads = split_slab(slab.as_mut(), 2);
+    let (first_scratchpad, second_scratchpad) = (scratchpads.next().unwrap(), scratchpads.next().unwrap());
+
+    let (a, b) = CryptoNight::digest2_with_buffers(INPUTS[1], INPUTS[2], first_scratchpad, second_scratchpad);
+    assert_eq!(a, CryptoNight::digest(INPUTS[1]));
+    assert_eq!(b, CryptoNight::digest(INPUTS[2]));
+
+    for &variant in Variant::all() {
+        let first = CryptoNight::with_variant(variant).chain(&[1u8; 76][..]);
+        let second = CryptoNight::with_variant(variant).chain(&[2u8; 76][..]);
+
+        let (a, b) = first.clone().fixed_result2_with_buffers(second.clone(), first_scratchpad, second_scratchpad);
+        assert_eq!(a, first.result(), "{}", variant);
+        assert_eq!(b, second.result(), "{}", variant);
+    }
+
+    // Hashers that cannot run in lockstep are computed one after the other.
+    let first = CryptoNight::with_height(1_806_260).chain(b"CryptoNight-R");
+    let second = CryptoNight::with_variant(Variant::Lite).chain(b"This is a test");
+    let (a, b) = first.clone().fixed_result2_with_buffers(second.clone(), first_scratchpad, second_scratchpad);
+    assert_eq!(a, first.result());
+    assert_eq!(b, second.result());
+}
+
+#[test]
+fn four_way_hashes_match() {
+    let mut slab = Scratchpad::allocate(4 * CryptoNight::SP_SIZE);
+    let mut scratchpads = split_slab(slab.as_mut(), 4);
+    let [s0, s1, s2, s3] = [(); 4].map(|_| scratchpads.next().unwrap());
+
+    for &variant in Variant::all() {
+        let hashers = [1u8, 2, 3, 4].map(|i| CryptoNight::with_variant(variant).chain(&[i; 76][..]));
+        let expected = hashers.clone().map(|hasher| hasher.result());
+
+        let digests = CryptoNight::fixed_result4_with_buffers(hashers, [&mut *s0, &mut *s1, &mut *s2, &mut *s3]);
+        assert_eq!(digests, expected, "{}", variant);
+    }
+
+    // Hashers that cannot run in lockstep are computed separately.
+    let hashers = [
+        CryptoNight::with_variant(Variant::V0).chain(b"This is a test"),
+        CryptoNight::with_variant(Variant::Lite).chain(b"This is a test"),
+        CryptoNight::with_height(1_806_260).chain(b"CryptoNight-R"),
+        CryptoNight::with_height(1_806_261).chain(b"CryptoNight-R"),
+    ];
+    let expected = hashers.clone().map(|hasher| hasher.result());
+    assert_eq!(CryptoNight::fixed_result4_with_buffers(hashers, [s0, s1, s2, s3]), expected);
+}
+
+#[test]
+fn n_way_hashes_match() {
+    let mut slab = Scratchpad::allocate(3 * CryptoNight::SP_SIZE);
+    let mut scratchpads = split_slab(slab.as_mut(), 3);
+    let [s0, s1, s2] = [(); 3].map(|_| scratchpads.next().unwrap());
+
+    for &variant in Variant::all() {
+        let hashers = [1u8, 2, 3].map(|i| CryptoNight::with_variant(variant).chain(&[i; 76][..]));
+        let expected = hashers.clone().map(|hasher| hasher.result());
+
+        let digests = CryptoNight::fixed_result_n_with_buffers(hashers, [&mut *s0, &mut *s1, &mut *s2]);
+        assert_eq!(digests, expected, "{}", variant);
+    }
+
+    let digests = CryptoNight::digest_n(&[INPUTS[1]], [&mut *s0]);
+    assert_eq!(digests, [CryptoNight::digest(INPUTS[1])]);
+
+    // Hashers that cannot run in lockstep are computed one after the other.
+    let hashers = [
+        CryptoNight::with_variant(Variant::Lite).chain(b"This is a test"),
+        CryptoNight::with_height(1_806_260).chain(b"CryptoNight-R"),
+        CryptoNight::with_height(1_806_261).chain(b"CryptoNight-R"),
+    ];
+    let expected = hashers.clone().map(|hasher| hasher.result());
+    assert_eq!(CryptoNight::fixed_result_n_with_buffers(hashers, [s0, s1, s2]), expected);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialized_hashers_resume() {
+    let input: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let (head, tail) = input.split_at(200);
+    let custom = CustomVariant::builder(Variant::V1).iterations(1 << 10).build().unwrap();
+
+    for hasher in [CryptoNight::with_variant(Variant::V1), CryptoNight::with_height(1_806_260), CryptoNight::with_custom(custom)] {
+        let expected = hasher.clone().chain(&input).result();
+
+        let saved = serde_json::to_string(&hasher.chain(head)).unwrap();
+        let resumed: CryptoNight = serde_json::from_str(&saved).unwrap();
+        assert_eq!(resumed.chain(tail).result(), expected);
+    }
+
+    let saved = serde_json::to_string(&CryptoNight::with_custom(custom)).unwrap();
+    let mismatched = saved.replace("\"variant\":\"cn/1\"", "\"variant\":\"cn/2\"");
+    assert_ne!(saved, mismatched);
+    assert!(serde_json::from_str::<CryptoNight>(&mismatched).is_err());
+}
+
+#[test]
+#[cfg(feature = "zeroize")]
+fn scratchpads_are_wiped() {
+    let mut slab = Scratchpad::allocate(3 * CryptoNight::SP_SIZE);
+    let mut scratchpads = split_slab(slab.as_mut(), 3);
+    let single = scratchpads.next().unwrap();
+    let expected = CryptoNight::digest(b"This is a test");
+
+    assert_eq!(CryptoNight::digest_with_buffer(b"This is a test", single), expected);
+    assert!(single.iter().all(|&byte| byte == 0));
+
+    let pair = [(); 2].map(|_| scratchpads.next().unwrap());
+    CryptoNight::digest_n(&[b"First", b"Other"], pair);
+    assert!(slab.as_ref().iter().all(|&byte| byte == 0));
+}